@@ -7,9 +7,10 @@ use std::sync::{Arc, Mutex};
 use crate::appop::AppOp;
 use crate::i18n::i18n;
 use crate::types::Message;
+use crate::util;
 use crate::widgets::FileDialog::open;
 use crate::App;
-use fractal_api::identifiers::{EventId, RoomId};
+use fractal_api::identifiers::{EventId, RoomId, UserId};
 use gio::prelude::*;
 use gio::SimpleAction;
 use gtk::prelude::*;
@@ -24,6 +25,7 @@ pub enum AppState {
     MediaViewer,
     AccountSettings,
     Directory,
+    Search,
 }
 impl<'a> From<&'a glib::Variant> for AppState {
     fn from(v: &glib::Variant) -> AppState {
@@ -42,6 +44,7 @@ impl From<String> for AppState {
             "account-settings" => AppState::AccountSettings,
             "room-settings" => AppState::RoomSettings,
             "directory" => AppState::Directory,
+            "search" => AppState::Search,
             _ => panic!("Invalid back state type"),
         }
     }
@@ -58,6 +61,7 @@ impl From<AppState> for glib::Variant {
             AppState::AccountSettings => "account-settings".to_variant(),
             AppState::RoomSettings => "room-setting".to_variant(),
             AppState::Directory => "directory".to_variant(),
+            AppState::Search => "search".to_variant(),
         }
     }
 }
@@ -70,6 +74,11 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
     let newr = SimpleAction::new("new_room", None);
     let joinr = SimpleAction::new("join_room", None);
     let logout = SimpleAction::new("logout", None);
+    let add_account = SimpleAction::new("add-account", None);
+    let cancel_add_account = SimpleAction::new("cancel-add-account", None);
+    let switch_account = SimpleAction::new("switch-account", glib::VariantTy::new("s").ok());
+    let retry_sync = SimpleAction::new("retry-sync", None);
+    let cancel_sso = SimpleAction::new("cancel-sso", None);
 
     let inv = SimpleAction::new("room_invite", None);
     let search = SimpleAction::new("search", None);
@@ -81,15 +90,26 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
     let main_menu = SimpleAction::new("main_menu", None);
 
     let open_room = SimpleAction::new("open-room", glib::VariantTy::new("s").ok());
+    let open_replacement_room = SimpleAction::new("open-replacement-room", None);
     let back = SimpleAction::new("back", None);
     let media_viewer = SimpleAction::new("open-media-viewer", glib::VariantTy::new("s").ok());
     let account = SimpleAction::new("open-account-settings", None);
     let directory = SimpleAction::new("directory", None);
+    let open_search_result =
+        SimpleAction::new("open-search-result", glib::VariantTy::new("s").ok());
     //TODO: use roomid as value
     let room_settings = SimpleAction::new("open-room-settings", None);
     // TODO: send file should be a message action
     let send_file = SimpleAction::new("send-file", None);
     let send_message = SimpleAction::new("send-message", None);
+    let retry_message = SimpleAction::new("retry-message", glib::VariantTy::new("s").ok());
+    let discard_message = SimpleAction::new("discard-message", glib::VariantTy::new("s").ok());
+    let cancel_reply = SimpleAction::new("cancel-reply", None);
+
+    let show_state = util::get_state_events_schema();
+    op.lock().unwrap().show_state_events = show_state;
+    let show_state_events =
+        SimpleAction::new_stateful("show-state-events", None, &show_state.to_variant());
 
     let previous_room = SimpleAction::new("previous-room", None);
     let next_room = SimpleAction::new("next-room", None);
@@ -106,6 +126,11 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
     app.add_action(&newr);
     app.add_action(&joinr);
     app.add_action(&logout);
+    app.add_action(&add_account);
+    app.add_action(&cancel_add_account);
+    app.add_action(&switch_account);
+    app.add_action(&retry_sync);
+    app.add_action(&cancel_sso);
 
     app.add_action(&inv);
     app.add_action(&search);
@@ -115,8 +140,10 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
     app.add_action(&shortcuts);
     app.add_action(&about);
     app.add_action(&open_room);
+    app.add_action(&open_replacement_room);
     app.add_action(&back);
     app.add_action(&directory);
+    app.add_action(&open_search_result);
     app.add_action(&room_settings);
     app.add_action(&media_viewer);
     app.add_action(&account);
@@ -124,6 +151,10 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
 
     app.add_action(&send_file);
     app.add_action(&send_message);
+    app.add_action(&retry_message);
+    app.add_action(&discard_message);
+    app.add_action(&cancel_reply);
+    app.add_action(&show_state_events);
 
     app.add_action(&previous_room);
     app.add_action(&next_room);
@@ -148,6 +179,22 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
     settings.set_enabled(false);
 
     logout.connect_activate(clone!(@strong op => move |_, _| op.lock().unwrap().logout() ));
+    add_account
+        .connect_activate(clone!(@strong op => move |_, _| op.lock().unwrap().add_account() ));
+    // Only there while we are adding an account
+    cancel_add_account.set_enabled(false);
+    cancel_add_account.connect_activate(
+        clone!(@strong op => move |_, _| op.lock().unwrap().cancel_add_account() ),
+    );
+    switch_account.connect_activate(clone!(@strong op => move |_, data| {
+        if let Some(uid) = get_user_id(data) {
+            op.lock().unwrap().switch_account(uid);
+        }
+    }));
+    retry_sync.connect_activate(clone!(@strong op => move |_, _| op.lock().unwrap().retry_sync() ));
+    // Only there while a single sign-on waits for the browser
+    cancel_sso.set_enabled(false);
+    cancel_sso.connect_activate(clone!(@strong op => move |_, _| op.lock().unwrap().cancel_sso() ));
     inv.connect_activate(
         clone!(@strong op => move |_, _| op.lock().unwrap().show_invite_user_dialog() ),
     );
@@ -161,6 +208,9 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
     joinr.connect_activate(
         clone!(@strong op => move |_, _| op.lock().unwrap().join_to_room_dialog() ),
     );
+    open_replacement_room.connect_activate(
+        clone!(@strong op => move |_, _| { op.lock().unwrap().open_replacement_room(); }),
+    );
 
     previous_room.connect_activate(clone!(@strong op => move |_, _| {
         let mut op = op.lock().unwrap();
@@ -227,6 +277,24 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
         back.borrow_mut().push(AppState::Directory);
     }));
 
+    search.connect_activate(clone!(
+    @strong op,
+    @weak back_history as back
+    => move |_, _| {
+        op.lock().unwrap().set_state(AppState::Search);
+        back.borrow_mut().push(AppState::Search);
+    }));
+
+    open_search_result.connect_activate(clone!(
+    @strong op,
+    @weak back_history as back
+    => move |_, data| {
+        if let Some(id) = get_event_id(data) {
+            op.lock().unwrap().open_search_result(id);
+            back.borrow_mut().push(AppState::Room);
+        }
+    }));
+
     /* TODO: We could pass a message to this to highlight it in the room history, might be
      * handy when opening the room from a notification */
     open_room.connect_activate(clone!(
@@ -308,22 +376,54 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
             let end = buffer.get_end_iter();
 
             if let Some(text) = buffer.get_text(&start, &end, false) {
-                op.lock().unwrap().send_message(text.to_string());
+                // Keep what the user typed so they can fix it
+                if !op.lock().unwrap().send_message(text.to_string()) {
+                    return;
+                }
             }
 
             buffer.set_text("");
         }
     }));
 
+    retry_message.connect_activate(clone!(@strong op => move |_, data| {
+        if let Some(txn_id) = data.and_then(|d| d.get_str()) {
+            op.lock().unwrap().retry_message(txn_id.to_string());
+        }
+    }));
+
+    discard_message.connect_activate(clone!(@strong op => move |_, data| {
+        if let Some(txn_id) = data.and_then(|d| d.get_str()) {
+            op.lock().unwrap().discard_message(txn_id.to_string());
+        }
+    }));
+
+    cancel_reply.connect_activate(clone!(@strong op => move |_, _| {
+        op.lock().unwrap().cancel_reply();
+    }));
+
+    // Activating a boolean action without an activate handler toggles its state
+    show_state_events.connect_change_state(clone!(@strong op => move |action, state| {
+        let show = unwrap_or_unit_return!(state.and_then(|state| state.get::<bool>()));
+        action.set_state(&show.to_variant());
+        util::set_state_events_schema(show);
+        op.lock().unwrap().set_show_state_events(show);
+    }));
+
     send_message.set_enabled(false);
     let buffer = op.lock().unwrap().ui.sventry.buffer.clone();
-    buffer.connect_changed(move |buffer| {
+    buffer.connect_changed(clone!(@strong op => move |buffer| {
         if 0 < buffer.get_char_count() {
             send_message.set_enabled(true);
         } else {
             send_message.set_enabled(false);
+            /* Emptying the composer cancels the edit, it's already locked when we switch rooms
+             * and then the text is for another room anyway */
+            if let Ok(mut op) = op.try_lock() {
+                op.editing = None;
+            }
         }
-    });
+    }));
 
     /* Add Keybindings to actions */
     app.set_accels_for_action("app.quit", &["<Ctrl>Q"]);
@@ -336,6 +436,7 @@ pub fn new(app: &gtk::Application, op: &Arc<Mutex<AppOp>>) {
     app.set_accels_for_action("app.older-messages", &["Page_Up"]);
     app.set_accels_for_action("app.newer-messages", &["Page_Down"]);
     app.set_accels_for_action("app.back", &["Escape"]);
+    app.set_accels_for_action("app.search", &["<Ctrl><Shift>F"]);
     app.set_accels_for_action("app.main_menu", &["F10"]);
 
     // connect mouse back button to app.back action
@@ -362,6 +463,10 @@ pub fn get_room_id(data: Option<&glib::Variant>) -> Option<RoomId> {
     data?.get_str().and_then(|rid| rid.try_into().ok())
 }
 
+pub fn get_user_id(data: Option<&glib::Variant>) -> Option<UserId> {
+    data?.get_str().and_then(|uid| uid.try_into().ok())
+}
+
 pub fn get_event_id(data: Option<&glib::Variant>) -> Option<EventId> {
     data?.get_str().and_then(|evid| evid.try_into().ok())
 }
@@ -1,5 +1,5 @@
-use log::{debug, warn};
-use std::cell::RefCell;
+use log::debug;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 use gio::prelude::*;
@@ -8,13 +8,12 @@ use gio::SimpleActionGroup;
 use glib::clone;
 use gtk::prelude::*;
 
-use crate::globals;
-
 #[derive(Debug, Clone, PartialEq)]
 pub enum LoginState {
     Greeter,
     ServerChooser,
     Credentials,
+    Registration,
 }
 
 impl From<String> for LoginState {
@@ -23,6 +22,7 @@ impl From<String> for LoginState {
             "greeter" => LoginState::Greeter,
             "server-chooser" => LoginState::ServerChooser,
             "credentials" => LoginState::Credentials,
+            "registration" => LoginState::Registration,
             _ => panic!("Invalid back state type"),
         }
     }
@@ -34,6 +34,7 @@ impl ToString for LoginState {
             LoginState::Greeter => "greeter",
             LoginState::ServerChooser => "server-chooser",
             LoginState::Credentials => "credentials",
+            LoginState::Registration => "registration",
         };
 
         String::from(str)
@@ -53,39 +54,47 @@ pub fn new(
     let credentials = SimpleAction::new("credentials", None);
     let back = SimpleAction::new("back", None);
     let login = SimpleAction::new("login", None);
+    let register = SimpleAction::new("register", None);
+    let sso = SimpleAction::new("sso", None);
 
     actions.add_action(&create_account);
     actions.add_action(&server_chooser);
     actions.add_action(&credentials);
     actions.add_action(&back);
     actions.add_action(&login);
-
-    create_account.connect_activate(clone!(@weak stack => move |_, _| {
-        let toplevel = stack
-            .get_toplevel()
-            .expect("Could not grab toplevel widget")
-            .downcast::<gtk::Window>()
-            .expect("Could not cast toplevel to GtkWindow");
-        let uri = globals::RIOT_REGISTER_URL;
-        if let Err(e) = gtk::show_uri_on_window(Some(&toplevel), uri, gtk::get_current_event_time())
-        {
-            warn!("Could not show {}: {}", uri, e)
-        }
-    }));
+    actions.add_action(&register);
+    actions.add_action(&sso);
 
     let back_history: Rc<RefCell<Vec<LoginState>>> = Rc::new(RefCell::new(vec![]));
+    // The server chooser is shared by the login and the registration
+    let registering = Rc::new(Cell::new(false));
 
-    server_chooser.connect_activate(
-        clone!(@weak stack, @weak back_history as back => move |_, _| {
-            let state = LoginState::ServerChooser;
-            stack.set_visible_child_name(&state.to_string());
-            back.borrow_mut().push(state);
-        }),
-    );
+    create_account.connect_activate(clone!(
+    @weak stack,
+    @weak back_history as back,
+    @weak registering
+    => move |_, _| {
+        registering.set(true);
+        let state = LoginState::ServerChooser;
+        stack.set_visible_child_name(&state.to_string());
+        back.borrow_mut().push(state);
+    }));
+
+    server_chooser.connect_activate(clone!(
+    @weak stack,
+    @weak back_history as back,
+    @weak registering
+    => move |_, _| {
+        registering.set(false);
+        let state = LoginState::ServerChooser;
+        stack.set_visible_child_name(&state.to_string());
+        back.borrow_mut().push(state);
+    }));
 
     credentials.connect_activate(clone!(
     @weak stack,
     @weak back_history as back,
+    @weak registering,
     @weak server_entry,
     @weak err_label
     => move |_, _| {
@@ -94,7 +103,11 @@ pub fn new(
                 err_label.show();
             } else {
                 err_label.hide();
-                let state = LoginState::Credentials;
+                let state = if registering.get() {
+                    LoginState::Registration
+                } else {
+                    LoginState::Credentials
+                };
                 stack.set_visible_child_name(&state.to_string());
                 back.borrow_mut().push(state);
             }
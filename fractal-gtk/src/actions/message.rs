@@ -43,6 +43,9 @@ pub fn new(
     let actions = SimpleActionGroup::new();
     /* Action for each message */
     let reply = SimpleAction::new("reply", glib::VariantTy::new("s").ok());
+    let open_reply = SimpleAction::new("open_reply", glib::VariantTy::new("s").ok());
+    let edit = SimpleAction::new("edit", glib::VariantTy::new("s").ok());
+    let edit_history = SimpleAction::new("edit_history", glib::VariantTy::new("s").ok());
     let open_with = SimpleAction::new("open_with", glib::VariantTy::new("s").ok());
     let save_as = SimpleAction::new("save_as", glib::VariantTy::new("s").ok());
     let copy_image = SimpleAction::new("copy_image", glib::VariantTy::new("s").ok());
@@ -55,6 +58,9 @@ pub fn new(
         SimpleAction::new("request_older_messages", glib::VariantTy::new("s").ok());
 
     actions.add_action(&reply);
+    actions.add_action(&open_reply);
+    actions.add_action(&edit);
+    actions.add_action(&edit_history);
     actions.add_action(&open_with);
     actions.add_action(&save_as);
     actions.add_action(&copy_image);
@@ -84,8 +90,7 @@ pub fn new(
         .expect("Couldn't find main_window in ui file.");
     reply.connect_activate(clone!(
     @weak back_history,
-    @weak window,
-    @weak ui.sventry.view as msg_entry
+    @weak window
     => move |_, data| {
         let state = back_history.borrow().last().cloned();
         if let Some(AppState::MediaViewer) = state {
@@ -95,23 +100,29 @@ pub fn new(
                 error!("The action group app is not attached to the main window.");
             }
         }
-        if let Some(buffer) = msg_entry.get_buffer() {
-            let mut start = buffer.get_start_iter();
-            if let Some(m) = get_message(data) {
-                let quote = m
-                    .body
-                    .lines()
-                    .map(|l| "> ".to_owned() + l)
-                    .collect::<Vec<String>>()
-                    .join("\n")
-                    + "\n"
-                    + "\n";
-                buffer.insert(&mut start, &quote);
-                msg_entry.grab_focus();
-            }
+        if let Some(msg) = get_message(data) {
+            APPOP!(set_reply_to, (msg));
         }
     }));
 
+    open_reply.connect_activate(move |_, data| {
+        if let Some(id) = get_event_id(data) {
+            APPOP!(open_reply, (id));
+        }
+    });
+
+    edit.connect_activate(move |_, data| {
+        if let Some(msg) = get_message(data) {
+            APPOP!(edit_message, (msg));
+        }
+    });
+
+    edit_history.connect_activate(move |_, data| {
+        if let Some(id) = get_event_id(data) {
+            APPOP!(show_edit_history, (id));
+        }
+    });
+
     open_with.connect_activate(clone!(@strong server_url => move |_, data| {
         if let Some(m) = get_message(data) {
             if let Some(url) = m.url {
@@ -277,11 +288,33 @@ fn request_more_messages(
     let op = op.lock().unwrap();
     let id = id?;
     let r = op.rooms.get(&id)?;
-    if let Some(prev_batch) = r.prev_batch.clone() {
+    let olm_machine = op.olm_machine.clone();
+    if let Some(ctx) = op.timeline_context.as_ref().filter(|ctx| ctx.room_id == id) {
+        // we are looking at the messages around a search result, keep loading from there
+        let prev_batch = ctx.prev_batch.clone()?;
         thread::spawn(move || {
-            match room::get_room_messages(server_url, access_token, id, prev_batch) {
+            match room::get_room_messages(server_url, access_token, id, prev_batch, olm_machine) {
                 Ok((msgs, room, prev_batch)) => {
-                    APPOP!(show_room_messages_top, (msgs, room, prev_batch));
+                    APPOP!(show_context_messages_top, (msgs, room, prev_batch));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    } else if let Some(prev_batch) = r.prev_batch.clone() {
+        // the history may have gone on in the room this one replaced
+        let from_room = r.prev_batch_room.clone().unwrap_or_else(|| id.clone());
+        thread::spawn(move || {
+            match room::get_room_messages(
+                server_url,
+                access_token,
+                from_room,
+                prev_batch,
+                olm_machine,
+            ) {
+                Ok((msgs, _, prev_batch)) => {
+                    APPOP!(show_room_messages_top, (msgs, id, prev_batch));
                 }
                 Err(err) => {
                     err.handle_error();
@@ -291,7 +324,7 @@ fn request_more_messages(
     } else if let Some(msg) = r.messages.iter().next().cloned() {
         // no prev_batch so we use the last message to calculate that in the backend
         thread::spawn(move || {
-            match room::get_room_messages_from_msg(server_url, access_token, id, msg) {
+            match room::get_room_messages_from_msg(server_url, access_token, id, msg, olm_machine) {
                 Ok((msgs, room, prev_batch)) => {
                     APPOP!(show_room_messages_top, (msgs, room, prev_batch));
                 }
@@ -302,16 +335,16 @@ fn request_more_messages(
         });
     } else if let Some(from) = op.since.clone() {
         // no messages and no prev_batch so we use the last since
-        thread::spawn(
-            move || match room::get_room_messages(server_url, access_token, id, from) {
+        thread::spawn(move || {
+            match room::get_room_messages(server_url, access_token, id, from, olm_machine) {
                 Ok((msgs, room, prev_batch)) => {
                     APPOP!(show_room_messages_top, (msgs, room, prev_batch));
                 }
                 Err(err) => {
                     err.handle_error();
                 }
-            },
-        );
+            }
+        });
     }
     None
 }
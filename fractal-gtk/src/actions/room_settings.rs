@@ -32,7 +32,32 @@ pub fn new(
         &ButtonState::Sensitive.into(),
     );
 
+    let upgrade = SimpleAction::new("upgrade", glib::VariantTy::new("s").ok());
+
     actions.add_action(&change_avatar);
+    actions.add_action(&upgrade);
+
+    upgrade.connect_activate(
+        clone!(@strong server_url, @strong access_token => move |_, data| {
+            if let Some(room_id) = data
+                .and_then(|x| x.get_str())
+                .and_then(|rid| RoomId::try_from(rid).ok())
+            {
+                let server = server_url.clone();
+                let access_token = access_token.clone();
+                thread::spawn(
+                    move || match room::get_default_room_version(server, access_token) {
+                        Ok(version) => {
+                            APPOP!(ask_room_upgrade, (room_id, version));
+                        }
+                        Err(err) => {
+                            err.handle_error();
+                        }
+                    },
+                );
+            }
+        }),
+    );
 
     change_avatar.connect_activate(clone!(@weak window => move |a, data| {
         if let Some(room_id) = data
@@ -70,6 +70,36 @@ impl App {
             .builder
             .get_object::<gtk::Button>("account_settings_delete_btn")
             .expect("Can't find account_settings_delete_btn in ui file.");
+        let sessions_entry = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("account_settings_sessions_password")
+            .expect("Can't find account_settings_sessions_password in ui file.");
+        let sessions_btn = self
+            .ui
+            .builder
+            .get_object::<gtk::Button>("account_settings_sessions_sign_out_btn")
+            .expect("Can't find account_settings_sessions_sign_out_btn in ui file.");
+        let keywords_entry = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("account_settings_keywords_entry")
+            .expect("Can't find account_settings_keywords_entry in ui file.");
+        let keywords_btn = self
+            .ui
+            .builder
+            .get_object::<gtk::Button>("account_settings_keywords_add")
+            .expect("Can't find account_settings_keywords_add in ui file.");
+        let credentials_entry = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("account_settings_credentials_passphrase")
+            .expect("Can't find account_settings_credentials_passphrase in ui file.");
+        let credentials_btn = self
+            .ui
+            .builder
+            .get_object::<gtk::Button>("account_settings_credentials_btn")
+            .expect("Can't find account_settings_credentials_btn in ui file.");
 
         let window = self.main_window.upcast_ref::<gtk::Window>();
         let actions = AccountSettings::new(&window, op.clone());
@@ -213,6 +243,40 @@ impl App {
             validate_password_input(&builder)
         }));
 
+        sessions_entry.connect_property_text_notify(clone!(@strong sessions_btn => move |w| {
+            let empty = w.get_text().map_or(true, |text| text.is_empty());
+            sessions_btn.set_sensitive(!empty);
+        }));
+
+        sessions_entry.connect_activate(clone!(@strong op => move |_| {
+            op.lock().unwrap().sign_out_other_devices();
+        }));
+
+        sessions_btn.connect_clicked(clone!(@strong op => move |_| {
+            op.lock().unwrap().sign_out_other_devices();
+        }));
+
+        keywords_entry.connect_property_text_notify(clone!(@strong keywords_btn => move |w| {
+            let empty = w.get_text().map_or(true, |text| text.trim().is_empty());
+            keywords_btn.set_sensitive(!empty);
+        }));
+
+        keywords_entry.connect_activate(clone!(@strong op => move |_| {
+            op.lock().unwrap().add_highlight_keyword();
+        }));
+
+        keywords_btn.connect_clicked(clone!(@strong op => move |_| {
+            op.lock().unwrap().add_highlight_keyword();
+        }));
+
+        credentials_entry.connect_activate(clone!(@strong op => move |_| {
+            op.lock().unwrap().set_credentials_passphrase();
+        }));
+
+        credentials_btn.connect_clicked(clone!(@strong op => move |_| {
+            op.lock().unwrap().set_credentials_passphrase();
+        }));
+
         destruction_entry.connect_property_text_notify(
             clone!(@strong destruction_btn => move |w| {
                 if let Some(text) = w.get_text() {
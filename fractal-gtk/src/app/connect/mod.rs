@@ -8,8 +8,10 @@ mod join_room;
 mod language;
 mod leave_room;
 mod markdown;
+mod network;
 mod new_room;
 mod roomlist_search;
+mod search;
 mod send;
 
 use crate::app::App;
@@ -24,6 +26,7 @@ impl App {
         self.connect_language();
 
         self.connect_directory();
+        self.connect_search();
         self.connect_leave_room_dialog();
         self.connect_new_room_dialog();
         self.connect_join_room_dialog();
@@ -34,5 +37,7 @@ impl App {
         self.connect_direct_chat();
 
         self.connect_roomlist_search();
+
+        self.connect_network_monitor();
     }
 }
@@ -0,0 +1,22 @@
+use gio::prelude::*;
+
+use crate::app::App;
+
+impl App {
+    pub fn connect_network_monitor(&self) {
+        let monitor = match gio::NetworkMonitor::get_default() {
+            Some(monitor) => monitor,
+            None => return,
+        };
+
+        let op = self.op.clone();
+        monitor.connect_network_changed(move |_, available| {
+            let mut op = op.lock().unwrap();
+            // The outbox waits for this to try again the messages we couldn't send
+            if available {
+                op.dequeue_message();
+            }
+            op.network_changed(available);
+        });
+    }
+}
@@ -0,0 +1,82 @@
+use gtk::prelude::*;
+use libhandy::{Column, ColumnExt};
+
+use crate::app::App;
+use crate::appop::RoomSearchPagination;
+
+impl App {
+    pub fn connect_search(&self) {
+        let q = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("message_search_entry")
+            .expect("Can't find message_search_entry in ui file.");
+
+        let order = self
+            .ui
+            .builder
+            .get_object::<gtk::ComboBoxText>("message_search_order")
+            .expect("Can't find message_search_order in ui file.");
+
+        let search_stack = self
+            .ui
+            .builder
+            .get_object::<gtk::Stack>("search_stack")
+            .expect("Can't find search_stack in ui file.");
+
+        let column = Column::new();
+        let results = gtk::Box::new(gtk::Orientation::Vertical, 18);
+
+        column.set_maximum_width(800);
+        /* Column is not seen as a gtk::Container, see connect_directory */
+        let column = column.upcast::<gtk::Widget>();
+        let column = column.downcast::<gtk::Container>().unwrap();
+        column.set_hexpand(true);
+        column.set_vexpand(true);
+        column.set_margin_top(24);
+        column.set_margin_bottom(24);
+        column.set_margin_start(12);
+        column.set_margin_end(12);
+
+        column.add(&results);
+        results.show();
+        column.show();
+        search_stack.add_named(&column, "search_column");
+
+        let column = column.upcast::<gtk::Widget>();
+        let column = column.downcast::<Column>().unwrap();
+        self.ui
+            .builder
+            .expose_object::<gtk::Box>("search_results", &results);
+        self.ui
+            .builder
+            .expose_object::<Column>("search_column", &column);
+
+        let scroll = self
+            .ui
+            .builder
+            .get_object::<gtk::ScrolledWindow>("search_scroll")
+            .expect("Can't find search_scroll in ui file.");
+
+        let mut op = self.op.clone();
+        scroll.connect_edge_reached(move |_, dir| {
+            if dir == gtk::PositionType::Bottom {
+                op.lock().unwrap().load_more_search_results();
+            }
+        });
+
+        op = self.op.clone();
+        q.connect_activate(move |_| {
+            let mut op = op.lock().unwrap();
+            op.search_pagination = RoomSearchPagination::Initial;
+            op.search_messages();
+        });
+
+        op = self.op.clone();
+        order.connect_changed(move |_| {
+            let mut op = op.lock().unwrap();
+            op.search_pagination = RoomSearchPagination::Initial;
+            op.search_messages();
+        });
+    }
+}
@@ -1,4 +1,5 @@
 use crate::backend::user;
+use chrono::prelude::*;
 use gtk::prelude::*;
 use log::info;
 use std::path::PathBuf;
@@ -10,11 +11,14 @@ use crate::appop::AppState;
 use crate::backend::HandleError;
 
 use crate::i18n::i18n;
+use crate::model::push_rules;
 use crate::widgets;
 use crate::widgets::AvatarExt;
 
 use crate::cache::{download_to_cache, remove_from_cache};
 use fractal_api::r0::contact::get_identifiers::ThirdPartyIdentifier;
+use fractal_api::r0::device::Device;
+use fractal_api::r0::pushrules::PushRule;
 use fractal_api::r0::Medium;
 
 use super::LoginData;
@@ -315,9 +319,22 @@ impl AppOp {
             .builder
             .get_object::<gtk::CheckButton>("account_settings_delete_check")
             .expect("Can't find account_settings_delete_check in ui file.");
+        let sessions_password = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("account_settings_sessions_password")
+            .expect("Can't find account_settings_sessions_password in ui file.");
+        let sessions_btn = self
+            .ui
+            .builder
+            .get_object::<gtk::Button>("account_settings_sessions_sign_out_btn")
+            .expect("Can't find account_settings_sessions_sign_out_btn in ui file.");
 
         stack.set_visible_child_name("loading");
         self.get_three_pid();
+        self.get_devices();
+        self.show_highlight_keywords();
+        self.show_credentials_settings();
         uid.set_text(&login_data.uid.to_string());
         device_id.set_text(
             self.device_id
@@ -344,6 +361,9 @@ impl AppOp {
         password_btn_stack.set_visible_child_name("label");
         password_btn.set_sensitive(true);
 
+        sessions_password.set_text("");
+        sessions_btn.set_sensitive(false);
+
         destruction_flag.set_active(false);
         destruction_btn.set_sensitive(false);
         destruction_entry.set_text("");
@@ -826,4 +846,304 @@ impl AppOp {
     pub fn account_destruction_logoff(&self) {
         /* Do logout */
     }
+
+    pub fn get_devices(&self) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let stack = self
+            .ui
+            .builder
+            .get_object::<gtk::Stack>("account_settings_sessions_stack")
+            .expect("Can't find account_settings_sessions_stack in ui file.");
+        stack.set_visible_child_name("loading");
+
+        thread::spawn(move || {
+            match user::get_devices(login_data.server_url, login_data.access_token) {
+                Ok(devices) => {
+                    let devices = Some(devices);
+                    APPOP!(set_devices, (devices));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    pub fn set_devices(&self, devices: Option<Vec<Device>>) {
+        let stack = self
+            .ui
+            .builder
+            .get_object::<gtk::Stack>("account_settings_sessions_stack")
+            .expect("Can't find account_settings_sessions_stack in ui file.");
+        let list = self
+            .ui
+            .builder
+            .get_object::<gtk::ListBox>("account_settings_sessions_list")
+            .expect("Can't find account_settings_sessions_list in ui file.");
+
+        let mut devices = match devices {
+            Some(devices) => devices,
+            None => {
+                stack.set_visible_child_name("error");
+                return;
+            }
+        };
+
+        for child in list.get_children().iter() {
+            list.remove(child);
+        }
+
+        // The current session goes first, then the most recently used ones
+        devices.sort_by_key(|device| {
+            (
+                Some(&device.device_id) != self.device_id.as_ref(),
+                std::cmp::Reverse(device.last_seen_ts),
+            )
+        });
+        for device in devices.iter() {
+            let current = Some(&device.device_id) == self.device_id.as_ref();
+            list.insert(&session_row(device, current), -1);
+        }
+
+        stack.set_visible_child_name("list");
+    }
+
+    pub fn rename_device(&self, name: String) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let device_id = unwrap_or_unit_return!(self.device_id.clone());
+
+        thread::spawn(move || {
+            match user::set_device_name(
+                login_data.server_url,
+                login_data.access_token,
+                device_id,
+                name,
+            ) {
+                Ok(_) => {
+                    APPOP!(get_devices);
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    pub fn sign_out_other_devices(&self) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let device_id = unwrap_or_unit_return!(self.device_id.clone());
+        let entry = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("account_settings_sessions_password")
+            .expect("Can't find account_settings_sessions_password in ui file.");
+        let button = self
+            .ui
+            .builder
+            .get_object::<gtk::Button>("account_settings_sessions_sign_out_btn")
+            .expect("Can't find account_settings_sessions_sign_out_btn in ui file.");
+        let stack = self
+            .ui
+            .builder
+            .get_object::<gtk::Stack>("account_settings_sessions_stack")
+            .expect("Can't find account_settings_sessions_stack in ui file.");
+
+        let password = entry
+            .get_text()
+            .map_or(String::new(), |gstr| gstr.to_string());
+        if password.is_empty() {
+            return;
+        }
+
+        entry.set_text("");
+        button.set_sensitive(false);
+        stack.set_visible_child_name("loading");
+
+        thread::spawn(move || {
+            match user::delete_other_devices(
+                login_data.server_url,
+                login_data.access_token,
+                login_data.uid.localpart().into(),
+                password,
+                device_id,
+            ) {
+                Ok(_) => {
+                    APPOP!(get_devices);
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+    /// Keywords can only be changed once we know the push rules
+    pub fn show_highlight_keywords(&self) {
+        let list = self
+            .ui
+            .builder
+            .get_object::<gtk::ListBox>("account_settings_keywords_list")
+            .expect("Can't find account_settings_keywords_list in ui file.");
+        let entry = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("account_settings_keywords_entry")
+            .expect("Can't find account_settings_keywords_entry in ui file.");
+
+        for child in list.get_children().iter() {
+            list.remove(child);
+        }
+
+        let rules = self.push_rules.as_ref();
+        for rule in rules.into_iter().flat_map(push_rules::highlight_keywords) {
+            list.insert(&keyword_row(rule), -1);
+        }
+        list.set_visible(!list.get_children().is_empty());
+        list.set_sensitive(true);
+        entry.set_sensitive(rules.is_some());
+    }
+
+    pub fn add_highlight_keyword(&self) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let entry = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("account_settings_keywords_entry")
+            .expect("Can't find account_settings_keywords_entry in ui file.");
+
+        let keyword = entry
+            .get_text()
+            .map_or(String::new(), |gstr| gstr.trim().to_string());
+        let exists = self.push_rules.as_ref().map_or(false, |rules| {
+            push_rules::highlight_keywords(rules).any(|rule| rule.pattern == Some(keyword.clone()))
+        });
+        if keyword.is_empty() || exists {
+            return;
+        }
+
+        entry.set_text("");
+        thread::spawn(move || {
+            match user::add_highlight_keyword(
+                login_data.server_url,
+                login_data.access_token,
+                keyword,
+            ) {
+                Ok(rules) => {
+                    APPOP!(set_push_rules, (rules));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    pub fn remove_highlight_keyword(&self, rule_id: String) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let list = self
+            .ui
+            .builder
+            .get_object::<gtk::ListBox>("account_settings_keywords_list")
+            .expect("Can't find account_settings_keywords_list in ui file.");
+        list.set_sensitive(false);
+
+        thread::spawn(move || {
+            match user::remove_highlight_keyword(
+                login_data.server_url,
+                login_data.access_token,
+                rule_id,
+            ) {
+                Ok(rules) => {
+                    APPOP!(set_push_rules, (rules));
+                }
+                Err(err) => {
+                    err.handle_error();
+                    APPOP!(show_highlight_keywords);
+                }
+            }
+        });
+    }
+}
+
+fn keyword_row(rule: &PushRule) -> gtk::ListBoxRow {
+    let row = gtk::ListBoxRow::new();
+    row.set_activatable(false);
+    let b = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    b.set_margin_top(6);
+    b.set_margin_bottom(6);
+    b.set_margin_start(12);
+    b.set_margin_end(6);
+
+    let label = gtk::Label::new(rule.pattern.as_deref());
+    label.set_xalign(0.0);
+    label.set_ellipsize(pango::EllipsizeMode::End);
+    b.pack_start(&label, true, true, 0);
+
+    let button =
+        gtk::Button::new_from_icon_name(Some("user-trash-symbolic"), gtk::IconSize::Button);
+    button.set_tooltip_text(Some(&i18n("Remove")));
+    button.get_style_context().add_class("flat");
+    let rule_id = rule.rule_id.clone();
+    button.connect_clicked(move |_| {
+        let rule_id = rule_id.clone();
+        APPOP!(remove_highlight_keyword, (rule_id));
+    });
+    b.pack_end(&button, false, false, 0);
+
+    row.add(&b);
+    row.show_all();
+    row
+}
+
+fn session_row(device: &Device, current: bool) -> gtk::ListBoxRow {
+    let row = gtk::ListBoxRow::new();
+    row.set_activatable(false);
+    let b = gtk::Box::new(gtk::Orientation::Vertical, 3);
+    b.set_margin_top(6);
+    b.set_margin_bottom(6);
+    b.set_margin_start(12);
+    b.set_margin_end(12);
+
+    let name = device
+        .display_name
+        .clone()
+        .unwrap_or_else(|| device.device_id.to_string());
+    if current {
+        // Our own session can be renamed from here
+        let entry = gtk::Entry::new();
+        entry.set_text(&name);
+        entry.set_tooltip_text(Some(&i18n("Press Enter to rename this session")));
+        entry.connect_activate(move |w| {
+            if let Some(name) = w.get_text().filter(|text| !text.is_empty()) {
+                let name = name.to_string();
+                APPOP!(rename_device, (name));
+            }
+        });
+        b.pack_start(&entry, false, false, 0);
+    } else {
+        let label = gtk::Label::new(Some(&name));
+        label.set_xalign(0.0);
+        label.set_ellipsize(pango::EllipsizeMode::End);
+        b.pack_start(&label, false, false, 0);
+    }
+
+    let mut details = vec![device.device_id.to_string()];
+    if current {
+        details.push(i18n("This session"));
+    }
+    if let Some(ip) = device.last_seen_ip.as_ref() {
+        details.push(ip.clone());
+    }
+    if let Some(ts) = device.last_seen_ts {
+        let date = Local.timestamp_millis(ts as i64);
+        details.push(date.format(&i18n("Last seen %c")).to_string());
+    }
+    let label = gtk::Label::new(Some(&details.join(" · ")));
+    label.set_xalign(0.0);
+    label.set_line_wrap(true);
+    label.get_style_context().add_class("dim-label");
+    b.pack_start(&label, false, false, 0);
+
+    row.add(&b);
+    row.show_all();
+    row
 }
@@ -0,0 +1,362 @@
+use fractal_api::identifiers::{RoomId, UserId};
+use fractal_api::r0::sync::sync_events::UnreadNotificationsCount;
+use gio::prelude::*;
+use glib::source::Continue;
+use gtk::prelude::*;
+use log::error;
+use std::collections::{HashMap, HashSet};
+use std::thread;
+
+use crate::actions::AppState;
+use crate::app::App;
+use crate::appop::AppOp;
+use crate::backend::sync::{self, UnreadSyncError};
+use crate::backend::{crypto, user, HandleError};
+use crate::cache::{self, download_to_cache};
+use crate::crypto::SharedOlmMachine;
+use crate::widgets;
+use crate::widgets::AvatarExt;
+
+use super::sync::network_available;
+use super::LoginData;
+
+/// An account we are logged in with. The one we show has its rooms in the `AppOp`, the
+/// other ones only sync the unread counts of theirs.
+pub struct Account {
+    pub login_data: LoginData,
+    /// Shared with the `AppOp` while we show the account
+    pub olm_machine: Option<SharedOlmMachine>,
+    /// The server ended the session, nothing syncs until we log in again
+    pub reauthenticating: bool,
+    since: Option<String>,
+    syncing: bool,
+    /// How many syncs in a row failed
+    sync_tries: u32,
+    /// Waits before trying the failed sync again
+    sync_retry: Option<glib::source::SourceId>,
+    unread_rooms: HashSet<RoomId>,
+}
+
+impl Account {
+    pub fn new(login_data: LoginData) -> Self {
+        Self {
+            login_data,
+            olm_machine: None,
+            reauthenticating: false,
+            since: None,
+            syncing: false,
+            sync_tries: 0,
+            sync_retry: None,
+            unread_rooms: HashSet::new(),
+        }
+    }
+
+    /// What we synced before showing the account is outdated, the counts start again
+    pub fn restart_sync(&mut self) {
+        self.since = None;
+        self.unread_rooms.clear();
+    }
+}
+
+impl AppOp {
+    pub fn is_open_account(&self, uid: &UserId) -> bool {
+        self.login_data
+            .as_ref()
+            .map_or(false, |login_data| login_data.uid == *uid)
+    }
+
+    pub fn switch_account(&mut self, uid: UserId) {
+        let popover = self
+            .ui
+            .builder
+            .get_object::<gtk::Popover>("user_popover")
+            .expect("Can't find user_popover in ui file.");
+        popover.popdown();
+
+        if self.is_open_account(&uid) {
+            return;
+        }
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.login_data.uid == uid);
+        if let Some(account) = account {
+            let login_data = account.login_data.clone();
+            self.open_account(login_data, None);
+        }
+    }
+
+    /// Shows the login, the account we show goes on until the new one is logged in
+    pub fn add_account(&mut self) {
+        let stack = self
+            .ui
+            .builder
+            .get_object::<gtk::Stack>("main_content_stack")
+            .expect("Can't find main_content_stack in ui file.");
+        let login_stack = stack
+            .get_child_by_name("login")
+            .and_then(|child| child.downcast::<gtk::Stack>().ok());
+        if let Some(login_stack) = login_stack {
+            login_stack.set_visible_child_name("greeter");
+        }
+
+        self.set_adding_account(true);
+        self.set_state(AppState::Login);
+    }
+
+    pub fn cancel_add_account(&mut self) {
+        self.set_adding_account(false);
+        if self.active_room.is_some() {
+            self.set_state(AppState::Room);
+        } else {
+            self.set_state(AppState::NoRoom);
+        }
+    }
+
+    /// The login can only be cancelled when there's an account to go back to
+    pub fn set_adding_account(&self, adding: bool) {
+        let action = gio::Application::get_default()
+            .and_then(|app| app.lookup_action("cancel-add-account"))
+            .and_then(|action| action.downcast::<gio::SimpleAction>().ok());
+        if let Some(action) = action {
+            action.set_enabled(adding);
+        }
+    }
+
+    /// Starts syncing the unread counts of the accounts we don't show
+    pub fn sync_accounts(&mut self) {
+        let uids: Vec<UserId> = self
+            .accounts
+            .iter()
+            .map(|account| account.login_data.uid.clone())
+            .collect();
+        for uid in uids {
+            self.sync_account(uid);
+        }
+    }
+
+    pub fn sync_account(&mut self, uid: UserId) {
+        if self.is_open_account(&uid) {
+            return;
+        }
+        let account = unwrap_or_unit_return!(self
+            .accounts
+            .iter_mut()
+            .find(|account| account.login_data.uid == uid));
+        if account.syncing || account.reauthenticating || account.sync_retry.is_some() {
+            return;
+        }
+        // It goes on when the network comes back, see `retry_account_syncs`
+        if !network_available() {
+            return;
+        }
+        account.syncing = true;
+
+        if account.olm_machine.is_none() {
+            let device_id = cache::for_account(&uid)
+                .get_st()
+                .ok()
+                .map(|st| st.device_id);
+            account.olm_machine = device_id.and_then(|device_id| {
+                crypto::load_machine(uid.clone(), device_id)
+                    .map_err(|err| error!("Can't load the encryption keys: {:?}", err))
+                    .ok()
+            });
+        }
+
+        let login_data = account.login_data.clone();
+        let olm_machine = account.olm_machine.clone();
+        let since = account.since.clone();
+        // The first sync of the account brings its name
+        if since.is_none() && login_data.username.is_none() {
+            let login_data = login_data.clone();
+            thread::spawn(move || {
+                let query = user::get_username(
+                    login_data.server_url,
+                    login_data.access_token,
+                    login_data.uid.clone(),
+                );
+                match query {
+                    Ok(username) => {
+                        let uid = login_data.uid;
+                        APPOP!(set_account_username, (uid, username));
+                    }
+                    Err(err) => {
+                        err.handle_error();
+                    }
+                }
+            });
+        }
+
+        thread::spawn(move || {
+            let query = sync::sync_unread(
+                login_data.server_url,
+                login_data.access_token,
+                olm_machine,
+                since,
+            );
+            let uid = login_data.uid;
+            APPOP!(account_synced, (uid, query));
+        });
+    }
+
+    pub fn account_synced(
+        &mut self,
+        uid: UserId,
+        query: Result<(HashMap<RoomId, UnreadNotificationsCount>, String), UnreadSyncError>,
+    ) {
+        let open = self.is_open_account(&uid);
+        let account = unwrap_or_unit_return!(self
+            .accounts
+            .iter_mut()
+            .find(|account| account.login_data.uid == uid));
+        account.syncing = false;
+        // The room list has the counts of the account we show
+        if open {
+            return;
+        }
+
+        match query {
+            Ok((counts, next_batch)) => {
+                account.sync_tries = 0;
+                for (room_id, count) in counts {
+                    if count.notification_count > 0 || count.highlight_count > 0 {
+                        account.unread_rooms.insert(room_id);
+                    } else {
+                        account.unread_rooms.remove(&room_id);
+                    }
+                }
+                account.since = Some(next_batch);
+                self.show_accounts();
+            }
+            Err(err) => {
+                err.handle_error();
+                // Like the sync of the account we show, we wait longer after each failure
+                account.sync_tries += 1;
+                let delay = sync::retry_delay(account.sync_tries, err.retry_after());
+                account.sync_retry = Some(gtk::timeout_add(delay.as_millis() as u32, move || {
+                    let uid = uid.clone();
+                    APPOP!(retry_account_sync, (uid));
+                    Continue(false)
+                }));
+                return;
+            }
+        }
+
+        self.sync_account(uid);
+    }
+
+    /// The wait after a failed sync of the account is over
+    pub fn retry_account_sync(&mut self, uid: UserId) {
+        let account = self
+            .accounts
+            .iter_mut()
+            .find(|account| account.login_data.uid == uid);
+        if let Some(account) = account {
+            account.sync_retry = None;
+        }
+        self.sync_account(uid);
+    }
+
+    /// There's no reason to wait once the network is back
+    pub fn retry_account_syncs(&mut self) {
+        for account in self.accounts.iter_mut() {
+            if let Some(timer) = account.sync_retry.take() {
+                glib::source::source_remove(timer);
+            }
+        }
+        self.sync_accounts();
+    }
+
+    pub fn set_account_username(&mut self, uid: UserId, username: Option<String>) {
+        let account = self
+            .accounts
+            .iter_mut()
+            .find(|account| account.login_data.uid == uid);
+        if let Some(account) = account {
+            account.login_data.username = username;
+            self.show_accounts();
+        }
+    }
+
+    /// Fills the account switcher of the main menu, with the number of rooms with unread
+    /// messages of each account
+    pub fn show_accounts(&self) {
+        let list = self
+            .ui
+            .builder
+            .get_object::<gtk::Box>("account_switcher_list")
+            .expect("Can't find account_switcher_list in ui file.");
+        for child in list.get_children().iter() {
+            list.remove(child);
+        }
+
+        for account in self.accounts.iter() {
+            let open = self.is_open_account(&account.login_data.uid);
+            let login_data = match self.login_data.as_ref() {
+                Some(login_data) if open => login_data,
+                _ => &account.login_data,
+            };
+            let unread = if open {
+                self.unread_rooms
+            } else {
+                account.unread_rooms.len()
+            };
+            list.add(&self.account_row(login_data, open, unread));
+        }
+        list.show_all();
+    }
+
+    fn account_row(&self, login_data: &LoginData, open: bool, unread: usize) -> gtk::Button {
+        let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+
+        let avatar = widgets::Avatar::avatar_new(Some(24));
+        let data = avatar.circle(
+            login_data.uid.to_string(),
+            login_data.username.clone(),
+            24,
+            None,
+            None,
+        );
+        download_to_cache(
+            self.thread_pool.clone(),
+            self.user_info_cache.clone(),
+            login_data.server_url.clone(),
+            login_data.access_token.clone(),
+            login_data.uid.clone(),
+            data,
+        );
+        row.pack_start(&avatar, false, false, 0);
+
+        let name = login_data
+            .username
+            .clone()
+            .unwrap_or_else(|| login_data.uid.to_string());
+        let label = gtk::Label::new(Some(name.as_str()));
+        label.set_ellipsize(pango::EllipsizeMode::End);
+        label.set_halign(gtk::Align::Start);
+        label.set_tooltip_text(Some(login_data.uid.to_string().as_str()));
+        row.pack_start(&label, true, true, 0);
+
+        if unread > 0 {
+            let badge = gtk::Label::new(Some(unread.to_string().as_str()));
+            badge.set_valign(gtk::Align::Center);
+            badge.get_style_context().add_class("notify-badge");
+            row.pack_end(&badge, false, false, 0);
+        }
+        if open {
+            let check =
+                gtk::Image::new_from_icon_name(Some("object-select-symbolic"), gtk::IconSize::Menu);
+            row.pack_end(&check, false, false, 0);
+        }
+
+        let button = gtk::Button::new();
+        button.set_relief(gtk::ReliefStyle::None);
+        button.add(&row);
+        button.set_action_name(Some("app.switch-account"));
+        let data = glib::Variant::from(login_data.uid.to_string().as_str());
+        button.set_action_target_value(Some(&data));
+
+        button
+    }
+}
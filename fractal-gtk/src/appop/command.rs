@@ -0,0 +1,394 @@
+use crate::backend::{room, user, HandleError};
+use crate::i18n::{i18n, i18n_k};
+use fractal_api::identifiers::{RoomId, UserId};
+use gtk::prelude::*;
+use lazy_static::lazy_static;
+use std::convert::TryFrom;
+use std::thread;
+
+use crate::actions::AppState;
+use crate::app::App;
+use crate::appop::AppOp;
+use crate::model::command::{Arguments, DEFAULT_OP_LEVEL};
+use crate::types::{Command, CommandError, CommandInfo, Member};
+use crate::widgets::SourceDialog;
+
+/// How the text of a message we send is formatted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextFormat {
+    /// Markdown, if the user turned it on
+    Markdown,
+    Plain,
+    Html,
+}
+
+/// The text of an HTML message for the clients that don't show HTML
+pub fn html_to_text(html: &str) -> String {
+    lazy_static! {
+        static ref TAG_REGEX: regex::Regex = regex::Regex::new(r"<[^>]*>").unwrap();
+    }
+
+    TAG_REGEX
+        .replace_all(html, "")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// The arguments of a command, as shown in the help
+pub fn usage(info: &CommandInfo) -> String {
+    match info.args {
+        Arguments::None => String::new(),
+        Arguments::Text => i18n("<text>"),
+        Arguments::OptionalText => i18n("[text]"),
+        Arguments::Room => i18n("<room>"),
+        Arguments::User => i18n("<user>"),
+        Arguments::UserAndReason => i18n("<user> [reason]"),
+        Arguments::UserAndLevel => i18n("<user> [level]"),
+        Arguments::Command => i18n("[command]"),
+    }
+}
+
+pub fn description(info: &CommandInfo) -> String {
+    match info.name {
+        "me" => i18n("Sends an action, in the third person"),
+        "shrug" => i18n("Sends the text after ¯\\_(ツ)_/¯"),
+        "plain" => i18n("Sends the text without formatting it as Markdown"),
+        "html" => i18n("Sends the text as HTML"),
+        "notice" => i18n("Sends the text as a notice, like bots do"),
+        "join" => i18n("Joins the room with this address or ID"),
+        "leave" => i18n("Leaves the room"),
+        "invite" => i18n("Invites the user to the room"),
+        "kick" => i18n("Removes the user from the room"),
+        "ban" => i18n("Bans the user from the room"),
+        "unban" => i18n("Lets a banned user join the room again"),
+        "nick" => i18n("Changes your display name"),
+        "topic" => i18n("Changes the topic of the room"),
+        "roomname" => i18n("Changes the name of the room"),
+        "op" => i18n_k(
+            "Changes the power level of the user, {level} if none is given",
+            &[("level", &DEFAULT_OP_LEVEL.to_string())],
+        ),
+        "deop" => i18n("Gives the user the default power level of the room"),
+        "query" => i18n("Opens a direct chat with the user"),
+        "ignore" => i18n("Hides the messages and invitations of the user"),
+        "devtools" => i18n("Shows the state of the room as the server sends it"),
+        "help" => i18n("Lists the commands, or explains one of them"),
+        _ => String::new(),
+    }
+}
+
+/// The command with its arguments, as the user should type it
+pub fn synopsis(info: &CommandInfo) -> String {
+    format!("/{} {}", info.name, usage(info))
+        .trim_end()
+        .to_string()
+}
+
+impl AppOp {
+    pub fn run_command(&mut self, command: Command) {
+        match command {
+            Command::Me(text) => self.send_text(text, "m.emote", TextFormat::Markdown),
+            Command::Shrug(text) => {
+                let text = format!("¯\\_(ツ)_/¯ {}", text);
+                self.send_text(text.trim_end().to_string(), "m.text", TextFormat::Plain);
+            }
+            Command::Plain(text) => self.send_text(text, "m.text", TextFormat::Plain),
+            Command::Html(text) => self.send_text(text, "m.text", TextFormat::Html),
+            Command::Notice(text) => self.send_text(text, "m.notice", TextFormat::Markdown),
+            Command::Join(room_id) => self.join_room(room_id),
+            Command::Leave => self.leave_active_room(),
+            Command::Invite(user) => self.invite_by_command(&user),
+            Command::Kick(user, reason) => self.kick_user(&user, reason),
+            Command::Ban(user, reason) => self.ban_user(&user, reason),
+            Command::Unban(user) => self.unban_user(&user),
+            Command::Nick(name) => self.change_display_name(name),
+            Command::Topic(topic) => self.change_room_topic(topic),
+            Command::RoomName(name) => self.change_room_name(name),
+            Command::Op(user, level) => self.set_power_level(&user, Some(level)),
+            Command::Deop(user) => self.set_power_level(&user, None),
+            Command::Query(user) => self.query_user(&user),
+            Command::Ignore(user) => self.ignore_user(&user),
+            Command::Devtools => self.load_room_state(),
+            Command::Help(name) => self.show_command_help(name),
+        }
+    }
+
+    pub fn show_command_error(&self, err: CommandError) {
+        match err {
+            CommandError::Unknown(name) => self.show_error_with_info(
+                i18n_k("Unknown command “/{name}”", &[("name", &name)]),
+                Some(i18n(
+                    "Type /help to list the commands. To send a message starting with a slash, start it with two.",
+                )),
+            ),
+            CommandError::Usage(info) => self.show_error_with_info(
+                i18n_k("Usage: {synopsis}", &[("synopsis", &synopsis(info))]),
+                Some(description(info)),
+            ),
+            CommandError::InvalidRoom(room) => self.show_error(i18n_k(
+                "“{room}” is not the address or the ID of a room",
+                &[("room", &room)],
+            )),
+            CommandError::InvalidPowerLevel(level) => self.show_error(i18n_k(
+                "“{level}” is not a power level, it must be a number",
+                &[("level", &level)],
+            )),
+        }
+    }
+
+    fn show_command_help(&self, name: Option<String>) {
+        let commands = match name {
+            Some(name) => match CommandInfo::find(&name) {
+                Some(info) => vec![info],
+                None => {
+                    self.show_command_error(CommandError::Unknown(name));
+                    return;
+                }
+            },
+            None => CommandInfo::completions(""),
+        };
+        let help = commands
+            .iter()
+            .map(|info| format!("{}  {}", synopsis(info), description(info)))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let window: gtk::Window = self
+            .ui
+            .builder
+            .get_object("main_window")
+            .expect("Can't find main_window in ui file.");
+        let dialog = gtk::MessageDialog::new(
+            Some(&window),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            gtk::MessageType::Info,
+            gtk::ButtonsType::Close,
+            &i18n("Commands"),
+        );
+        dialog.set_property_secondary_text(Some(help.as_str()));
+        dialog.connect_response(|dialog, _| dialog.destroy());
+        dialog.show_all();
+    }
+
+    /// Finds the user a command is about, they can be given by their Matrix ID or by the
+    /// name they have in the active room
+    fn resolve_user(&self, user: &str) -> Option<UserId> {
+        if let Ok(uid) = UserId::try_from(user) {
+            return Some(uid);
+        }
+
+        let name = user.trim_start_matches('@').to_lowercase();
+        let room = self
+            .active_room
+            .as_ref()
+            .and_then(|room_id| self.rooms.get(room_id));
+        let mut matches = room
+            .into_iter()
+            .flat_map(|room| room.members.values())
+            .filter(|member| {
+                member.alias.as_ref().map(|alias| alias.to_lowercase()) == Some(name.clone())
+                    || member.uid.localpart().to_lowercase() == name
+            });
+
+        match (matches.next(), matches.next()) {
+            (Some(member), None) => Some(member.uid.clone()),
+            (Some(_), Some(_)) => {
+                self.show_error(i18n_k(
+                    "More than one member of the room is called “{user}”, use their Matrix ID",
+                    &[("user", user)],
+                ));
+                None
+            }
+            (None, _) => {
+                self.show_error(i18n_k(
+                    "There is no member called “{user}” in this room",
+                    &[("user", user)],
+                ));
+                None
+            }
+        }
+    }
+
+    fn invite_by_command(&self, user: &str) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        let user_id = unwrap_or_unit_return!(self.resolve_user(user));
+
+        thread::spawn(move || {
+            let query = room::invite(
+                login_data.server_url,
+                login_data.access_token,
+                room_id,
+                user_id,
+            );
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    fn kick_user(&self, user: &str, reason: Option<String>) {
+        let user_id = unwrap_or_unit_return!(self.resolve_user(user));
+        self.kick_member(user_id, reason);
+    }
+
+    fn ban_user(&self, user: &str, reason: Option<String>) {
+        let user_id = unwrap_or_unit_return!(self.resolve_user(user));
+        self.ban_member(user_id, reason);
+    }
+
+    fn unban_user(&self, user: &str) {
+        // Banned users aren't members anymore, we need their Matrix ID
+        match UserId::try_from(user) {
+            Ok(user_id) => self.unban_member(user_id),
+            Err(_) => {
+                self.show_error(i18n_k("“{user}” is not a Matrix ID", &[("user", user)]));
+            }
+        }
+    }
+
+    fn set_power_level(&self, user: &str, level: Option<i64>) {
+        let user_id = unwrap_or_unit_return!(self.resolve_user(user));
+        self.set_member_power_level(user_id, level);
+    }
+
+    fn change_display_name(&self, name: String) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+
+        thread::spawn(move || {
+            match user::set_username(
+                login_data.server_url,
+                login_data.access_token,
+                login_data.uid,
+                name,
+            ) {
+                Ok(username) => {
+                    let u = Some(username);
+                    APPOP!(show_new_username, (u));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    /// The new name comes with the next sync
+    fn change_room_name(&self, name: String) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        thread::spawn(move || {
+            let query = room::set_room_name(
+                login_data.server_url,
+                login_data.access_token,
+                room_id,
+                name,
+            );
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    /// The new topic comes with the next sync
+    fn change_room_topic(&self, topic: String) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        thread::spawn(move || {
+            let query = room::set_room_topic(
+                login_data.server_url,
+                login_data.access_token,
+                room_id,
+                topic,
+            );
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    /// Opens the direct chat we have with the user, or starts one
+    fn query_user(&mut self, user: &str) {
+        let user_id = unwrap_or_unit_return!(self.resolve_user(user));
+
+        let direct = self
+            .rooms
+            .values()
+            .find(|room| room.direct && room.members.contains_key(&user_id))
+            .map(|room| room.id.clone());
+        if let Some(room_id) = direct {
+            self.set_active_room_by_id(room_id);
+            self.set_state(AppState::Room);
+            return;
+        }
+
+        let alias = self
+            .active_room
+            .as_ref()
+            .and_then(|room_id| self.rooms.get(room_id))
+            .and_then(|room| room.members.get(&user_id))
+            .and_then(|member| member.alias.clone());
+        self.start_chat_with(Member {
+            uid: user_id,
+            alias,
+            avatar: None,
+        });
+    }
+
+    fn ignore_user(&self, user: &str) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let user_id = unwrap_or_unit_return!(self.resolve_user(user));
+
+        thread::spawn(move || {
+            let query = room::ignore_user(
+                login_data.server_url,
+                login_data.access_token,
+                login_data.uid,
+                user_id,
+            );
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    fn load_room_state(&self) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        thread::spawn(move || {
+            match room::get_room_state(
+                login_data.server_url,
+                login_data.access_token,
+                room_id.clone(),
+            ) {
+                Ok(state) => {
+                    let state = serde_json::to_string_pretty(&state).unwrap_or_default();
+                    APPOP!(show_room_state, (room_id, state));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    pub fn show_room_state(&self, room_id: RoomId, state: String) {
+        let window: gtk::Window = self
+            .ui
+            .builder
+            .get_object("main_window")
+            .expect("Can't find main_window in ui file.");
+        let viewer = SourceDialog::new();
+        viewer.set_parent_window(&window);
+        viewer.set_title(&i18n_k(
+            "State of {room_id}",
+            &[("room_id", &room_id.to_string())],
+        ));
+        viewer.show(&state);
+    }
+}
@@ -0,0 +1,137 @@
+use glib::clone;
+use gtk::prelude::*;
+use log::error;
+
+use crate::actions::AppState;
+use crate::app::App;
+use crate::appop::AppOp;
+use crate::i18n::i18n;
+use crate::passwd::{self, Error, StorageType};
+
+impl AppOp {
+    /// The credentials file is encrypted, we can't log in until we have its passphrase
+    pub fn ask_credentials_passphrase(&self, retry: bool) {
+        let parent = self
+            .ui
+            .builder
+            .get_object::<gtk::Window>("main_window")
+            .expect("Can't find main_window in ui file.");
+
+        let msg = if retry {
+            i18n("Wrong passphrase, try again")
+        } else {
+            i18n("Enter the passphrase of your stored credentials")
+        };
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&parent),
+            flags,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &msg,
+        );
+        dialog.set_property_secondary_text(Some(
+            i18n("Without it you have to log in again, and the credentials are forgotten.")
+                .as_str(),
+        ));
+        let forget = dialog.add_button(&i18n("Log In Again"), gtk::ResponseType::Reject);
+        forget.get_style_context().add_class("destructive-action");
+        let button = dialog.add_button(&i18n("Unlock"), gtk::ResponseType::Ok);
+        button.get_style_context().add_class("suggested-action");
+        button.set_sensitive(false);
+
+        let entry = gtk::Entry::new();
+        entry.set_visibility(false);
+        entry.set_input_purpose(gtk::InputPurpose::Password);
+        if let Some(area) = dialog
+            .get_message_area()
+            .and_then(|area| area.downcast::<gtk::Box>().ok())
+        {
+            area.add(&entry);
+        }
+        entry.connect_property_text_notify(clone!(@weak button => move |w| {
+            let empty = w.get_text().map_or(true, |text| text.is_empty());
+            button.set_sensitive(!empty);
+        }));
+        entry.connect_activate(clone!(@weak button => move |_| {
+            if button.get_sensitive() {
+                let _ = button.emit("clicked", &[]);
+            }
+        }));
+
+        dialog.connect_response(move |w, r| {
+            match r {
+                gtk::ResponseType::Ok => {
+                    let passphrase = entry
+                        .get_text()
+                        .map_or(String::new(), |gstr| gstr.to_string());
+                    APPOP!(unlock_credentials, (passphrase));
+                }
+                gtk::ResponseType::Reject => {
+                    APPOP!(forget_credentials);
+                }
+                // There's nothing to show until we have an answer
+                _ => {
+                    let retry = false;
+                    APPOP!(ask_credentials_passphrase, (retry));
+                }
+            }
+            w.destroy();
+        });
+        dialog.show_all();
+    }
+
+    pub fn unlock_credentials(&mut self, passphrase: String) {
+        match passwd::unlock(passphrase) {
+            Ok(()) => self.load_accounts(),
+            Err(Error::BadPassphrase) => self.ask_credentials_passphrase(true),
+            Err(err) => {
+                error!("Can't read the stored credentials: {:?}", err);
+                self.set_state(AppState::Login);
+            }
+        }
+    }
+
+    pub fn forget_credentials(&mut self) {
+        if let Err(err) = passwd::forget() {
+            error!("Can't remove the stored credentials: {:?}", err);
+        }
+        self.load_accounts();
+    }
+
+    /// The passphrase is only for the file we keep when there's no Secret Service
+    pub fn show_credentials_settings(&self) {
+        let credentials_box = self
+            .ui
+            .builder
+            .get_object::<gtk::Box>("account_settings_credentials_box")
+            .expect("Can't find account_settings_credentials_box in ui file.");
+        let entry = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("account_settings_credentials_passphrase")
+            .expect("Can't find account_settings_credentials_passphrase in ui file.");
+
+        entry.set_text("");
+        credentials_box.set_visible(StorageType::current() == StorageType::PlainText);
+    }
+
+    pub fn set_credentials_passphrase(&self) {
+        let entry = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("account_settings_credentials_passphrase")
+            .expect("Can't find account_settings_credentials_passphrase in ui file.");
+
+        // An empty passphrase stores the credentials unencrypted again
+        let passphrase = entry
+            .get_text()
+            .map(|gstr| gstr.to_string())
+            .filter(|passphrase| !passphrase.is_empty());
+        entry.set_text("");
+        if let Err(err) = passwd::set_passphrase(passphrase) {
+            error!("Can't encrypt the stored credentials: {:?}", err);
+            self.show_error_dialog_in_settings(i18n("Couldn't change the passphrase"));
+        }
+    }
+}
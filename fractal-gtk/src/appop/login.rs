@@ -1,7 +1,14 @@
-use log::error;
+use gio::prelude::*;
+use glib::clone;
+use gtk::prelude::*;
+use log::{error, warn};
 
+use crate::backend::crypto;
 use crate::backend::register;
+use crate::backend::register::RegistrationPrompt;
+use crate::backend::sso::{SsoCancel, SsoError, SsoListener};
 use fractal_api::identifiers::{DeviceId, UserId};
+use fractal_api::r0::account::sso_redirect_url;
 use fractal_api::r0::AccessToken;
 
 use fractal_api::url::Url;
@@ -11,13 +18,24 @@ use crate::appop::AppOp;
 
 use crate::backend::HandleError;
 use crate::cache;
+use crate::i18n::{i18n, i18n_k};
+use crate::types::Room;
 
+use std::sync::mpsc::Sender;
 use std::thread;
 
 use crate::passwd::PasswordStorage;
 
 use crate::actions::AppState;
 
+use super::accounts::Account;
+
+/// A single sign-on waiting for the browser, the user can give up on it
+pub struct SsoWait {
+    cancel: SsoCancel,
+    /// Shown while we wait when there's no loading page
+    dialog: Option<gtk::MessageDialog>,
+}
 use super::LoginData;
 
 impl AppOp {
@@ -29,41 +47,165 @@ impl AppOp {
         server_url: Url,
         identity_url: Url,
     ) {
-        if self.store_token(uid.clone(), access_token.clone()).is_err() {
-            error!("Can't store the token using libsecret");
+        let stored = self.store_token(
+            uid.clone(),
+            access_token.clone(),
+            server_url.clone(),
+            identity_url.clone(),
+        );
+        if stored.is_err() {
+            error!("Can't store the token");
         }
 
-        self.set_login_data(LoginData {
+        let login_data = LoginData {
             access_token,
             uid,
             username: None,
             avatar: None,
             server_url,
             identity_url,
-        });
+        };
+        self.open_account(login_data, device);
+    }
+
+    /// Starts showing the account, the one we were showing goes on syncing in the background
+    pub fn open_account(&mut self, login_data: LoginData, device: Option<Box<DeviceId>>) {
+        self.close_account();
+        self.set_adding_account(false);
 
+        let uid = login_data.uid.clone();
+        let account = self
+            .accounts
+            .iter_mut()
+            .find(|account| account.login_data.uid == uid);
+        // The account may have its encryption machine from syncing in the background
+        let olm_machine = match account {
+            Some(account) => {
+                account.login_data = login_data.clone();
+                account.olm_machine.clone()
+            }
+            None => {
+                self.accounts.push(Account::new(login_data.clone()));
+                None
+            }
+        };
+
+        cache::set_account(&uid);
+        // FIXME: Username and uid should not be duplicated in cache.
+        if let Ok(data) = cache::load() {
+            let r: Vec<Room> = data.rooms.values().cloned().collect();
+            self.set_rooms(r, true);
+            /* Make sure that since is never an empty string */
+            self.since = data.since.filter(|s| !s.is_empty());
+            self.device_id = Some(data.device_id);
+            self.load_outbox();
+        }
+
+        self.set_login_data(login_data);
         self.set_state(AppState::NoRoom);
         self.device_id = self.device_id.clone().or(device);
+        self.olm_machine = olm_machine.or_else(|| {
+            let device_id = self.device_id.clone()?;
+            crypto::load_machine(uid, device_id)
+                .map_err(|err| {
+                    error!("Can't load the encryption keys: {:?}", err);
+                    self.show_error(i18n(
+                        "Can’t load the encryption keys of this device, encrypted rooms won’t work",
+                    ));
+                })
+                .ok()
+        });
         self.since = None;
         self.get_username();
+        self.get_push_rules();
 
         // initial sync, we're shoing some feedback to the user
         self.initial_sync(true);
-        self.sync(true, 0);
+        self.sync(true);
         self.init_protocols();
+        self.show_accounts();
+        self.sync_accounts();
     }
 
-    pub fn bk_logout(&mut self) {
-        self.set_rooms(vec![], true);
-        if cache::get().destroy().is_err() {
-            error!("Error removing cache file");
+    /// Saves the account we show, it only syncs its unread counts from now on
+    fn close_account(&mut self) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        self.cache_rooms();
+        let account = self
+            .accounts
+            .iter_mut()
+            .find(|account| account.login_data.uid == login_data.uid);
+        if let Some(account) = account {
+            account.login_data = login_data;
+            account.olm_machine = self.olm_machine.clone();
+            account.restart_sync();
         }
+        self.clear_account();
+    }
 
-        self.syncing = false;
+    /// Forgets everything of the account we show
+    fn clear_account(&mut self) {
+        self.active_room = None;
+        self.set_rooms(vec![], true);
 
-        self.set_state(AppState::Login);
+        self.syncing = false;
+        self.cancel_sync_retry();
+        self.since = None;
         self.login_data = None;
         self.device_id = None;
+        self.olm_machine = None;
+        self.push_rules = None;
+        self.join_to_room = None;
+        self.msg_queue = vec![];
+        *self.room_back_history.borrow_mut() = vec![];
+    }
+
+    /// The server doesn't know the access token of the account anymore, and it has to log in
+    /// again as a new device. The account we show goes through the usual logout.
+    pub fn end_session(&mut self, uid: UserId) {
+        if self.is_open_account(&uid) {
+            self.logout();
+        } else {
+            self.forget_account(&uid);
+            self.show_accounts();
+        }
+        self.show_error(i18n_k(
+            "The session of {user} has ended, log in again",
+            &[("user", &uid.to_string())],
+        ));
+    }
+
+    /// Removes everything we keep of the account, it isn't in the account switcher anymore
+    fn forget_account(&mut self, uid: &UserId) {
+        if cache::for_account(uid).destroy().is_err() {
+            error!("Error removing cache file");
+        }
+        crypto::remove_machine(uid);
+        if self.delete_token(uid).is_err() {
+            error!("Can't remove the token using libsecret");
+        }
+
+        self.accounts
+            .retain(|account| account.login_data.uid != *uid);
+    }
+
+    pub fn bk_logout(&mut self) {
+        let uid = unwrap_or_unit_return!(self.login_data.clone()).uid;
+        self.forget_account(&uid);
+        self.clear_account();
+
+        // We go on with another account if there's one left
+        match self.accounts.first() {
+            Some(account) => {
+                let login_data = account.login_data.clone();
+                self.open_account(login_data, None);
+            }
+            None => {
+                let _ = self.delete_pass("fractal");
+                self.set_state(AppState::Login);
+                self.show_accounts();
+            }
+        }
     }
 
     pub fn connect(&mut self, username: String, password: String, server: Url, identity: Url) {
@@ -90,23 +232,250 @@ impl AppOp {
         );
     }
 
-    // TODO: Remove function
-    pub fn disconnect(&self) {}
+    /// Opens the single sign-on page of the server in the browser and logs
+    /// in once it's done
+    pub fn connect_sso(&mut self, server: Url, identity: Url) {
+        let listener = match self.open_sso_page(&server) {
+            Ok(listener) => listener,
+            Err(err) => {
+                err.handle_error();
+                return;
+            }
+        };
 
-    pub fn logout(&mut self) {
-        let login_data = unwrap_or_unit_return!(self.login_data.clone());
-        let _ = self.delete_pass("fractal");
+        self.start_sso_wait(&listener, None);
+
+        // There is no password, the servers are stored with the access token
+        // in `bk_login`
         thread::spawn(move || {
-            match register::logout(login_data.server_url, login_data.access_token) {
-                Ok(_) => {
-                    APPOP!(bk_logout);
+            let token = listener.wait_for_token();
+            // A cancelled wait is already over
+            if !matches!(token, Err(SsoError::Cancelled)) {
+                APPOP!(end_sso_wait);
+            }
+
+            match token {
+                Ok(token) => match register::login_with_token(token, server.clone(), None) {
+                    Ok((uid, tk, dev)) => {
+                        APPOP!(bk_login, (uid, tk, dev, server, identity));
+                    }
+                    Err(err) => {
+                        err.handle_error();
+                    }
+                },
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    /// Opens the single sign-on page of the server in the browser, the
+    /// listener gets the token to log in with once it's done
+    pub fn open_sso_page(&self, server: &Url) -> Result<SsoListener, SsoError> {
+        let listener = SsoListener::bind()?;
+
+        let url = sso_redirect_url(server, listener.redirect_url());
+        let parent = self
+            .ui
+            .builder
+            .get_object::<gtk::Window>("main_window")
+            .expect("Can't find main_window in ui file.");
+        if let Err(err) =
+            gtk::show_uri_on_window(Some(&parent), url.as_str(), gtk::get_current_event_time())
+        {
+            warn!("Could not show {}: {}", url, err);
+        }
+
+        Ok(listener)
+    }
+
+    /// The wait can be cancelled from the loading page, or from `dialog` when there's one
+    pub fn start_sso_wait(&mut self, listener: &SsoListener, dialog: Option<gtk::MessageDialog>) {
+        self.cancel_sso();
+        set_cancel_sso_enabled(dialog.is_none());
+        self.sso_wait = Some(SsoWait {
+            cancel: listener.cancel_handle(),
+            dialog,
+        });
+    }
+
+    pub fn cancel_sso(&mut self) {
+        if let Some(wait) = self.sso_wait.as_ref() {
+            wait.cancel.cancel();
+        }
+        self.end_sso_wait();
+    }
+
+    /// The browser came back, or we stopped waiting for it
+    pub fn end_sso_wait(&mut self) {
+        if let Some(dialog) = self.sso_wait.take().and_then(|wait| wait.dialog) {
+            dialog.destroy();
+        }
+        set_cancel_sso_enabled(false);
+    }
+
+    pub fn register(
+        &mut self,
+        username: String,
+        password: String,
+        email: Option<String>,
+        phone_number: Option<String>,
+        server: Url,
+        identity: Url,
+    ) {
+        self.store_pass(
+            username.clone(),
+            password.clone(),
+            server.clone(),
+            identity.clone(),
+        )
+        .unwrap_or_else(|_| {
+            // TODO: show an error
+            error!("Can't store the password using libsecret");
+        });
+
+        thread::spawn(move || {
+            match register::register(
+                server.clone(),
+                identity.clone(),
+                username,
+                password,
+                email,
+                phone_number,
+            ) {
+                Ok((uid, tk, dev)) => {
+                    APPOP!(bk_login, (uid, tk, dev, server, identity));
                 }
                 Err(err) => {
                     err.handle_error();
                 }
             }
         });
+    }
+
+    /// Asks the user to complete a registration stage. `answer` gets `None`
+    /// if they cancelled.
+    pub fn show_registration_prompt(
+        &self,
+        prompt: RegistrationPrompt,
+        answer: Sender<Option<String>>,
+    ) {
+        let parent = self
+            .ui
+            .builder
+            .get_object::<gtk::Window>("main_window")
+            .expect("Can't find main_window in ui file.");
+
+        let msg = match &prompt {
+            RegistrationPrompt::Email(email) => i18n_k(
+                "We sent an email to {email}. Follow the link it contains, then click Continue.",
+                &[("email", email.as_str())],
+            ),
+            RegistrationPrompt::Msisdn(phone_number) => i18n_k(
+                "Enter the code sent via SMS to {number}",
+                &[("number", phone_number.as_str())],
+            ),
+            RegistrationPrompt::Terms(_) => i18n(
+                "You have to accept the following policies of the server to create an account:",
+            ),
+            RegistrationPrompt::Fallback(_) => {
+                i18n("Complete this step in your web browser, then click Continue.")
+            }
+        };
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&parent),
+            flags,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &msg,
+        );
+        dialog.add_button(&i18n("Cancel"), gtk::ResponseType::Cancel);
+        let label = match prompt {
+            RegistrationPrompt::Terms(_) => i18n("Accept"),
+            _ => i18n("Continue"),
+        };
+        let button = dialog.add_button(&label, gtk::ResponseType::Ok);
+        button.get_style_context().add_class("suggested-action");
+
+        let area = dialog
+            .get_message_area()
+            .and_then(|area| area.downcast::<gtk::Box>().ok());
+        let entry = gtk::Entry::new();
+        match &prompt {
+            RegistrationPrompt::Msisdn(_) => {
+                if let Some(area) = area {
+                    area.add(&entry);
+                }
+                button.set_sensitive(false);
+                entry.connect_property_text_notify(clone!(@weak button => move |w| {
+                    let empty = w.get_text().map_or(true, |text| text.is_empty());
+                    button.set_sensitive(!empty);
+                }));
+                entry.connect_activate(clone!(@weak button => move |_| {
+                    if button.get_sensitive() {
+                        let _ = button.emit("clicked", &[]);
+                    }
+                }));
+            }
+            RegistrationPrompt::Terms(policies) => {
+                if let Some(area) = area {
+                    for (name, url) in policies {
+                        let link =
+                            gtk::LinkButton::new_with_label(url.as_str(), Some(name.as_str()));
+                        link.set_halign(gtk::Align::Start);
+                        area.add(&link);
+                    }
+                }
+            }
+            RegistrationPrompt::Fallback(url) => {
+                if let Err(err) = gtk::show_uri_on_window(
+                    Some(&parent),
+                    url.as_str(),
+                    gtk::get_current_event_time(),
+                ) {
+                    warn!("Could not show {}: {}", url, err);
+                }
+            }
+            RegistrationPrompt::Email(_) => {}
+        }
+
+        dialog.connect_response(move |w, r| {
+            let response = match r {
+                gtk::ResponseType::Ok => Some(
+                    entry
+                        .get_text()
+                        .map_or(String::new(), |gstr| gstr.to_string()),
+                ),
+                _ => None,
+            };
+            let _ = answer.send(response);
+            w.destroy();
+        });
+        dialog.show_all();
+    }
+
+    // TODO: Remove function
+    pub fn disconnect(&self) {}
+
+    pub fn logout(&mut self) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        thread::spawn(move || {
+            if let Err(err) = register::logout(login_data.server_url, login_data.access_token) {
+                err.handle_error();
+            }
+        });
         self.bk_logout();
-        *self.room_back_history.borrow_mut() = vec![];
+    }
+}
+
+/// The button of the loading page shows when the action is enabled
+fn set_cancel_sso_enabled(enabled: bool) {
+    let action = gio::Application::get_default()
+        .and_then(|app| app.lookup_action("cancel-sso"))
+        .and_then(|action| action.downcast::<gio::SimpleAction>().ok());
+    if let Some(action) = action {
+        action.set_enabled(enabled);
     }
 }
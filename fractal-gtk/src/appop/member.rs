@@ -1,6 +1,7 @@
 use crate::backend::{user, HandleError};
 use either::Either;
 use fractal_api::{
+    events::room::{MembershipState, RoomEventContent},
     identifiers::{RoomId, UserId},
     url::Url,
 };
@@ -55,20 +56,26 @@ impl AppOp {
         // to the user
 
         let sender = ev.sender;
-        match ev.content["membership"].as_str() {
-            Some("leave") => {
+        let content = match ev.content {
+            RoomEventContent::Member(content) => content,
+            _ => return,
+        };
+
+        match content.membership {
+            MembershipState::Leave => {
                 if let Some(r) = self.rooms.get_mut(&ev.room) {
                     r.members.remove(&sender);
                 }
             }
-            Some("join") => {
+            MembershipState::Join => {
                 let m = Member {
-                    avatar: ev.content["avatar_url"]
-                        .as_str()
-                        .map(Url::parse)
+                    avatar: content
+                        .avatar_url
+                        .as_ref()
+                        .map(|url| Url::parse(url))
                         .and_then(Result::ok)
                         .map(Either::Left),
-                    alias: ev.content["displayname"].as_str().map(String::from),
+                    alias: content.displayname,
                     uid: sender,
                 };
                 if let Some(r) = self.rooms.get_mut(&ev.room.clone()) {
@@ -1,4 +1,6 @@
 use crate::backend::{room, HandleError};
+use crate::cache::{self, OutboxState};
+use crate::crypto::SharedOlmMachine;
 use crate::types::ExtraContent;
 use comrak::{markdown_to_html, ComrakOptions};
 use fractal_api::identifiers::{EventId, RoomId};
@@ -6,8 +8,6 @@ use fractal_api::r0::AccessToken;
 use fractal_api::url::Url;
 use gdk_pixbuf::Pixbuf;
 use gio::prelude::FileExt;
-use glib::clone;
-use glib::source::Continue;
 use gtk::prelude::*;
 use lazy_static::lazy_static;
 use log::error;
@@ -19,18 +19,27 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::thread;
 
+use crate::appop::command::{html_to_text, TextFormat};
 use crate::appop::room::Force;
 use crate::appop::AppOp;
+use crate::i18n::{i18n, i18n_k};
+use crate::util::markup_text;
 use crate::App;
 
 use crate::uitypes::MessageContent;
+use crate::uitypes::ReactionGroup;
+use crate::uitypes::ReplyHeader;
 use crate::uitypes::RowType;
 use crate::widgets;
 
+use crate::types::Command;
 use crate::types::Message;
 
+/// A message in the outbox, it stays there until the server has it
 pub struct TmpMsg {
     pub msg: Message,
+    pub txn_id: String,
+    pub state: OutboxState,
     pub widget: Option<gtk::Widget>,
 }
 
@@ -38,7 +47,17 @@ impl AppOp {
     pub fn get_message_by_id(&self, room_id: &RoomId, id: &EventId) -> Option<Message> {
         let room = self.rooms.get(room_id)?;
         let id = Some(id);
-        room.messages.iter().find(|m| m.id.as_ref() == id).cloned()
+        let context_messages = self
+            .timeline_context
+            .as_ref()
+            .filter(|ctx| ctx.room_id == *room_id)
+            .map(|ctx| ctx.messages.as_slice())
+            .unwrap_or_default();
+        room.messages
+            .iter()
+            .chain(context_messages)
+            .find(|m| m.id.as_ref() == id)
+            .cloned()
     }
 
     /// This function is used to mark as read the last message of a room when the focus comes in,
@@ -48,6 +67,16 @@ impl AppOp {
     }
 
     pub fn add_room_message(&mut self, msg: &Message) -> Option<()> {
+        if let Some(ref target) = msg.annotates {
+            self.refresh_reactions(&msg.room, target);
+            return None;
+        }
+
+        if self.is_showing_context_of(&msg.room) {
+            /* New messages don't belong after the messages around a search result */
+            return None;
+        }
+
         if let Some(ui_msg) = self.create_new_room_message(msg) {
             if let Some(ref mut history) = self.history {
                 history.add_new_message(
@@ -72,32 +101,53 @@ impl AppOp {
         }
     }
 
+    /// Put a new message in the outbox and show it at the end of the room history
     pub fn add_tmp_room_message(&mut self, msg: Message) -> Option<()> {
-        let login_data = self.login_data.clone()?;
-        let messages = self.history.as_ref()?.get_listbox();
-        if let Some(ui_msg) = self.create_new_room_message(&msg) {
-            let mb = widgets::MessageBox::new(login_data.server_url, login_data.access_token)
-                .tmpwidget(
-                    self.thread_pool.clone(),
-                    self.user_info_cache.clone(),
-                    &ui_msg,
-                );
-            let m = mb.get_listbox_row();
-            messages.add(m);
-
-            if let Some(w) = messages.get_children().iter().last() {
-                self.msg_queue.insert(
-                    0,
-                    TmpMsg {
-                        msg: msg.clone(),
-                        widget: Some(w.clone()),
-                    },
-                );
-            };
+        if self.is_showing_context_of(&msg.room) {
+            self.leave_timeline_context();
+        }
+
+        let txn_id = Message::new_txn_id();
+        if let Err(err) = cache::get().queue_message(&txn_id, &msg) {
+            error!("Can't store the message in the outbox: {}", err);
         }
+
+        let state = OutboxState::Queued;
+        let widget = self.add_tmp_msg_widget(&msg, &txn_id, state);
+        self.msg_queue.insert(
+            0,
+            TmpMsg {
+                msg,
+                txn_id,
+                state,
+                widget,
+            },
+        );
         None
     }
 
+    fn add_tmp_msg_widget(
+        &self,
+        msg: &Message,
+        txn_id: &str,
+        state: OutboxState,
+    ) -> Option<gtk::Widget> {
+        let login_data = self.login_data.clone()?;
+        let messages = self.history.as_ref()?.get_listbox();
+        let ui_msg = self.create_new_room_message(msg)?;
+        let mb = widgets::MessageBox::new(login_data.server_url, login_data.access_token)
+            .tmpwidget(
+                self.thread_pool.clone(),
+                self.user_info_cache.clone(),
+                &ui_msg,
+                txn_id,
+                state,
+            );
+        messages.add(mb.get_listbox_row());
+
+        messages.get_children().last().cloned()
+    }
+
     pub fn clear_tmp_msgs(&mut self) {
         for t in self.msg_queue.iter_mut() {
             if let Some(ref w) = t.widget {
@@ -108,37 +158,98 @@ impl AppOp {
     }
 
     pub fn append_tmp_msgs(&mut self) -> Option<()> {
-        let login_data = self.login_data.clone()?;
-        let messages = self.history.as_ref()?.get_listbox();
+        let room_id = self.active_room.clone()?;
+        if self.is_showing_context_of(&room_id) {
+            // They go after the latest messages, not after the ones we are looking at
+            return None;
+        }
 
-        let r = self.rooms.get(self.active_room.as_ref()?)?;
-        let mut widgets = vec![];
-        for t in self.msg_queue.iter().rev().filter(|m| m.msg.room == r.id) {
-            if let Some(ui_msg) = self.create_new_room_message(&t.msg) {
-                let mb = widgets::MessageBox::new(
-                    login_data.server_url.clone(),
-                    login_data.access_token.clone(),
-                )
-                .tmpwidget(
-                    self.thread_pool.clone(),
-                    self.user_info_cache.clone(),
-                    &ui_msg,
-                );
-                let m = mb.get_listbox_row();
-                messages.add(m);
+        let widgets: Vec<_> = self
+            .msg_queue
+            .iter()
+            .rev()
+            .map(|t| {
+                Some(&t.msg)
+                    .filter(|msg| msg.room == room_id)
+                    .and_then(|msg| self.add_tmp_msg_widget(msg, &t.txn_id, t.state))
+            })
+            .collect();
+
+        for (t, w) in self.msg_queue.iter_mut().rev().zip(widgets) {
+            t.widget = w;
+        }
+        None
+    }
 
-                if let Some(w) = messages.get_children().iter().last() {
-                    widgets.push(w.clone());
-                }
+    /// Load the messages that were waiting in the outbox when we quit
+    pub fn load_outbox(&mut self) {
+        match cache::get().get_outbox() {
+            Ok(outbox) => {
+                self.msg_queue = outbox
+                    .into_iter()
+                    .rev()
+                    .map(|(txn_id, state, msg)| TmpMsg {
+                        msg,
+                        txn_id,
+                        state,
+                        widget: None,
+                    })
+                    .collect();
+            }
+            Err(err) => {
+                error!("Can't load the outbox: {}", err);
             }
         }
+    }
 
-        for (t, w) in self.msg_queue.iter_mut().rev().zip(widgets.iter()) {
-            t.widget = Some(w.clone());
+    fn set_tmp_msg_state(&mut self, txn_id: &str, state: OutboxState) -> Option<()> {
+        let t = self.msg_queue.iter_mut().find(|t| t.txn_id == txn_id)?;
+        t.state = state;
+        if let Err(err) = cache::get().set_outgoing_state(txn_id, state) {
+            error!("Can't update the message in the outbox: {}", err);
         }
+
+        self.clear_tmp_msgs();
+        self.append_tmp_msgs();
         None
     }
 
+    fn remove_tmp_msg(&mut self, txn_id: &str) -> Option<TmpMsg> {
+        let i = self.msg_queue.iter().position(|t| t.txn_id == txn_id)?;
+        let t = self.msg_queue.remove(i);
+        if let Some(ref w) = t.widget {
+            w.destroy();
+        }
+        if let Err(err) = cache::get().remove_outgoing(txn_id) {
+            error!("Can't remove the message from the outbox: {}", err);
+        }
+
+        Some(t)
+    }
+
+    /// Send again a message that failed
+    pub fn retry_message(&mut self, txn_id: String) {
+        let failed = self
+            .msg_queue
+            .iter()
+            .any(|t| t.txn_id == txn_id && t.state == OutboxState::Failed);
+        if failed {
+            self.set_tmp_msg_state(&txn_id, OutboxState::Queued);
+            self.dequeue_message();
+        }
+    }
+
+    /// Forget a message we haven't sent yet
+    pub fn discard_message(&mut self, txn_id: String) {
+        let sending = self
+            .msg_queue
+            .iter()
+            .any(|t| t.txn_id == txn_id && t.state == OutboxState::Sending);
+        if !sending {
+            self.remove_tmp_msg(&txn_id);
+        }
+    }
+
     pub fn mark_last_message_as_read(&mut self, Force(force): Force) -> Option<()> {
         let login_data = self.login_data.clone()?;
         let window: gtk::Window = self
@@ -180,24 +291,44 @@ impl AppOp {
         None
     }
 
-    pub fn msg_sent(&mut self, _txid: String, evid: Option<EventId>) {
-        if let Some(ref mut m) = self.msg_queue.pop() {
-            if let Some(ref w) = m.widget {
-                w.destroy();
-            }
-            m.widget = None;
+    pub fn msg_sent(&mut self, txid: String, evid: Option<EventId>) {
+        if let Some(mut m) = self.remove_tmp_msg(&txid) {
             m.msg.id = evid;
-            self.show_room_messages(vec![m.msg.clone()]);
+            self.show_room_messages(vec![m.msg]);
         }
         self.force_dequeue_message();
     }
 
-    pub fn retry_send(&mut self) {
-        gtk::timeout_add(5000, move || {
-            /* This will be removed once tmp messages are refactored */
-            APPOP!(force_dequeue_message);
-            Continue(false)
-        });
+    /// Messages that couldn't go because we are offline wait in the queue for the network
+    /// to come back, the others wait for the user to retry them
+    pub fn msg_not_sent(&mut self, txid: String, offline: bool) {
+        self.sending_message = false;
+        if offline {
+            self.set_tmp_msg_state(&txid, OutboxState::Queued);
+        } else {
+            self.set_tmp_msg_state(&txid, OutboxState::Failed);
+            self.dequeue_message();
+        }
+    }
+
+    /// The machine that encrypts the events sent to the room, if it's an
+    /// encrypted room. Nothing can be sent to an encrypted room without it.
+    fn room_olm_machine(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<SharedOlmMachine>, MissingOlmMachine> {
+        let encrypted = self.rooms.get(room_id).map_or(false, |r| r.encrypted);
+        match self.olm_machine.clone() {
+            _ if !encrypted => Ok(None),
+            Some(olm_machine) => Ok(Some(olm_machine)),
+            None => Err(MissingOlmMachine),
+        }
+    }
+
+    fn show_missing_olm_machine_error(&self) {
+        self.show_error(i18n(
+            "Can’t send to an encrypted room, the encryption keys of this device aren’t loaded",
+        ));
     }
 
     pub fn force_dequeue_message(&mut self) {
@@ -211,57 +342,99 @@ impl AppOp {
             return None;
         }
 
-        self.sending_message = true;
-        if let Some(next) = self.msg_queue.last() {
-            let msg = next.msg.clone();
-            match &next.msg.mtype[..] {
+        let next = self
+            .msg_queue
+            .iter()
+            .rev()
+            .find(|t| t.state == OutboxState::Queued)
+            .map(|t| (t.txn_id.clone(), t.msg.clone()));
+        if let Some((txn_id, msg)) = next {
+            let olm_machine = match self.room_olm_machine(&msg.room) {
+                Ok(olm_machine) => olm_machine,
+                Err(MissingOlmMachine) => {
+                    self.show_missing_olm_machine_error();
+                    let offline = false;
+                    self.msg_not_sent(txn_id, offline);
+                    return None;
+                }
+            };
+
+            self.sending_message = true;
+            self.set_tmp_msg_state(&txn_id, OutboxState::Sending);
+
+            match &msg.mtype[..] {
                 "m.image" | "m.file" | "m.audio" | "m.video" => {
                     thread::spawn(move || {
-                        attach_file(login_data.server_url, login_data.access_token, msg)
+                        let id = txn_id.clone();
+                        let attached = attach_file(
+                            login_data.server_url,
+                            login_data.access_token,
+                            id,
+                            msg,
+                            olm_machine,
+                        );
+                        if attached.is_err() {
+                            // Nothing to upload, this can't ever be sent
+                            let offline = false;
+                            APPOP!(msg_not_sent, (txn_id, offline));
+                        }
                     });
                 }
                 _ => {
                     thread::spawn(move || {
-                        match room::send_msg(login_data.server_url, login_data.access_token, msg) {
-                            Ok((txid, evid)) => {
-                                APPOP!(msg_sent, (txid, evid));
-                                let initial = false;
-                                let number_tries = 0;
-                                APPOP!(sync, (initial, number_tries));
-                            }
-                            Err(err) => {
-                                err.handle_error();
-                            }
-                        }
+                        send_msg_and_manage(
+                            login_data.server_url,
+                            login_data.access_token,
+                            txn_id,
+                            msg,
+                            olm_machine,
+                        )
                     });
                 }
             }
-        } else {
-            self.sending_message = false;
         }
         None
     }
 
-    pub fn send_message(&mut self, msg: String) {
+    /// Sends what the user typed in the composer, or runs it if it's a command. Returns
+    /// false if it isn't a command we understand, so it can be fixed.
+    pub fn send_message(&mut self, msg: String) -> bool {
         if msg.is_empty() {
             // Not sending empty messages
-            return;
+            return true;
+        }
+
+        match Command::parse(&msg) {
+            Some(Ok(command)) => self.run_command(command),
+            Some(Err(err)) => {
+                self.show_command_error(err);
+                return false;
+            }
+            // A double slash sends the message with a single one
+            None if msg.starts_with("//") => {
+                self.send_text(msg[1..].to_string(), "m.text", TextFormat::Markdown)
+            }
+            None => self.send_text(msg, "m.text", TextFormat::Markdown),
         }
 
+        true
+    }
+
+    pub fn send_text(&mut self, msg: String, mtype: &str, format: TextFormat) {
         if let Some(room) = self.active_room.clone() {
             if let Some(sender) = self.login_data.as_ref().map(|ld| ld.uid.clone()) {
                 let body = msg.clone();
-                let mtype = String::from("m.text");
-                let mut m = Message::new(room, sender, body, mtype, None);
+                let mut m = Message::new(room, sender, body, mtype.to_string(), None);
 
-                if msg.starts_with("/me ") {
-                    m.body = msg.trim_start_matches("/me ").to_owned();
-                    m.mtype = String::from("m.emote");
+                if let TextFormat::Html = format {
+                    m.body = html_to_text(&msg);
+                    m.formatted_body = Some(msg.clone());
+                    m.format = Some(String::from("org.matrix.custom.html"));
                 }
 
                 // Riot does not properly show emotes with Markdown;
                 // Emotes with markdown have a newline after the username
-                if m.mtype != "m.emote" && self.md_enabled {
+                if m.mtype != "m.emote" && self.md_enabled && format == TextFormat::Markdown {
                     let mut md_options = ComrakOptions::default();
                     md_options.hardbreaks = true;
                     let mut md_parsed_msg = markdown_to_html(&msg, &md_options);
@@ -285,6 +458,26 @@ impl AppOp {
                     }
                 }
 
+                if let Some((edit_room, replace)) = self.editing.take() {
+                    if edit_room == m.room {
+                        m.replace = Some(replace);
+                    }
+                }
+
+                // Only text messages can be replies
+                if let Some(original) = self.replying_to.take() {
+                    if original.room == m.room && m.mtype == "m.text" && m.replace.is_none() {
+                        let (body, formatted_body) = m.reply_fallbacks(&original);
+                        m.in_reply_to = original.id.clone();
+                        m.extra_content = Some(json!({
+                            "body": body,
+                            "format": "org.matrix.custom.html",
+                            "formatted_body": formatted_body,
+                        }));
+                    }
+                    self.ui.sventry.reply_revealer.set_reveal_child(false);
+                }
+
                 self.add_tmp_room_message(m);
                 self.dequeue_message();
             } else {
@@ -295,6 +488,121 @@ impl AppOp {
         }
     }
 
+    /// Make the next message we send a reply to `msg`
+    pub fn set_reply_to(&mut self, msg: Message) {
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        if msg.room != room_id {
+            return;
+        }
+        // Replies go to the original message, not to its edits
+        let mut original = msg;
+        if let Some(replace) = original.replace.take() {
+            original.id = Some(replace);
+        }
+
+        let name = self
+            .rooms
+            .get(&room_id)
+            .and_then(|room| room.members.get(&original.sender))
+            .and_then(|member| member.alias.clone())
+            .unwrap_or_else(|| original.sender.to_string());
+        let title = i18n_k("Replying to {name}", &[("name", &name)]);
+        let snippet = original.body.lines().next().unwrap_or_default();
+        self.ui.sventry.reply_label.set_markup(&format!(
+            "<b>{}</b> {}",
+            markup_text(&title),
+            markup_text(snippet)
+        ));
+        self.ui.sventry.reply_revealer.set_reveal_child(true);
+        self.ui.sventry.view.grab_focus();
+
+        self.editing = None;
+        self.replying_to = Some(original);
+    }
+
+    pub fn cancel_reply(&mut self) {
+        self.replying_to = None;
+        self.ui.sventry.reply_revealer.set_reveal_child(false);
+    }
+
+    /// Show the message another one replies to, the messages around it are loaded if
+    /// it isn't in the room history
+    pub fn open_reply(&mut self, event_id: EventId) {
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        let shown = self
+            .history
+            .as_ref()
+            .map_or(false, |history| history.scroll_to_message(&event_id));
+        if shown {
+            return;
+        }
+
+        self.load_message_context(room_id, event_id);
+    }
+
+    /// Put the text of one of our messages in the composer, the next message we send
+    /// replaces it
+    pub fn edit_message(&mut self, msg: Message) {
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        // An edit always points to the original message, not to other edits
+        let replace = unwrap_or_unit_return!(msg.replace.clone().or(msg.id));
+        if msg.room != room_id {
+            return;
+        }
+        self.cancel_reply();
+
+        let text = if msg.mtype == "m.emote" {
+            format!("/me {}", msg.body)
+        } else {
+            msg.body
+        };
+
+        let msg_entry = self.ui.sventry.view.clone();
+        if let Some(buffer) = msg_entry.get_buffer() {
+            buffer.set_text(&text);
+            buffer.place_cursor(&buffer.get_end_iter());
+        }
+        msg_entry.grab_focus();
+
+        self.editing = Some((room_id, replace));
+    }
+
+    /// Show the original text of a message and every edit it had, the oldest first
+    pub fn show_edit_history(&self, event_id: EventId) {
+        let room_id = unwrap_or_unit_return!(self.active_room.as_ref());
+        let room = unwrap_or_unit_return!(self.rooms.get(room_id));
+        let original_id = unwrap_or_unit_return!(room
+            .messages
+            .iter()
+            .find(|m| m.id.as_ref() == Some(&event_id))
+            .and_then(|m| m.replace.clone().or_else(|| m.id.clone())));
+        let original = unwrap_or_unit_return!(room
+            .messages
+            .iter()
+            .find(|m| m.id.as_ref() == Some(&original_id) && m.replace.is_none()));
+
+        let mut versions: Vec<&Message> = room
+            .messages
+            .iter()
+            .filter(|m| !m.redacted && m.replace.as_ref() == Some(&original_id))
+            .collect();
+        versions.sort_by_key(|m| m.date);
+        versions.insert(0, original);
+
+        let history: Vec<_> = versions
+            .into_iter()
+            .map(|m| (m.date, m.body.clone()))
+            .collect();
+
+        let window: gtk::Window = self
+            .ui
+            .builder
+            .get_object("main_window")
+            .expect("Can't find main_window in ui file.");
+        let dialog = widgets::EditHistoryDialog::new(&window, &history);
+        dialog.show();
+    }
+
     pub fn attach_message(&mut self, path: PathBuf) {
         if let Some(room) = self.active_room.clone() {
             if let Some(sender) = self.login_data.as_ref().map(|ld| ld.uid.clone()) {
@@ -351,17 +659,15 @@ impl AppOp {
     }
 
     /// This method is called when a tmp message with an attach is sent correctly
-    /// to the matrix media server and we've the real url to use so we can
-    /// replace the tmp message with the same id with this new one
-    pub fn attached_file(&mut self, msg: Message) {
-        let p = self.msg_queue.iter().position(|m| m.msg == msg);
-        if let Some(i) = p {
-            let w = self.msg_queue.remove(i);
-            if let Some(w) = w.widget {
-                w.destroy()
+    /// to the matrix media server and we've the real url to use, so if we have to
+    /// send the message again we don't upload the file again
+    pub fn attached_file(&mut self, txn_id: String, msg: Message) {
+        if let Some(t) = self.msg_queue.iter_mut().find(|t| t.txn_id == txn_id) {
+            t.msg = msg;
+            if let Err(err) = cache::get().update_outgoing(&txn_id, &t.msg) {
+                error!("Can't update the message in the outbox: {}", err);
             }
         }
-        self.add_tmp_room_message(msg);
     }
 
     /* TODO: find a better name for this function */
@@ -378,24 +684,19 @@ impl AppOp {
         }
 
         let mut msg_in_active = false;
-        let login_data = self.login_data.clone()?;
-        let uid = login_data.uid;
         for msg in msgs.iter() {
             if !msg.redacted && self.active_room.as_ref().map_or(false, |x| x == &msg.room) {
                 self.add_room_message(&msg);
                 msg_in_active = true;
             }
 
-            if msg.replace != None {
-                /* No need to notify (and confuse the user) about edits. */
+            if msg.replace != None || msg.annotates != None || msg.is_state_event() {
+                /* No need to notify (and confuse the user) about edits, reactions or
+                 * changes of the room. */
                 continue;
             }
 
-            let should_notify = msg.sender != uid
-                && (msg.body.contains(&login_data.username.clone()?)
-                    || self.rooms.get(&msg.room).map_or(false, |r| r.direct));
-
-            if should_notify {
+            if self.message_notification(msg).notify {
                 let window: gtk::Window = self
                     .ui
                     .builder
@@ -424,20 +725,27 @@ impl AppOp {
         room_id: RoomId,
         prev_batch: Option<String>,
     ) {
+        let mut reached_start = false;
         if let Some(r) = self.rooms.get_mut(&room_id) {
+            // At the start of the room the server has no token for us, or gives the same back
+            reached_start = prev_batch.is_none() || (msgs.is_empty() && prev_batch == r.prev_batch);
             r.prev_batch = prev_batch;
         }
 
         let active_room = self.active_room.as_ref();
         let mut list = vec![];
         for item in msgs.iter().rev() {
-            /* create a list of new messages to load to the history */
-            if active_room.map_or(false, |a_room| item.room == *a_room) && !item.redacted {
+            /* create a list of new messages to load to the history, they can come from the
+             * predecessor of the room */
+            if active_room.map_or(false, |a_room| room_id == *a_room) && !item.redacted {
                 if let Some(ui_msg) = self.create_new_room_message(item) {
                     list.push(ui_msg);
                 }
             }
 
+            if item.room != room_id {
+                continue;
+            }
             if let Some(r) = self.rooms.get_mut(&item.room) {
                 r.messages.insert(0, item.clone());
             }
@@ -450,24 +758,179 @@ impl AppOp {
                 list,
             );
         }
+
+        if reached_start {
+            self.load_predecessor_messages(room_id);
+        }
     }
 
     pub fn remove_message(&mut self, room_id: RoomId, id: EventId) -> Option<()> {
         let message = self.get_message_by_id(&room_id, &id);
 
         if let Some(msg) = message {
-            self.remove_room_message(&msg);
+            if msg.annotates.is_none() {
+                self.remove_room_message(&msg);
+            }
             if let Some(ref mut room) = self.rooms.get_mut(&msg.room) {
                 if let Some(ref mut message) = room.messages.iter_mut().find(|e| e.id == msg.id) {
                     message.redacted = true;
                 }
             }
+            if let Some(ref target) = msg.annotates {
+                self.refresh_reactions(&msg.room, target);
+            }
+        }
+        None
+    }
+
+    /// The reactions to a message grouped by key, in the order they were first used
+    fn get_reactions(&self, room_id: &RoomId, target: &EventId) -> Vec<ReactionGroup> {
+        let uid = self.login_data.as_ref().map(|ld| &ld.uid);
+        let room = match self.rooms.get(room_id) {
+            Some(room) => room,
+            None => return vec![],
+        };
+
+        let mut groups: Vec<ReactionGroup> = vec![];
+        let reactions = room
+            .messages
+            .iter()
+            .filter(|m| !m.redacted && m.annotates.as_ref() == Some(target));
+        for reaction in reactions {
+            let ours = reaction
+                .id
+                .clone()
+                .filter(|_| Some(&reaction.sender) == uid);
+            match groups.iter_mut().find(|g| g.key == reaction.body) {
+                Some(group) => {
+                    group.count += 1;
+                    group.ours = group.ours.take().or(ours);
+                }
+                None => groups.push(ReactionGroup {
+                    key: reaction.body.clone(),
+                    count: 1,
+                    ours,
+                }),
+            }
+        }
+
+        groups
+    }
+
+    fn refresh_reactions(&mut self, room_id: &RoomId, target: &EventId) -> Option<()> {
+        if self.active_room.as_ref() != Some(room_id) {
+            return None;
+        }
+
+        let reactions = self.get_reactions(room_id, target);
+        self.history.as_mut()?.update_reactions(
+            self.thread_pool.clone(),
+            self.user_info_cache.clone(),
+            target,
+            reactions,
+        );
+        None
+    }
+
+    /// Add our reaction to a message, or take it back if we already reacted with that key
+    pub fn toggle_reaction(&mut self, target: EventId, key: String) -> Option<()> {
+        let login_data = self.login_data.clone()?;
+        let room_id = self.active_room.clone()?;
+        let room = self.rooms.get(&room_id)?;
+        // Reactions go to the original message, not to its edits
+        let target = room
+            .messages
+            .iter()
+            .find(|m| m.id.as_ref() == Some(&target))
+            .and_then(|m| m.replace.clone())
+            .unwrap_or(target);
+
+        let ours = room
+            .messages
+            .iter()
+            .find(|m| {
+                !m.redacted
+                    && m.annotates.as_ref() == Some(&target)
+                    && m.sender == login_data.uid
+                    && m.body == key
+            })
+            .cloned();
+
+        if let Some(reaction) = ours {
+            thread::spawn(move || {
+                let query =
+                    room::redact_msg(login_data.server_url, login_data.access_token, reaction);
+                if let Err(err) = query {
+                    err.handle_error();
+                }
+            });
+            return None;
         }
+
+        let olm_machine = match self.room_olm_machine(&room_id) {
+            Ok(olm_machine) => olm_machine,
+            Err(MissingOlmMachine) => {
+                self.show_missing_olm_machine_error();
+                return None;
+            }
+        };
+        thread::spawn(move || {
+            let query = room::send_reaction(
+                login_data.server_url,
+                login_data.access_token,
+                room_id.clone(),
+                target.clone(),
+                key.clone(),
+                olm_machine,
+            );
+            match query {
+                Ok(event_id) => {
+                    // Show it now, the sync would bring it a bit later
+                    let mut reaction = Message::new(
+                        room_id,
+                        login_data.uid,
+                        key,
+                        String::from("m.reaction"),
+                        event_id,
+                    );
+                    reaction.annotates = Some(target);
+                    let msgs = vec![reaction];
+                    APPOP!(show_room_messages, (msgs));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
         None
     }
 
     /* parese a backend Message into a Message for the UI */
     pub fn create_new_room_message(&self, msg: &Message) -> Option<MessageContent> {
+        if msg.mtype == "m.reaction" {
+            // They are shown with the message they are for
+            return None;
+        }
+
+        if msg.is_state_event() {
+            let change = msg
+                .state_change
+                .as_ref()
+                .filter(|_| self.show_state_events)?;
+            let uid = &self.login_data.as_ref()?.uid;
+            let mut ui_msg = create_ui_message(
+                msg.clone(),
+                None,
+                RowType::State,
+                vec![],
+                false,
+                false,
+                msg.receipt.contains_key(uid),
+            );
+            ui_msg.body = self.describe_state_change(msg, change);
+            return Some(ui_msg);
+        }
+
         let login_data = self.login_data.clone()?;
         let mut highlights = vec![];
         lazy_static! {
@@ -489,19 +952,14 @@ impl AppOp {
             "m.video" => RowType::Video,
             "m.file" => RowType::File,
             _ => {
-                /* set message type to mention if the body contains the username, we should
-                 * also match for MXID */
-                let is_mention = if let Some(user) = login_data.username.clone() {
-                    msg.sender != login_data.uid && msg.body.contains(&user)
-                } else {
-                    false
-                };
-
-                if is_mention {
+                /* set message type to mention if the push rules highlight it */
+                if self.message_notification(msg).highlight {
                     if let Some(user) = login_data.username {
                         highlights.push(user);
                     }
                     highlights.push(login_data.uid.to_string());
+                    highlights.push(String::from("@room"));
+                    highlights.extend(self.highlight_keywords());
                     highlights.push(String::from("message_menu"));
 
                     RowType::Mention
@@ -526,16 +984,87 @@ impl AppOp {
             .copied()
             .unwrap_or_default();
         let redactable = admin != 0 || login_data.uid == msg.sender;
+        let editable = login_data.uid == msg.sender
+            && msg.id.is_some()
+            && (msg.mtype == "m.text" || msg.mtype == "m.emote");
 
         let is_last_viewed = msg.receipt.contains_key(&login_data.uid);
-        Some(create_ui_message(
+        let mut ui_msg = create_ui_message(
             msg.clone(),
             name,
             t,
             highlights,
             redactable,
+            editable,
             is_last_viewed,
-        ))
+        );
+        // The reactions are for the original message, not for its edits
+        if let Some(target) = msg.replace.as_ref().or_else(|| msg.id.as_ref()) {
+            ui_msg.reactions = self.get_reactions(&msg.room, target);
+        }
+        ui_msg.reply = self.get_reply_header(msg);
+
+        Some(ui_msg)
+    }
+
+    fn find_message(&self, room_id: &RoomId, event_id: &EventId) -> Option<&Message> {
+        let context = self
+            .timeline_context
+            .as_ref()
+            .filter(|ctx| ctx.room_id == *room_id)
+            .map(|ctx| ctx.messages.iter());
+        self.rooms
+            .get(room_id)?
+            .messages
+            .iter()
+            .chain(context.into_iter().flatten())
+            .find(|m| m.id.as_ref() == Some(event_id))
+    }
+
+    fn get_reply_header(&self, msg: &Message) -> Option<ReplyHeader> {
+        // Edits don't repeat what the message replies to
+        let msg = match msg.replace.as_ref() {
+            Some(replace) if msg.in_reply_to.is_none() => {
+                self.find_message(&msg.room, replace).unwrap_or(msg)
+            }
+            _ => msg,
+        };
+        let event_id = msg.in_reply_to.clone()?;
+        let room = self.rooms.get(&msg.room)?;
+
+        let quoted = match self.find_message(&msg.room, &event_id) {
+            Some(original) => {
+                // Show its text as it was last edited
+                let body = room
+                    .messages
+                    .iter()
+                    .filter(|m| !m.redacted && m.replace.as_ref() == Some(&event_id))
+                    .last()
+                    .map_or(&original.body, |edit| &edit.body);
+                Some((original.sender.clone(), body.clone()))
+            }
+            // We don't have it, but the fallback tells us what it was
+            None => msg.reply_quote.clone(),
+        };
+
+        let (sender_name, snippet) = match quoted {
+            Some((sender, body)) => {
+                let name = room
+                    .members
+                    .get(&sender)
+                    .and_then(|member| member.alias.clone())
+                    .unwrap_or_else(|| sender.to_string());
+                let snippet = body.lines().next().unwrap_or_default().to_string();
+                (Some(name), snippet)
+            }
+            None => (None, String::new()),
+        };
+
+        Some(ReplyHeader {
+            event_id,
+            sender_name,
+            snippet,
+        })
     }
 }
 
@@ -546,6 +1075,7 @@ fn create_ui_message(
     t: RowType,
     highlights: Vec<String>,
     redactable: bool,
+    editable: bool,
     last_viewed: bool,
 ) -> MessageContent {
     MessageContent {
@@ -569,6 +1099,10 @@ fn create_ui_message(
         last_viewed,
         highlights,
         redactable,
+        editable,
+        reactions: vec![],
+        reply: None,
+        collapsed: vec![],
         widget: None,
     }
 }
@@ -651,7 +1185,15 @@ fn get_file_media_info(file: &Path, mimetype: &str) -> Option<JsonValue> {
 
 struct NonMediaMsg;
 
-fn attach_file(baseu: Url, tk: AccessToken, mut msg: Message) -> Result<(), NonMediaMsg> {
+struct MissingOlmMachine;
+
+fn attach_file(
+    baseu: Url,
+    tk: AccessToken,
+    txn_id: String,
+    mut msg: Message,
+    olm_machine: Option<SharedOlmMachine>,
+) -> Result<(), NonMediaMsg> {
     let mut extra_content: Option<ExtraContent> = msg
         .extra_content
         .clone()
@@ -660,17 +1202,28 @@ fn attach_file(baseu: Url, tk: AccessToken, mut msg: Message) -> Result<(), NonM
     let thumb_url = extra_content.clone().and_then(|c| c.info.thumbnail_url);
 
     match (msg.url.clone(), msg.local_path.as_ref(), thumb_url) {
-        (Some(url), _, Some(thumb)) if url.scheme() == "mxc" && thumb.scheme() == "mxc" => {
-            send_msg_and_manage(baseu, tk, msg);
+        // Already uploaded, we failed to send the message the last time
+        (Some(url), _, thumb)
+            if url.scheme() == "mxc" && thumb.map_or(true, |thumb| thumb.scheme() == "mxc") =>
+        {
+            send_msg_and_manage(baseu, tk, txn_id, msg, olm_machine);
 
             Ok(())
         }
         (_, Some(local_path), _) => {
-            if let Some(ref local_path_thumb) = msg.local_path_thumb {
-                let response = room::upload_file(baseu.clone(), tk.clone(), local_path_thumb)
-                    .and_then(|response| Url::parse(&response.content_uri).map_err(Into::into));
+            // In encrypted rooms the server only gets the encrypted files
+            let encrypted = olm_machine.is_some();
+            let upload = |path: &Path| {
+                if encrypted {
+                    room::upload_encrypted_file(baseu.clone(), tk.clone(), path)
+                } else {
+                    room::upload_file(baseu.clone(), tk.clone(), path)
+                        .and_then(|response| Url::parse(&response.content_uri).map_err(Into::into))
+                }
+            };
 
-                match response {
+            if let Some(ref local_path_thumb) = msg.local_path_thumb {
+                match upload(local_path_thumb) {
                     Ok(thumb_uri) => {
                         msg.thumb = Some(thumb_uri.clone());
                         if let Some(ref mut xctx) = extra_content {
@@ -688,22 +1241,18 @@ fn attach_file(baseu: Url, tk: AccessToken, mut msg: Message) -> Result<(), NonM
                 }
             }
 
-            let query =
-                room::upload_file(baseu.clone(), tk.clone(), local_path).and_then(|response| {
-                    msg.url = Some(Url::parse(&response.content_uri)?);
-                    thread::spawn(
-                        clone!(@strong msg => move || send_msg_and_manage(baseu, tk, msg)),
-                    );
-
-                    Ok(msg)
-                });
-
-            match query {
-                Ok(msg) => {
-                    APPOP!(attached_file, (msg));
+            match upload(local_path) {
+                Ok(url) => {
+                    msg.url = Some(url);
+                    let attached = msg.clone();
+                    let id = txn_id.clone();
+                    APPOP!(attached_file, (id, attached));
+                    send_msg_and_manage(baseu, tk, txn_id, msg, olm_machine);
                 }
                 Err(err) => {
+                    let offline = err.is_offline();
                     err.handle_error();
+                    APPOP!(msg_not_sent, (txn_id, offline));
                 }
             };
 
@@ -713,8 +1262,14 @@ fn attach_file(baseu: Url, tk: AccessToken, mut msg: Message) -> Result<(), NonM
     }
 }
 
-fn send_msg_and_manage(baseu: Url, tk: AccessToken, msg: Message) {
-    match room::send_msg(baseu, tk, msg) {
+fn send_msg_and_manage(
+    baseu: Url,
+    tk: AccessToken,
+    txn_id: String,
+    msg: Message,
+    olm_machine: Option<SharedOlmMachine>,
+) {
+    match room::send_msg(baseu, tk, txn_id, msg, olm_machine) {
         Ok((txid, evid)) => {
             APPOP!(msg_sent, (txid, evid));
             let initial = false;
@@ -6,47 +6,62 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use fractal_api::identifiers::{DeviceId, RoomId, UserId};
+use fractal_api::r0::pushrules::Ruleset;
 use fractal_api::r0::AccessToken;
 
 use gtk::prelude::*;
+use log::{error, info};
 
 use crate::backend::ThreadPool;
 use crate::cache::CacheMap;
+use crate::crypto::SharedOlmMachine;
 use fractal_api::url::Url;
 
 use crate::i18n;
 
 use crate::types::Member;
+use crate::types::Message;
 use crate::types::Room;
 use crate::types::RoomList;
 
-use crate::passwd::PasswordStorage;
+use crate::passwd::{self, PasswordStorage, StorageType};
 
 use crate::actions::AppState;
-use crate::cache;
 use crate::uibuilder;
 use crate::widgets;
 
 mod about;
 mod account;
+mod accounts;
 pub mod attach;
+pub mod command;
+mod credentials;
 mod directory;
 mod invite;
 mod login;
 mod media_viewer;
 mod member;
 mod message;
+mod moderation;
 mod notifications;
 mod notify;
 pub mod room;
 mod room_settings;
+mod search;
+mod session;
 mod start_chat;
 pub mod state;
+mod state_change;
 mod sync;
+mod upgrade;
 mod user;
 
+use self::accounts::Account;
+use self::login::SsoWait;
 use self::member::SearchType;
 use self::message::TmpMsg;
+use self::search::TimelineContext;
+use self::sync::SyncRetry;
 
 pub type UserInfoCache = Arc<Mutex<CacheMap<UserId, (String, PathBuf)>>>;
 
@@ -86,11 +101,23 @@ pub struct AppOp {
     pub ui: uibuilder::UI,
 
     pub syncing: bool, // TODO: Replace with a Mutex
+    /// Set while the sync failed and waits to be tried again
+    pub sync_retry: Option<SyncRetry>,
     pub msg_queue: Vec<TmpMsg>,
     pub sending_message: bool,
+    /// The message the composer is editing, if any
+    pub editing: Option<(RoomId, EventId)>,
+    /// The message the next one we send replies to, if any
+    pub replying_to: Option<Message>,
 
     pub login_data: Option<LoginData>,
+    pub sso_wait: Option<SsoWait>,
+    /// The accounts we are logged in with, including the one we show
+    pub accounts: Vec<Account>,
     pub device_id: Option<Box<DeviceId>>, // TODO: Shouldn't be optional
+    pub olm_machine: Option<SharedOlmMachine>,
+    /// Decide which messages notify us, we fall back to mentions and direct chats without them
+    pub push_rules: Option<Ruleset>,
 
     pub active_room: Option<RoomId>,
     pub join_to_room: Option<RoomId>,
@@ -105,16 +132,23 @@ pub struct AppOp {
     pub media_viewer: Rc<RefCell<Option<widgets::MediaViewer>>>,
 
     pub directory_pagination: RoomSearchPagination,
+    pub search_pagination: RoomSearchPagination,
     pub state: AppState,
     pub since: Option<String>,
     pub room_back_history: Rc<RefCell<Vec<AppState>>>,
 
     pub invitation_roomid: Option<RoomId>,
     pub md_enabled: bool,
+    pub show_state_events: bool,
     pub invite_list: Vec<(Member, gtk::TextChildAnchor)>,
     search_type: SearchType,
 
     pub directory: Vec<Room>,
+    pub search_results: Vec<Message>,
+    pub search_highlights: Vec<String>,
+    pub search_groups: HashMap<RoomId, gtk::ListBox>,
+    pub search_local: bool,
+    pub timeline_context: Option<TimelineContext>,
     pub leaflet: libhandy::Leaflet,
 
     pub thread_pool: ThreadPool,
@@ -138,14 +172,22 @@ impl AppOp {
             room_settings: None,
             history: None,
             login_data: None,
+            sso_wait: None,
+            accounts: vec![],
             device_id: None,
+            olm_machine: None,
+            push_rules: None,
             syncing: false,
+            sync_retry: None,
             msg_queue: vec![],
             sending_message: false,
+            editing: None,
+            replying_to: None,
             state: AppState::Login,
             room_back_history: Rc::new(RefCell::new(vec![])),
             roomlist: widgets::RoomList::new(None, None),
             directory_pagination: RoomSearchPagination::Initial,
+            search_pagination: RoomSearchPagination::Initial,
             unread_rooms: 0,
             since: None,
             unsent_messages: HashMap::new(),
@@ -153,11 +195,17 @@ impl AppOp {
             media_viewer: Rc::new(RefCell::new(None)),
 
             md_enabled: false,
+            show_state_events: true,
             invitation_roomid: None,
             invite_list: vec![],
             search_type: SearchType::Invite,
 
             directory: vec![],
+            search_results: vec![],
+            search_highlights: vec![],
+            search_groups: HashMap::new(),
+            search_local: false,
+            timeline_context: None,
             leaflet,
 
             thread_pool: ThreadPool::new(20),
@@ -170,23 +218,42 @@ impl AppOp {
     pub fn init(&mut self) {
         self.set_state(AppState::Loading);
 
-        // FIXME: Username and uid should not be duplicated in cache.
-        if let Ok(data) = cache::load() {
-            let r: Vec<Room> = data.rooms.values().cloned().collect();
-            self.set_rooms(r, true);
-            /* Make sure that since is never an empty string */
-            self.since = data.since.filter(|s| !s.is_empty());
-            self.device_id = Some(data.device_id);
+        if passwd::is_locked() {
+            self.ask_credentials_passphrase(false);
+        } else {
+            self.load_accounts();
         }
+    }
 
-        // FIXME: Storing and getting the password is insecure.
-        //        Only the access token should be used.
-        if let Ok((username, password, server, id_url)) = self.get_pass() {
-            if let Ok((Some(access_token), uid)) = self.get_token() {
-                self.bk_login(uid, access_token, self.device_id.clone(), server, id_url);
-            } else {
-                self.connect(username, password, server, id_url);
-            }
+    pub fn load_accounts(&mut self) {
+        // The credentials follow when the password-storage setting changes
+        let storage = StorageType::current();
+        match passwd::migrate(storage.other(), storage) {
+            Ok(true) => info!("Moved the credentials to {:?}", storage),
+            Ok(false) => {}
+            Err(err) => error!("Can't move the credentials to {:?}: {:?}", storage, err),
+        }
+
+        // We show the first account, the other ones sync in the background
+        let tokens = self.get_tokens().unwrap_or_default();
+        for (access_token, uid, server_url, identity_url) in tokens {
+            self.accounts.push(Account::new(LoginData {
+                access_token,
+                uid,
+                username: None,
+                avatar: None,
+                server_url,
+                identity_url,
+            }));
+        }
+
+        if let Some(account) = self.accounts.first() {
+            let login_data = account.login_data.clone();
+            self.open_account(login_data, None);
+        } else if let Ok((username, password, server, id_url)) = self.get_pass() {
+            // FIXME: Storing and getting the password is insecure.
+            //        Only the access token should be used.
+            self.connect(username, password, server, id_url);
         } else {
             self.set_state(AppState::Login);
         }
@@ -217,6 +284,7 @@ impl AppOp {
                 window.set_title(&i18n::i18n_f("Fractal [{}]", &[&unread.to_string()]));
             }
             self.unread_rooms = unread;
+            self.show_accounts();
         }
     }
 
@@ -0,0 +1,159 @@
+use crate::backend::{room, HandleError};
+use crate::i18n::{i18n, i18n_k};
+use fractal_api::events::room::PowerLevelsEventContent;
+use fractal_api::identifiers::{RoomId, UserId};
+use gtk::prelude::*;
+use std::thread;
+
+use crate::app::App;
+use crate::appop::AppOp;
+use crate::types::Moderation;
+
+impl AppOp {
+    /// Confirms a kick or a ban of a member of the active room, giving the chance to tell
+    /// them why
+    pub fn ask_moderation_reason(&self, uid: UserId, action: Moderation) {
+        let name = self
+            .active_room
+            .as_ref()
+            .and_then(|room_id| self.rooms.get(room_id)?.members.get(&uid))
+            .map(|member| member.get_alias())
+            .unwrap_or_else(|| uid.to_string());
+        let (msg, accept) = match action {
+            Moderation::Kick => (
+                i18n_k("Remove {name} from the room?", &[("name", &name)]),
+                i18n("Remove"),
+            ),
+            Moderation::Ban => (
+                i18n_k(
+                    "Ban {name} from the room? They won’t be able to join it again until they are unbanned.",
+                    &[("name", &name)],
+                ),
+                i18n("Ban"),
+            ),
+        };
+
+        let parent = self
+            .ui
+            .builder
+            .get_object::<gtk::Window>("main_window")
+            .expect("Can't find main_window in ui file.");
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&parent),
+            flags,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &msg,
+        );
+
+        let entry = gtk::Entry::new();
+        entry.set_placeholder_text(Some(i18n("Reason (optional)").as_str()));
+        entry.set_activates_default(true);
+        if let Some(area) = dialog.get_message_area() {
+            if let Ok(area) = area.downcast::<gtk::Box>() {
+                area.add(&entry);
+            }
+        }
+
+        dialog.add_button(&i18n("Cancel"), gtk::ResponseType::Cancel);
+        let button = dialog.add_button(&accept, gtk::ResponseType::Ok);
+        button.get_style_context().add_class("destructive-action");
+        dialog.set_default_response(gtk::ResponseType::Ok);
+
+        dialog.connect_response(move |w, r| {
+            if let gtk::ResponseType::Ok = r {
+                let reason = entry
+                    .get_text()
+                    .map(|gstr| gstr.trim().to_string())
+                    .filter(|reason| !reason.is_empty());
+                let uid = uid.clone();
+                match action {
+                    Moderation::Kick => APPOP!(kick_member, (uid, reason)),
+                    Moderation::Ban => APPOP!(ban_member, (uid, reason)),
+                }
+            }
+            w.destroy();
+        });
+        dialog.show_all();
+    }
+
+    pub fn kick_member(&self, user_id: UserId, reason: Option<String>) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        thread::spawn(move || {
+            let query = room::kick_user(
+                login_data.server_url,
+                login_data.access_token,
+                room_id,
+                user_id,
+                reason,
+            );
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    pub fn ban_member(&self, user_id: UserId, reason: Option<String>) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        thread::spawn(move || {
+            let query = room::ban_user(
+                login_data.server_url,
+                login_data.access_token,
+                room_id,
+                user_id,
+                reason,
+            );
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    pub fn unban_member(&self, user_id: UserId) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        thread::spawn(move || {
+            let query = room::unban_user(
+                login_data.server_url,
+                login_data.access_token,
+                room_id,
+                user_id,
+            );
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    /// Without a level the member gets the default one of the room
+    pub fn set_member_power_level(&self, user_id: UserId, level: Option<i64>) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        thread::spawn(move || {
+            let query = room::set_power_level(
+                login_data.server_url,
+                login_data.access_token,
+                room_id,
+                user_id,
+                level,
+            );
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    /// We don't wait for the sync to know the new levels of the room
+    pub fn set_room_power_levels(&mut self, room_id: RoomId, levels: PowerLevelsEventContent) {
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            room.set_power_levels(levels);
+        }
+    }
+}
@@ -1,5 +1,12 @@
+use crate::app::App;
 use crate::appop::AppOp;
+use crate::backend::{user, HandleError};
+use crate::model::push_rules;
+use crate::types::{Message, Notification, PushContext};
 use fractal_api::identifiers::RoomId;
+use fractal_api::r0::pushrules::Ruleset;
+use serde_json::Value as JsonValue;
+use std::thread;
 
 impl AppOp {
     pub fn clear_room_notifications(&mut self, room_id: RoomId) {
@@ -17,4 +24,88 @@ impl AppOp {
         }
         self.update_title();
     }
+
+    pub fn get_push_rules(&self) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+
+        thread::spawn(move || {
+            match user::get_push_rules(login_data.server_url, login_data.access_token) {
+                Ok(rules) => {
+                    APPOP!(set_push_rules, (rules));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    pub fn set_push_rules(&mut self, rules: Ruleset) {
+        self.push_rules = Some(rules);
+        self.show_room_notifications_level();
+        self.show_highlight_keywords();
+    }
+
+    /// The keywords to highlight in messages, the ones with wildcards can't be shown
+    pub fn highlight_keywords(&self) -> Vec<String> {
+        self.push_rules
+            .iter()
+            .flat_map(push_rules::highlight_keywords)
+            .filter_map(|rule| rule.pattern.clone())
+            .filter(|pattern| !pattern.contains(|c| c == '*' || c == '?'))
+            .collect()
+    }
+
+    /// What the push rules say about a message, our own messages never notify us
+    pub fn message_notification(&self, msg: &Message) -> Notification {
+        let login_data = match self.login_data.as_ref() {
+            Some(login_data) if msg.sender != login_data.uid => login_data,
+            _ => return Notification::default(),
+        };
+        let room = match self.rooms.get(&msg.room) {
+            Some(room) => room,
+            None => return Notification::default(),
+        };
+
+        let rules = match self.push_rules.as_ref() {
+            Some(rules) => rules,
+            None => {
+                let mention = login_data
+                    .username
+                    .as_ref()
+                    .map_or(false, |name| msg.body.contains(name))
+                    || msg.body.contains(&login_data.uid.to_string())
+                    || msg.body.contains("@room");
+                return Notification {
+                    notify: mention || room.direct,
+                    highlight: mention,
+                    sound: false,
+                };
+            }
+        };
+
+        // The events of the sync don't say which room they belong to
+        let mut event: JsonValue = msg
+            .source
+            .as_ref()
+            .and_then(|source| serde_json::from_str(source).ok())
+            .unwrap_or_default();
+        if !event.is_object() {
+            return Notification::default();
+        }
+        event["room_id"] = JsonValue::from(msg.room.to_string());
+
+        let display_name = room
+            .members
+            .get(&login_data.uid)
+            .and_then(|member| member.alias.as_deref())
+            .or_else(|| login_data.username.as_deref());
+        let ctx = PushContext {
+            display_name,
+            member_count: room.members.len(),
+            power_levels: room.power_levels.as_ref(),
+        };
+
+        push_rules::evaluate(rules, &event, &ctx)
+    }
 }
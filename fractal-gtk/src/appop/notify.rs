@@ -30,10 +30,27 @@ impl AppOp {
             .builder
             .get_object("inapp_label")
             .expect("Can't find inapp_label in ui file.");
+        let retry: gtk::Button = self
+            .ui
+            .builder
+            .get_object("inapp_retry_button")
+            .expect("Can't find inapp_retry_button in ui file.");
         label.set_text(msg);
+        retry.hide();
         inapp.set_reveal_child(true);
     }
 
+    /// For the notifications about the sync, the user can try it again without waiting
+    pub fn inapp_retry_notify(&self, msg: &str) {
+        self.inapp_notify(msg);
+        let retry: gtk::Button = self
+            .ui
+            .builder
+            .get_object("inapp_retry_button")
+            .expect("Can't find inapp_retry_button in ui file.");
+        retry.show();
+    }
+
     pub fn hide_inapp_notify(&self) {
         let inapp: gtk::Revealer = self
             .ui
@@ -1,6 +1,6 @@
 use crate::backend::room;
 use crate::i18n::{i18n, i18n_k, ni18n_f};
-use fractal_api::identifiers::{DeviceId, RoomId, ServerName};
+use fractal_api::identifiers::{DeviceId, RoomId, RoomIdOrAliasId, ServerName};
 use fractal_api::r0::HostAndPort;
 use fractal_api::url::Url;
 use log::{error, warn};
@@ -23,6 +23,7 @@ use crate::cache;
 use crate::widgets;
 
 use crate::types::{Member, Reason, Room, RoomMembership, RoomTag};
+use crate::uitypes::MessageContent;
 
 use crate::util::markup_text;
 
@@ -48,6 +49,7 @@ impl AppOp {
             self.rooms.clear();
         }
         let mut roomlist = vec![];
+        let mut new_rooms = vec![];
         for room in rooms {
             // removing left rooms
             if let RoomMembership::Left(kicked) = room.membership.clone() {
@@ -67,6 +69,10 @@ impl AppOp {
                 if room.language.is_some() {
                     update_room.language = room.language.clone();
                 };
+                if room.encrypted {
+                    update_room.encrypted = true;
+                }
+                update_room.update_state(&room);
 
                 let typing_users: Vec<Member> = room
                     .typing_users
@@ -111,6 +117,7 @@ impl AppOp {
                     self.roomlist.add_room(room.clone());
                     self.roomlist.moveup(room.id.clone());
                 }
+                new_rooms.push(room.id.clone());
                 self.rooms.insert(room.id.clone(), room);
             }
         }
@@ -140,6 +147,10 @@ impl AppOp {
                 }
             }
 
+            let roomlist = roomlist
+                .into_iter()
+                .filter(|room| !self.is_room_replaced(room))
+                .collect();
             self.roomlist = widgets::RoomList::new(adj, Some(login_data.server_url.clone()));
             self.roomlist.add_rooms(roomlist);
             container.add(self.roomlist.widget());
@@ -164,7 +175,18 @@ impl AppOp {
             if let Some(active_room) = self.active_room.clone() {
                 self.set_active_room_by_id(active_room);
             }
-            self.cache_rooms();
+        } else {
+            self.hide_replaced_rooms();
+
+            // Open the room we were waiting for, like the one replacing an upgraded room
+            let joined = self
+                .join_to_room
+                .clone()
+                .filter(|room_id| new_rooms.contains(room_id));
+            if let Some(room_id) = joined {
+                self.join_to_room = None;
+                self.set_active_room_by_id(room_id);
+            }
         }
     }
 
@@ -203,7 +225,9 @@ impl AppOp {
                 .unwrap_or(room.default_power_level);
 
             // No room admin information, assuming normal
-            if user_power >= 0 || room.admins.is_empty() {
+            if room.replacement.is_some() {
+                self.show_replaced_room_banner();
+            } else if user_power >= 0 || room.admins.is_empty() {
                 msg_entry.set_editable(true);
                 msg_entry_stack.set_visible_child_name("Text Entry");
 
@@ -235,7 +259,16 @@ impl AppOp {
             }
         }
 
+        // The messages of the predecessor aren't kept, they are loaded again when scrolling back
+        if let Some(room) = self.rooms.get_mut(&id) {
+            if room.prev_batch_room.take().is_some() {
+                room.prev_batch = None;
+            }
+        }
+
         self.clear_tmp_msgs();
+        self.timeline_context = None;
+        self.cancel_reply();
 
         /* Transform id into the active_room */
         let active_room = id;
@@ -291,6 +324,19 @@ impl AppOp {
 
         self.append_tmp_msgs();
 
+        self.set_room_history(active_room.clone(), messages);
+
+        self.active_room = Some(active_room);
+        self.set_state(AppState::Room);
+        /* Mark the new active room as read */
+        self.mark_last_message_as_read(Force(false));
+        self.update_typing_notification();
+    }
+
+    /// Replace the displayed room history with a new one filled with `messages`
+    pub fn set_room_history(&mut self, room_id: RoomId, messages: Vec<MessageContent>) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+
         /* make sure we remove the old room history first, because the lazy loading could try to
          * load messages */
         if let Some(history) = self.history.take() {
@@ -305,7 +351,7 @@ impl AppOp {
             self.ui.clone(),
             back_history,
         );
-        let history = widgets::RoomHistory::new(actions, active_room.clone(), self);
+        let history = widgets::RoomHistory::new(actions, room_id, self);
         self.history = if let Some(mut history) = history {
             history.create(
                 self.thread_pool.clone(),
@@ -316,12 +362,6 @@ impl AppOp {
         } else {
             None
         };
-
-        self.active_room = Some(active_room);
-        self.set_state(AppState::Room);
-        /* Mark the new active room as read */
-        self.mark_last_message_as_read(Force(false));
-        self.update_typing_notification();
     }
 
     // FIXME: This should be a special case in a generic
@@ -434,8 +474,6 @@ impl AppOp {
 
     pub fn cache_rooms(&self) {
         let login_data = unwrap_or_unit_return!(self.login_data.clone());
-        // serializing rooms
-        let rooms = self.rooms.clone();
         let since = self.since.clone();
         let username = login_data.username.unwrap_or_default();
         let uid = login_data.uid;
@@ -444,8 +482,8 @@ impl AppOp {
             .clone()
             .unwrap_or_else(|| Box::<DeviceId>::from(""));
 
-        if cache::store(&rooms, since, username, uid, device_id).is_err() {
-            error!("Error caching rooms");
+        if let Err(err) = cache::store(&self.rooms, since, username, uid, device_id) {
+            error!("Error caching rooms: {}", err);
         };
     }
 
@@ -555,7 +593,6 @@ impl AppOp {
     }
 
     pub fn join_to_room(&mut self) {
-        let login_data = unwrap_or_unit_return!(self.login_data.clone());
         let try_room_id = self
             .ui
             .builder
@@ -566,16 +603,16 @@ impl AppOp {
             .trim()
             .try_into();
 
-        thread::spawn(move || {
-            let room_id = match try_room_id {
-                Ok(rid) => rid,
-                Err(_) => {
-                    let error = i18n("The room ID is malformed");
-                    APPOP!(show_error, (error));
-                    return;
-                }
-            };
+        match try_room_id {
+            Ok(room_id) => self.join_room(room_id),
+            Err(_) => self.show_error(i18n("The room ID is malformed")),
+        }
+    }
+
+    pub fn join_room(&mut self, room_id: RoomIdOrAliasId) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
 
+        thread::spawn(move || {
             match room::join_room(login_data.server_url, login_data.access_token, room_id) {
                 Ok(jtr) => {
                     let jtr = Some(jtr);
@@ -1,7 +1,10 @@
+use fractal_api::events::room::{CanonicalAliasEventContent, HistoryVisibility};
+use fractal_api::identifiers::RoomId;
 use gtk::prelude::*;
 
 use crate::actions::AppState;
 use crate::appop::AppOp;
+use crate::types::RoomNotifications;
 
 use crate::widgets;
 
@@ -48,6 +51,7 @@ impl AppOp {
 
             self.room_settings = Some(panel);
         }
+        self.show_room_notifications_level();
 
         self.set_state(AppState::RoomSettings);
 
@@ -71,4 +75,65 @@ impl AppOp {
         panel.show_new_room_topic();
         None
     }
+
+    pub fn show_power_levels_error(&self) -> Option<()> {
+        let panel = self.room_settings.clone()?;
+        panel.show_power_levels_error();
+        None
+    }
+
+    pub fn show_room_settings_switch(&self, id: &str, active: bool) -> Option<()> {
+        let panel = self.room_settings.clone()?;
+        panel.show_switch_state(id, active);
+        None
+    }
+
+    pub fn show_room_published(&self, room_id: RoomId, published: bool) -> Option<()> {
+        let panel = self.room_settings.clone()?;
+        if self.active_room.as_ref() == Some(&room_id) {
+            panel.show_room_published(published);
+        }
+        None
+    }
+
+    pub fn show_room_history_visibility(
+        &self,
+        visibility: Option<HistoryVisibility>,
+    ) -> Option<()> {
+        let panel = self.room_settings.clone()?;
+        panel.show_history_visibility(visibility);
+        None
+    }
+
+    /// We don't wait for the sync to know the new addresses of the room
+    pub fn set_room_aliases(
+        &mut self,
+        room_id: RoomId,
+        aliases: CanonicalAliasEventContent,
+    ) -> Option<()> {
+        self.rooms
+            .get_mut(&room_id)?
+            .set_canonical_alias(aliases.clone());
+        if self.active_room.as_ref() == Some(&room_id) {
+            self.room_settings.clone()?.show_aliases(&aliases);
+        }
+        None
+    }
+
+    pub fn show_room_notifications_level(&self) -> Option<()> {
+        let panel = self.room_settings.clone()?;
+        let room_id = self.active_room.as_ref()?;
+        let level = self
+            .push_rules
+            .as_ref()
+            .map(|rules| RoomNotifications::of_room(rules, room_id));
+        panel.show_notifications_level(level);
+        None
+    }
+
+    pub fn show_room_aliases_error(&self) -> Option<()> {
+        let panel = self.room_settings.clone()?;
+        panel.show_aliases_error();
+        None
+    }
 }
@@ -0,0 +1,440 @@
+use fractal_api::identifiers::{EventId, RoomId};
+use fractal_api::r0::search::events::OrderBy;
+use gtk::prelude::*;
+use libhandy::Column;
+use log::error;
+use std::thread;
+
+use crate::backend::{search, HandleError};
+use crate::cache::{self, SearchQuery};
+use crate::globals;
+use crate::i18n::i18n;
+
+use crate::app::App;
+use crate::appop::AppOp;
+
+use crate::util::markup_text;
+use crate::widgets;
+
+use super::RoomSearchPagination;
+use crate::types::Message;
+use crate::uitypes::RowType;
+
+/// The messages shown in the room history when we jump to a search result instead of the latest
+/// ones, they are kept apart from the room messages because there can be a gap between them
+pub struct TimelineContext {
+    pub room_id: RoomId,
+    pub prev_batch: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+impl AppOp {
+    pub fn search_messages(&mut self) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let q = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("message_search_entry")
+            .expect("Can't find message_search_entry in ui file.");
+        let order = self
+            .ui
+            .builder
+            .get_object::<gtk::ComboBoxText>("message_search_order")
+            .expect("Can't find message_search_order in ui file.");
+
+        let search_term = q.get_text().map_or(String::new(), |gstr| gstr.to_string());
+        let query = SearchQuery::parse(&search_term);
+
+        if !self.search_pagination.has_more() {
+            self.clear_search_results();
+
+            if query.is_empty() {
+                self.search_pagination = RoomSearchPagination::NoMorePages;
+                self.reset_search_state();
+                return;
+            }
+
+            let search_stack = self
+                .ui
+                .builder
+                .get_object::<gtk::Stack>("search_stack")
+                .expect("Can't find search_stack in ui file.");
+            let search_spinner = self
+                .ui
+                .builder
+                .get_object::<gtk::Box>("search_spinner")
+                .expect("Can't find search_spinner in ui file.");
+            search_stack.set_visible_child(&search_spinner);
+
+            q.set_sensitive(false);
+            order.set_sensitive(false);
+
+            if query.text.trim().is_empty() {
+                // The server needs some text to look for, only the local index can use the
+                // filters alone
+                self.search_pagination = RoomSearchPagination::NoMorePages;
+            }
+            let query = query.clone();
+            thread::spawn(move || {
+                match cache::get().search_messages(&query, globals::LOCAL_SEARCH_LIMIT) {
+                    Ok(hits) => {
+                        let highlights = query.highlights();
+                        APPOP!(show_local_search_results, (hits, highlights));
+                    }
+                    Err(err) => {
+                        error!("Error searching the message index: {}", err);
+                        let hits: Vec<Message> = vec![];
+                        let highlights: Vec<String> = vec![];
+                        APPOP!(show_local_search_results, (hits, highlights));
+                    }
+                }
+            });
+        }
+
+        if let RoomSearchPagination::NoMorePages = self.search_pagination {
+            // there are no more results. We don't need to request for more
+            return;
+        }
+
+        let order_by = match order.get_active_id() {
+            Some(ref id) if id.as_str() == "rank" => OrderBy::Rank,
+            _ => OrderBy::Recent,
+        };
+        let next_batch = self.search_pagination.clone().into();
+        thread::spawn(move || {
+            let query = search::search_messages(
+                login_data.server_url,
+                login_data.access_token,
+                query,
+                order_by,
+                next_batch,
+            );
+
+            match query {
+                Ok((hits, highlights, next_batch)) => {
+                    APPOP!(append_search_results, (hits, highlights, next_batch));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    #[inline]
+    pub fn load_more_search_results(&mut self) {
+        self.search_messages();
+    }
+
+    fn clear_search_results(&mut self) {
+        let results = self
+            .ui
+            .builder
+            .get_object::<gtk::Box>("search_results")
+            .expect("Can't find search_results in ui file.");
+        for ch in results.get_children() {
+            results.remove(&ch);
+        }
+
+        self.search_results.clear();
+        self.search_highlights.clear();
+        self.search_groups.clear();
+        self.search_local = false;
+    }
+
+    /// Show what we found in the local index while we wait for the server, its results are
+    /// more complete so they will replace these ones
+    pub fn show_local_search_results(&mut self, hits: Vec<Message>, highlights: Vec<String>) {
+        if self.search_pagination != RoomSearchPagination::Initial {
+            // The server already answered or we aren't going to ask it
+            if self.search_pagination == RoomSearchPagination::NoMorePages
+                && self.search_results.is_empty()
+            {
+                self.add_search_results(hits, highlights);
+                self.search_local = true;
+                self.reset_search_state();
+            }
+            return;
+        }
+
+        if hits.is_empty() {
+            return;
+        }
+
+        self.add_search_results(hits, highlights);
+        self.search_local = true;
+
+        let search_stack = self
+            .ui
+            .builder
+            .get_object::<gtk::Stack>("search_stack")
+            .expect("Can't find search_stack in ui file.");
+        let search_column = self
+            .ui
+            .builder
+            .get_object::<Column>("search_column")
+            .expect("Can't find search_column in ui file.");
+        search_stack.set_visible_child(&search_column);
+    }
+
+    pub fn append_search_results(
+        &mut self,
+        hits: Vec<Message>,
+        highlights: Vec<String>,
+        next_batch: Option<String>,
+    ) {
+        if self.search_local {
+            self.clear_search_results();
+        }
+
+        self.search_pagination = next_batch
+            .map(RoomSearchPagination::Next)
+            .unwrap_or(RoomSearchPagination::NoMorePages);
+
+        self.add_search_results(hits, highlights);
+        self.reset_search_state();
+    }
+
+    /// Keep the results of the local index if the server can't be reached
+    pub fn search_failed(&mut self) {
+        if self.search_local && !self.search_results.is_empty() {
+            self.search_pagination = RoomSearchPagination::NoMorePages;
+            self.reset_search_state();
+            return;
+        }
+
+        self.reset_search_state();
+        let error = i18n("Error searching for messages");
+        self.show_error(error);
+    }
+
+    fn add_search_results(&mut self, hits: Vec<Message>, highlights: Vec<String>) {
+        for light in highlights {
+            if !self.search_highlights.contains(&light) {
+                self.search_highlights.push(light);
+            }
+        }
+
+        let results = self
+            .ui
+            .builder
+            .get_object::<gtk::Box>("search_results")
+            .expect("Can't find search_results in ui file.");
+
+        for hit in hits {
+            /* The same page can be requested twice if we reach the bottom again before
+             * receiving it */
+            if self.search_results.iter().any(|r| r.id == hit.id) {
+                continue;
+            }
+
+            let list = match self.search_groups.get(&hit.room) {
+                Some(list) => list.clone(),
+                None => {
+                    let list = self.build_search_group(&results, &hit.room);
+                    self.search_groups.insert(hit.room.clone(), list.clone());
+                    list
+                }
+            };
+
+            let rb = widgets::SearchResultBox::new(&hit, &self.search_highlights, &self);
+            list.add(&rb.widget());
+            self.search_results.push(hit);
+        }
+    }
+
+    fn build_search_group(&self, results: &gtk::Box, room_id: &RoomId) -> gtk::ListBox {
+        let room_name = self
+            .rooms
+            .get(room_id)
+            .and_then(|room| room.name.clone())
+            .unwrap_or_else(|| room_id.to_string());
+
+        let group = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        let name_label = gtk::Label::new(None);
+        name_label.set_markup(&format!("<b>{}</b>", markup_text(&room_name)));
+        name_label.set_ellipsize(pango::EllipsizeMode::End);
+        name_label.set_halign(gtk::Align::Start);
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::None);
+        let frame = gtk::Frame::new(None);
+        frame.set_shadow_type(gtk::ShadowType::In);
+        frame.add(&list);
+
+        group.add(&name_label);
+        group.add(&frame);
+        group.show_all();
+        results.add(&group);
+
+        list
+    }
+
+    pub fn reset_search_state(&self) {
+        let q = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("message_search_entry")
+            .expect("Can't find message_search_entry in ui file.");
+        q.set_sensitive(true);
+        let order = self
+            .ui
+            .builder
+            .get_object::<gtk::ComboBoxText>("message_search_order")
+            .expect("Can't find message_search_order in ui file.");
+        order.set_sensitive(true);
+
+        let search_stack = self
+            .ui
+            .builder
+            .get_object::<gtk::Stack>("search_stack")
+            .expect("Can't find search_stack in ui file.");
+        if self.search_results.is_empty() && !q.get_text().map_or(true, |t| t.is_empty()) {
+            let search_empty = self
+                .ui
+                .builder
+                .get_object::<gtk::Label>("search_empty")
+                .expect("Can't find search_empty in ui file.");
+            search_stack.set_visible_child(&search_empty);
+        } else {
+            let search_column = self
+                .ui
+                .builder
+                .get_object::<Column>("search_column")
+                .expect("Can't find search_column in ui file.");
+            search_stack.set_visible_child(&search_column);
+        }
+    }
+
+    /// Open the room of a search result and show the messages around it
+    pub fn open_search_result(&mut self, event_id: EventId) {
+        let room_id = unwrap_or_unit_return!(self
+            .search_results
+            .iter()
+            .find(|m| m.id.as_ref() == Some(&event_id))
+            .map(|m| m.room.clone()));
+        if !self.rooms.contains_key(&room_id) {
+            return;
+        }
+
+        self.set_active_room_by_id(room_id.clone());
+        self.load_message_context(room_id, event_id);
+    }
+
+    /// Ask the server for the messages around one, they replace the room history
+    pub fn load_message_context(&self, room_id: RoomId, event_id: EventId) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let olm_machine = self.olm_machine.clone();
+        thread::spawn(move || {
+            match search::get_message_context(
+                login_data.server_url,
+                login_data.access_token,
+                room_id,
+                event_id,
+                olm_machine,
+            ) {
+                Ok((msgs, room_id, event_id, prev_batch)) => {
+                    APPOP!(show_message_context, (msgs, room_id, event_id, prev_batch));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    pub fn show_message_context(
+        &mut self,
+        msgs: Vec<Message>,
+        room_id: RoomId,
+        event_id: EventId,
+        prev_batch: Option<String>,
+    ) {
+        if self.active_room.as_ref() != Some(&room_id) {
+            // The user already moved to another room
+            return;
+        }
+
+        let mut messages = vec![];
+        for msg in msgs.iter().filter(|msg| !msg.redacted) {
+            if let Some(mut row) = self.create_new_room_message(msg) {
+                if msg.id.as_ref() == Some(&event_id) {
+                    row.mtype = RowType::Mention;
+                    row.highlights = self.search_highlights.clone();
+                }
+                row.last_viewed = false;
+                messages.push(row);
+            }
+        }
+
+        self.clear_tmp_msgs();
+        self.timeline_context = Some(TimelineContext {
+            room_id: room_id.clone(),
+            prev_batch,
+            messages: msgs,
+        });
+        self.set_room_history(room_id, messages);
+    }
+
+    /* TODO: find a better name for this function */
+    pub fn show_context_messages_top(
+        &mut self,
+        msgs: Vec<Message>,
+        room_id: RoomId,
+        prev_batch: Option<String>,
+    ) {
+        if !self.is_showing_context_of(&room_id) {
+            return;
+        }
+
+        let mut list = vec![];
+        for item in msgs.iter().rev().filter(|item| !item.redacted) {
+            if let Some(ui_msg) = self.create_new_room_message(item) {
+                list.push(ui_msg);
+            }
+        }
+
+        if let Some(ref mut ctx) = self.timeline_context {
+            let mut msgs = msgs;
+            msgs.append(&mut ctx.messages);
+            ctx.messages = msgs;
+            ctx.prev_batch = prev_batch;
+        }
+
+        if let Some(ref mut history) = self.history {
+            history.add_old_messages_in_batch(
+                self.thread_pool.clone(),
+                self.user_info_cache.clone(),
+                list,
+            );
+        }
+    }
+
+    pub fn is_showing_context_of(&self, room_id: &RoomId) -> bool {
+        self.timeline_context
+            .as_ref()
+            .map_or(false, |ctx| ctx.room_id == *room_id)
+    }
+
+    /// Go back from the messages around a search result to the latest messages of the room
+    pub fn leave_timeline_context(&mut self) {
+        let ctx = unwrap_or_unit_return!(self.timeline_context.take());
+        self.show_latest_messages(ctx.room_id);
+    }
+
+    /// Fill the room history again with the messages we have of the room
+    pub fn show_latest_messages(&mut self, room_id: RoomId) {
+        let mut messages = vec![];
+        if let Some(room) = self.rooms.get(&room_id) {
+            for msg in room.messages.iter().filter(|msg| !msg.redacted) {
+                if let Some(row) = self.create_new_room_message(msg) {
+                    messages.push(row);
+                }
+            }
+        }
+
+        self.clear_tmp_msgs();
+        self.set_room_history(room_id, messages);
+        self.append_tmp_msgs();
+    }
+}
@@ -0,0 +1,331 @@
+use fractal_api::identifiers::{DeviceId, UserId};
+use fractal_api::r0::AccessToken;
+use fractal_api::url::Url;
+use glib::clone;
+use gtk::prelude::*;
+use log::{error, warn};
+use std::thread;
+
+use crate::app::App;
+use crate::appop::AppOp;
+use crate::backend::sso::SsoError;
+use crate::backend::{register, HandleError};
+use crate::cache;
+use crate::i18n::{i18n, i18n_k};
+use crate::passwd::PasswordStorage;
+
+impl AppOp {
+    /// The server told us it doesn't know `access_token` anymore. With a soft logout the
+    /// session can go on with a new token for the same device, and we keep its cache and
+    /// encryption keys.
+    pub fn token_invalidated(&mut self, access_token: AccessToken, soft_logout: bool) {
+        let access_token = access_token.to_string();
+        let uid = self
+            .login_data
+            .iter()
+            .chain(self.accounts.iter().map(|account| &account.login_data))
+            .find(|login_data| login_data.access_token.to_string() == access_token)
+            .map(|login_data| login_data.uid.clone());
+        // It's a token we already replaced, or of an account we logged out of
+        let uid = unwrap_or_unit_return!(uid);
+
+        if !soft_logout {
+            self.end_session(uid);
+            return;
+        }
+
+        let account = unwrap_or_unit_return!(self
+            .accounts
+            .iter_mut()
+            .find(|account| account.login_data.uid == uid));
+        // Every request that was going on fails the same way, we only ask once
+        if account.reauthenticating {
+            return;
+        }
+        account.reauthenticating = true;
+        self.ask_reauthentication(uid, None);
+    }
+
+    /// Asks the user to log in again, with the ways to log in the server supports
+    pub fn ask_reauthentication(&self, uid: UserId, error: Option<String>) {
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.login_data.uid == uid);
+        let server_url = unwrap_or_unit_return!(account)
+            .login_data
+            .server_url
+            .clone();
+
+        thread::spawn(move || {
+            let flows = register::get_login_flows(server_url).unwrap_or_else(|err| {
+                warn!("Failed to get the login flows: {:?}", err);
+                Vec::new()
+            });
+            APPOP!(show_reauthentication, (uid, error, flows));
+        });
+    }
+
+    /// Accounts logged in with single sign-on have no password, they log in again in the web
+    /// browser
+    pub fn show_reauthentication(&self, uid: UserId, error: Option<String>, flows: Vec<String>) {
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.login_data.uid == uid);
+        let server_url = unwrap_or_unit_return!(account)
+            .login_data
+            .server_url
+            .clone();
+        let parent = self
+            .ui
+            .builder
+            .get_object::<gtk::Window>("main_window")
+            .expect("Can't find main_window in ui file.");
+
+        // The password is the default until the server tells otherwise
+        let password_login =
+            flows.is_empty() || flows.iter().any(|flow| flow == "m.login.password");
+        let sso_login = flows.iter().any(|flow| flow == "m.login.sso")
+            && (!password_login || !self.has_stored_password(&server_url));
+
+        let msg = i18n_k(
+            "The session of {user} has ended",
+            &[("user", &uid.to_string())],
+        );
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&parent),
+            flags,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &msg,
+        );
+        let secondary = error.unwrap_or_else(|| match (password_login, sso_login) {
+            (true, false) => {
+                i18n("Enter your password to go on, your messages and encryption keys are kept.")
+            }
+            (true, true) => i18n(
+                "Enter your password or log in with your browser to go on, your messages and encryption keys are kept.",
+            ),
+            (false, _) => i18n(
+                "Log in with your browser to go on, your messages and encryption keys are kept.",
+            ),
+        });
+        dialog.set_property_secondary_text(Some(secondary.as_str()));
+        let logout = dialog.add_button(&i18n("Log Out"), gtk::ResponseType::Reject);
+        logout.get_style_context().add_class("destructive-action");
+        if sso_login {
+            let sso = dialog.add_button(&i18n("Log In with Browser"), gtk::ResponseType::Apply);
+            if !password_login {
+                sso.get_style_context().add_class("suggested-action");
+            }
+        }
+
+        let entry = gtk::Entry::new();
+        if password_login {
+            let button = dialog.add_button(&i18n("Log In"), gtk::ResponseType::Ok);
+            button.get_style_context().add_class("suggested-action");
+            button.set_sensitive(false);
+
+            entry.set_visibility(false);
+            entry.set_input_purpose(gtk::InputPurpose::Password);
+            if let Some(area) = dialog
+                .get_message_area()
+                .and_then(|area| area.downcast::<gtk::Box>().ok())
+            {
+                area.add(&entry);
+            }
+            entry.connect_property_text_notify(clone!(@weak button => move |w| {
+                let empty = w.get_text().map_or(true, |text| text.is_empty());
+                button.set_sensitive(!empty);
+            }));
+            entry.connect_activate(clone!(@weak button => move |_| {
+                if button.get_sensitive() {
+                    let _ = button.emit("clicked", &[]);
+                }
+            }));
+        }
+
+        dialog.connect_response(move |w, r| {
+            let uid = uid.clone();
+            match r {
+                gtk::ResponseType::Ok => {
+                    let password = entry
+                        .get_text()
+                        .map_or(String::new(), |gstr| gstr.to_string());
+                    APPOP!(reauthenticate, (uid, password));
+                }
+                gtk::ResponseType::Apply => {
+                    APPOP!(reauthenticate_sso, (uid));
+                }
+                gtk::ResponseType::Reject => {
+                    APPOP!(end_session, (uid));
+                }
+                // Nothing of the account works until we have an answer
+                _ => {
+                    let error = None;
+                    APPOP!(ask_reauthentication, (uid, error));
+                }
+            }
+            w.destroy();
+        });
+        dialog.show_all();
+    }
+
+    /// The password we keep is the one of the last login with a password
+    fn has_stored_password(&self, server_url: &Url) -> bool {
+        self.get_pass().map_or(false, |(_, password, server, _)| {
+            !password.is_empty() && server == *server_url
+        })
+    }
+
+    /// The device of the session, to log in again to it
+    fn account_device_id(&self, uid: &UserId) -> Option<Box<DeviceId>> {
+        if self.is_open_account(uid) {
+            self.device_id.clone()
+        } else {
+            cache::for_account(uid).get_st().ok().map(|st| st.device_id)
+        }
+    }
+
+    pub fn reauthenticate(&self, uid: UserId, password: String) {
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.login_data.uid == uid);
+        let server_url = unwrap_or_unit_return!(account)
+            .login_data
+            .server_url
+            .clone();
+        let device_id = self.account_device_id(&uid);
+
+        thread::spawn(move || {
+            match register::login_to_device(uid.clone(), password, server_url, device_id) {
+                Ok(access_token) => {
+                    APPOP!(reauthenticated, (uid, access_token));
+                }
+                Err(err) => {
+                    let error = Some(err.info().unwrap_or_else(|| i18n("Can’t login, try again")));
+                    APPOP!(ask_reauthentication, (uid, error));
+                }
+            }
+        });
+    }
+
+    pub fn reauthenticate_sso(&mut self, uid: UserId) {
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.login_data.uid == uid);
+        let server_url = unwrap_or_unit_return!(account)
+            .login_data
+            .server_url
+            .clone();
+        let device_id = self.account_device_id(&uid);
+
+        let listener = match self.open_sso_page(&server_url) {
+            Ok(listener) => listener,
+            Err(err) => {
+                warn!("Single sign-on failed: {:?}", err);
+                self.ask_reauthentication(uid, Some(i18n("Can’t login, try again")));
+                return;
+            }
+        };
+
+        let parent = self
+            .ui
+            .builder
+            .get_object::<gtk::Window>("main_window")
+            .expect("Can't find main_window in ui file.");
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&parent),
+            flags,
+            gtk::MessageType::Info,
+            gtk::ButtonsType::Cancel,
+            &i18n("Waiting for the browser"),
+        );
+        let secondary = i18n_k(
+            "Log in as {user} in your browser to go on.",
+            &[("user", &uid.to_string())],
+        );
+        dialog.set_property_secondary_text(Some(secondary.as_str()));
+        dialog.connect_response(|_, _| {
+            APPOP!(cancel_sso);
+        });
+        dialog.show_all();
+        self.start_sso_wait(&listener, Some(dialog));
+
+        thread::spawn(move || {
+            let token = listener.wait_for_token();
+            if let Err(SsoError::Cancelled) = token {
+                let error = None;
+                APPOP!(ask_reauthentication, (uid, error));
+                return;
+            }
+            APPOP!(end_sso_wait);
+
+            let query = token
+                .map_err(|err| {
+                    warn!("Single sign-on failed: {:?}", err);
+                    None
+                })
+                .and_then(|token| {
+                    register::login_with_token(token, server_url.clone(), device_id)
+                        .map_err(|err| err.info())
+                });
+            let error = match query {
+                Ok((logged_uid, access_token, _)) if logged_uid == uid => {
+                    APPOP!(reauthenticated, (uid, access_token));
+                    return;
+                }
+                // The browser may be logged in to another account of the server
+                Ok((logged_uid, access_token, _)) => {
+                    if let Err(err) = register::logout(server_url, access_token) {
+                        err.handle_error();
+                    }
+                    i18n_k(
+                        "You logged in as {other}, log in as {user} to go on",
+                        &[
+                            ("other", &logged_uid.to_string()),
+                            ("user", &uid.to_string()),
+                        ],
+                    )
+                }
+                Err(info) => info.unwrap_or_else(|| i18n("Can’t login, try again")),
+            };
+            let error = Some(error);
+            APPOP!(ask_reauthentication, (uid, error));
+        });
+    }
+
+    pub fn reauthenticated(&mut self, uid: UserId, access_token: AccessToken) {
+        let account = unwrap_or_unit_return!(self
+            .accounts
+            .iter_mut()
+            .find(|account| account.login_data.uid == uid));
+        account.reauthenticating = false;
+        account.login_data.access_token = access_token.clone();
+        let login_data = account.login_data.clone();
+
+        let stored = self.store_token(
+            uid.clone(),
+            access_token.clone(),
+            login_data.server_url,
+            login_data.identity_url,
+        );
+        if stored.is_err() {
+            error!("Can't store the token");
+        }
+
+        if self.is_open_account(&uid) {
+            if let Some(login_data) = self.login_data.as_mut() {
+                login_data.access_token = access_token;
+            }
+            self.sync(false);
+        } else {
+            self.sync_account(uid);
+        }
+    }
+}
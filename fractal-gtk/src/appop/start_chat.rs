@@ -11,7 +11,7 @@ use crate::appop::AppOp;
 use crate::appop::SearchType;
 use crate::backend::HandleError;
 
-use crate::types::{Room, RoomMembership, RoomTag};
+use crate::types::{Member, Room, RoomMembership, RoomTag};
 
 impl AppOp {
     pub fn start_chat(&mut self) {
@@ -19,8 +19,13 @@ impl AppOp {
             return;
         }
 
+        let user = self.invite_list[0].0.clone();
+        self.close_direct_chat_dialog();
+        self.start_chat_with(user);
+    }
+
+    pub fn start_chat_with(&mut self, user: Member) {
         let login_data = unwrap_or_unit_return!(self.login_data.clone());
-        let user = self.invite_list[0].clone();
 
         let server_name: Box<ServerName> = HostAndPort::try_from(&login_data.server_url)
             .expect("The server domain should have been validated")
@@ -30,7 +35,7 @@ impl AppOp {
         let internal_id = RoomId::new(&server_name);
 
         let int_id = internal_id.clone();
-        let member = user.0.clone();
+        let member = user.clone();
         thread::spawn(move || {
             match room::direct_chat(
                 login_data.server_url,
@@ -49,10 +54,8 @@ impl AppOp {
             }
         });
 
-        self.close_direct_chat_dialog();
-
         let fakeroom = Room {
-            name: user.0.alias,
+            name: user.alias,
             direct: true,
             ..Room::new(internal_id.clone(), RoomMembership::Joined(RoomTag::None))
         };
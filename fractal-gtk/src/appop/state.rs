@@ -34,6 +34,7 @@ impl AppOp {
                 "chat"
             }
             AppState::Directory => "directory",
+            AppState::Search => "search",
             AppState::Loading => "loading",
             AppState::AccountSettings => "account-settings",
             AppState::RoomSettings => "room-settings",
@@ -50,6 +51,7 @@ impl AppOp {
         let bar_name = match self.state {
             AppState::Login => "login",
             AppState::Directory => "back",
+            AppState::Search => "search",
             AppState::Loading => "loading",
             AppState::AccountSettings => "account-settings",
             AppState::RoomSettings => "room-settings",
@@ -66,6 +68,7 @@ impl AppOp {
         //set focus for views
         let widget_focus = match self.state {
             AppState::Directory => "directory_search_entry",
+            AppState::Search => "message_search_entry",
             _ => "",
         };
 
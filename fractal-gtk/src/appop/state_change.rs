@@ -0,0 +1,123 @@
+use crate::i18n::i18n_k;
+
+use crate::appop::AppOp;
+use crate::types::{MembershipChange, Message, StateChange};
+use fractal_api::identifiers::UserId;
+
+impl AppOp {
+    fn member_name(&self, msg: &Message, uid: &UserId) -> Option<String> {
+        self.rooms
+            .get(&msg.room)?
+            .members
+            .get(uid)
+            .and_then(|member| member.alias.clone())
+    }
+
+    /// The sentence shown in the room history for a state event
+    pub fn describe_state_change(&self, msg: &Message, change: &StateChange) -> String {
+        let sender = self
+            .member_name(msg, &msg.sender)
+            .unwrap_or_else(|| msg.sender.to_string());
+        let sender = sender.as_str();
+
+        match change {
+            StateChange::Member {
+                target,
+                name,
+                change,
+                reason,
+            } => {
+                let target = name
+                    .clone()
+                    .or_else(|| self.member_name(msg, target))
+                    .unwrap_or_else(|| target.to_string());
+                let target = target.as_str();
+                let args = [("sender", sender), ("target", target)];
+
+                let description = match change {
+                    MembershipChange::Joined => i18n_k("{target} joined the room", &args),
+                    MembershipChange::Left => i18n_k("{target} left the room", &args),
+                    MembershipChange::Invited => i18n_k("{sender} invited {target}", &args),
+                    MembershipChange::InviteRejected => {
+                        i18n_k("{target} rejected the invitation", &args)
+                    }
+                    MembershipChange::InviteRevoked => {
+                        i18n_k("{sender} revoked the invitation of {target}", &args)
+                    }
+                    MembershipChange::Kicked => i18n_k("{sender} removed {target}", &args),
+                    MembershipChange::Banned => i18n_k("{sender} banned {target}", &args),
+                    MembershipChange::Unbanned => i18n_k("{sender} unbanned {target}", &args),
+                    MembershipChange::Renamed(Some(old)) => i18n_k(
+                        "{old} changed their name to {target}",
+                        &[("old", old), ("target", target)],
+                    ),
+                    MembershipChange::Renamed(None) => i18n_k("{target} set their name", &args),
+                    MembershipChange::AvatarChanged => {
+                        i18n_k("{target} changed their avatar", &args)
+                    }
+                };
+
+                match reason.as_ref().filter(|reason| !reason.is_empty()) {
+                    Some(reason) => i18n_k(
+                        "{description}: {reason}",
+                        &[("description", &description), ("reason", reason)],
+                    ),
+                    None => description,
+                }
+            }
+            StateChange::Name(name) if name.is_empty() => {
+                i18n_k("{sender} removed the room name", &[("sender", sender)])
+            }
+            StateChange::Name(name) => i18n_k(
+                "{sender} changed the room name to {name}",
+                &[("sender", sender), ("name", name)],
+            ),
+            StateChange::Topic(topic) if topic.is_empty() => {
+                i18n_k("{sender} removed the topic", &[("sender", sender)])
+            }
+            StateChange::Topic(topic) => i18n_k(
+                "{sender} changed the topic to “{topic}”",
+                &[("sender", sender), ("topic", topic)],
+            ),
+            StateChange::Avatar => {
+                i18n_k("{sender} changed the room avatar", &[("sender", sender)])
+            }
+            StateChange::PowerLevels(levels) if levels.is_empty() => i18n_k(
+                "{sender} changed the room permissions",
+                &[("sender", sender)],
+            ),
+            StateChange::PowerLevels(levels) => {
+                let changes = levels
+                    .iter()
+                    .map(|(uid, level)| {
+                        let name = self
+                            .member_name(msg, uid)
+                            .unwrap_or_else(|| uid.to_string());
+                        i18n_k(
+                            "{name} to {level}",
+                            &[("name", &name), ("level", &level.to_string())],
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                i18n_k(
+                    "{sender} changed the power level of {changes}",
+                    &[("sender", sender), ("changes", &changes)],
+                )
+            }
+        }
+    }
+
+    pub fn set_show_state_events(&mut self, show: bool) {
+        if self.show_state_events == show {
+            return;
+        }
+        self.show_state_events = show;
+
+        if let Some(room_id) = self.active_room.clone() {
+            if self.timeline_context.is_none() {
+                self.show_latest_messages(room_id);
+            }
+        }
+    }
+}
@@ -1,14 +1,40 @@
+use gio::prelude::*;
+use glib::source::Continue;
 use log::info;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::i18n::i18n;
+use crate::i18n::{i18n, ni18n_f};
 
 use crate::app::App;
 use crate::appop::AppOp;
 use crate::backend::{
-    sync::{self, RoomElement, SyncRet},
+    sync::{self, RoomElement, SyncError, SyncRet},
     HandleError,
 };
+use fractal_api::error::ErrorCode;
+use fractal_api::identifiers::UserId;
+
+/// A sync that failed, waiting to be tried again
+pub struct SyncRetry {
+    /// How many syncs in a row failed
+    tries: u32,
+    /// `None` while we are offline, the network coming back tries again
+    due: Option<Instant>,
+    timer: Option<glib::source::SourceId>,
+}
+
+impl SyncRetry {
+    fn stop_timer(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            glib::source::source_remove(timer);
+        }
+    }
+}
+
+pub fn network_available() -> bool {
+    gio::NetworkMonitor::get_default().map_or(true, |monitor| monitor.get_network_available())
+}
 
 impl AppOp {
     pub fn initial_sync(&self, show: bool) {
@@ -19,7 +45,7 @@ impl AppOp {
         }
     }
 
-    pub fn sync(&mut self, initial: bool, number_tries: u64) {
+    pub fn sync(&mut self, initial: bool) {
         if let (Some(login_data), false) = (self.login_data.clone(), self.syncing) {
             self.syncing = true;
             // for the initial sync we set the since to None to avoid long syncing
@@ -28,132 +54,243 @@ impl AppOp {
             // https://matrix.org/docs/spec/client_server/latest.html#syncing
             let join_to_room = self.join_to_room.clone();
             let since = self.since.clone().filter(|_| !initial);
+            let olm_machine = self.olm_machine.clone();
             thread::spawn(move || {
-                match sync::sync(
+                let uid = login_data.uid.clone();
+                let query = sync::sync(
                     login_data.server_url,
                     login_data.access_token,
                     login_data.uid,
+                    olm_machine,
                     join_to_room,
                     since,
                     initial,
-                    number_tries,
-                ) {
-                    Ok(SyncRet::NoSince { rooms, next_batch }) => {
-                        match rooms {
-                            Ok((rooms, default)) => {
-                                let clear_room_list = true;
-                                APPOP!(set_rooms, (rooms, clear_room_list));
-                                // Open the newly joined room
-                                let jtr = default.as_ref().map(|r| r.id.clone());
-                                APPOP!(set_join_to_room, (jtr));
-                                if let Some(room) = default {
-                                    let room_id = room.id;
-                                    APPOP!(set_active_room_by_id, (room_id));
-                                }
-                            }
-                            Err(err) => {
-                                err.handle_error();
-                            }
-                        };
+                );
+                APPOP!(sync_response, (uid, query));
+            });
+        }
+    }
 
-                        info!("SYNC");
-                        let s = Some(next_batch);
-                        APPOP!(synced, (s));
-                    }
-                    Ok(SyncRet::WithSince {
-                        update_rooms,
-                        room_messages,
-                        room_notifications,
-                        update_rooms_2,
-                        other,
-                        next_batch,
-                    }) => {
-                        match update_rooms {
-                            Ok(rooms) => {
-                                let clear_room_list = false;
-                                APPOP!(set_rooms, (rooms, clear_room_list));
-                            }
-                            Err(err) => {
-                                err.handle_error();
-                            }
-                        }
+    /// Applies what the sync brought, unless we switched to another account meanwhile
+    pub fn sync_response(&mut self, uid: UserId, query: Result<SyncRet, SyncError>) {
+        if self.login_data.as_ref().map(|login_data| &login_data.uid) != Some(&uid) {
+            return;
+        }
 
-                        match room_messages {
-                            Ok(msgs) => {
-                                APPOP!(show_room_messages, (msgs));
-                            }
-                            Err(err) => {
-                                err.handle_error();
-                            }
+        match query {
+            Ok(SyncRet::NoSince { rooms, next_batch }) => {
+                match rooms {
+                    Ok((rooms, default)) => {
+                        let clear_room_list = true;
+                        self.set_rooms(rooms, clear_room_list);
+                        // Open the newly joined room
+                        self.set_join_to_room(default.as_ref().map(|r| r.id.clone()));
+                        if let Some(room) = default {
+                            self.set_active_room_by_id(room.id);
                         }
+                    }
+                    Err(err) => {
+                        err.handle_error();
+                    }
+                };
 
-                        match update_rooms_2 {
-                            Ok(rooms) => {
-                                let clear_room_list = false;
-                                APPOP!(set_rooms, (rooms, clear_room_list));
-                            }
-                            Err(err) => {
-                                err.handle_error();
-                            }
-                        }
+                info!("SYNC");
+                self.synced(Some(next_batch));
+            }
+            Ok(SyncRet::WithSince {
+                update_rooms,
+                room_messages,
+                room_notifications,
+                update_rooms_2,
+                other,
+                next_batch,
+            }) => {
+                match update_rooms {
+                    Ok(rooms) => {
+                        let clear_room_list = false;
+                        self.set_rooms(rooms, clear_room_list);
+                    }
+                    Err(err) => {
+                        err.handle_error();
+                    }
+                }
 
-                        for (room_id, unread_notifications) in room_notifications {
-                            let r = room_id;
-                            let n = unread_notifications.notification_count;
-                            let h = unread_notifications.highlight_count;
-                            APPOP!(set_room_notifications, (r, n, h));
-                        }
+                match room_messages {
+                    Ok(msgs) => {
+                        self.show_room_messages(msgs);
+                    }
+                    Err(err) => {
+                        err.handle_error();
+                    }
+                }
+
+                match update_rooms_2 {
+                    Ok(rooms) => {
+                        let clear_room_list = false;
+                        self.set_rooms(rooms, clear_room_list);
+                    }
+                    Err(err) => {
+                        err.handle_error();
+                    }
+                }
 
-                        match other {
-                            Ok(other) => {
-                                for room_element in other {
-                                    match room_element {
-                                        RoomElement::Name(room_id, name) => {
-                                            let n = Some(name);
-                                            APPOP!(room_name_change, (room_id, n));
-                                        }
-                                        RoomElement::Topic(room_id, topic) => {
-                                            let t = Some(topic);
-                                            APPOP!(room_topic_change, (room_id, t));
-                                        }
-                                        RoomElement::NewAvatar(room_id) => {
-                                            APPOP!(new_room_avatar, (room_id));
-                                        }
-                                        RoomElement::MemberEvent(event) => {
-                                            APPOP!(room_member_event, (event));
-                                        }
-                                        RoomElement::RemoveMessage(room_id, msg_id) => {
-                                            APPOP!(remove_message, (room_id, msg_id));
-                                        }
-                                    }
+                for (room_id, unread_notifications) in room_notifications {
+                    let n = unread_notifications.notification_count;
+                    let h = unread_notifications.highlight_count;
+                    self.set_room_notifications(room_id, n, h);
+                }
+
+                match other {
+                    Ok(other) => {
+                        for room_element in other {
+                            match room_element {
+                                RoomElement::Name(room_id, name) => {
+                                    self.room_name_change(room_id, Some(name));
+                                }
+                                RoomElement::Topic(room_id, topic) => {
+                                    self.room_topic_change(room_id, Some(topic));
+                                }
+                                RoomElement::NewAvatar(room_id) => {
+                                    self.new_room_avatar(room_id);
+                                }
+                                RoomElement::MemberEvent(event) => {
+                                    self.room_member_event(event);
+                                }
+                                RoomElement::RemoveMessage(room_id, msg_id) => {
+                                    self.remove_message(room_id, msg_id);
+                                }
+                                RoomElement::Tombstone(room_id, replacement) => {
+                                    self.room_replaced(room_id, replacement);
                                 }
-                            }
-                            Err(err) => {
-                                err.handle_error();
                             }
                         }
-
-                        info!("SYNC");
-                        let s = Some(next_batch);
-                        APPOP!(synced, (s));
                     }
                     Err(err) => {
                         err.handle_error();
                     }
                 }
-            });
+
+                info!("SYNC");
+                self.synced(Some(next_batch));
+            }
+            Err(err) => {
+                err.handle_error();
+                match err.matrix_error().map(|err| &err.kind) {
+                    // The account was told already, the sync goes on once it has a new token
+                    Some(ErrorCode::UnknownToken { .. }) => {
+                        self.syncing = false;
+                        let reauthenticating = self
+                            .accounts
+                            .iter()
+                            .find(|account| account.login_data.uid == uid)
+                            .map_or(false, |account| account.reauthenticating);
+                        // Unless this sync started before we got the new token
+                        if !reauthenticating {
+                            self.sync(false);
+                        }
+                    }
+                    _ => self.sync_error(err.retry_after()),
+                }
+            }
         }
     }
 
     pub fn synced(&mut self, since: Option<String>) {
         self.syncing = false;
         self.since = since;
-        self.sync(false, 0);
+        self.cancel_sync_retry();
+        // Everything the sync brought is already in the rooms, save it with its since
+        self.cache_rooms();
+        // We are online, send what couldn't go before
+        self.dequeue_message();
+        self.sync(false);
         self.initial_sync(false);
     }
 
-    pub fn sync_error(&mut self, number_tries: u64) {
+    /// Waits longer after each failure in a row before trying the sync again, or for the
+    /// network to come back when we are offline
+    pub fn sync_error(&mut self, retry_after: Option<Duration>) {
         self.syncing = false;
-        self.sync(false, number_tries);
+        let tries = self.sync_retry.take().map_or(0, |mut retry| {
+            retry.stop_timer();
+            retry.tries
+        }) + 1;
+        self.sync_retry = Some(SyncRetry {
+            tries,
+            due: None,
+            timer: None,
+        });
+
+        if network_available() {
+            self.schedule_sync_retry(sync::retry_delay(tries, retry_after));
+        } else {
+            self.inapp_retry_notify(&i18n("Offline, waiting for the network"));
+        }
+    }
+
+    fn schedule_sync_retry(&mut self, delay: Duration) {
+        let retry = unwrap_or_unit_return!(self.sync_retry.as_mut());
+        retry.stop_timer();
+        retry.due = Some(Instant::now() + delay);
+        // Ticks every second to count down in the notification
+        retry.timer = Some(gtk::timeout_add_seconds(1, || {
+            APPOP!(sync_retry_tick);
+            Continue(true)
+        }));
+        self.sync_retry_tick();
+    }
+
+    pub fn sync_retry_tick(&mut self) {
+        let due = unwrap_or_unit_return!(self.sync_retry.as_ref().and_then(|retry| retry.due));
+        let now = Instant::now();
+        if now >= due {
+            self.retry_sync();
+        } else {
+            let seconds = (due - now).as_secs() as u32 + 1;
+            self.inapp_retry_notify(&ni18n_f(
+                "Can't reach the server, reconnecting in {} second",
+                "Can't reach the server, reconnecting in {} seconds",
+                seconds,
+                &[&seconds.to_string()],
+            ));
+        }
+    }
+
+    /// Tries the failed sync again without waiting any longer
+    pub fn retry_sync(&mut self) {
+        let retry = unwrap_or_unit_return!(self.sync_retry.as_mut());
+        retry.stop_timer();
+        retry.due = None;
+        self.inapp_notify(&i18n("Reconnecting…"));
+        // Without a since it's still the initial sync that failed
+        let initial = self.since.is_none();
+        self.sync(initial);
+    }
+
+    pub fn cancel_sync_retry(&mut self) {
+        if let Some(mut retry) = self.sync_retry.take() {
+            retry.stop_timer();
+            self.hide_inapp_notify();
+        }
+    }
+
+    /// There's no use in trying while we are offline, and no reason to wait once we are
+    /// back online
+    pub fn network_changed(&mut self, available: bool) {
+        if available {
+            self.retry_account_syncs();
+        }
+
+        let retry = unwrap_or_unit_return!(self.sync_retry.as_mut());
+        if available {
+            // Otherwise we are already waiting for a retry, or the server to answer one
+            if retry.due.is_none() && !self.syncing {
+                self.retry_sync();
+            }
+        } else {
+            retry.stop_timer();
+            retry.due = None;
+            self.inapp_retry_notify(&i18n("Offline, waiting for the network"));
+        }
     }
 }
@@ -0,0 +1,206 @@
+use crate::backend::{room, HandleError};
+use crate::i18n::{i18n, i18n_k};
+use fractal_api::identifiers::RoomId;
+use gtk::prelude::*;
+use std::thread;
+
+use crate::app::App;
+use crate::appop::AppOp;
+use crate::types::{Message, Room};
+
+impl AppOp {
+    /// Confirms the upgrade of a room to the version the server recommends
+    pub fn ask_room_upgrade(&self, room_id: RoomId, version: Option<String>) -> Option<()> {
+        let room = self.rooms.get(&room_id)?;
+        let version = match version {
+            Some(version) if room.room_version.as_ref() != Some(&version) => version,
+            Some(_) => {
+                self.show_error(i18n("The room already uses the recommended version"));
+                return None;
+            }
+            None => {
+                self.show_error(i18n("The server doesn’t recommend any room version"));
+                return None;
+            }
+        };
+
+        let parent = self
+            .ui
+            .builder
+            .get_object::<gtk::Window>("main_window")
+            .expect("Can't find main_window in ui file.");
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let msg = i18n_k(
+            "Upgrade this room to version {version}?",
+            &[("version", &version)],
+        );
+        let dialog = gtk::MessageDialog::new(
+            Some(&parent),
+            flags,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &msg,
+        );
+        dialog.set_property_secondary_text(Some(
+            i18n("A new room will replace this one, its members will have to join it.").as_str(),
+        ));
+        dialog.add_button(&i18n("Cancel"), gtk::ResponseType::Cancel);
+        let button = dialog.add_button(&i18n("Upgrade"), gtk::ResponseType::Ok);
+        button.get_style_context().add_class("suggested-action");
+
+        dialog.connect_response(move |w, r| {
+            if let gtk::ResponseType::Ok = r {
+                let room_id = room_id.clone();
+                let version = version.clone();
+                APPOP!(upgrade_room, (room_id, version));
+            }
+            w.destroy();
+        });
+        dialog.show_all();
+
+        None
+    }
+
+    pub fn upgrade_room(&self, room_id: RoomId, version: String) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+
+        thread::spawn(move || {
+            let query = room::upgrade_room(
+                login_data.server_url,
+                login_data.access_token,
+                room_id,
+                version,
+            );
+            match query {
+                Ok((room_id, replacement)) => {
+                    APPOP!(room_upgraded, (room_id, replacement));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    /// The server already joined us to the new room, it is opened once the sync brings it
+    pub fn room_upgraded(&mut self, room_id: RoomId, replacement: RoomId) {
+        self.room_replaced(room_id, replacement.clone());
+        if self.rooms.contains_key(&replacement) {
+            self.set_active_room_by_id(replacement);
+        } else {
+            self.join_to_room = Some(replacement);
+        }
+    }
+
+    pub fn room_replaced(&mut self, room_id: RoomId, replacement: RoomId) {
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            room.replacement = Some(replacement);
+        }
+        if self.active_room.as_ref() == Some(&room_id) {
+            self.show_replaced_room_banner();
+        }
+        self.hide_replaced_rooms();
+    }
+
+    /// Takes the place of the message entry in rooms that were upgraded
+    pub fn show_replaced_room_banner(&self) {
+        let msg_entry_stack = self
+            .ui
+            .sventry_box
+            .clone()
+            .downcast::<gtk::Stack>()
+            .unwrap();
+        self.ui.sventry.view.set_editable(false);
+        msg_entry_stack.set_visible_child_name("Replaced Room");
+    }
+
+    pub fn open_replacement_room(&mut self) -> Option<()> {
+        let room = self.rooms.get(self.active_room.as_ref()?)?;
+        let replacement = room.replacement.clone()?;
+        let joined = self
+            .rooms
+            .get(&replacement)
+            .map_or(false, |room| room.membership.is_joined());
+
+        if joined {
+            self.set_active_room_by_id(replacement);
+        } else {
+            self.join_room(replacement.into());
+        }
+
+        None
+    }
+
+    /// Whether we already are in the room that replaced this one
+    pub fn is_room_replaced(&self, room: &Room) -> bool {
+        room.replacement
+            .as_ref()
+            .and_then(|id| self.rooms.get(id))
+            .map_or(false, |replacement| replacement.membership.is_joined())
+    }
+
+    /// Upgraded rooms leave the room list once we are in their replacement, we keep them
+    /// around to load their history from the new room
+    pub fn hide_replaced_rooms(&mut self) {
+        let replaced: Vec<RoomId> = self
+            .rooms
+            .values()
+            .filter(|room| self.is_room_replaced(room))
+            .map(|room| room.id.clone())
+            .collect();
+        for room_id in replaced {
+            self.roomlist.remove_room(room_id);
+        }
+    }
+
+    /// Goes on with the history of the room an upgraded one replaced, once we got to its start
+    pub fn load_predecessor_messages(&self, room_id: RoomId) -> Option<()> {
+        let login_data = self.login_data.clone()?;
+        let room = self.rooms.get(&room_id)?;
+        let history_room = room.prev_batch_room.as_ref().unwrap_or(&room_id);
+        let predecessor = self
+            .rooms
+            .get(history_room)?
+            .predecessor
+            .clone()
+            .filter(|predecessor| predecessor.room_id != room_id)?;
+        let olm_machine = self.olm_machine.clone();
+
+        thread::spawn(move || {
+            let predecessor_id = predecessor.room_id.clone();
+            let query = room::get_predecessor_messages(
+                login_data.server_url,
+                login_data.access_token,
+                predecessor,
+                olm_machine,
+            );
+            match query {
+                Ok((msgs, _, prev_batch)) => {
+                    APPOP!(
+                        show_predecessor_messages_top,
+                        (msgs, room_id, predecessor_id, prev_batch)
+                    );
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+
+        None
+    }
+
+    pub fn show_predecessor_messages_top(
+        &mut self,
+        msgs: Vec<Message>,
+        room_id: RoomId,
+        predecessor_id: RoomId,
+        prev_batch: Option<String>,
+    ) {
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            room.prev_batch_room = Some(predecessor_id);
+            room.prev_batch = None;
+        }
+        self.show_room_messages_top(msgs, room_id, prev_batch);
+    }
+}
@@ -0,0 +1,372 @@
+use fractal_api::error::MatrixError;
+use fractal_api::events::room::RoomEventContent;
+use fractal_api::events::RoomEvent;
+use fractal_api::identifiers::{DeviceId, RoomId, UserId};
+use fractal_api::r0::keys::claim_keys::request as claim_keys;
+use fractal_api::r0::keys::claim_keys::Body as ClaimKeysBody;
+use fractal_api::r0::keys::claim_keys::Parameters as ClaimKeysParameters;
+use fractal_api::r0::keys::claim_keys::Response as ClaimKeysResponse;
+use fractal_api::r0::keys::get_keys::request as get_keys;
+use fractal_api::r0::keys::get_keys::Body as GetKeysBody;
+use fractal_api::r0::keys::get_keys::Parameters as GetKeysParameters;
+use fractal_api::r0::keys::get_keys::Response as GetKeysResponse;
+use fractal_api::r0::keys::upload_keys::request as upload_keys_req;
+use fractal_api::r0::keys::upload_keys::Body as UploadKeysBody;
+use fractal_api::r0::keys::upload_keys::Parameters as UploadKeysParameters;
+use fractal_api::r0::keys::upload_keys::Response as UploadKeysResponse;
+use fractal_api::r0::sync::get_joined_members::request as get_joined_members;
+use fractal_api::r0::sync::get_joined_members::Parameters as JoinedMembersParameters;
+use fractal_api::r0::sync::get_joined_members::Response as JoinedMembersResponse;
+use fractal_api::r0::sync::sync_events::Response as SyncResponse;
+use fractal_api::r0::to_device::send_event_to_device::request as send_event_to_device;
+use fractal_api::r0::to_device::send_event_to_device::Body as SendToDeviceBody;
+use fractal_api::r0::to_device::send_event_to_device::Parameters as SendToDeviceParameters;
+use fractal_api::r0::AccessToken;
+use fractal_api::reqwest::Error as ReqwestError;
+use fractal_api::url::Url;
+use log::{error, warn};
+use rand::Rng;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::crypto::{CryptoError, OlmMachine, SharedOlmMachine};
+use crate::error::Error;
+use crate::globals;
+use crate::util::account_dir;
+
+use super::{execute, HandleError};
+
+fn store_path(user_id: &UserId) -> PathBuf {
+    globals::DATA_PATH
+        .join(account_dir(user_id))
+        .join("crypto.json")
+}
+
+fn store(machine: &OlmMachine) {
+    if let Err(err) = machine.store(&store_path(machine.user_id())) {
+        error!("Can't store the encryption keys: {:?}", err);
+    }
+}
+
+/// Loads the encryption keys of the device, creating them the first time.
+/// The stored keys are never replaced by new ones when they can't be read.
+pub fn load_machine(
+    user_id: UserId,
+    device_id: Box<DeviceId>,
+) -> Result<SharedOlmMachine, CryptoError> {
+    let path = store_path(&user_id);
+    if let Some(Err(err)) = path.parent().map(fs::create_dir_all) {
+        error!("Can't create the data directory: {:?}", err);
+    }
+
+    // The keys of the only account we had before were at the top of the data directory
+    let single_account_path = globals::DATA_PATH.join("crypto.json");
+    if single_account_path.exists() && !path.exists() {
+        if let Err(err) = fs::rename(single_account_path, &path) {
+            error!("Can't move the encryption keys: {:?}", err);
+        }
+    }
+
+    let machine = OlmMachine::load(&path, user_id, device_id)?;
+    store(&machine);
+
+    Ok(Arc::new(Mutex::new(machine)))
+}
+
+/// The keys belong to a device, which doesn't exist anymore after a logout
+pub fn remove_machine(user_id: &UserId) {
+    let path = store_path(user_id);
+    if path.exists() && fs::remove_file(path).is_err() {
+        error!("Error removing the encryption keys");
+    }
+}
+
+#[derive(Debug)]
+pub struct UploadKeysError(Error);
+
+impl<T: Into<Error>> From<T> for UploadKeysError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for UploadKeysError {}
+
+/// Publishes the keys of the device and new one-time keys, if needed
+pub fn upload_keys(
+    base: Url,
+    access_token: AccessToken,
+    machine: &SharedOlmMachine,
+) -> Result<(), UploadKeysError> {
+    let body = {
+        let mut machine = machine.lock().unwrap();
+        if !machine.should_upload_keys() {
+            return Ok(());
+        }
+
+        let (device_keys, one_time_keys) = machine.keys_for_upload();
+        // The private part of the new one-time keys has to be kept before
+        // anyone can use them
+        store(&machine);
+
+        UploadKeysBody {
+            device_keys,
+            one_time_keys,
+        }
+    };
+
+    let params = UploadKeysParameters { access_token };
+    let request = upload_keys_req(base, &params, &body)?;
+    let response: UploadKeysResponse = execute::<UploadKeysError>(request)?.json()?;
+
+    let mut machine = machine.lock().unwrap();
+    machine.receive_keys_upload_response(&response.one_time_key_counts);
+    store(&machine);
+
+    Ok(())
+}
+
+/// Handles the encryption related parts of a sync response: the room keys
+/// sent to this device, the device list updates, the state changes that
+/// rotate the group sessions and the one-time key count.
+/// The encrypted events of the timelines are replaced by their decrypted
+/// version, so they are handled like any other event.
+pub fn decrypt_sync_response(
+    base: Url,
+    access_token: AccessToken,
+    machine: &SharedOlmMachine,
+    response: &mut SyncResponse,
+) {
+    {
+        let mut machine = machine.lock().unwrap();
+        if let Some(device_lists) = response.device_lists.as_ref() {
+            machine.receive_device_list_changes(&device_lists.changed, &device_lists.left);
+        }
+        // Room keys are only accepted from devices we know the keys of
+        if let Some(to_device) = response.to_device.as_ref() {
+            machine.update_tracked_users(
+                to_device
+                    .events
+                    .iter()
+                    .filter(|ev| ev.event_type == "m.room.encrypted")
+                    .map(|ev| &ev.sender),
+            );
+        }
+    }
+    if let Err(err) = query_keys::<Error>(base, access_token, machine) {
+        warn!("Can't query the device keys: {:?}", err);
+    }
+
+    let mut machine = machine.lock().unwrap();
+    if let Some(to_device) = response.to_device.as_ref() {
+        machine.receive_to_device_events(&to_device.events);
+    }
+    machine.update_one_time_key_count(&response.device_one_time_keys_count);
+
+    for room_id in response.rooms.leave.keys() {
+        machine.discard_group_session(room_id);
+    }
+
+    for (room_id, room) in response.rooms.join.iter_mut() {
+        for event in room.state.events.iter() {
+            machine.receive_room_state_event(room_id, &event.content);
+        }
+        for event in room
+            .timeline
+            .events
+            .iter()
+            .filter(|ev| ev.state_key.is_some())
+        {
+            machine.receive_room_state_event(room_id, &event.content);
+        }
+
+        for event in room.timeline.events.iter_mut() {
+            if let RoomEventContent::Encrypted(_) = event.content {
+                match decrypt_event(&mut machine, room_id, event.json()) {
+                    Ok(decrypted) => *event = decrypted,
+                    Err(err) => warn!("Can't decrypt event {}: {:?}", event.event_id, err),
+                }
+            }
+        }
+    }
+
+    store(&machine);
+}
+
+fn decrypt_event(
+    machine: &mut OlmMachine,
+    room_id: &RoomId,
+    event: &JsonValue,
+) -> Result<RoomEvent, CryptoError> {
+    let decrypted = machine.decrypt_room_event(room_id, event)?;
+    RoomEvent::try_from(decrypted).map_err(Into::into)
+}
+
+/// Replaces the encrypted events of a list of room events, as returned by
+/// the `messages` endpoint, by their decrypted version
+pub fn decrypt_room_events(
+    machine: &SharedOlmMachine,
+    room_id: &RoomId,
+    events: Vec<JsonValue>,
+) -> Vec<JsonValue> {
+    let mut machine = machine.lock().unwrap();
+
+    let events = events
+        .into_iter()
+        .map(|event| {
+            if event["type"] != "m.room.encrypted" {
+                return event;
+            }
+
+            machine
+                .decrypt_room_event(room_id, &event)
+                .unwrap_or_else(|err| {
+                    warn!("Can't decrypt event {}: {:?}", event["event_id"], err);
+                    event
+                })
+        })
+        .collect();
+
+    store(&machine);
+
+    events
+}
+
+#[derive(Debug)]
+pub enum EncryptEventError {
+    Reqwest(ReqwestError),
+    Matrix(MatrixError),
+    Crypto(CryptoError),
+}
+
+impl From<ReqwestError> for EncryptEventError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Reqwest(err)
+    }
+}
+
+impl From<MatrixError> for EncryptEventError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
+impl From<CryptoError> for EncryptEventError {
+    fn from(err: CryptoError) -> Self {
+        Self::Crypto(err)
+    }
+}
+
+impl HandleError for EncryptEventError {}
+
+/// Encrypts the content of an event for a room, making sure every device of
+/// the room got the key of the group session first
+pub fn encrypt_room_event(
+    base: Url,
+    access_token: AccessToken,
+    machine: &SharedOlmMachine,
+    room_id: &RoomId,
+    event_type: &str,
+    content: &JsonValue,
+) -> Result<JsonValue, EncryptEventError> {
+    share_group_session(base, access_token, machine, room_id)?;
+
+    let mut machine = machine.lock().unwrap();
+    let encrypted = machine.encrypt_room_event(room_id, event_type, content)?;
+    store(&machine);
+
+    Ok(encrypted)
+}
+
+/// Updates the devices of the tracked users whose device list changed
+fn query_keys<E>(base: Url, access_token: AccessToken, machine: &SharedOlmMachine) -> Result<(), E>
+where
+    E: From<ReqwestError> + From<MatrixError>,
+{
+    let users_for_key_query = machine.lock().unwrap().users_for_key_query();
+    if users_for_key_query.is_empty() {
+        return Ok(());
+    }
+
+    let params = GetKeysParameters { access_token };
+    let body = GetKeysBody {
+        timeout: Some(10000),
+        device_keys: users_for_key_query
+            .into_iter()
+            .map(|user_id| (user_id, Vec::new()))
+            .collect(),
+        token: None,
+    };
+    let request = get_keys(base, &params, &body)?;
+    let response: GetKeysResponse = execute::<E>(request)?.json()?;
+
+    machine
+        .lock()
+        .unwrap()
+        .receive_keys_query_response(&response.device_keys);
+
+    Ok(())
+}
+
+fn share_group_session(
+    base: Url,
+    access_token: AccessToken,
+    machine: &SharedOlmMachine,
+    room_id: &RoomId,
+) -> Result<(), EncryptEventError> {
+    let params = JoinedMembersParameters {
+        access_token: access_token.clone(),
+    };
+    let request = get_joined_members(base.clone(), room_id, &params)?;
+    let response: JoinedMembersResponse = execute::<EncryptEventError>(request)?.json()?;
+    let members: Vec<UserId> = response.joined.keys().cloned().collect();
+
+    machine.lock().unwrap().update_tracked_users(&members);
+    query_keys::<EncryptEventError>(base.clone(), access_token.clone(), machine)?;
+
+    let missing_sessions = machine.lock().unwrap().missing_sessions(&members);
+    if !missing_sessions.is_empty() {
+        let params = ClaimKeysParameters {
+            access_token: access_token.clone(),
+        };
+        let body = ClaimKeysBody {
+            timeout: Some(10000),
+            one_time_keys: missing_sessions,
+        };
+        let request = claim_keys(base.clone(), &params, &body)?;
+        let response: ClaimKeysResponse = execute::<EncryptEventError>(request)?.json()?;
+
+        machine
+            .lock()
+            .unwrap()
+            .receive_keys_claim_response(&response.one_time_keys);
+    }
+
+    let messages = {
+        let mut machine = machine.lock().unwrap();
+        let messages = machine.share_group_session(room_id, &members)?;
+        store(&machine);
+        messages
+    };
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    let params = SendToDeviceParameters { access_token };
+    let body = SendToDeviceBody { messages };
+    let txn_id = rand::thread_rng().gen::<u64>().to_string();
+    let request = send_event_to_device(base, &params, &body, "m.room.encrypted", &txn_id)?;
+    if let Err(err) = execute::<EncryptEventError>(request) {
+        // Some devices may not have the key, start over with a new session
+        let mut machine = machine.lock().unwrap();
+        machine.discard_group_session(room_id);
+        store(&machine);
+        return Err(err);
+    }
+
+    Ok(())
+}
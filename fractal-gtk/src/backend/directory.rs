@@ -1,10 +1,10 @@
+use fractal_api::error::MatrixError;
 use fractal_api::reqwest::Error as ReqwestError;
 use fractal_api::url::{Host, ParseError as UrlError, Url};
 use std::convert::TryInto;
 
 use crate::globals;
 
-use crate::backend::HTTP_CLIENT;
 use crate::util::cache_dir_path;
 
 use crate::types::Room;
@@ -20,7 +20,7 @@ use fractal_api::r0::thirdparty::get_supported_protocols::ProtocolInstance;
 use fractal_api::r0::thirdparty::get_supported_protocols::Response as SupportedProtocolsResponse;
 use fractal_api::r0::AccessToken;
 
-use super::{dw_media, ContentType, HandleError};
+use super::{dw_media, execute, ContentType, HandleError};
 use crate::app::App;
 use crate::i18n::i18n;
 use crate::APPOP;
@@ -34,6 +34,12 @@ impl From<ReqwestError> for DirectoryProtocolsError {
     }
 }
 
+impl From<MatrixError> for DirectoryProtocolsError {
+    fn from(_: MatrixError) -> Self {
+        Self
+    }
+}
+
 impl HandleError for DirectoryProtocolsError {
     fn handle_error(&self) {
         let error = i18n("Error searching for rooms");
@@ -48,7 +54,8 @@ pub fn protocols(
 ) -> Result<Vec<ProtocolInstance>, DirectoryProtocolsError> {
     let params = SupportedProtocolsParameters { access_token };
     let request = get_supported_protocols(base, &params)?;
-    let response: SupportedProtocolsResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: SupportedProtocolsResponse =
+        execute::<DirectoryProtocolsError>(request)?.json()?;
 
     Ok(response
         .into_iter()
@@ -60,6 +67,7 @@ pub fn protocols(
 pub enum DirectorySearchError {
     InvalidHomeserverUrl(UrlError),
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     ParseUrl(UrlError),
 }
 
@@ -69,6 +77,12 @@ impl From<ReqwestError> for DirectorySearchError {
     }
 }
 
+impl From<MatrixError> for DirectorySearchError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl From<UrlError> for DirectorySearchError {
     fn from(err: UrlError) -> Self {
         Self::ParseUrl(err)
@@ -129,7 +143,7 @@ pub fn room_search(
     };
 
     let request = post_public_rooms(base.clone(), &params, &body)?;
-    let response: PublicRoomsResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: PublicRoomsResponse = execute::<DirectorySearchError>(request)?.json()?;
 
     let since = response.next_batch;
     let rooms = response
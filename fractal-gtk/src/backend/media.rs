@@ -1,12 +1,13 @@
 use super::MediaError;
 use crate::globals;
+use fractal_api::error::MatrixError;
 use fractal_api::identifiers::{Error as IdError, EventId, RoomId};
 use fractal_api::reqwest::Error as ReqwestError;
 use fractal_api::url::Url;
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 
-use crate::backend::HTTP_CLIENT;
+use crate::error::Error;
 use crate::util::ResultExpectLog;
 use fractal_api::r0::AccessToken;
 
@@ -17,7 +18,7 @@ use fractal_api::r0::message::get_message_events::Direction as GetMessagesEvents
 use fractal_api::r0::message::get_message_events::Parameters as GetMessagesEventsParams;
 use fractal_api::r0::message::get_message_events::Response as GetMessagesEventsResponse;
 
-use super::{dw_media, get_prev_batch_from, ContentType, ThreadPool};
+use super::{dw_media, execute, get_prev_batch_from, ContentType, ThreadPool};
 
 pub type MediaResult = Result<PathBuf, MediaError>;
 pub type MediaList = (Vec<Message>, String);
@@ -48,7 +49,7 @@ pub fn get_media_list_async(
     thread_pool.run(move || {
         let media_list = prev_batch
             // FIXME: This should never be an empty token
-            .or_else(|| get_prev_batch_from(baseu.clone(), access_token.clone(), &room_id, &first_media_id).ok())
+            .or_else(|| get_prev_batch_from::<Error>(baseu.clone(), access_token.clone(), &room_id, &first_media_id).ok())
             .and_then(|from| {
                 get_room_media_list(
                     baseu,
@@ -65,6 +66,7 @@ pub fn get_media_list_async(
 
 enum GetRoomMediaListError {
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     EventsDeserialization(IdError),
 }
 
@@ -74,6 +76,12 @@ impl From<ReqwestError> for GetRoomMediaListError {
     }
 }
 
+impl From<MatrixError> for GetRoomMediaListError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 fn get_room_media_list(
     baseu: Url,
     access_token: AccessToken,
@@ -89,13 +97,13 @@ fn get_room_media_list(
         limit,
         filter: RoomEventFilter {
             contains_url: true,
-            not_types: vec!["m.sticker"],
+            not_types: vec!["m.sticker", "m.room.avatar"],
             ..Default::default()
         },
     };
 
     let request = get_messages_events_req(baseu, &params, room_id)?;
-    let response: GetMessagesEventsResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: GetMessagesEventsResponse = execute::<GetRoomMediaListError>(request)?.json()?;
 
     let prev_batch = response.end.unwrap_or_default();
     let evs = response.chunk.iter().rev();
@@ -1,4 +1,6 @@
+use fractal_api::error::{ErrorCode, MatrixError};
 use fractal_api::identifiers::{EventId, RoomId};
+use fractal_api::reqwest::blocking::{Client as BlockingClient, Request, Response};
 use fractal_api::reqwest::Error as ReqwestError;
 use fractal_api::url::Url;
 use lazy_static::lazy_static;
@@ -11,8 +13,13 @@ use std::path::PathBuf;
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 
+use crate::app::App;
 use crate::client::Client;
+use crate::crypto::attachment::{decrypt_attachment, EncryptedFile};
+use crate::crypto::CryptoError;
+use crate::i18n::{i18n, i18n_k};
 use crate::util::cache_dir_path;
+use crate::APPOP;
 use fractal_api::r0::context::get_context::request as get_context;
 use fractal_api::r0::context::get_context::Parameters as GetContextParameters;
 use fractal_api::r0::context::get_context::Response as GetContextResponse;
@@ -23,11 +30,15 @@ use fractal_api::r0::media::get_content_thumbnail::Method;
 use fractal_api::r0::media::get_content_thumbnail::Parameters as GetContentThumbnailParameters;
 use fractal_api::r0::AccessToken;
 
+pub mod crypto;
 pub mod directory;
 pub mod media;
 pub mod register;
 pub mod room;
+pub mod search;
+pub mod sso;
 pub mod sync;
+pub mod uia;
 pub mod user;
 
 lazy_static! {
@@ -95,12 +106,15 @@ impl ContentType {
     }
 }
 
-pub fn get_prev_batch_from(
+pub fn get_prev_batch_from<E>(
     base: Url,
     access_token: AccessToken,
     room_id: &RoomId,
     event_id: &EventId,
-) -> Result<String, ReqwestError> {
+) -> Result<String, E>
+where
+    E: From<ReqwestError> + From<MatrixError>,
+{
     let params = GetContextParameters {
         access_token,
         limit: 0,
@@ -108,7 +122,7 @@ pub fn get_prev_batch_from(
     };
 
     let request = get_context(base, &params, room_id, event_id)?;
-    let response: GetContextResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: GetContextResponse = execute::<E>(request)?.json()?;
     let prev_batch = response.start.unwrap_or_default();
 
     Ok(prev_batch)
@@ -117,8 +131,10 @@ pub fn get_prev_batch_from(
 #[derive(Debug)]
 pub enum MediaError {
     MalformedMxcUrl,
+    Decryption(CryptoError),
     Io(IoError),
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
 }
 
 impl From<ReqwestError> for MediaError {
@@ -127,12 +143,24 @@ impl From<ReqwestError> for MediaError {
     }
 }
 
+impl From<MatrixError> for MediaError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl From<IoError> for MediaError {
     fn from(err: IoError) -> Self {
         Self::Io(err)
     }
 }
 
+impl From<CryptoError> for MediaError {
+    fn from(err: CryptoError) -> Self {
+        Self::Decryption(err)
+    }
+}
+
 impl HandleError for MediaError {}
 
 pub fn dw_media(
@@ -147,6 +175,14 @@ pub fn dw_media(
 
     let server = mxc.host().ok_or(MediaError::MalformedMxcUrl)?.to_owned();
 
+    // The server can't make thumbnails of encrypted files, we get the file
+    // itself and decrypt it
+    let encrypted_file = EncryptedFile::from_media_url(mxc);
+    let media_type = match encrypted_file {
+        Some(_) => ContentType::Download,
+        None => media_type,
+    };
+
     let media_id = mxc
         .path_segments()
         .and_then(|mut ps| ps.next())
@@ -187,13 +223,92 @@ pub fn dw_media(
     if fname.is_file() && (dest.is_none() || is_fname_recent) {
         Ok(fname)
     } else {
-        HTTP_CLIENT
-            .get_client()
-            .execute(request)?
-            .bytes()
-            .map_err(Into::into)
-            .and_then(|media| write(&fname, media).map_err(Into::into))
-            .and(Ok(fname))
+        let mut media = execute::<MediaError>(request)?.bytes()?.to_vec();
+        if let Some(file) = encrypted_file {
+            decrypt_attachment(&mut media, &file.keys)?;
+        }
+        write(&fname, media)?;
+        Ok(fname)
+    }
+}
+
+/// Sends a request with the shared client, see `check_response`
+pub fn execute<E>(request: Request) -> Result<Response, E>
+where
+    E: From<ReqwestError> + From<MatrixError>,
+{
+    execute_with(&HTTP_CLIENT.get_client(), request)
+}
+
+/// Sends a request, see `check_response`
+pub fn execute_with<E>(client: &BlockingClient, request: Request) -> Result<Response, E>
+where
+    E: From<ReqwestError> + From<MatrixError>,
+{
+    let access_token = request_access_token(&request);
+    let response = client.execute(request)?;
+
+    Ok(check_response(response, access_token)?)
+}
+
+/// The access token a request is sent with, if it's an authenticated one
+pub fn request_access_token(request: &Request) -> Option<AccessToken> {
+    request
+        .url()
+        .query_pairs()
+        .find(|(key, _)| key == "access_token")
+        .map(|(_, value)| AccessToken::from(value.into_owned()))
+}
+
+/// Like `fractal_api::error::check_response`, see `check_access_token`
+pub fn check_response(
+    response: Response,
+    access_token: Option<AccessToken>,
+) -> Result<Response, MatrixError> {
+    fractal_api::error::check_response(response).map_err(|err| {
+        check_access_token(&err, access_token);
+        err
+    })
+}
+
+/// When the server doesn't know the access token the request was sent with anymore, tells
+/// the account it belongs to
+pub fn check_access_token(err: &MatrixError, access_token: Option<AccessToken>) {
+    if let ErrorCode::UnknownToken { soft_logout } = err.kind {
+        if let Some(access_token) = access_token {
+            APPOP!(token_invalidated, (access_token, soft_logout));
+        }
+    }
+}
+
+/// What went wrong, for the user. The message of the server is only there for the errors
+/// we don't know better, it isn't translated.
+pub fn matrix_error_info(err: &MatrixError) -> String {
+    match &err.kind {
+        ErrorCode::UnknownToken { .. } | ErrorCode::MissingToken => {
+            i18n("Your session has ended, log in again")
+        }
+        ErrorCode::UserDeactivated => i18n("This account has been deactivated"),
+        ErrorCode::LimitExceeded { .. } => {
+            i18n("The server received too many requests, wait a moment before trying again")
+        }
+        ErrorCode::ResourceLimitExceeded {
+            admin_contact: Some(admin_contact),
+        } => i18n_k(
+            "The server has reached a limit, contact its administrator at {contact}",
+            &[("contact", admin_contact)],
+        ),
+        ErrorCode::ResourceLimitExceeded {
+            admin_contact: None,
+        } => i18n("The server has reached a limit, contact its administrator"),
+        ErrorCode::TooLarge => i18n("It’s too large for the server"),
+        ErrorCode::GuestAccessForbidden => i18n("Guests aren’t allowed in this room"),
+        ErrorCode::UnsupportedRoomVersion | ErrorCode::IncompatibleRoomVersion { .. } => {
+            i18n("The server doesn’t support the version of this room")
+        }
+        ErrorCode::WeakPassword => i18n("The password is too weak"),
+        _ if err.message.is_empty() => err.to_string(),
+        _ => err.message.clone(),
     }
 }
 
@@ -1,42 +1,92 @@
+use fractal_api::error::{ErrorCode, MatrixError};
 use fractal_api::identifiers::{DeviceId, UserId};
 use fractal_api::reqwest::Error as ReqwestError;
 use fractal_api::url::{ParseError as UrlError, Url};
+use log::error;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde_json::Value as JsonValue;
+use std::convert::TryInto;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::{Receiver, Sender};
 
 use crate::actions::AppState;
-use crate::backend::HTTP_CLIENT;
+use crate::error::Error;
 use crate::globals;
+use fractal_api::identity::r0::association::msisdn::submit_token::request as submit_phone_token_req;
+use fractal_api::identity::r0::association::msisdn::submit_token::request_to as submit_phone_token_to;
+use fractal_api::identity::r0::association::msisdn::submit_token::Body as SubmitPhoneTokenBody;
+use fractal_api::identity::r0::association::msisdn::submit_token::Response as SubmitPhoneTokenResponse;
+use fractal_api::r0::account::auth_fallback_url;
+use fractal_api::r0::account::get_login_types::request as get_login_types;
+use fractal_api::r0::account::get_login_types::Response as LoginTypesResponse;
+use fractal_api::r0::account::get_username_availability::request as get_username_availability;
+use fractal_api::r0::account::get_username_availability::Parameters as UsernameAvailabilityParameters;
+use fractal_api::r0::account::get_username_availability::Response as UsernameAvailabilityResponse;
 use fractal_api::r0::account::login::request as login_req;
 use fractal_api::r0::account::login::Auth;
 use fractal_api::r0::account::login::Body as LoginBody;
 use fractal_api::r0::account::login::Response as LoginResponse;
 use fractal_api::r0::account::logout::request as logout_req;
 use fractal_api::r0::account::logout::Parameters as LogoutParameters;
+use fractal_api::r0::account::register::request as register_req;
+use fractal_api::r0::account::register::Body as RegisterBody;
+use fractal_api::r0::account::register::Parameters as RegisterParameters;
+use fractal_api::r0::account::register::Response as RegisterResponse;
+use fractal_api::r0::account::request_registration_token_email::request as request_registration_token_email;
+use fractal_api::r0::account::request_registration_token_email::Body as EmailTokenBody;
+use fractal_api::r0::account::request_registration_token_email::Response as EmailTokenResponse;
+use fractal_api::r0::account::request_registration_token_msisdn::request as request_registration_token_msisdn;
+use fractal_api::r0::account::request_registration_token_msisdn::Body as PhoneTokenBody;
+use fractal_api::r0::account::request_registration_token_msisdn::Response as PhoneTokenResponse;
+use fractal_api::r0::account::AuthData;
+use fractal_api::r0::account::AuthenticationData;
 use fractal_api::r0::account::Identifier;
 use fractal_api::r0::account::UserIdentifier;
 use fractal_api::r0::server::domain_info::request as domain_info;
 use fractal_api::r0::server::domain_info::Response as DomainInfoResponse;
 use fractal_api::r0::AccessToken;
 use fractal_api::r0::Medium;
+use fractal_api::r0::ThreePIDCredentials;
 
-use super::HandleError;
+use super::uia::{self, StageHandler, UiaError};
+use super::{execute, matrix_error_info, remove_matrix_access_token_if_present, HandleError};
 use crate::app::App;
 use crate::i18n::i18n;
 use crate::APPOP;
 
+/// Has the answer of the server when it refused the login
 #[derive(Debug)]
-pub struct LoginError;
+pub struct LoginError(Option<MatrixError>);
 
 impl From<ReqwestError> for LoginError {
     fn from(_: ReqwestError) -> Self {
-        Self
+        Self(None)
+    }
+}
+
+impl From<MatrixError> for LoginError {
+    fn from(err: MatrixError) -> Self {
+        Self(Some(err))
+    }
+}
+
+impl LoginError {
+    /// Why the server refused the login, for the user
+    pub fn info(&self) -> Option<String> {
+        self.0.as_ref().map(|err| match err.kind {
+            ErrorCode::Forbidden => i18n("The username or the password is wrong"),
+            _ => matrix_error_info(err),
+        })
     }
 }
 
 impl HandleError for LoginError {
     fn handle_error(&self) {
         let error = i18n("Can’t login, try again");
+        let info = self.info();
         let st = AppState::Login;
-        APPOP!(show_error, (error));
+        APPOP!(show_error_with_info, (error, info));
         APPOP!(logout);
         APPOP!(set_state, (st));
     }
@@ -47,41 +97,105 @@ pub fn login(
     password: String,
     server: Url,
 ) -> Result<(UserId, AccessToken, Option<Box<DeviceId>>), LoginError> {
-    let body = if globals::EMAIL_RE.is_match(&user) {
-        LoginBody {
-            auth: Auth::Password { password },
-            identifier: Identifier::new(UserIdentifier::ThirdParty {
-                medium: Medium::Email,
-                address: user,
-            }),
-            initial_device_display_name: Some(globals::DEVICE_NAME.into()),
-            device_id: None,
-        }
+    let identifier = if globals::EMAIL_RE.is_match(&user) {
+        Identifier::new(UserIdentifier::ThirdParty {
+            medium: Medium::Email,
+            address: user,
+        })
     } else {
-        LoginBody {
-            auth: Auth::Password { password },
-            identifier: Identifier::new(UserIdentifier::User { user }),
-            initial_device_display_name: Some(globals::DEVICE_NAME.into()),
-            device_id: None,
-        }
+        Identifier::new(UserIdentifier::User { user })
+    };
+
+    let body = LoginBody {
+        auth: Auth::Password { password },
+        identifier: Some(identifier),
+        initial_device_display_name: Some(globals::DEVICE_NAME.into()),
+        device_id: None,
+    };
+
+    send_login(server, &body)
+}
+
+/// Logs in again to the device of a session the server ended, so we keep its encryption keys
+pub fn login_to_device(
+    uid: UserId,
+    password: String,
+    server: Url,
+    device_id: Option<Box<DeviceId>>,
+) -> Result<AccessToken, LoginError> {
+    let body = LoginBody {
+        auth: Auth::Password { password },
+        identifier: Some(Identifier::new(UserIdentifier::User {
+            user: uid.to_string(),
+        })),
+        initial_device_display_name: None,
+        device_id,
+    };
+
+    send_login(server, &body).map(|(_, access_token, _)| access_token)
+}
+
+/// Logs in with the token given by the single sign-on, to `device_id` when
+/// it's a session the server ended
+pub fn login_with_token(
+    token: String,
+    server: Url,
+    device_id: Option<Box<DeviceId>>,
+) -> Result<(UserId, AccessToken, Option<Box<DeviceId>>), LoginError> {
+    let initial_device_display_name = if device_id.is_none() {
+        Some(globals::DEVICE_NAME.into())
+    } else {
+        None
+    };
+    let body = LoginBody {
+        auth: Auth::Token { token },
+        identifier: None,
+        initial_device_display_name,
+        device_id,
     };
 
-    let request = login_req(server, &body)?;
-    let response: LoginResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    send_login(server, &body)
+}
+
+fn send_login(
+    server: Url,
+    body: &LoginBody,
+) -> Result<(UserId, AccessToken, Option<Box<DeviceId>>), LoginError> {
+    let request = login_req(server, body)?;
+    let response: LoginResponse = execute::<LoginError>(request)?.json()?;
 
     if let (Some(tk), Some(uid)) = (response.access_token, response.user_id) {
         Ok((uid, tk, response.device_id))
     } else {
-        Err(LoginError)
+        Err(LoginError(None))
     }
 }
 
 #[derive(Debug)]
-pub struct LogoutError(ReqwestError);
+pub struct GetLoginFlowsError(Error);
 
-impl From<ReqwestError> for LogoutError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for GetLoginFlowsError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for GetLoginFlowsError {}
+
+/// Returns the types of login the server supports
+pub fn get_login_flows(server: Url) -> Result<Vec<String>, GetLoginFlowsError> {
+    let request = get_login_types(server)?;
+    let response: LoginTypesResponse = execute::<GetLoginFlowsError>(request)?.json()?;
+
+    Ok(response.flows.into_iter().map(|flow| flow.kind).collect())
+}
+
+#[derive(Debug)]
+pub struct LogoutError(Error);
+
+impl<T: Into<Error>> From<T> for LogoutError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -91,7 +205,7 @@ pub fn logout(server: Url, access_token: AccessToken) -> Result<(), LogoutError>
     let params = LogoutParameters { access_token };
 
     let request = logout_req(server, &params)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<LogoutError>(request)?;
 
     Ok(())
 }
@@ -99,6 +213,7 @@ pub fn logout(server: Url, access_token: AccessToken) -> Result<(), LogoutError>
 #[derive(Debug)]
 pub enum GetWellKnownError {
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     ParseUrl(UrlError),
 }
 
@@ -108,6 +223,12 @@ impl From<ReqwestError> for GetWellKnownError {
     }
 }
 
+impl From<MatrixError> for GetWellKnownError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl From<UrlError> for GetWellKnownError {
     fn from(err: UrlError) -> Self {
         Self::ParseUrl(err)
@@ -117,9 +238,409 @@ impl From<UrlError> for GetWellKnownError {
 pub fn get_well_known(domain: Url) -> Result<DomainInfoResponse, GetWellKnownError> {
     let request = domain_info(domain)?;
 
-    HTTP_CLIENT
-        .get_client()
-        .execute(request)?
+    execute::<GetWellKnownError>(request)?
         .json()
         .map_err(Into::into)
 }
+
+#[derive(Debug)]
+pub enum UsernameAvailabilityError {
+    Reqwest(ReqwestError),
+    Matrix(MatrixError),
+    InUse,
+    /// The server refused the username, with its explanation
+    Invalid(Option<String>),
+}
+
+impl From<ReqwestError> for UsernameAvailabilityError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Reqwest(err)
+    }
+}
+
+// Unavailable usernames are reported as errors
+impl From<MatrixError> for UsernameAvailabilityError {
+    fn from(err: MatrixError) -> Self {
+        match err.kind {
+            ErrorCode::UserInUse => Self::InUse,
+            _ if err.status_code.is_client_error() => Self::Invalid(Some(err.message)),
+            _ => Self::Matrix(err),
+        }
+    }
+}
+
+impl HandleError for UsernameAvailabilityError {}
+
+pub fn check_username_availability(
+    server: Url,
+    username: String,
+) -> Result<(), UsernameAvailabilityError> {
+    let params = UsernameAvailabilityParameters { username };
+    let request = get_username_availability(server, &params)?;
+    let response: UsernameAvailabilityResponse =
+        execute::<UsernameAvailabilityError>(request)?.json()?;
+    if response.available {
+        Ok(())
+    } else {
+        Err(UsernameAvailabilityError::InUse)
+    }
+}
+
+/// What the user has to do to complete a registration stage
+#[derive(Clone, Debug)]
+pub enum RegistrationPrompt {
+    /// Follow the link sent to this email address
+    Email(String),
+    /// Enter the code sent to this phone number
+    Msisdn(String),
+    /// Accept the policies of the server, as their name and link
+    Terms(Vec<(String, Url)>),
+    /// Complete the stage in the web page
+    Fallback(Url),
+}
+
+/// Shows the prompt and waits for the answer of the user, if they didn't
+/// cancel
+fn ask(prompt: RegistrationPrompt) -> Option<String> {
+    let (tx, rx): (Sender<Option<String>>, Receiver<Option<String>>) = channel();
+    APPOP!(show_registration_prompt, (prompt, tx));
+
+    rx.recv().ok().and_then(|answer| answer)
+}
+
+/// Returns the name and link of each policy in the parameters of the terms
+/// stage, in English if possible
+pub fn terms_policies(params: Option<&JsonValue>) -> Vec<(String, Url)> {
+    let policies = params
+        .and_then(|params| params["policies"].as_object())
+        .into_iter()
+        .flat_map(|policies| policies.values());
+
+    policies
+        .filter_map(|policy| {
+            let translations = policy.as_object()?;
+            let translation = translations
+                .get("en")
+                .or_else(|| translations.values().find(|value| value.is_object()))?;
+            let name = translation["name"].as_str()?.to_string();
+            let url = Url::parse(translation["url"].as_str()?).ok()?;
+
+            Some((name, url))
+        })
+        .collect()
+}
+
+/// The calling codes and their country, the main one for the codes that several share.
+/// No code is the start of another.
+#[rustfmt::skip]
+const CALLING_CODES: &[(&str, &str)] = &[
+    ("1", "US"),
+    ("20", "EG"), ("211", "SS"), ("212", "MA"), ("213", "DZ"), ("216", "TN"), ("218", "LY"),
+    ("220", "GM"), ("221", "SN"), ("222", "MR"), ("223", "ML"), ("224", "GN"), ("225", "CI"),
+    ("226", "BF"), ("227", "NE"), ("228", "TG"), ("229", "BJ"), ("230", "MU"), ("231", "LR"),
+    ("232", "SL"), ("233", "GH"), ("234", "NG"), ("235", "TD"), ("236", "CF"), ("237", "CM"),
+    ("238", "CV"), ("239", "ST"), ("240", "GQ"), ("241", "GA"), ("242", "CG"), ("243", "CD"),
+    ("244", "AO"), ("245", "GW"), ("246", "IO"), ("247", "AC"), ("248", "SC"), ("249", "SD"),
+    ("250", "RW"), ("251", "ET"), ("252", "SO"), ("253", "DJ"), ("254", "KE"), ("255", "TZ"),
+    ("256", "UG"), ("257", "BI"), ("258", "MZ"), ("260", "ZM"), ("261", "MG"), ("262", "RE"),
+    ("263", "ZW"), ("264", "NA"), ("265", "MW"), ("266", "LS"), ("267", "BW"), ("268", "SZ"),
+    ("269", "KM"), ("27", "ZA"), ("290", "SH"), ("291", "ER"), ("297", "AW"), ("298", "FO"),
+    ("299", "GL"),
+    ("30", "GR"), ("31", "NL"), ("32", "BE"), ("33", "FR"), ("34", "ES"), ("350", "GI"),
+    ("351", "PT"), ("352", "LU"), ("353", "IE"), ("354", "IS"), ("355", "AL"), ("356", "MT"),
+    ("357", "CY"), ("358", "FI"), ("359", "BG"), ("36", "HU"), ("370", "LT"), ("371", "LV"),
+    ("372", "EE"), ("373", "MD"), ("374", "AM"), ("375", "BY"), ("376", "AD"), ("377", "MC"),
+    ("378", "SM"), ("379", "VA"), ("380", "UA"), ("381", "RS"), ("382", "ME"), ("383", "XK"),
+    ("385", "HR"), ("386", "SI"), ("387", "BA"), ("389", "MK"), ("39", "IT"),
+    ("40", "RO"), ("41", "CH"), ("420", "CZ"), ("421", "SK"), ("423", "LI"), ("43", "AT"),
+    ("44", "GB"), ("45", "DK"), ("46", "SE"), ("47", "NO"), ("48", "PL"), ("49", "DE"),
+    ("500", "FK"), ("501", "BZ"), ("502", "GT"), ("503", "SV"), ("504", "HN"), ("505", "NI"),
+    ("506", "CR"), ("507", "PA"), ("508", "PM"), ("509", "HT"), ("51", "PE"), ("52", "MX"),
+    ("53", "CU"), ("54", "AR"), ("55", "BR"), ("56", "CL"), ("57", "CO"), ("58", "VE"),
+    ("590", "GP"), ("591", "BO"), ("592", "GY"), ("593", "EC"), ("594", "GF"), ("595", "PY"),
+    ("596", "MQ"), ("597", "SR"), ("598", "UY"), ("599", "CW"),
+    ("60", "MY"), ("61", "AU"), ("62", "ID"), ("63", "PH"), ("64", "NZ"), ("65", "SG"),
+    ("66", "TH"), ("670", "TL"), ("672", "NF"), ("673", "BN"), ("674", "NR"), ("675", "PG"),
+    ("676", "TO"), ("677", "SB"), ("678", "VU"), ("679", "FJ"), ("680", "PW"), ("681", "WF"),
+    ("682", "CK"), ("683", "NU"), ("685", "WS"), ("686", "KI"), ("687", "NC"), ("688", "TV"),
+    ("689", "PF"), ("690", "TK"), ("691", "FM"), ("692", "MH"),
+    ("7", "RU"),
+    ("81", "JP"), ("82", "KR"), ("84", "VN"), ("850", "KP"), ("852", "HK"), ("853", "MO"),
+    ("855", "KH"), ("856", "LA"), ("86", "CN"), ("880", "BD"), ("886", "TW"),
+    ("90", "TR"), ("91", "IN"), ("92", "PK"), ("93", "AF"), ("94", "LK"), ("95", "MM"),
+    ("960", "MV"), ("961", "LB"), ("962", "JO"), ("963", "SY"), ("964", "IQ"), ("965", "KW"),
+    ("966", "SA"), ("967", "YE"), ("968", "OM"), ("970", "PS"), ("971", "AE"), ("972", "IL"),
+    ("973", "BH"), ("974", "QA"), ("975", "BT"), ("976", "MN"), ("977", "NP"), ("98", "IR"),
+    ("992", "TJ"), ("993", "TM"), ("994", "AZ"), ("995", "GE"), ("996", "KG"), ("998", "UZ"),
+];
+
+/// The country of a phone number that starts with its calling code, like +44 7700 900123
+pub fn phone_country(phone_number: &str) -> Option<&'static str> {
+    if !phone_number.starts_with('+') {
+        return None;
+    }
+    let digits: String = phone_number.chars().filter(char::is_ascii_digit).collect();
+
+    CALLING_CODES
+        .iter()
+        .find(|(code, _)| digits.starts_with(code))
+        .map(|(_, country)| *country)
+}
+
+struct RegistrationStages {
+    identity: Url,
+    email: Option<String>,
+    phone_number: Option<String>,
+    client_secret: String,
+}
+
+impl RegistrationStages {
+    fn threepid_creds(&self, sid: String) -> Result<ThreePIDCredentials, UiaError> {
+        Ok(ThreePIDCredentials {
+            client_secret: self.client_secret.clone(),
+            id_server: (&self.identity)
+                .try_into()
+                .map_err(|_| UiaError::Cancelled)?,
+            sid,
+        })
+    }
+}
+
+impl StageHandler for RegistrationStages {
+    fn supports(&self, stage: &str) -> bool {
+        match stage {
+            s if s == uia::EMAIL_IDENTITY => self.email.is_some(),
+            s if s == uia::MSISDN => self.phone_number.is_some(),
+            // The other stages are done in the fallback page
+            _ => true,
+        }
+    }
+
+    fn complete(
+        &mut self,
+        base: &Url,
+        stage: &str,
+        session: Option<String>,
+        params: Option<&JsonValue>,
+    ) -> Result<AuthData, UiaError> {
+        let data = match (stage, &self.email, &self.phone_number) {
+            (s, _, _) if s == uia::DUMMY => AuthenticationData::Dummy { session },
+            (s, Some(email), _) if s == uia::EMAIL_IDENTITY => {
+                let body = EmailTokenBody {
+                    client_secret: self.client_secret.clone(),
+                    email: email.clone(),
+                    id_server: (&self.identity)
+                        .try_into()
+                        .map_err(|_| UiaError::Cancelled)?,
+                    send_attempt: 1,
+                    next_link: None,
+                };
+                let request = request_registration_token_email(base.clone(), &body)?;
+                let response: EmailTokenResponse = execute::<UiaError>(request)?.json()?;
+
+                ask(RegistrationPrompt::Email(email.clone())).ok_or(UiaError::Cancelled)?;
+
+                AuthenticationData::Email {
+                    threepid_creds: self.threepid_creds(response.sid)?,
+                    session,
+                }
+            }
+            (s, _, Some(phone_number)) if s == uia::MSISDN => {
+                let body = PhoneTokenBody {
+                    client_secret: self.client_secret.clone(),
+                    phone_number: phone_number.clone(),
+                    country: phone_country(phone_number).unwrap_or_default().to_string(),
+                    id_server: (&self.identity)
+                        .try_into()
+                        .map_err(|_| UiaError::Cancelled)?,
+                    send_attempt: 1,
+                    next_link: None,
+                };
+                let request = request_registration_token_msisdn(base.clone(), &body)?;
+                let response: PhoneTokenResponse = execute::<UiaError>(request)?.json()?;
+
+                let token = ask(RegistrationPrompt::Msisdn(phone_number.clone()))
+                    .ok_or(UiaError::Cancelled)?;
+                let body = SubmitPhoneTokenBody {
+                    sid: response.sid.clone(),
+                    client_secret: self.client_secret.clone(),
+                    token,
+                };
+                // The homeserver may check the token itself instead of the identity server
+                let request = match response.submit_url.clone() {
+                    Some(submit_url) => submit_phone_token_to(submit_url, &body)?,
+                    None => submit_phone_token_req(self.identity.clone(), &body)?,
+                };
+                let submitted: SubmitPhoneTokenResponse = execute::<UiaError>(request)?.json()?;
+                if !submitted.success {
+                    return Err(UiaError::StageFailed {
+                        stage: stage.to_string(),
+                        errcode: None,
+                        error: None,
+                    });
+                }
+
+                AuthenticationData::Msisdn {
+                    threepid_creds: self.threepid_creds(response.sid)?,
+                    session,
+                }
+            }
+            (s, _, _) if s == uia::TERMS => {
+                ask(RegistrationPrompt::Terms(terms_policies(params)))
+                    .ok_or(UiaError::Cancelled)?;
+
+                AuthenticationData::Terms { session }
+            }
+            _ => {
+                let session = session.ok_or(UiaError::Cancelled)?;
+                let url = auth_fallback_url(base, stage, &session);
+                ask(RegistrationPrompt::Fallback(url)).ok_or(UiaError::Cancelled)?;
+
+                return Ok(AuthData::FallbackAcknowledgement { session });
+            }
+        };
+
+        Ok(data.into())
+    }
+}
+
+#[derive(Debug)]
+pub enum RegisterError {
+    Uia(UiaError),
+    NoAccessToken,
+}
+
+impl From<UiaError> for RegisterError {
+    fn from(err: UiaError) -> Self {
+        Self::Uia(err)
+    }
+}
+
+impl HandleError for RegisterError {
+    fn handle_error(&self) {
+        let error = match self {
+            Self::Uia(UiaError::Cancelled) => None,
+            Self::Uia(UiaError::StageFailed {
+                error: Some(error), ..
+            }) => Some(error.clone()),
+            Self::Uia(UiaError::Matrix(err)) => Some(matrix_error_info(err)),
+            Self::Uia(UiaError::NoSupportedFlow(flows)) => {
+                error!("No supported registration flow in {:?}", flows);
+                Some(i18n(
+                    "This server doesn’t allow creating an account with Fractal",
+                ))
+            }
+            err => {
+                let err_str = format!("{:?}", err);
+                error!(
+                    "{}",
+                    remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+                );
+                Some(i18n("Couldn’t create the account, try again"))
+            }
+        };
+
+        if let Some(error) = error {
+            APPOP!(show_error, (error));
+        }
+        let st = AppState::Login;
+        APPOP!(logout);
+        APPOP!(set_state, (st));
+    }
+}
+
+/// Creates an account and logs into it. The stages that need the user are
+/// shown with `AppOp::show_registration_prompt`.
+pub fn register(
+    server: Url,
+    identity: Url,
+    username: String,
+    password: String,
+    email: Option<String>,
+    phone_number: Option<String>,
+) -> Result<(UserId, AccessToken, Option<Box<DeviceId>>), RegisterError> {
+    let params = RegisterParameters::default();
+    let bind_email = email.is_some();
+    let mut stages = RegistrationStages {
+        identity,
+        email,
+        phone_number,
+        client_secret: thread_rng().sample_iter(&Alphanumeric).take(36).collect(),
+    };
+
+    let response: RegisterResponse = uia::perform(&server, &mut stages, |auth| {
+        let body = RegisterBody {
+            auth,
+            bind_email,
+            username: Some(username.clone()),
+            password: Some(password.clone()),
+            initial_device_display_name: Some(globals::DEVICE_NAME.into()),
+            ..Default::default()
+        };
+
+        register_req(server.clone(), &params, &body)
+    })?;
+
+    let access_token = response.access_token.ok_or(RegisterError::NoAccessToken)?;
+
+    Ok((response.user_id, access_token, response.device_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_phone_country() {
+        assert_eq!(phone_country("+44 7700 900123"), Some("GB"));
+        assert_eq!(phone_country("+1 (555) 010-0199"), Some("US"));
+        assert_eq!(phone_country("+353861234567"), Some("IE"));
+        assert_eq!(phone_country("+886 912 345 678"), Some("TW"));
+        // The calling code is needed
+        assert_eq!(phone_country("07700 900123"), None);
+        assert_eq!(phone_country("+"), None);
+    }
+
+    #[test]
+    fn register_terms_policies_prefer_english() {
+        let params = serde_json::json!({
+            "policies": {
+                "privacy_policy": {
+                    "version": "1.0",
+                    "fr": {
+                        "name": "Politique de confidentialité",
+                        "url": "https://example.org/fr/privacy"
+                    },
+                    "en": {
+                        "name": "Privacy Policy",
+                        "url": "https://example.org/en/privacy"
+                    }
+                },
+                "terms_of_service": {
+                    "version": "2.0",
+                    "de": {
+                        "name": "Nutzungsbedingungen",
+                        "url": "https://example.org/de/tos"
+                    }
+                }
+            }
+        });
+
+        let mut policies = terms_policies(Some(&params));
+        policies.sort();
+        assert_eq!(
+            policies,
+            vec![
+                (
+                    "Nutzungsbedingungen".to_string(),
+                    Url::parse("https://example.org/de/tos").unwrap()
+                ),
+                (
+                    "Privacy Policy".to_string(),
+                    Url::parse("https://example.org/en/privacy").unwrap()
+                ),
+            ]
+        );
+        assert!(terms_policies(None).is_empty());
+    }
+}
@@ -1,7 +1,10 @@
 use log::error;
 use serde_json::json;
 
-use fractal_api::identifiers::{Error as IdError, EventId, RoomId, RoomIdOrAliasId, UserId};
+use fractal_api::error::MatrixError;
+use fractal_api::identifiers::{
+    Error as IdError, EventId, RoomAliasId, RoomId, RoomIdOrAliasId, UserId,
+};
 use fractal_api::reqwest::Error as ReqwestError;
 use fractal_api::reqwest::StatusCode;
 use fractal_api::url::{ParseError as UrlError, Url};
@@ -11,36 +14,59 @@ use std::path::{Path, PathBuf};
 
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::fmt::Debug;
 use std::time::Duration;
 
 use crate::globals;
 
 use crate::actions::AppState;
-use crate::backend::HTTP_CLIENT;
+use crate::backend::crypto;
+use crate::crypto::attachment::{encrypt_attachment, EncryptedFile};
+use crate::crypto::SharedOlmMachine;
+use crate::error::Error;
 use crate::util::cache_dir_path;
 
-use crate::error::StandardErrorResponse;
 use crate::types::Member;
 use crate::types::Message;
 use crate::types::{Room, RoomMembership, RoomTag};
+use fractal_api::events::room::{PowerLevelsEventContent, PreviousRoom};
 use fractal_api::r0::config::get_global_account_data::request as get_global_account_data;
 use fractal_api::r0::config::get_global_account_data::Parameters as GetGlobalAccountDataParameters;
 use fractal_api::r0::config::set_global_account_data::request as set_global_account_data;
 use fractal_api::r0::config::set_global_account_data::Parameters as SetGlobalAccountDataParameters;
 use fractal_api::r0::config::set_room_account_data::request as set_room_account_data;
 use fractal_api::r0::config::set_room_account_data::Parameters as SetRoomAccountDataParameters;
+use fractal_api::r0::directory::create_room_alias::request as create_room_alias_req;
+use fractal_api::r0::directory::create_room_alias::Body as CreateRoomAliasBody;
+use fractal_api::r0::directory::create_room_alias::Parameters as CreateRoomAliasParameters;
+use fractal_api::r0::directory::delete_room_alias::request as delete_room_alias_req;
+use fractal_api::r0::directory::delete_room_alias::Parameters as DeleteRoomAliasParameters;
+use fractal_api::r0::directory::get_room_visibility::request as get_room_visibility_req;
+use fractal_api::r0::directory::get_room_visibility::Response as GetRoomVisibilityResponse;
+use fractal_api::r0::directory::set_room_visibility::request as set_room_visibility_req;
+use fractal_api::r0::directory::set_room_visibility::Body as SetRoomVisibilityBody;
+use fractal_api::r0::directory::set_room_visibility::Parameters as SetRoomVisibilityParameters;
 use fractal_api::r0::filter::RoomEventFilter;
 use fractal_api::r0::media::create_content::request as create_content;
 use fractal_api::r0::media::create_content::Parameters as CreateContentParameters;
 use fractal_api::r0::media::create_content::Response as CreateContentResponse;
+use fractal_api::r0::membership::ban_user::request as ban_user_req;
+use fractal_api::r0::membership::ban_user::Body as BanUserBody;
+use fractal_api::r0::membership::ban_user::Parameters as BanUserParameters;
 use fractal_api::r0::membership::invite_user::request as invite_user;
 use fractal_api::r0::membership::invite_user::Body as InviteUserBody;
 use fractal_api::r0::membership::invite_user::Parameters as InviteUserParameters;
 use fractal_api::r0::membership::join_room_by_id_or_alias::request as join_room_req;
 use fractal_api::r0::membership::join_room_by_id_or_alias::Parameters as JoinRoomParameters;
 use fractal_api::r0::membership::join_room_by_id_or_alias::Response as JoinRoomResponse;
+use fractal_api::r0::membership::kick_user::request as kick_user_req;
+use fractal_api::r0::membership::kick_user::Body as KickUserBody;
+use fractal_api::r0::membership::kick_user::Parameters as KickUserParameters;
 use fractal_api::r0::membership::leave_room::request as leave_room_req;
 use fractal_api::r0::membership::leave_room::Parameters as LeaveRoomParameters;
+use fractal_api::r0::membership::unban_user::request as unban_user_req;
+use fractal_api::r0::membership::unban_user::Body as UnbanUserBody;
+use fractal_api::r0::membership::unban_user::Parameters as UnbanUserParameters;
 use fractal_api::r0::message::create_message_event::request as create_message_event;
 use fractal_api::r0::message::create_message_event::Parameters as CreateMessageEventParameters;
 use fractal_api::r0::message::create_message_event::Response as CreateMessageEventResponse;
@@ -60,9 +86,18 @@ use fractal_api::r0::room::create_room::Body as CreateRoomBody;
 use fractal_api::r0::room::create_room::Parameters as CreateRoomParameters;
 use fractal_api::r0::room::create_room::Response as CreateRoomResponse;
 use fractal_api::r0::room::create_room::RoomPreset;
+use fractal_api::r0::room::upgrade_room::request as upgrade_room_req;
+use fractal_api::r0::room::upgrade_room::Body as UpgradeRoomBody;
+use fractal_api::r0::room::upgrade_room::Parameters as UpgradeRoomParameters;
+use fractal_api::r0::room::upgrade_room::Response as UpgradeRoomResponse;
 use fractal_api::r0::room::Visibility;
+use fractal_api::r0::server::get_capabilities::request as get_capabilities;
+use fractal_api::r0::server::get_capabilities::Parameters as GetCapabilitiesParameters;
+use fractal_api::r0::server::get_capabilities::Response as GetCapabilitiesResponse;
 use fractal_api::r0::state::create_state_events_for_key::request as create_state_events_for_key;
 use fractal_api::r0::state::create_state_events_for_key::Parameters as CreateStateEventsForKeyParameters;
+use fractal_api::r0::state::get_state_events::request as get_state_events;
+use fractal_api::r0::state::get_state_events::Parameters as GetStateEventsParameters;
 use fractal_api::r0::state::get_state_events_for_key::request as get_state_events_for_key;
 use fractal_api::r0::state::get_state_events_for_key::Parameters as GetStateEventsForKeyParameters;
 use fractal_api::r0::sync::get_joined_members::request as get_joined_members;
@@ -82,7 +117,8 @@ use fractal_api::r0::AccessToken;
 use serde_json::Value as JsonValue;
 
 use super::{
-    dw_media, get_prev_batch_from, remove_matrix_access_token_if_present, ContentType, HandleError,
+    dw_media, execute, get_prev_batch_from, matrix_error_info,
+    remove_matrix_access_token_if_present, ContentType, HandleError,
 };
 use crate::app::App;
 use crate::i18n::i18n;
@@ -92,6 +128,7 @@ use crate::APPOP;
 pub enum RoomDetailError {
     MalformedKey,
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
 }
 
 impl From<ReqwestError> for RoomDetailError {
@@ -100,6 +137,12 @@ impl From<ReqwestError> for RoomDetailError {
     }
 }
 
+impl From<MatrixError> for RoomDetailError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl HandleError for RoomDetailError {}
 
 pub fn get_room_detail(
@@ -112,7 +155,7 @@ pub fn get_room_detail(
     let params = GetStateEventsForKeyParameters { access_token };
 
     let request = get_state_events_for_key(base, &params, &room_id, &key)?;
-    let response: JsonValue = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: JsonValue = execute::<RoomDetailError>(request)?.json()?;
 
     let value = response[&k].as_str().map(Into::into).unwrap_or_default();
 
@@ -120,11 +163,11 @@ pub fn get_room_detail(
 }
 
 #[derive(Debug)]
-pub struct RoomAvatarError(ReqwestError);
+pub struct RoomAvatarError(Error);
 
-impl From<ReqwestError> for RoomAvatarError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for RoomAvatarError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -138,7 +181,7 @@ pub fn get_room_avatar(
     let params = GetStateEventsForKeyParameters { access_token };
 
     let request = get_state_events_for_key(base.clone(), &params, &room_id, "m.room.avatar")?;
-    let response: JsonValue = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: JsonValue = execute::<RoomAvatarError>(request)?.json()?;
 
     let avatar = response["url"].as_str().and_then(|s| Url::parse(s).ok());
     let dest = cache_dir_path(None, &room_id.to_string()).ok();
@@ -152,6 +195,7 @@ pub fn get_room_avatar(
 #[derive(Debug)]
 pub enum RoomMembersError {
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     ParseUrl(UrlError),
 }
 
@@ -161,6 +205,12 @@ impl From<ReqwestError> for RoomMembersError {
     }
 }
 
+impl From<MatrixError> for RoomMembersError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl From<UrlError> for RoomMembersError {
     fn from(err: UrlError) -> Self {
         Self::ParseUrl(err)
@@ -177,7 +227,7 @@ pub fn get_room_members(
     let params = JoinedMembersParameters { access_token };
 
     let request = get_joined_members(base, &room_id, &params)?;
-    let response: JoinedMembersResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: JoinedMembersResponse = execute::<RoomMembersError>(request)?.json()?;
 
     let ms = response
         .joined
@@ -192,6 +242,7 @@ pub fn get_room_members(
 pub enum RoomMessagesToError {
     MessageNotSent,
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     EventsDeserialization(IdError),
 }
 
@@ -201,6 +252,12 @@ impl From<ReqwestError> for RoomMessagesToError {
     }
 }
 
+impl From<MatrixError> for RoomMessagesToError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl HandleError for RoomMessagesToError {}
 
 /* Load older messages starting by prev_batch
@@ -211,6 +268,7 @@ pub fn get_room_messages(
     access_token: AccessToken,
     room_id: RoomId,
     from: String,
+    olm_machine: Option<SharedOlmMachine>,
 ) -> Result<(Vec<Message>, RoomId, Option<String>), RoomMessagesToError> {
     let params = GetMessagesEventsParams {
         access_token,
@@ -219,16 +277,20 @@ pub fn get_room_messages(
         dir: GetMessagesEventsDirection::Backward,
         limit: globals::PAGE_LIMIT as u64,
         filter: RoomEventFilter {
-            types: Some(vec!["m.room.message", "m.sticker"]),
+            types: Some(Message::timeline_types()),
             ..Default::default()
         },
     };
 
     let request = get_messages_events(base, &params, &room_id)?;
-    let response: GetMessagesEventsResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: GetMessagesEventsResponse = execute::<RoomMessagesToError>(request)?.json()?;
 
     let prev_batch = response.end;
-    let evs = response.chunk.iter().rev();
+    let chunk = match olm_machine {
+        Some(olm_machine) => crypto::decrypt_room_events(&olm_machine, &room_id, response.chunk),
+        None => response.chunk,
+    };
+    let evs = chunk.iter().rev();
     let list = Message::from_json_events_iter(&room_id, evs)
         .map_err(RoomMessagesToError::EventsDeserialization)?;
 
@@ -240,30 +302,74 @@ pub fn get_room_messages_from_msg(
     access_token: AccessToken,
     room_id: RoomId,
     msg: Message,
+    olm_machine: Option<SharedOlmMachine>,
 ) -> Result<(Vec<Message>, RoomId, Option<String>), RoomMessagesToError> {
     let event_id = msg.id.as_ref().ok_or(RoomMessagesToError::MessageNotSent)?;
 
     // first of all, we calculate the from param using the context api, then we call the
     // normal get_room_messages
-    let from = get_prev_batch_from(base.clone(), access_token.clone(), &room_id, event_id)?;
+    let from = get_prev_batch_from::<RoomMessagesToError>(
+        base.clone(),
+        access_token.clone(),
+        &room_id,
+        event_id,
+    )?;
 
-    get_room_messages(base, access_token, room_id, from)
+    get_room_messages(base, access_token, room_id, from, olm_machine)
+}
+
+/// Loads the messages of the predecessor of a room, from the point where it was replaced
+pub fn get_predecessor_messages(
+    base: Url,
+    access_token: AccessToken,
+    predecessor: PreviousRoom,
+    olm_machine: Option<SharedOlmMachine>,
+) -> Result<(Vec<Message>, RoomId, Option<String>), RoomMessagesToError> {
+    let from = get_prev_batch_from::<RoomMessagesToError>(
+        base.clone(),
+        access_token.clone(),
+        &predecessor.room_id,
+        &predecessor.event_id,
+    )?;
+
+    get_room_messages(base, access_token, predecessor.room_id, from, olm_machine)
 }
 
 #[derive(Debug)]
-pub struct SendMsgError(String);
+pub enum SendMsgError {
+    /// We couldn't reach the server, the message can go when we are back online
+    Offline(String),
+    Failed(String),
+}
+
+impl SendMsgError {
+    fn from_error(txn_id: String, err: Error) -> Self {
+        match err {
+            Error::ReqwestError(err) if err.is_timeout() || err.is_request() => {
+                Self::Offline(txn_id)
+            }
+            _ => Self::Failed(txn_id),
+        }
+    }
+}
 
 impl HandleError for SendMsgError {
     fn handle_error(&self) {
-        error!("sending {}: retrying send", self.0);
-        APPOP!(retry_send);
+        let (txn_id, offline) = match self {
+            Self::Offline(txn_id) => (txn_id.clone(), true),
+            Self::Failed(txn_id) => (txn_id.clone(), false),
+        };
+        error!("Can't send {}, offline: {}", txn_id, offline);
+        APPOP!(msg_not_sent, (txn_id, offline));
     }
 }
 
 pub fn send_msg(
     base: Url,
     access_token: AccessToken,
+    txn_id: String,
     msg: Message,
+    olm_machine: Option<SharedOlmMachine>,
 ) -> Result<(String, Option<EventId>), SendMsgError> {
     let room_id: RoomId = msg.room.clone();
 
@@ -274,8 +380,13 @@ pub fn send_msg(
         "msgtype": msg.mtype,
     });
 
+    // The files of encrypted rooms are sent with their keys, see
+    // `upload_encrypted_file`
     if let Some(u) = msg.url.as_ref() {
-        body["url"] = json!(u);
+        match EncryptedFile::from_media_url(u) {
+            Some(file) => body["file"] = file.to_json(),
+            None => body["url"] = json!(u),
+        }
     }
 
     if let (Some(f), Some(f_b)) = (msg.format.as_ref(), msg.formatted_body.as_ref()) {
@@ -294,26 +405,159 @@ pub fn send_msg(
         body[k] = v;
     }
 
-    let txn_id = msg.get_txn_id();
+    let thumbnail_file = body["info"]["thumbnail_url"]
+        .as_str()
+        .and_then(|u| Url::parse(u).ok())
+        .and_then(|u| EncryptedFile::from_media_url(&u));
+    if let Some(file) = thumbnail_file {
+        if let Some(info) = body["info"].as_object_mut() {
+            info.remove("thumbnail_url");
+            info.insert("thumbnail_file".to_string(), file.to_json());
+        }
+    }
+
+    if let Some(in_reply_to) = msg.in_reply_to.as_ref() {
+        body["m.relates_to"] = json!({
+            "m.in_reply_to": {
+                "event_id": in_reply_to,
+            },
+        });
+    }
+
+    // The fallback is for the clients that don't know about edits
+    if let Some(replace) = msg.replace.as_ref() {
+        let mut fallback = json!({
+            "body": format!("* {}", msg.body),
+            "msgtype": msg.mtype,
+        });
+        if let (Some(f), Some(f_b)) = (msg.format.as_ref(), msg.formatted_body.as_ref()) {
+            fallback["formatted_body"] = json!(format!("* {}", f_b));
+            fallback["format"] = json!(f);
+        }
+        fallback["m.new_content"] = body;
+        fallback["m.relates_to"] = json!({
+            "rel_type": "m.replace",
+            "event_id": replace,
+        });
+        body = fallback;
+    }
+
+    // In encrypted rooms only the ciphertext is sent to the server
+    let (event_type, body) = match olm_machine {
+        Some(olm_machine) => {
+            // Files uploaded before the room was encrypted aren't sent to it
+            if body["url"].is_string() || body["info"]["thumbnail_url"].is_string() {
+                error!("Can't send an unencrypted file to an encrypted room");
+                return Err(SendMsgError::Failed(txn_id));
+            }
+
+            let encrypted = crypto::encrypt_room_event(
+                base.clone(),
+                params.access_token.clone(),
+                &olm_machine,
+                &room_id,
+                "m.room.message",
+                &body,
+            )
+            .map_err(|err| {
+                error!("Can't encrypt the message: {:?}", err);
+                SendMsgError::Failed(txn_id.clone())
+            })?;
+            ("m.room.encrypted", encrypted)
+        }
+        None => ("m.room.message", body),
+    };
 
-    create_message_event(base, &params, &body, &room_id, "m.room.message", &txn_id)
-        .and_then(|request| {
-            let response = HTTP_CLIENT
-                .get_client()
-                .execute(request)?
-                .json::<CreateMessageEventResponse>()?;
+    create_message_event(base, &params, &body, &room_id, event_type, &txn_id)
+        .map_err(Into::into)
+        .and_then(|request| -> Result<_, Error> {
+            let response: CreateMessageEventResponse = execute::<Error>(request)?.json()?;
 
             Ok((txn_id.clone(), response.event_id))
         })
-        .or(Err(SendMsgError(txn_id)))
+        .map_err(|err| SendMsgError::from_error(txn_id, err))
 }
 
 #[derive(Debug)]
-pub struct SendTypingError(ReqwestError);
+pub enum SendReactionError {
+    Reqwest(ReqwestError),
+    Matrix(MatrixError),
+    Encryption(crypto::EncryptEventError),
+}
 
-impl From<ReqwestError> for SendTypingError {
+impl From<ReqwestError> for SendReactionError {
     fn from(err: ReqwestError) -> Self {
-        Self(err)
+        Self::Reqwest(err)
+    }
+}
+
+impl From<MatrixError> for SendReactionError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
+impl From<crypto::EncryptEventError> for SendReactionError {
+    fn from(err: crypto::EncryptEventError) -> Self {
+        Self::Encryption(err)
+    }
+}
+
+impl HandleError for SendReactionError {
+    fn handle_error(&self) {
+        error!("Can't send the reaction: {:?}", self);
+        let error = i18n("Couldn’t send the reaction");
+        APPOP!(show_error, (error));
+    }
+}
+
+/// Annotate a message with a reaction, `key` is usually an emoji
+pub fn send_reaction(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    event_id: EventId,
+    key: String,
+    olm_machine: Option<SharedOlmMachine>,
+) -> Result<Option<EventId>, SendReactionError> {
+    let params = CreateMessageEventParameters { access_token };
+    let txn_id = Message::new_txn_id();
+
+    let body = json!({
+        "m.relates_to": {
+            "rel_type": "m.annotation",
+            "event_id": event_id,
+            "key": key,
+        }
+    });
+
+    let (event_type, body) = match olm_machine {
+        Some(olm_machine) => {
+            let encrypted = crypto::encrypt_room_event(
+                base.clone(),
+                params.access_token.clone(),
+                &olm_machine,
+                &room_id,
+                "m.reaction",
+                &body,
+            )?;
+            ("m.room.encrypted", encrypted)
+        }
+        None => ("m.reaction", body),
+    };
+
+    let request = create_message_event(base, &params, &body, &room_id, event_type, &txn_id)?;
+    let response: CreateMessageEventResponse = execute::<SendReactionError>(request)?.json()?;
+
+    Ok(response.event_id)
+}
+
+#[derive(Debug)]
+pub struct SendTypingError(Error);
+
+impl<T: Into<Error>> From<T> for SendTypingError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -329,7 +573,7 @@ pub fn send_typing(
     let body = TypingNotificationBody::Typing(Duration::from_secs(4));
 
     let request = send_typing_notification(base, &room_id, &user_id, &params, &body)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<SendTypingError>(request)?;
 
     Ok(())
 }
@@ -338,6 +582,7 @@ pub fn send_typing(
 pub enum SendMsgRedactionError {
     MessageNotSent,
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
 }
 
 impl From<ReqwestError> for SendMsgRedactionError {
@@ -346,6 +591,12 @@ impl From<ReqwestError> for SendMsgRedactionError {
     }
 }
 
+impl From<MatrixError> for SendMsgRedactionError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl HandleError for SendMsgRedactionError {
     fn handle_error(&self) {
         error!("Error deleting message: {:?}", self);
@@ -360,7 +611,7 @@ pub fn redact_msg(
     msg: Message,
 ) -> Result<(EventId, Option<EventId>), SendMsgRedactionError> {
     let room_id = &msg.room;
-    let txn_id = msg.get_txn_id();
+    let txn_id = Message::new_txn_id();
     let event_id = msg
         .id
         .as_ref()
@@ -373,7 +624,7 @@ pub fn redact_msg(
     };
 
     let request = redact_event(base, &params, &body, room_id, event_id, &txn_id)?;
-    let response: RedactEventResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: RedactEventResponse = execute::<SendMsgRedactionError>(request)?.json()?;
 
     Ok((event_id.clone(), response.event_id))
 }
@@ -381,7 +632,7 @@ pub fn redact_msg(
 #[derive(Debug)]
 pub enum JoinRoomError {
     Request(ReqwestError),
-    Response(StandardErrorResponse),
+    Response(MatrixError),
 }
 
 impl From<ReqwestError> for JoinRoomError {
@@ -390,8 +641,8 @@ impl From<ReqwestError> for JoinRoomError {
     }
 }
 
-impl From<StandardErrorResponse> for JoinRoomError {
-    fn from(err: StandardErrorResponse) -> Self {
+impl From<MatrixError> for JoinRoomError {
+    fn from(err: MatrixError) -> Self {
         Self::Response(err)
     }
 }
@@ -407,8 +658,8 @@ impl HandleError for JoinRoomError {
                 info = None;
             }
             JoinRoomError::Response(error) => {
-                err_str = error.error.clone();
-                info = Some(error.error.clone());
+                err_str = error.to_string();
+                info = Some(matrix_error_info(error));
             }
         };
 
@@ -436,23 +687,17 @@ pub fn join_room(
     };
 
     let request = join_room_req(base, &room_id_or_alias_id, &params)?;
-    let response = HTTP_CLIENT.get_client().execute(request)?;
+    let response: JoinRoomResponse = execute::<JoinRoomError>(request)?.json()?;
 
-    if response.status() != StatusCode::OK {
-        let resp: StandardErrorResponse = response.json()?;
-        Err(JoinRoomError::Response(resp))
-    } else {
-        let resp: JoinRoomResponse = response.json()?;
-        Ok(resp.room_id)
-    }
+    Ok(response.room_id)
 }
 
 #[derive(Debug)]
-pub struct LeaveRoomError(ReqwestError);
+pub struct LeaveRoomError(Error);
 
-impl From<ReqwestError> for LeaveRoomError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for LeaveRoomError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -466,17 +711,17 @@ pub fn leave_room(
     let params = LeaveRoomParameters { access_token };
 
     let request = leave_room_req(base, &room_id, &params)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<LeaveRoomError>(request)?;
 
     Ok(())
 }
 
 #[derive(Debug)]
-pub struct MarkedAsReadError(ReqwestError);
+pub struct MarkedAsReadError(Error);
 
-impl From<ReqwestError> for MarkedAsReadError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for MarkedAsReadError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -496,16 +741,16 @@ pub fn mark_as_read(
     };
 
     let request = set_read_marker(base, &params, &body, &room_id)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<MarkedAsReadError>(request)?;
 
     Ok((room_id, event_id))
 }
 #[derive(Debug)]
-pub struct SetRoomNameError(ReqwestError);
+pub struct SetRoomNameError(Error);
 
-impl From<ReqwestError> for SetRoomNameError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for SetRoomNameError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -524,17 +769,17 @@ pub fn set_room_name(
     });
 
     let request = create_state_events_for_key(base, &params, &body, &room_id, "m.room.name")?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<SetRoomNameError>(request)?;
 
     Ok(())
 }
 
 #[derive(Debug)]
-pub struct SetRoomTopicError(ReqwestError);
+pub struct SetRoomTopicError(Error);
 
-impl From<ReqwestError> for SetRoomTopicError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for SetRoomTopicError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -553,7 +798,7 @@ pub fn set_room_topic(
     });
 
     let request = create_state_events_for_key(base, &params, &body, &room_id, "m.room.topic")?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<SetRoomTopicError>(request)?;
 
     Ok(())
 }
@@ -562,6 +807,7 @@ pub fn set_room_topic(
 pub enum SetRoomAvatarError {
     Io(IoError),
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     ParseUrl(UrlError),
 }
 
@@ -571,11 +817,18 @@ impl From<ReqwestError> for SetRoomAvatarError {
     }
 }
 
+impl From<MatrixError> for SetRoomAvatarError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl From<AttachedFileError> for SetRoomAvatarError {
     fn from(err: AttachedFileError) -> Self {
         match err {
             AttachedFileError::Io(err) => Self::Io(err),
             AttachedFileError::Reqwest(err) => Self::Reqwest(err),
+            AttachedFileError::Matrix(err) => Self::Matrix(err),
             AttachedFileError::ParseUrl(err) => Self::ParseUrl(err),
         }
     }
@@ -597,7 +850,7 @@ pub fn set_room_avatar(
 
     let body = json!({ "url": upload_file_response.content_uri.as_str() });
     let request = create_state_events_for_key(base, &params, &body, &room_id, "m.room.avatar")?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<SetRoomAvatarError>(request)?;
 
     Ok(())
 }
@@ -606,6 +859,7 @@ pub fn set_room_avatar(
 pub enum AttachedFileError {
     Io(IoError),
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     ParseUrl(UrlError),
 }
 
@@ -615,6 +869,12 @@ impl From<ReqwestError> for AttachedFileError {
     }
 }
 
+impl From<MatrixError> for AttachedFileError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl From<IoError> for AttachedFileError {
     fn from(err: IoError) -> Self {
         Self::Io(err)
@@ -627,14 +887,23 @@ impl From<UrlError> for AttachedFileError {
     }
 }
 
+impl AttachedFileError {
+    /// Whether the upload failed because we couldn't reach the server
+    pub fn is_offline(&self) -> bool {
+        match self {
+            Self::Reqwest(err) => err.is_timeout() || err.is_request(),
+            _ => false,
+        }
+    }
+}
+
 impl HandleError for AttachedFileError {
     fn handle_error(&self) {
         let err_str = format!("{:?}", self);
         error!(
-            "attaching {}: retrying send",
+            "attaching {}",
             remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
         );
-        APPOP!(retry_send);
     }
 }
 
@@ -651,13 +920,32 @@ pub fn upload_file(
     let contents = fs::read(fname)?;
     let request = create_content(base, &params_upload, contents)?;
 
-    HTTP_CLIENT
-        .get_client()
-        .execute(request)?
+    execute::<AttachedFileError>(request)?
         .json()
         .map_err(Into::into)
 }
 
+/// Uploads the file encrypted with a new key, for the encrypted rooms. The keys
+/// are in the fragment of the returned URL, see `EncryptedFile::media_url`
+pub fn upload_encrypted_file(
+    base: Url,
+    access_token: AccessToken,
+    fname: &Path,
+) -> Result<Url, AttachedFileError> {
+    let params_upload = CreateContentParameters {
+        access_token,
+        filename: None,
+    };
+
+    let mut contents = fs::read(fname)?;
+    let keys = encrypt_attachment(&mut contents);
+    let request = create_content(base, &params_upload, contents)?;
+    let response: CreateContentResponse = execute::<AttachedFileError>(request)?.json()?;
+    let url = Url::parse(&response.content_uri)?;
+
+    Ok(EncryptedFile { url, keys }.media_url())
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum RoomType {
     Public,
@@ -665,11 +953,11 @@ pub enum RoomType {
 }
 
 #[derive(Debug)]
-pub struct NewRoomError(ReqwestError);
+pub struct NewRoomError(Error);
 
-impl From<ReqwestError> for NewRoomError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for NewRoomError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -709,7 +997,7 @@ pub fn new_room(
     };
 
     let request = create_room(base, &params, &body)?;
-    let response: CreateRoomResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: CreateRoomResponse = execute::<NewRoomError>(request)?.json()?;
 
     Ok(Room {
         name: Some(name),
@@ -720,6 +1008,7 @@ pub fn new_room(
 #[derive(Debug)]
 pub enum DirectChatError {
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     EventsDeserialization(IdError),
 }
 
@@ -729,6 +1018,12 @@ impl From<ReqwestError> for DirectChatError {
     }
 }
 
+impl From<MatrixError> for DirectChatError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl HandleError for DirectChatError {
     fn handle_error(&self) {
         error!("Can't set m.direct: {:?}", self);
@@ -757,7 +1052,7 @@ fn update_direct_chats(
     };
 
     let request = get_global_account_data(base.clone(), &params, &user_id, "m.direct")?;
-    let response: JsonValue = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: JsonValue = execute::<DirectChatError>(request)?.json()?;
 
     let mut directs = response
         .as_object()
@@ -784,7 +1079,7 @@ fn update_direct_chats(
     let params = SetGlobalAccountDataParameters { access_token };
 
     let request = set_global_account_data(base, &params, &json!(directs), &user_id, "m.direct")?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<DirectChatError>(request)?;
 
     Ok(())
 }
@@ -814,7 +1109,7 @@ pub fn direct_chat(
     };
 
     let request = create_room(base.clone(), &params, &body)?;
-    let response: CreateRoomResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: CreateRoomResponse = execute::<DirectChatError>(request)?.json()?;
 
     update_direct_chats(
         base,
@@ -832,11 +1127,11 @@ pub fn direct_chat(
 }
 
 #[derive(Debug)]
-pub struct AddedToFavError(ReqwestError);
+pub struct AddedToFavError(Error);
 
-impl From<ReqwestError> for AddedToFavError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for AddedToFavError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -858,17 +1153,17 @@ pub fn add_to_fav(
         delete_tag(base, &user_id, &room_id, "m.favourite", &params)
     }?;
 
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<AddedToFavError>(request)?;
 
     Ok((room_id, tofav))
 }
 
 #[derive(Debug)]
-pub struct InviteError(ReqwestError);
+pub struct InviteError(Error);
 
-impl From<ReqwestError> for InviteError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for InviteError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -884,17 +1179,17 @@ pub fn invite(
     let body = InviteUserBody { user_id };
 
     let request = invite_user(base, &room_id, &params, &body)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<InviteError>(request)?;
 
     Ok(())
 }
 
 #[derive(Debug)]
-pub struct ChangeLanguageError(ReqwestError);
+pub struct ChangeLanguageError(Error);
 
-impl From<ReqwestError> for ChangeLanguageError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for ChangeLanguageError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -928,7 +1223,545 @@ pub fn set_language(
         "org.gnome.fractal.language",
     )?;
 
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<ChangeLanguageError>(request)?;
+
+    Ok(())
+}
+
+/// Logs why a change the user asked for failed and tells them
+pub(super) fn show_request_error(error: String, err: &dyn Debug, response: Option<&MatrixError>) {
+    let err_str = format!("{:?}", err);
+    error!(
+        "{}",
+        remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+    );
+    let info = response.map(matrix_error_info);
+    APPOP!(show_error_with_info, (error, info));
+}
+
+#[derive(Debug)]
+pub enum KickUserError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for KickUserError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for KickUserError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for KickUserError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Can’t remove the user from the room");
+        show_request_error(error, self, response);
+    }
+}
+
+pub fn kick_user(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    user_id: UserId,
+    reason: Option<String>,
+) -> Result<(), KickUserError> {
+    let params = KickUserParameters { access_token };
+    let body = KickUserBody { user_id, reason };
+
+    let request = kick_user_req(base, &room_id, &params, &body)?;
+    execute::<KickUserError>(request)?;
 
     Ok(())
 }
+
+#[derive(Debug)]
+pub enum BanUserError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for BanUserError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for BanUserError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for BanUserError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Can’t ban the user");
+        show_request_error(error, self, response);
+    }
+}
+
+pub fn ban_user(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    user_id: UserId,
+    reason: Option<String>,
+) -> Result<(), BanUserError> {
+    let params = BanUserParameters { access_token };
+    let body = BanUserBody { user_id, reason };
+
+    let request = ban_user_req(base, &room_id, &params, &body)?;
+    execute::<BanUserError>(request)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum UnbanUserError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for UnbanUserError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for UnbanUserError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for UnbanUserError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Can’t unban the user");
+        show_request_error(error, self, response);
+    }
+}
+
+pub fn unban_user(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    user_id: UserId,
+) -> Result<(), UnbanUserError> {
+    let params = UnbanUserParameters { access_token };
+    let body = UnbanUserBody { user_id };
+
+    let request = unban_user_req(base, &room_id, &params, &body)?;
+    execute::<UnbanUserError>(request)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum SetPowerLevelError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for SetPowerLevelError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for SetPowerLevelError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for SetPowerLevelError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Can’t change the power level");
+        show_request_error(error, self, response);
+    }
+}
+
+/// Changes the power level of a member of the room, without a level they get the default
+/// one of the room
+pub fn set_power_level(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    user_id: UserId,
+    level: Option<i64>,
+) -> Result<(), SetPowerLevelError> {
+    let params = GetStateEventsForKeyParameters {
+        access_token: access_token.clone(),
+    };
+
+    let request = get_state_events_for_key(base.clone(), &params, &room_id, "m.room.power_levels")?;
+    let mut content: JsonValue = execute::<SetPowerLevelError>(request)?.json()?;
+
+    let uid = user_id.to_string();
+    match level {
+        Some(level) => content["users"][&uid] = json!(level),
+        None => {
+            if let Some(users) = content["users"].as_object_mut() {
+                users.remove(&uid);
+            }
+        }
+    }
+
+    let params = CreateStateEventsForKeyParameters { access_token };
+    let request =
+        create_state_events_for_key(base, &params, &content, &room_id, "m.room.power_levels")?;
+    execute::<SetPowerLevelError>(request)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum SetPowerLevelsError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for SetPowerLevelsError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for SetPowerLevelsError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for SetPowerLevelsError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Can’t save the permissions of the room");
+        show_request_error(error, self, response);
+    }
+}
+
+/// Replaces the power levels of the room, the fields of the current event we don't know
+/// about are kept as they are
+pub fn set_power_levels(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    levels: PowerLevelsEventContent,
+) -> Result<(), SetPowerLevelsError> {
+    let params = GetStateEventsForKeyParameters {
+        access_token: access_token.clone(),
+    };
+
+    let request = get_state_events_for_key(base.clone(), &params, &room_id, "m.room.power_levels")?;
+    let mut content: JsonValue = execute::<SetPowerLevelsError>(request)?.json()?;
+
+    if let (Some(content), JsonValue::Object(levels)) = (content.as_object_mut(), json!(levels)) {
+        content.extend(levels);
+    }
+
+    let params = CreateStateEventsForKeyParameters { access_token };
+    let request =
+        create_state_events_for_key(base, &params, &content, &room_id, "m.room.power_levels")?;
+    execute::<SetPowerLevelsError>(request)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum IgnoreUserError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for IgnoreUserError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for IgnoreUserError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for IgnoreUserError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Can’t ignore the user");
+        show_request_error(error, self, response);
+    }
+}
+
+/// Adds a user to the ones whose messages and invitations the server doesn't send us
+pub fn ignore_user(
+    base: Url,
+    access_token: AccessToken,
+    user_id: UserId,
+    ignored: UserId,
+) -> Result<(), IgnoreUserError> {
+    let params = GetGlobalAccountDataParameters {
+        access_token: access_token.clone(),
+    };
+
+    let request = get_global_account_data(base.clone(), &params, &user_id, "m.ignored_user_list")?;
+    // We haven't ignored anyone yet
+    let mut content: JsonValue = match execute(request) {
+        Ok(response) => response.json()?,
+        Err(IgnoreUserError::Response(err)) if err.status_code == StatusCode::NOT_FOUND => {
+            json!({})
+        }
+        Err(err) => return Err(err),
+    };
+
+    content["ignored_users"][&ignored.to_string()] = json!({});
+
+    let params = SetGlobalAccountDataParameters { access_token };
+    let request =
+        set_global_account_data(base, &params, &content, &user_id, "m.ignored_user_list")?;
+    execute::<IgnoreUserError>(request)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum GetRoomStateError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for GetRoomStateError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for GetRoomStateError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for GetRoomStateError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Can’t load the state of the room");
+        show_request_error(error, self, response);
+    }
+}
+
+/// All the state events of the room, as the server sends them
+pub fn get_room_state(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+) -> Result<JsonValue, GetRoomStateError> {
+    let params = GetStateEventsParameters { access_token };
+
+    let request = get_state_events(base, &params, &room_id)?;
+    let state = execute::<GetRoomStateError>(request)?.json()?;
+
+    Ok(state)
+}
+
+#[derive(Debug)]
+pub enum RoomSettingsError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for RoomSettingsError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for RoomSettingsError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for RoomSettingsError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Can’t change the settings of the room");
+        show_request_error(error, self, response);
+    }
+}
+
+/// Sends a state event with an empty state key, the ones of the room settings are like this
+pub fn set_room_state(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    event_type: &str,
+    content: JsonValue,
+) -> Result<(), RoomSettingsError> {
+    let params = CreateStateEventsForKeyParameters { access_token };
+    let request = create_state_events_for_key(base, &params, &content, &room_id, event_type)?;
+    execute::<RoomSettingsError>(request)?;
+
+    Ok(())
+}
+
+/// Points the alias to the room in the directory of the server of the alias
+pub fn create_room_alias(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    alias: RoomAliasId,
+) -> Result<(), RoomSettingsError> {
+    let params = CreateRoomAliasParameters { access_token };
+    let body = CreateRoomAliasBody { room_id };
+    let request = create_room_alias_req(base, &alias, &params, &body)?;
+    execute::<RoomSettingsError>(request)?;
+
+    Ok(())
+}
+
+pub fn delete_room_alias(
+    base: Url,
+    access_token: AccessToken,
+    alias: RoomAliasId,
+) -> Result<(), RoomSettingsError> {
+    let params = DeleteRoomAliasParameters { access_token };
+    let request = delete_room_alias_req(base, &alias, &params)?;
+    execute::<RoomSettingsError>(request)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct RoomVisibilityError(Error);
+
+impl<T: Into<Error>> From<T> for RoomVisibilityError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for RoomVisibilityError {}
+
+/// Whether the room is published in the public directory of the server
+pub fn get_room_visibility(
+    base: Url,
+    room_id: RoomId,
+) -> Result<(RoomId, bool), RoomVisibilityError> {
+    let request = get_room_visibility_req(base, &room_id)?;
+    let response: GetRoomVisibilityResponse = execute::<RoomVisibilityError>(request)?.json()?;
+
+    Ok((room_id, response.visibility == Visibility::Public))
+}
+
+pub fn set_room_visibility(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    published: bool,
+) -> Result<(), RoomSettingsError> {
+    let visibility = if published {
+        Visibility::Public
+    } else {
+        Visibility::Private
+    };
+    let params = SetRoomVisibilityParameters { access_token };
+    let body = SetRoomVisibilityBody { visibility };
+    let request = set_room_visibility_req(base, &room_id, &params, &body)?;
+    execute::<RoomSettingsError>(request)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum UpgradeRoomError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for UpgradeRoomError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for UpgradeRoomError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for UpgradeRoomError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Can’t upgrade the room");
+        show_request_error(error, self, response);
+    }
+}
+
+/// The version new rooms get on the server, the one rooms should be upgraded to
+pub fn get_default_room_version(
+    base: Url,
+    access_token: AccessToken,
+) -> Result<Option<String>, UpgradeRoomError> {
+    let params = GetCapabilitiesParameters { access_token };
+    let request = get_capabilities(base, &params)?;
+    let response: GetCapabilitiesResponse = execute::<UpgradeRoomError>(request)?.json()?;
+
+    Ok(response
+        .capabilities
+        .room_versions
+        .map(|versions| versions.default))
+}
+
+/// Returns the id of the room that replaces the upgraded one, we are already in it
+pub fn upgrade_room(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    new_version: String,
+) -> Result<(RoomId, RoomId), UpgradeRoomError> {
+    let params = UpgradeRoomParameters { access_token };
+    let body = UpgradeRoomBody { new_version };
+    let request = upgrade_room_req(base, &room_id, &params, &body)?;
+    let response: UpgradeRoomResponse = execute::<UpgradeRoomError>(request)?.json()?;
+
+    Ok((room_id, response.replacement_room))
+}
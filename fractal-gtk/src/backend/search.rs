@@ -0,0 +1,187 @@
+use fractal_api::error::MatrixError;
+use fractal_api::identifiers::{Error as IdError, EventId, RoomId};
+use fractal_api::reqwest::Error as ReqwestError;
+use fractal_api::url::Url;
+use std::convert::TryFrom;
+
+use crate::globals;
+
+use crate::backend::crypto;
+use crate::crypto::SharedOlmMachine;
+
+use crate::cache::SearchQuery;
+use crate::types::Message;
+use fractal_api::r0::context::get_context::request as get_context;
+use fractal_api::r0::context::get_context::Parameters as GetContextParameters;
+use fractal_api::r0::context::get_context::Response as GetContextResponse;
+use fractal_api::r0::filter::RoomEventFilter;
+use fractal_api::r0::search::events::request as search_events;
+use fractal_api::r0::search::events::Body as SearchEventsBody;
+use fractal_api::r0::search::events::Categories as SearchCategories;
+use fractal_api::r0::search::events::Key as SearchKey;
+use fractal_api::r0::search::events::OrderBy;
+use fractal_api::r0::search::events::Parameters as SearchEventsParameters;
+use fractal_api::r0::search::events::Response as SearchEventsResponse;
+use fractal_api::r0::search::events::RoomEventsCriteria;
+use fractal_api::r0::AccessToken;
+
+use super::{execute, HandleError};
+use crate::app::App;
+use crate::i18n::i18n;
+use crate::APPOP;
+
+#[derive(Debug)]
+pub enum MessageSearchError {
+    Reqwest(ReqwestError),
+    Matrix(MatrixError),
+    EventsDeserialization(IdError),
+}
+
+impl From<ReqwestError> for MessageSearchError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Reqwest(err)
+    }
+}
+
+impl From<MatrixError> for MessageSearchError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
+impl From<IdError> for MessageSearchError {
+    fn from(err: IdError) -> Self {
+        Self::EventsDeserialization(err)
+    }
+}
+
+impl HandleError for MessageSearchError {
+    fn handle_error(&self) {
+        APPOP!(search_failed);
+    }
+}
+
+/* Search the message history of every joined room, the server only knows about the sender and
+ * room filters of the query so we apply the dates ourselves
+ * https://matrix.org/docs/spec/client_server/r0.6.1#post-matrix-client-r0-search
+ */
+pub fn search_messages(
+    base: Url,
+    access_token: AccessToken,
+    query: SearchQuery,
+    order_by: OrderBy,
+    next_batch: Option<String>,
+) -> Result<(Vec<Message>, Vec<String>, Option<String>), MessageSearchError> {
+    let params = SearchEventsParameters {
+        access_token,
+        next_batch,
+    };
+
+    let sender = query.sender.as_ref().map(ToString::to_string);
+    let room = query.room.as_ref().map(ToString::to_string);
+    let body = SearchEventsBody {
+        search_categories: SearchCategories {
+            room_events: RoomEventsCriteria {
+                search_term: query.text.clone(),
+                keys: Some(vec![SearchKey::ContentBody]),
+                filter: RoomEventFilter {
+                    types: Some(Message::types().to_vec()),
+                    senders: sender.as_ref().map(|s| vec![s.as_str()]),
+                    rooms: room.as_ref().map(|r| vec![r.as_str()]),
+                    ..Default::default()
+                },
+                order_by: Some(order_by),
+                ..Default::default()
+            },
+        },
+    };
+
+    let request = search_events(base, &params, &body)?;
+    let response: SearchEventsResponse = execute::<MessageSearchError>(request)?.json()?;
+    let room_events = response.search_categories.room_events;
+
+    let hits: Vec<Message> = room_events
+        .results
+        .iter()
+        .map(|hit| &hit.result)
+        .filter(Message::supported_event)
+        .map(|ev| {
+            let room_id = RoomId::try_from(ev["room_id"].as_str().unwrap_or_default())?;
+            Message::parse_room_message(&room_id, ev)
+        })
+        .collect::<Result<_, IdError>>()?;
+    let hits = hits.into_iter().filter(|msg| query.matches(msg)).collect();
+
+    Ok((hits, room_events.highlights, room_events.next_batch))
+}
+
+#[derive(Debug)]
+pub enum MessageContextError {
+    Reqwest(ReqwestError),
+    Matrix(MatrixError),
+    EventsDeserialization(IdError),
+}
+
+impl From<ReqwestError> for MessageContextError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Reqwest(err)
+    }
+}
+
+impl From<MatrixError> for MessageContextError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
+impl From<IdError> for MessageContextError {
+    fn from(err: IdError) -> Self {
+        Self::EventsDeserialization(err)
+    }
+}
+
+impl HandleError for MessageContextError {
+    fn handle_error(&self) {
+        let error = i18n("Couldn’t load the message history around this message");
+        APPOP!(show_error, (error));
+    }
+}
+
+/* Load the messages surrounding an event, returning them from the oldest to the newest together
+ * with the token to keep paginating backwards from there
+ * https://matrix.org/docs/spec/client_server/r0.6.1#get-matrix-client-r0-rooms-roomid-context-eventid
+ */
+pub fn get_message_context(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    event_id: EventId,
+    olm_machine: Option<SharedOlmMachine>,
+) -> Result<(Vec<Message>, RoomId, EventId, Option<String>), MessageContextError> {
+    let params = GetContextParameters {
+        access_token,
+        limit: globals::SEARCH_CONTEXT_LIMIT,
+        filter: RoomEventFilter {
+            types: Some(Message::timeline_types()),
+            ..Default::default()
+        },
+    };
+
+    let request = get_context(base, &params, &room_id, &event_id)?;
+    let response: GetContextResponse = execute::<MessageContextError>(request)?.json()?;
+
+    let events: Vec<_> = response
+        .events_before
+        .into_iter()
+        .rev()
+        .chain(Some(response.event))
+        .chain(response.events_after)
+        .collect();
+    let events = match olm_machine {
+        Some(olm_machine) => crypto::decrypt_room_events(&olm_machine, &room_id, events),
+        None => events,
+    };
+    let list = Message::from_json_events_iter(&room_id, events.iter())?;
+
+    Ok((list, room_id, event_id, response.start))
+}
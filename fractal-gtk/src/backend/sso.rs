@@ -0,0 +1,250 @@
+//! Single sign-on happens in the web browser. The homeserver ends it with a
+//! redirection to a local address, with the token to log in as a parameter,
+//! so a tiny HTTP server waits for it on the loopback interface.
+
+use fractal_api::url::Url;
+use log::warn;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::io::Error as IoError;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::actions::AppState;
+use crate::app::App;
+use crate::i18n::i18n;
+use crate::APPOP;
+
+use super::HandleError;
+
+// The user may never finish in the browser
+const SSO_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+#[derive(Debug)]
+pub enum SsoError {
+    Io(IoError),
+    Timeout,
+    /// The user gave up on the login in the browser
+    Cancelled,
+}
+
+impl From<IoError> for SsoError {
+    fn from(err: IoError) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl HandleError for SsoError {
+    fn handle_error(&self) {
+        let st = AppState::Login;
+        match self {
+            Self::Cancelled => {
+                APPOP!(set_state, (st));
+                return;
+            }
+            Self::Io(err) => warn!("Single sign-on failed: {:?}", err),
+            Self::Timeout => {}
+        }
+
+        let error = i18n("Can’t login, try again");
+        APPOP!(show_error, (error));
+        APPOP!(set_state, (st));
+    }
+}
+
+/// Stops a listener waiting for the browser
+#[derive(Clone, Debug, Default)]
+pub struct SsoCancel(Arc<AtomicBool>);
+
+impl SsoCancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+pub struct SsoListener {
+    listener: TcpListener,
+    redirect_url: Url,
+    cancel: SsoCancel,
+}
+
+impl SsoListener {
+    pub fn bind() -> Result<Self, SsoError> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let port = listener.local_addr()?.port();
+        /* Any program of the machine can connect to the listener, only the browser coming
+         * back from the homeserver knows the secret path so nobody else can log us in */
+        let secret: String = thread_rng().sample_iter(&Alphanumeric).take(32).collect();
+        let redirect_url = Url::parse(&format!("http://127.0.0.1:{}/{}", port, secret))
+            .expect("Malformed SSO redirect URL");
+
+        Ok(Self {
+            listener,
+            redirect_url,
+            cancel: Default::default(),
+        })
+    }
+
+    /// The address the homeserver has to redirect the browser to
+    pub fn redirect_url(&self) -> &Url {
+        &self.redirect_url
+    }
+
+    /// Lets the UI stop the wait for the browser
+    pub fn cancel_handle(&self) -> SsoCancel {
+        self.cancel.clone()
+    }
+
+    /// Blocks until the browser comes back with the login token
+    pub fn wait_for_token(self) -> Result<String, SsoError> {
+        let deadline = Instant::now() + SSO_TIMEOUT;
+        self.listener.set_nonblocking(true)?;
+
+        while Instant::now() < deadline {
+            if self.cancel.is_cancelled() {
+                return Err(SsoError::Cancelled);
+            }
+
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(200));
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            // Browsers also ask for things like the favicon, only the
+            // redirection has the token
+            match self.handle_connection(stream) {
+                Ok(Some(token)) => return Ok(token),
+                Ok(None) => {}
+                Err(err) => warn!("Bad request on the SSO listener: {:?}", err),
+            }
+        }
+
+        Err(SsoError::Timeout)
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> Result<Option<String>, IoError> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+            let read = stream.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+
+        let request = String::from_utf8_lossy(&request);
+        let token = request
+            .lines()
+            .next()
+            .and_then(|line| login_token(&self.redirect_url, line));
+
+        let (status, body) = if token.is_some() {
+            (
+                "200 OK",
+                i18n("You are logged in. You can close this page and go back to Fractal."),
+            )
+        } else {
+            ("404 Not Found", String::new())
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+
+        Ok(token)
+    }
+}
+
+/// Gets the `loginToken` parameter from the request line of an HTTP request,
+/// like `GET /secret?loginToken=abc HTTP/1.1`. The path has to be the one of
+/// `redirect_url`, with its secret.
+pub fn login_token(redirect_url: &Url, request_line: &str) -> Option<String> {
+    let mut parts = request_line.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+
+    let url = redirect_url.join(parts.next()?).ok()?;
+    if url.path() != redirect_url.path() {
+        return None;
+    }
+    url.query_pairs()
+        .find(|(key, _)| key == "loginToken")
+        .map(|(_, value)| value.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sso_login_token_from_request_line() {
+        let redirect_url = Url::parse("http://127.0.0.1:4242/s3cr3t").unwrap();
+
+        assert_eq!(
+            login_token(&redirect_url, "GET /s3cr3t?loginToken=abc%2Bdef HTTP/1.1"),
+            Some("abc+def".to_string())
+        );
+        assert_eq!(
+            login_token(&redirect_url, "GET /favicon.ico HTTP/1.1"),
+            None
+        );
+        assert_eq!(
+            login_token(&redirect_url, "POST /s3cr3t?loginToken=abc HTTP/1.1"),
+            None
+        );
+        // Without the secret
+        assert_eq!(
+            login_token(&redirect_url, "GET /?loginToken=abc HTTP/1.1"),
+            None
+        );
+        assert_eq!(
+            login_token(&redirect_url, "GET /guess?loginToken=abc HTTP/1.1"),
+            None
+        );
+        assert_eq!(
+            login_token(&redirect_url, "GET /s3cr3t/../?loginToken=abc HTTP/1.1"),
+            None
+        );
+    }
+
+    #[test]
+    fn sso_listener_secret() {
+        let first = SsoListener::bind().unwrap();
+        let second = SsoListener::bind().unwrap();
+
+        assert_eq!(first.redirect_url().path().len(), 33);
+        assert_ne!(first.redirect_url().path(), second.redirect_url().path());
+    }
+
+    #[test]
+    fn sso_listener_cancel() {
+        let listener = SsoListener::bind().unwrap();
+        listener.cancel_handle().cancel();
+
+        assert!(matches!(
+            listener.wait_for_token(),
+            Err(SsoError::Cancelled)
+        ));
+    }
+}
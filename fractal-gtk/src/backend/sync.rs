@@ -1,5 +1,7 @@
+use crate::cache::{self, FCache};
 use crate::client::ProxySettings;
-use crate::error::{Error, StandardErrorResponse};
+use crate::crypto::SharedOlmMachine;
+use crate::error::Error;
 use crate::globals;
 use crate::types::Event;
 use crate::types::Member;
@@ -7,6 +9,10 @@ use crate::types::Message;
 use crate::types::Room;
 use crate::types::RoomMembership;
 use crate::types::RoomTag;
+use fractal_api::error::MatrixError;
+use fractal_api::events::ephemeral::EphemeralEventContent;
+use fractal_api::events::room::RoomEventContent;
+use fractal_api::events::RoomEvent;
 use fractal_api::r0::filter::EventFilter;
 use fractal_api::r0::filter::Filter;
 use fractal_api::r0::filter::RoomEventFilter;
@@ -19,21 +25,18 @@ use fractal_api::r0::sync::sync_events::UnreadNotificationsCount;
 use fractal_api::r0::AccessToken;
 
 use fractal_api::identifiers::{EventId, RoomId, UserId};
-use fractal_api::reqwest::blocking::{Client, Response};
+use fractal_api::reqwest::blocking::{Client, Request};
 use fractal_api::url::Url;
 use log::error;
+use rand::Rng;
 use serde::de::DeserializeOwned;
-use serde_json::value::from_value;
 use std::{
     collections::HashMap,
-    convert::{TryFrom, TryInto},
-    thread,
     time::{self, Duration},
 };
 
-use super::{remove_matrix_access_token_if_present, HandleError};
-use crate::app::App;
-use crate::APPOP;
+use super::crypto;
+use super::{execute_with, remove_matrix_access_token_if_present, HandleError};
 
 pub enum RoomElement {
     Name(RoomId, String),
@@ -41,10 +44,31 @@ pub enum RoomElement {
     NewAvatar(RoomId),
     MemberEvent(Event),
     RemoveMessage(RoomId, EventId),
+    /// The room was upgraded, the second one replaces it
+    Tombstone(RoomId, RoomId),
 }
 
+/// The first wait after a failed sync, it doubles with each failure that follows
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
 #[derive(Debug)]
-pub struct SyncError(Error, u64);
+pub struct SyncError(Error);
+
+impl SyncError {
+    /// The error answer of the server, if it got to answer
+    pub fn matrix_error(&self) -> Option<&MatrixError> {
+        match &self.0 {
+            Error::MatrixError(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    /// How long the server asked us to wait before the next sync
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.matrix_error().and_then(MatrixError::retry_after)
+    }
+}
 
 impl HandleError for SyncError {
     fn handle_error(&self) {
@@ -53,11 +77,22 @@ impl HandleError for SyncError {
             "SYNC Error: {}",
             remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
         );
-        let new_number_tries = self.1 + 1;
-        APPOP!(sync_error, (new_number_tries));
     }
 }
 
+/// How long to wait before trying the sync again after it failed `tries` times in a row.
+/// The wait is random between half and all of the exponential backoff, so clients don't
+/// come back all at once, and it's never shorter than what the server asked for.
+pub fn retry_delay(tries: u32, retry_after: Option<Duration>) -> Duration {
+    // Long before 2^16 it's past the maximum, and it can't overflow
+    let factor = 2u32.pow(tries.saturating_sub(1).min(16));
+    let backoff = (RETRY_DELAY * factor).min(MAX_RETRY_DELAY);
+    let half = backoff.as_millis() as u64 / 2;
+    let delay = Duration::from_millis(half + rand::thread_rng().gen_range(0, half + 1));
+
+    retry_after.map_or(delay, |retry_after| delay.max(retry_after))
+}
+
 #[derive(Debug)]
 pub struct RoomsError(Error);
 
@@ -92,15 +127,32 @@ impl<T: Into<Error>> From<T> for RoomMessagesError {
 impl HandleError for RoomMessagesError {}
 
 #[derive(Debug)]
-pub struct RoomElementError(Error);
+pub enum RoomElementError {
+    MissingRedacts,
+}
+
+impl HandleError for RoomElementError {}
+
+#[derive(Debug)]
+pub struct UnreadSyncError(Error);
 
-impl<T: Into<Error>> From<T> for RoomElementError {
+impl<T: Into<Error>> From<T> for UnreadSyncError {
     fn from(err: T) -> Self {
         Self(err.into())
     }
 }
 
-impl HandleError for RoomElementError {}
+impl UnreadSyncError {
+    /// How long the server asked us to wait before the next sync
+    pub fn retry_after(&self) -> Option<Duration> {
+        match &self.0 {
+            Error::MatrixError(err) => err.retry_after(),
+            _ => None,
+        }
+    }
+}
+
+impl HandleError for UnreadSyncError {}
 
 pub enum SyncRet {
     NoSince {
@@ -121,10 +173,10 @@ pub fn sync(
     base: Url,
     access_token: AccessToken,
     user_id: UserId,
+    olm_machine: Option<SharedOlmMachine>,
     join_to_room: Option<RoomId>,
     since: Option<String>,
     initial: bool,
-    number_tries: u64,
 ) -> Result<SyncRet, SyncError> {
     let (timeout, filter) = if !initial {
         (time::Duration::from_secs(30), Default::default())
@@ -137,7 +189,7 @@ pub fn sync(
                     ..Default::default()
                 }),
                 timeline: Some(RoomEventFilter {
-                    types: Some(vec!["m.room.message", "m.sticker"]),
+                    types: Some(Message::timeline_types()),
                     not_types: vec!["m.call.*"],
                     limit: Some(globals::PAGE_LIMIT),
                     ..Default::default()
@@ -158,6 +210,7 @@ pub fn sync(
                 "sender",
                 "origin_server_ts",
                 "event_id",
+                "state_key",
                 "unsigned",
             ]),
             ..Default::default()
@@ -184,13 +237,27 @@ pub fn sync(
             .apply_to_client_builder(client_builder_timeout)
             .build()?;
         let request = sync_events(base.clone(), &params)?;
-        let response = client.execute(request)?;
-
-        matrix_response::<SyncResponse>(response)
+        matrix_response::<SyncResponse>(&client, request)
     });
 
     match query {
-        Ok(response) => {
+        Ok(mut response) => {
+            // We may be showing another account by now, this one keeps its own cache
+            let cache = cache::for_account(&user_id);
+            if let Some(olm_machine) = olm_machine.as_ref() {
+                crypto::decrypt_sync_response(
+                    base.clone(),
+                    access_token.clone(),
+                    olm_machine,
+                    &mut response,
+                );
+                if let Err(err) =
+                    crypto::upload_keys(base.clone(), access_token.clone(), olm_machine)
+                {
+                    err.handle_error();
+                }
+            }
+
             if since.is_none() {
                 let rooms = Room::from_sync_response(&response, user_id, access_token, base)
                     .map(|rooms| {
@@ -200,11 +267,14 @@ pub fn sync(
                     })
                     .map_err(Into::into);
 
+                start_timeline_chunks(&cache, &response);
+
                 let next_batch = response.next_batch;
 
                 Ok(SyncRet::NoSince { rooms, next_batch })
             } else {
                 let join = &response.rooms.join;
+                start_timeline_chunks(&cache, &response);
 
                 // New rooms
                 let update_rooms =
@@ -215,7 +285,7 @@ pub fn sync(
                 let room_messages = join
                     .iter()
                     .try_fold(Vec::new(), |mut acum, (k, room)| {
-                        let events = room.timeline.events.iter();
+                        let events = room.timeline.events.iter().map(RoomEvent::json);
                         Message::from_json_events_iter(&k, events).map(|msgs| {
                             acum.extend(msgs);
                             acum
@@ -233,25 +303,21 @@ pub fn sync(
                 let update_rooms_2 = Ok(join
                     .iter()
                     .map(|(k, room)| {
-                        let ephemerals = &room.ephemeral.events;
-                        let typing: Vec<Member> = ephemerals.iter()
-                            .flat_map(|event| {
-                                event
-                                    .get("content")
-                                    .and_then(|x| x.get("user_ids"))
-                                    .and_then(|x| x.as_array())
-                                    .unwrap_or(&vec![])
-                                    .to_owned()
+                        let typing: Vec<Member> = room
+                            .ephemeral
+                            .events
+                            .iter()
+                            .filter_map(|event| match &event.content {
+                                EphemeralEventContent::Typing(c) => Some(c.user_ids.iter()),
+                                _ => None,
                             })
-                            .filter_map(|user| from_value(user).ok())
+                            .flatten()
                             // ignoring the user typing notifications
-                            .filter(|user| *user != user_id)
-                            .map(|uid| {
-                                Member {
-                                    uid,
-                                    alias: None,
-                                    avatar: None,
-                                }
+                            .filter(|user| **user != user_id)
+                            .map(|uid| Member {
+                                uid: uid.clone(),
+                                alias: None,
+                                avatar: None,
                             })
                             .collect();
 
@@ -265,68 +331,59 @@ pub fn sync(
                 // Other events
                 let other = join
                     .iter()
-                    .flat_map(|(k, room)| {
-                        room.timeline
-                            .events
-                            .iter()
-                            .filter(|x| x["type"] != "m.room.message")
-                            .map(move |ev| {
-                                Ok(Event {
-                                    room: k.clone(),
-                                    sender: UserId::try_from(
-                                        ev["sender"].as_str().unwrap_or_default(),
-                                    )?,
-                                    content: ev["content"].clone(),
-                                    redacts: ev["redacts"]
-                                        .as_str()
-                                        .map(|r| r.try_into())
-                                        .transpose()?,
-                                    stype: ev["type"].as_str().map(Into::into).unwrap_or_default(),
-                                    id: ev["id"].as_str().map(Into::into).unwrap_or_default(),
-                                })
-                            })
-                    })
-                    .filter_map(|ev| {
-                        let ev = match ev {
-                            Ok(ev) => ev,
-                            Err(err) => return Some(Err(err)),
-                        };
-
-                        match ev.stype.as_ref() {
-                            "m.room.name" => {
-                                let name = ev.content["name"]
-                                    .as_str()
-                                    .map(Into::into)
-                                    .unwrap_or_default();
-                                Some(Ok(RoomElement::Name(ev.room.clone(), name)))
-                            }
-                            "m.room.topic" => {
-                                let t = ev.content["topic"]
-                                    .as_str()
-                                    .map(Into::into)
-                                    .unwrap_or_default();
-                                Some(Ok(RoomElement::Topic(ev.room.clone(), t)))
-                            }
-                            "m.room.avatar" => Some(Ok(RoomElement::NewAvatar(ev.room.clone()))),
-                            "m.room.member" => Some(Ok(RoomElement::MemberEvent(ev))),
-                            "m.room.redaction" => Some(Ok(RoomElement::RemoveMessage(
-                                ev.room.clone(),
-                                ev.redacts.expect(
-                                    "Events of type m.room.redaction should have a 'redacts' field",
-                                ),
-                            ))),
-                            "m.sticker" => {
-                                // This event is managed in the room list
-                                None
-                            }
-                            _ => {
-                                error!("EVENT NOT MANAGED: {:?}", ev);
-                                None
-                            }
+                    .flat_map(|(k, room)| room.timeline.events.iter().map(move |ev| (k, ev)))
+                    .filter_map(|(k, ev)| match &ev.content {
+                        RoomEventContent::Message(_) => None,
+                        RoomEventContent::Name(c) => {
+                            Some(Ok(RoomElement::Name(k.clone(), c.name.clone())))
+                        }
+                        RoomEventContent::Topic(c) => {
+                            Some(Ok(RoomElement::Topic(k.clone(), c.topic.clone())))
+                        }
+                        RoomEventContent::Avatar(_) => Some(Ok(RoomElement::NewAvatar(k.clone()))),
+                        RoomEventContent::Member(_) => Some(Ok(RoomElement::MemberEvent(Event {
+                            room: k.clone(),
+                            sender: ev.sender.clone(),
+                            content: ev.content.clone(),
+                            redacts: ev.redacts.clone(),
+                            stype: ev.event_type.clone(),
+                            id: ev.event_id.to_string(),
+                        }))),
+                        RoomEventContent::Redaction(_) => Some(
+                            ev.redacts
+                                .clone()
+                                .map(|redacts| RoomElement::RemoveMessage(k.clone(), redacts))
+                                .ok_or(RoomElementError::MissingRedacts),
+                        ),
+                        RoomEventContent::Tombstone(c) => Some(Ok(RoomElement::Tombstone(
+                            k.clone(),
+                            c.replacement_room.clone(),
+                        ))),
+                        RoomEventContent::Sticker(_) | RoomEventContent::Reaction(_) => {
+                            // This event is managed in the room list
+                            None
+                        }
+                        RoomEventContent::Encryption(_) | RoomEventContent::Encrypted(_) => {
+                            // Managed by the room list and the crypto backend
+                            None
+                        }
+                        _ => {
+                            error!("EVENT NOT MANAGED: {:?}", ev);
+                            None
                         }
                     })
                     .collect();
 
+                if let Ok(ref other) = other {
+                    for room_element in other {
+                        if let RoomElement::RemoveMessage(_, msg_id) = room_element {
+                            if let Err(err) = cache.redact_event(msg_id) {
+                                error!("Can't redact the message in the cache: {}", err);
+                            }
+                        }
+                    }
+                }
+
                 let next_batch = response.next_batch;
 
                 Ok(SyncRet::WithSince {
@@ -339,39 +396,152 @@ pub fn sync(
                 })
             }
         }
-        Err(err) => {
-            // we wait if there's an error to avoid 100% CPU
-            // we wait even longer, if it's a 429 (Too Many Requests) error
-            let waiting_time = match err {
-                Error::NetworkError(status) if status.as_u16() == 429 => {
-                    10 * 2_u64.pow(
-                        number_tries
-                            .try_into()
-                            .expect("The number of sync tries couldn't be transformed into a u32."),
-                    )
+        // The caller decides when to try again, see `retry_delay`
+        Err(err) => Err(SyncError(err)),
+    }
+}
+
+/// Syncs only the unread counts of the rooms, for the accounts we aren't showing. The rooms
+/// we left come with no unread messages. The keys sent to the device are only there once,
+/// they go to the encryption machine of the account.
+pub fn sync_unread(
+    base: Url,
+    access_token: AccessToken,
+    olm_machine: Option<SharedOlmMachine>,
+    since: Option<String>,
+) -> Result<(HashMap<RoomId, UnreadNotificationsCount>, String), UnreadSyncError> {
+    let timeout = if since.is_some() {
+        time::Duration::from_secs(30)
+    } else {
+        Default::default()
+    };
+    let filter = Filter {
+        room: Some(RoomFilter {
+            state: Some(RoomEventFilter {
+                types: Some(vec![]),
+                ..Default::default()
+            }),
+            timeline: Some(RoomEventFilter {
+                types: Some(Message::timeline_types()),
+                limit: Some(1),
+                ..Default::default()
+            }),
+            ephemeral: Some(RoomEventFilter {
+                types: Some(vec![]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        presence: Some(EventFilter {
+            types: Some(vec![]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let params = SyncParameters {
+        access_token: access_token.clone(),
+        filter,
+        include_state: IncludeState::Changed {
+            since: since.unwrap_or_default(),
+            timeout,
+        },
+        set_presence: Default::default(),
+    };
+
+    let client_builder_timeout =
+        Client::builder().timeout(Some(Duration::from_secs(globals::TIMEOUT) + timeout));
+
+    let query = ProxySettings::current().and_then(|proxy_settings| {
+        let client = proxy_settings
+            .apply_to_client_builder(client_builder_timeout)
+            .build()?;
+        let request = sync_events(base.clone(), &params)?;
+        matrix_response::<SyncResponse>(&client, request)
+    });
+
+    match query {
+        Ok(mut response) => {
+            if let Some(olm_machine) = olm_machine.as_ref() {
+                crypto::decrypt_sync_response(
+                    base.clone(),
+                    access_token.clone(),
+                    olm_machine,
+                    &mut response,
+                );
+                if let Err(err) = crypto::upload_keys(base, access_token, olm_machine) {
+                    err.handle_error();
                 }
-                _ => 10,
-            };
-            error!(
-                "Sync Error, waiting {:?} seconds to respond for the next sync",
-                waiting_time
-            );
-            thread::sleep(time::Duration::from_secs(waiting_time));
-
-            Err(SyncError(err, number_tries))
+            }
+
+            let rooms = response.rooms;
+            let counts = rooms
+                .join
+                .into_iter()
+                .map(|(room_id, room)| (room_id, room.unread_notifications))
+                .chain(
+                    rooms
+                        .leave
+                        .into_iter()
+                        .map(|(room_id, _)| (room_id, Default::default())),
+                )
+                .collect();
+
+            Ok((counts, response.next_batch))
+        }
+        // The caller decides when to try again, see `retry_delay`
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The timeline of the rooms that skipped some events continues in a new chunk of the cache
+fn start_timeline_chunks(cache: &FCache, response: &SyncResponse) {
+    let limited = response
+        .rooms
+        .join
+        .iter()
+        .filter(|(_, room)| room.timeline.limited);
+
+    for (room_id, room) in limited {
+        let prev_batch = room.timeline.prev_batch.clone();
+        if let Err(err) = cache.start_timeline_chunk(room_id, prev_batch) {
+            error!("Can't start a new timeline chunk in the cache: {}", err);
         }
     }
 }
 
 /// Returns the deserialized response to the given request. Handles Matrix errors.
-fn matrix_response<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
-    if !response.status().is_success() {
-        let status = response.status();
-        return match response.json::<StandardErrorResponse>() {
-            Ok(error_response) => Err(Error::from(error_response)),
-            Err(_) => Err(Error::NetworkError(status)),
-        };
+fn matrix_response<T: DeserializeOwned>(client: &Client, request: Request) -> Result<T, Error> {
+    execute_with::<Error>(client, request)?
+        .json::<T>()
+        .map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_backs_off() {
+        for _ in 0..100 {
+            let first = retry_delay(1, None);
+            assert!(first >= RETRY_DELAY / 2 && first <= RETRY_DELAY);
+
+            let fourth = retry_delay(4, None);
+            assert!(fourth >= RETRY_DELAY * 4 && fourth <= RETRY_DELAY * 8);
+
+            let later = retry_delay(30, None);
+            assert!(later >= MAX_RETRY_DELAY / 2 && later <= MAX_RETRY_DELAY);
+
+            let endless = retry_delay(u32::MAX, None);
+            assert!(endless >= MAX_RETRY_DELAY / 2 && endless <= MAX_RETRY_DELAY);
+        }
     }
 
-    response.json::<T>().map_err(Into::into)
+    #[test]
+    fn retry_delay_honours_retry_after() {
+        let retry_after = Duration::from_secs(60);
+        assert_eq!(retry_delay(1, Some(retry_after)), retry_after);
+        assert!(retry_delay(30, Some(retry_after)) >= retry_after);
+    }
 }
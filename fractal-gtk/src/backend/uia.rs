@@ -0,0 +1,312 @@
+//! Driver for the user-interactive authentication of the client-server API.
+//!
+//! Protected endpoints answer with a 401 listing the flows of stages the
+//! client can follow. `perform` picks a flow the `StageHandler` can complete,
+//! asks it for the data of each stage and retries the original request until
+//! it succeeds.
+
+use fractal_api::error::MatrixError;
+use fractal_api::r0::account::auth_fallback_url;
+use fractal_api::r0::account::AuthData;
+use fractal_api::r0::account::AuthFlow;
+use fractal_api::r0::account::AuthenticationData;
+use fractal_api::r0::account::Identifier;
+use fractal_api::r0::account::UiaaResponse;
+use fractal_api::r0::account::UserIdentifier;
+use fractal_api::r0::ThreePIDCredentials;
+use fractal_api::reqwest::blocking::Request;
+use fractal_api::reqwest::Error as ReqwestError;
+use fractal_api::reqwest::StatusCode;
+use fractal_api::url::Url;
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+
+use super::{check_access_token, check_response, request_access_token, HTTP_CLIENT};
+
+// Each round completes a stage, no flow is this long
+const MAX_ROUNDS: usize = 10;
+
+pub static PASSWORD: &str = "m.login.password";
+pub static EMAIL_IDENTITY: &str = "m.login.email.identity";
+pub static MSISDN: &str = "m.login.msisdn";
+pub static TERMS: &str = "m.login.terms";
+pub static DUMMY: &str = "m.login.dummy";
+
+#[derive(Debug)]
+pub enum UiaError {
+    Reqwest(ReqwestError),
+    Matrix(MatrixError),
+    /// None of the flows offered by the server can be completed
+    NoSupportedFlow(Vec<AuthFlow>),
+    /// The server rejected the data of a stage, like a wrong password
+    StageFailed {
+        stage: String,
+        errcode: Option<String>,
+        error: Option<String>,
+    },
+    /// The handler gave up on a stage, usually the user cancelled it
+    Cancelled,
+    TooManyRounds,
+}
+
+impl From<ReqwestError> for UiaError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Reqwest(err)
+    }
+}
+
+impl From<MatrixError> for UiaError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
+/// Completes the stages of an authentication flow
+pub trait StageHandler {
+    fn supports(&self, stage: &str) -> bool;
+
+    /// Returns the data to send for `stage`. It's called from the backend
+    /// threads, so it can block while the user does something elsewhere.
+    fn complete(
+        &mut self,
+        base: &Url,
+        stage: &str,
+        session: Option<String>,
+        params: Option<&JsonValue>,
+    ) -> Result<AuthData, UiaError>;
+}
+
+/// Sends the request built by `build_request` until the server stops asking
+/// for authentication. `build_request` gets the `auth` field to use.
+pub fn perform<T, F>(
+    base: &Url,
+    handler: &mut dyn StageHandler,
+    mut build_request: F,
+) -> Result<T, UiaError>
+where
+    T: DeserializeOwned,
+    F: FnMut(Option<AuthData>) -> Result<Request, ReqwestError>,
+{
+    let mut auth = None;
+    let mut last_stage: Option<String> = None;
+
+    for _ in 0..MAX_ROUNDS {
+        let request = build_request(auth.take())?;
+        let access_token = request_access_token(&request);
+        let response = HTTP_CLIENT.get_client().execute(request)?;
+
+        let status_code = response.status();
+        if status_code != StatusCode::UNAUTHORIZED {
+            return check_response(response, access_token)?
+                .json()
+                .map_err(Into::into);
+        }
+
+        // Without flows it's about the access token, not about authenticating again
+        let body = response.bytes()?;
+        let info: UiaaResponse = serde_json::from_slice(&body).map_err(|_| {
+            let err = MatrixError::from_body(status_code, &body);
+            check_access_token(&err, access_token);
+            err
+        })?;
+
+        // The server answers with an error when the data of the last stage
+        // is wrong instead of marking it as completed
+        if let Some(stage) = last_stage.take() {
+            if info.errcode.is_some() && !info.completed.contains(&stage) {
+                return Err(UiaError::StageFailed {
+                    stage,
+                    errcode: info.errcode,
+                    error: info.error,
+                });
+            }
+        }
+
+        let stage = select_flow(&info.flows, &info.completed, |s| handler.supports(s))
+            .and_then(|flow| next_stage(flow, &info.completed))
+            .ok_or_else(|| UiaError::NoSupportedFlow(info.flows.clone()))?
+            .to_string();
+
+        auth =
+            Some(handler.complete(base, &stage, info.session.clone(), info.params.get(&stage))?);
+        last_stage = Some(stage);
+    }
+
+    Err(UiaError::TooManyRounds)
+}
+
+/// Picks the shortest flow that contains the stages already completed and
+/// whose remaining stages are all supported
+pub fn select_flow<'a, F>(
+    flows: &'a [AuthFlow],
+    completed: &[String],
+    supports: F,
+) -> Option<&'a AuthFlow>
+where
+    F: Fn(&str) -> bool,
+{
+    flows
+        .iter()
+        .filter(|flow| completed.iter().all(|stage| flow.stages.contains(stage)))
+        .filter(|flow| {
+            flow.stages
+                .iter()
+                .filter(|stage| !completed.contains(stage))
+                .all(|stage| supports(stage))
+        })
+        .min_by_key(|flow| flow.stages.len())
+}
+
+pub fn next_stage<'a>(flow: &'a AuthFlow, completed: &[String]) -> Option<&'a str> {
+    flow.stages
+        .iter()
+        .find(|stage| !completed.contains(stage))
+        .map(String::as_str)
+}
+
+/// The data the user gave to authenticate. Stages without a native
+/// implementation are done in the fallback web page if `fallback` is set.
+#[derive(Default)]
+pub struct Credentials {
+    /// The localpart of the user and its password
+    pub password: Option<(String, String)>,
+    /// A validated email address
+    pub email: Option<ThreePIDCredentials>,
+    /// A validated phone number
+    pub msisdn: Option<ThreePIDCredentials>,
+    /// Whether the user agreed to the terms of the server
+    pub accept_terms: bool,
+    /// Shows the fallback page of a stage and waits until the user is done.
+    /// Returns false if the user cancelled.
+    pub fallback: Option<Box<dyn FnMut(Url) -> bool + Send>>,
+}
+
+impl Credentials {
+    pub fn with_password(user: String, password: String) -> Self {
+        Self {
+            password: Some((user, password)),
+            ..Default::default()
+        }
+    }
+}
+
+impl StageHandler for Credentials {
+    fn supports(&self, stage: &str) -> bool {
+        match stage {
+            s if s == DUMMY => true,
+            s if s == PASSWORD => self.password.is_some(),
+            s if s == EMAIL_IDENTITY => self.email.is_some(),
+            s if s == MSISDN => self.msisdn.is_some(),
+            s if s == TERMS && self.accept_terms => true,
+            _ => self.fallback.is_some(),
+        }
+    }
+
+    fn complete(
+        &mut self,
+        base: &Url,
+        stage: &str,
+        session: Option<String>,
+        _params: Option<&JsonValue>,
+    ) -> Result<AuthData, UiaError> {
+        let data = match (stage, &self.password, &self.email, &self.msisdn) {
+            (s, _, _, _) if s == DUMMY => AuthenticationData::Dummy { session },
+            (s, Some((user, password)), _, _) if s == PASSWORD => AuthenticationData::Password {
+                identifier: Identifier::new(UserIdentifier::User { user: user.clone() }),
+                password: password.clone(),
+                session,
+            },
+            (s, _, Some(creds), _) if s == EMAIL_IDENTITY => AuthenticationData::Email {
+                threepid_creds: creds.clone(),
+                session,
+            },
+            (s, _, _, Some(creds)) if s == MSISDN => AuthenticationData::Msisdn {
+                threepid_creds: creds.clone(),
+                session,
+            },
+            (s, _, _, _) if s == TERMS && self.accept_terms => {
+                AuthenticationData::Terms { session }
+            }
+            _ => {
+                // The fallback pages need a session to know what was done
+                let session = session.ok_or(UiaError::Cancelled)?;
+                let fallback = self.fallback.as_mut().ok_or(UiaError::Cancelled)?;
+                if !fallback(auth_fallback_url(base, stage, &session)) {
+                    return Err(UiaError::Cancelled);
+                }
+
+                return Ok(AuthData::FallbackAcknowledgement { session });
+            }
+        };
+
+        Ok(data.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static RECAPTCHA: &str = "m.login.recaptcha";
+
+    fn flow(stages: &[&str]) -> AuthFlow {
+        AuthFlow {
+            stages: stages.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn uia_select_flow_skips_unsupported_stages() {
+        let flows = vec![
+            flow(&[RECAPTCHA, PASSWORD]),
+            flow(&[EMAIL_IDENTITY, PASSWORD]),
+            flow(&[PASSWORD]),
+        ];
+        let supported = |s: &str| s == PASSWORD || s == EMAIL_IDENTITY;
+
+        let selected = select_flow(&flows, &[], supported).unwrap();
+        assert_eq!(selected.stages, vec![PASSWORD]);
+
+        // The flow has to go on with what was already completed
+        let completed = vec![EMAIL_IDENTITY.to_string()];
+        let selected = select_flow(&flows, &completed, supported).unwrap();
+        assert_eq!(selected.stages, vec![EMAIL_IDENTITY, PASSWORD]);
+        assert_eq!(next_stage(selected, &completed), Some(PASSWORD));
+    }
+
+    #[test]
+    fn uia_select_flow_without_supported_flow() {
+        let flows = vec![flow(&[RECAPTCHA, TERMS])];
+
+        assert!(select_flow(&flows, &[], |s| s == DUMMY).is_none());
+    }
+
+    #[test]
+    fn uia_credentials_use_fallback_for_unknown_stages() {
+        let base = Url::parse("https://matrix.example.org").unwrap();
+        let mut credentials = Credentials {
+            fallback: Some(Box::new(|url: Url| {
+                url.path() == "/_matrix/client/r0/auth/m.login.recaptcha/fallback/web"
+            })),
+            ..Credentials::with_password("user".into(), "secret".into())
+        };
+        assert!(credentials.supports(RECAPTCHA));
+        assert!(!credentials.supports(EMAIL_IDENTITY));
+
+        let auth = credentials
+            .complete(&base, RECAPTCHA, Some("abc".into()), None)
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(auth).unwrap(),
+            serde_json::json!({ "session": "abc" })
+        );
+
+        let auth = credentials
+            .complete(&base, PASSWORD, Some("abc".into()), None)
+            .unwrap();
+        let auth = serde_json::to_value(auth).unwrap();
+        assert_eq!(auth["type"], PASSWORD);
+        assert_eq!(auth["password"], "secret");
+        assert_eq!(auth["session"], "abc");
+    }
+}
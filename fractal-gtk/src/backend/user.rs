@@ -1,5 +1,7 @@
-use fractal_api::identifiers::UserId;
+use fractal_api::error::{ErrorCode, MatrixError};
+use fractal_api::identifiers::{DeviceId, RoomId, UserId};
 use fractal_api::reqwest::Error as ReqwestError;
+use fractal_api::reqwest::StatusCode;
 use fractal_api::url::{ParseError as UrlError, Url};
 use std::fs;
 use std::io::Error as IoError;
@@ -8,16 +10,18 @@ use super::MediaError;
 use crate::actions::global::activate_action;
 use crate::appop::UserInfoCache;
 use crate::backend::ThreadPool;
-use crate::backend::HTTP_CLIENT;
+use crate::error::Error;
 use crate::util::cache_dir_path;
 use crate::util::ResultExpectLog;
 use log::error;
+use serde_json::Value as JsonValue;
 use std::convert::TryInto;
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::thread;
 
-use crate::types::Member;
+use crate::model::push_rules;
+use crate::types::{Member, RoomNotifications};
 use fractal_api::identity::r0::association::msisdn::submit_token::request as submit_phone_token_req;
 use fractal_api::identity::r0::association::msisdn::submit_token::Body as SubmitPhoneTokenBody;
 use fractal_api::identity::r0::association::msisdn::submit_token::Response as SubmitPhoneTokenResponse;
@@ -27,9 +31,6 @@ use fractal_api::r0::account::change_password::Parameters as ChangePasswordParam
 use fractal_api::r0::account::deactivate::request as deactivate;
 use fractal_api::r0::account::deactivate::Body as DeactivateBody;
 use fractal_api::r0::account::deactivate::Parameters as DeactivateParameters;
-use fractal_api::r0::account::AuthenticationData;
-use fractal_api::r0::account::Identifier;
-use fractal_api::r0::account::UserIdentifier;
 use fractal_api::r0::contact::create::request as create_contact;
 use fractal_api::r0::contact::create::Body as AddThreePIDBody;
 use fractal_api::r0::contact::create::Parameters as AddThreePIDParameters;
@@ -48,6 +49,16 @@ use fractal_api::r0::contact::request_verification_token_msisdn::request as requ
 use fractal_api::r0::contact::request_verification_token_msisdn::Body as PhoneTokenBody;
 use fractal_api::r0::contact::request_verification_token_msisdn::Parameters as PhoneTokenParameters;
 use fractal_api::r0::contact::request_verification_token_msisdn::Response as PhoneTokenResponse;
+use fractal_api::r0::device::delete_devices::request as delete_devices_req;
+use fractal_api::r0::device::delete_devices::Body as DeleteDevicesBody;
+use fractal_api::r0::device::delete_devices::Parameters as DeleteDevicesParameters;
+use fractal_api::r0::device::get_devices::request as get_devices_req;
+use fractal_api::r0::device::get_devices::Parameters as GetDevicesParameters;
+use fractal_api::r0::device::get_devices::Response as GetDevicesResponse;
+use fractal_api::r0::device::update_device::request as update_device;
+use fractal_api::r0::device::update_device::Body as UpdateDeviceBody;
+use fractal_api::r0::device::update_device::Parameters as UpdateDeviceParameters;
+use fractal_api::r0::device::Device;
 use fractal_api::r0::media::create_content::request as create_content;
 use fractal_api::r0::media::create_content::Parameters as CreateContentParameters;
 use fractal_api::r0::media::create_content::Response as CreateContentResponse;
@@ -63,6 +74,15 @@ use fractal_api::r0::profile::set_avatar_url::Parameters as SetAvatarUrlParamete
 use fractal_api::r0::profile::set_display_name::request as set_display_name;
 use fractal_api::r0::profile::set_display_name::Body as SetDisplayNameBody;
 use fractal_api::r0::profile::set_display_name::Parameters as SetDisplayNameParameters;
+use fractal_api::r0::pushrules::delete_pushrule::request as delete_pushrule;
+use fractal_api::r0::pushrules::delete_pushrule::Parameters as DeletePushRuleParameters;
+use fractal_api::r0::pushrules::get_pushrules::request as get_pushrules;
+use fractal_api::r0::pushrules::get_pushrules::Parameters as GetPushRulesParameters;
+use fractal_api::r0::pushrules::get_pushrules::Response as GetPushRulesResponse;
+use fractal_api::r0::pushrules::set_pushrule::request as set_pushrule;
+use fractal_api::r0::pushrules::set_pushrule::Body as SetPushRuleBody;
+use fractal_api::r0::pushrules::set_pushrule::Parameters as SetPushRuleParameters;
+use fractal_api::r0::pushrules::{RuleKind, Ruleset};
 use fractal_api::r0::search::user::request as user_directory;
 use fractal_api::r0::search::user::Body as UserDirectoryBody;
 use fractal_api::r0::search::user::Parameters as UserDirectoryParameters;
@@ -71,9 +91,11 @@ use fractal_api::r0::AccessToken;
 use fractal_api::r0::Medium;
 use fractal_api::r0::ThreePIDCredentials;
 
+use super::room::show_request_error;
+use super::uia::{self, Credentials, UiaError};
 use super::{dw_media, ContentType};
 
-use super::{remove_matrix_access_token_if_present, HandleError};
+use super::{execute, remove_matrix_access_token_if_present, HandleError};
 use crate::app::App;
 use crate::i18n::i18n;
 use crate::APPOP;
@@ -81,11 +103,11 @@ use crate::APPOP;
 pub type UserInfo = (String, PathBuf);
 
 #[derive(Debug)]
-pub struct NameError(ReqwestError);
+pub struct NameError(Error);
 
-impl From<ReqwestError> for NameError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for NameError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -98,7 +120,7 @@ pub fn get_username(
 ) -> Result<Option<String>, NameError> {
     let params = GetDisplayNameParameters { access_token };
     let request = get_display_name(base, &params, &uid)?;
-    let response: GetDisplayNameResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: GetDisplayNameResponse = execute::<NameError>(request)?.json()?;
 
     Ok(response.displayname)
 }
@@ -109,11 +131,9 @@ pub fn get_username_async(base: Url, access_token: AccessToken, uid: UserId) ->
     let params = GetDisplayNameParameters { access_token };
 
     get_display_name(base, &params, &uid)
-        .and_then(|request| {
-            HTTP_CLIENT
-                .get_client()
-                .execute(request)?
-                .json::<GetDisplayNameResponse>()
+        .map_err(Into::into)
+        .and_then(|request| -> Result<_, Error> {
+            Ok(execute::<Error>(request)?.json::<GetDisplayNameResponse>()?)
         })
         .ok()
         .and_then(|response| response.displayname)
@@ -121,11 +141,11 @@ pub fn get_username_async(base: Url, access_token: AccessToken, uid: UserId) ->
 }
 
 #[derive(Debug)]
-pub struct SetUserNameError(ReqwestError);
+pub struct SetUserNameError(Error);
 
-impl From<ReqwestError> for SetUserNameError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for SetUserNameError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -143,7 +163,7 @@ pub fn set_username(
     };
 
     let request = set_display_name(base, &params, &body, &uid)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<SetUserNameError>(request)?;
 
     Ok(username)
 }
@@ -157,6 +177,12 @@ impl From<ReqwestError> for GetThreePIDError {
     }
 }
 
+impl From<MatrixError> for GetThreePIDError {
+    fn from(_: MatrixError) -> Self {
+        Self
+    }
+}
+
 impl HandleError for GetThreePIDError {
     fn handle_error(&self) {
         let error = i18n("Sorry, account settings can’t be loaded.");
@@ -175,7 +201,7 @@ pub fn get_threepid(
     let params = ThirdPartyIDParameters { access_token };
 
     let request = get_identifiers(base, &params)?;
-    let response: ThirdPartyIDResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: ThirdPartyIDResponse = execute::<GetThreePIDError>(request)?.json()?;
 
     Ok(response.threepids)
 }
@@ -184,6 +210,7 @@ pub fn get_threepid(
 pub enum GetTokenEmailError {
     IdentityServerUrl(UrlError),
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     TokenUsed,
     Denied,
 }
@@ -194,6 +221,16 @@ impl From<ReqwestError> for GetTokenEmailError {
     }
 }
 
+impl From<MatrixError> for GetTokenEmailError {
+    fn from(err: MatrixError) -> Self {
+        match err.kind {
+            ErrorCode::ThreepidInUse => Self::TokenUsed,
+            ErrorCode::ThreepidDenied | ErrorCode::InvalidParam => Self::Denied,
+            _ => Self::Matrix(err),
+        }
+    }
+}
+
 impl HandleError for GetTokenEmailError {
     fn handle_error(&self) {
         match self {
@@ -214,6 +251,11 @@ impl HandleError for GetTokenEmailError {
                 );
                 APPOP!(show_error_dialog_in_settings, (error));
             }
+            Self::Matrix(err) => {
+                let error = i18n("Couldn’t add the email address.");
+                error!("{}", err);
+                APPOP!(show_error_dialog_in_settings, (error));
+            }
             Self::IdentityServerUrl(err) => {
                 let error = i18n("The identity server is invalid.");
                 error!("The identity server is invalid: {:?}", err);
@@ -245,11 +287,7 @@ pub fn get_email_token(
 
     let request = request_contact_verification_token_email(base, &params, &body)?;
 
-    match HTTP_CLIENT
-        .get_client()
-        .execute(request)?
-        .json::<EmailTokenResponse>()?
-    {
+    match execute::<GetTokenEmailError>(request)?.json::<EmailTokenResponse>()? {
         Passed(info) => Ok((info.sid, client_secret)),
         Failed(info) if info.errcode == "M_THREEPID_IN_USE" => Err(GetTokenEmailError::TokenUsed),
         Failed(_) => Err(GetTokenEmailError::Denied),
@@ -260,6 +298,7 @@ pub fn get_email_token(
 pub enum GetTokenPhoneError {
     IdentityServerUrl(UrlError),
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     TokenUsed,
     Denied,
 }
@@ -270,6 +309,16 @@ impl From<ReqwestError> for GetTokenPhoneError {
     }
 }
 
+impl From<MatrixError> for GetTokenPhoneError {
+    fn from(err: MatrixError) -> Self {
+        match err.kind {
+            ErrorCode::ThreepidInUse => Self::TokenUsed,
+            ErrorCode::ThreepidDenied | ErrorCode::InvalidParam => Self::Denied,
+            _ => Self::Matrix(err),
+        }
+    }
+}
+
 impl HandleError for GetTokenPhoneError {
     fn handle_error(&self) {
         match self {
@@ -292,6 +341,11 @@ impl HandleError for GetTokenPhoneError {
                 );
                 APPOP!(show_error_dialog_in_settings, (error));
             }
+            Self::Matrix(err) => {
+                let error = i18n("Couldn’t add the phone number.");
+                error!("{}", err);
+                APPOP!(show_error_dialog_in_settings, (error));
+            }
             Self::IdentityServerUrl(err) => {
                 let error = i18n("The identity server is invalid.");
                 error!("The identity server is invalid: {:?}", err);
@@ -324,11 +378,7 @@ pub fn get_phone_token(
 
     let request = request_contact_verification_token_msisdn(base, &params, &body)?;
 
-    match HTTP_CLIENT
-        .get_client()
-        .execute(request)?
-        .json::<PhoneTokenResponse>()?
-    {
+    match execute::<GetTokenPhoneError>(request)?.json::<PhoneTokenResponse>()? {
         Passed(info) => Ok((info.sid, client_secret)),
         Failed(info) if info.errcode == "M_THREEPID_IN_USE" => Err(GetTokenPhoneError::TokenUsed),
         Failed(_) => Err(GetTokenPhoneError::Denied),
@@ -339,6 +389,7 @@ pub fn get_phone_token(
 pub enum AddedToFavError {
     IdentityServerUrl(UrlError),
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
 }
 
 impl From<ReqwestError> for AddedToFavError {
@@ -347,6 +398,12 @@ impl From<ReqwestError> for AddedToFavError {
     }
 }
 
+impl From<MatrixError> for AddedToFavError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl HandleError for AddedToFavError {}
 
 pub fn add_threepid(
@@ -369,17 +426,17 @@ pub fn add_threepid(
     };
 
     let request = create_contact(base, &params, &body)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<AddedToFavError>(request)?;
 
     Ok(())
 }
 
 #[derive(Debug)]
-pub struct SubmitPhoneTokenError(ReqwestError);
+pub struct SubmitPhoneTokenError(Error);
 
-impl From<ReqwestError> for SubmitPhoneTokenError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for SubmitPhoneTokenError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -398,17 +455,17 @@ pub fn submit_phone_token(
     };
 
     let request = submit_phone_token_req(base, &body)?;
-    let response: SubmitPhoneTokenResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: SubmitPhoneTokenResponse = execute::<SubmitPhoneTokenError>(request)?.json()?;
 
     Ok((Some(sid).filter(|_| response.success), client_secret))
 }
 
 #[derive(Debug)]
-pub struct DeleteThreePIDError(ReqwestError);
+pub struct DeleteThreePIDError(Error);
 
-impl From<ReqwestError> for DeleteThreePIDError {
-    fn from(err: ReqwestError) -> Self {
-        Self(err)
+impl<T: Into<Error>> From<T> for DeleteThreePIDError {
+    fn from(err: T) -> Self {
+        Self(err.into())
     }
 }
 
@@ -424,16 +481,16 @@ pub fn delete_three_pid(
     let body = DeleteThreePIDBody { address, medium };
 
     let request = delete_contact(base, &params, &body)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<DeleteThreePIDError>(request)?;
 
     Ok(())
 }
 
 #[derive(Debug)]
-pub struct ChangePasswordError(ReqwestError);
+pub struct ChangePasswordError(UiaError);
 
-impl From<ReqwestError> for ChangePasswordError {
-    fn from(err: ReqwestError) -> Self {
+impl From<UiaError> for ChangePasswordError {
+    fn from(err: UiaError) -> Self {
         Self(err)
     }
 }
@@ -458,26 +515,24 @@ pub fn change_password(
     new_password: String,
 ) -> Result<(), ChangePasswordError> {
     let params = ChangePasswordParameters { access_token };
-    let body = ChangePasswordBody {
-        new_password,
-        auth: Some(AuthenticationData::Password {
-            identifier: Identifier::new(UserIdentifier::User { user }),
-            password: old_password,
-            session: None,
-        }),
-    };
+    let mut credentials = Credentials::with_password(user, old_password);
 
-    let request = change_password_req(base, &params, &body)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    uia::perform::<JsonValue, _>(&base, &mut credentials, |auth| {
+        let body = ChangePasswordBody {
+            new_password: new_password.clone(),
+            auth,
+        };
+        change_password_req(base.clone(), &params, &body)
+    })?;
 
     Ok(())
 }
 
 #[derive(Debug)]
-pub struct AccountDestructionError(ReqwestError);
+pub struct AccountDestructionError(UiaError);
 
-impl From<ReqwestError> for AccountDestructionError {
-    fn from(err: ReqwestError) -> Self {
+impl From<UiaError> for AccountDestructionError {
+    fn from(err: UiaError) -> Self {
         Self(err)
     }
 }
@@ -501,16 +556,152 @@ pub fn account_destruction(
     password: String,
 ) -> Result<(), AccountDestructionError> {
     let params = DeactivateParameters { access_token };
-    let body = DeactivateBody {
-        auth: Some(AuthenticationData::Password {
-            identifier: Identifier::new(UserIdentifier::User { user }),
-            password,
-            session: None,
-        }),
+    let mut credentials = Credentials::with_password(user, password);
+
+    uia::perform::<JsonValue, _>(&base, &mut credentials, |auth| {
+        deactivate(base.clone(), &params, &DeactivateBody { auth })
+    })?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct GetDevicesError(Error);
+
+impl<T: Into<Error>> From<T> for GetDevicesError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for GetDevicesError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "Couldn’t load the sessions: {}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        let devices: Option<Vec<Device>> = None;
+        APPOP!(set_devices, (devices));
+    }
+}
+
+pub fn get_devices(base: Url, access_token: AccessToken) -> Result<Vec<Device>, GetDevicesError> {
+    let params = GetDevicesParameters { access_token };
+
+    let request = get_devices_req(base, &params)?;
+    let response: GetDevicesResponse = execute::<GetDevicesError>(request)?.json()?;
+
+    Ok(response.devices)
+}
+
+#[derive(Debug)]
+pub struct SetDeviceNameError(Error);
+
+impl<T: Into<Error>> From<T> for SetDeviceNameError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for SetDeviceNameError {
+    fn handle_error(&self) {
+        let error = i18n("Couldn’t rename the session");
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        APPOP!(show_error_dialog_in_settings, (error));
+        APPOP!(get_devices);
+    }
+}
+
+pub fn set_device_name(
+    base: Url,
+    access_token: AccessToken,
+    device_id: Box<DeviceId>,
+    display_name: String,
+) -> Result<(), SetDeviceNameError> {
+    let params = UpdateDeviceParameters { access_token };
+    let body = UpdateDeviceBody {
+        display_name: Some(display_name),
     };
 
-    let request = deactivate(base, &params, &body)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    let request = update_device(base, &params, &body, &device_id)?;
+    execute::<SetDeviceNameError>(request)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct DeleteDevicesError(UiaError);
+
+impl From<ReqwestError> for DeleteDevicesError {
+    fn from(err: ReqwestError) -> Self {
+        Self(err.into())
+    }
+}
+
+impl From<MatrixError> for DeleteDevicesError {
+    fn from(err: MatrixError) -> Self {
+        Self(err.into())
+    }
+}
+
+impl From<UiaError> for DeleteDevicesError {
+    fn from(err: UiaError) -> Self {
+        Self(err)
+    }
+}
+
+impl HandleError for DeleteDevicesError {
+    fn handle_error(&self) {
+        let error = i18n("Couldn’t sign out the other sessions");
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        APPOP!(show_error_dialog_in_settings, (error));
+        APPOP!(get_devices);
+    }
+}
+
+/// Signs out every session of the account but `own_device`
+pub fn delete_other_devices(
+    base: Url,
+    access_token: AccessToken,
+    user: String,
+    password: String,
+    own_device: Box<DeviceId>,
+) -> Result<(), DeleteDevicesError> {
+    let params = GetDevicesParameters {
+        access_token: access_token.clone(),
+    };
+    let request = get_devices_req(base.clone(), &params)?;
+    let response: GetDevicesResponse = execute::<DeleteDevicesError>(request)?.json()?;
+
+    let devices: Vec<Box<DeviceId>> = response
+        .devices
+        .into_iter()
+        .map(|device| device.device_id)
+        .filter(|device_id| *device_id != own_device)
+        .collect();
+    if devices.is_empty() {
+        return Ok(());
+    }
+
+    let params = DeleteDevicesParameters { access_token };
+    let mut credentials = Credentials::with_password(user, password);
+
+    uia::perform::<JsonValue, _>(&base, &mut credentials, |auth| {
+        let body = DeleteDevicesBody {
+            devices: devices.clone(),
+            auth,
+        };
+        delete_devices_req(base.clone(), &params, &body)
+    })?;
 
     Ok(())
 }
@@ -519,6 +710,7 @@ pub fn account_destruction(
 pub enum SetUserAvatarError {
     Io(IoError),
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
 }
 
 impl From<IoError> for SetUserAvatarError {
@@ -533,6 +725,12 @@ impl From<ReqwestError> for SetUserAvatarError {
     }
 }
 
+impl From<MatrixError> for SetUserAvatarError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl HandleError for SetUserAvatarError {}
 
 pub fn set_user_avatar(
@@ -548,8 +746,7 @@ pub fn set_user_avatar(
 
     let contents = fs::read(&avatar)?;
     let request = create_content(base.clone(), &params_upload, contents)?;
-    let upload_response: CreateContentResponse =
-        HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let upload_response: CreateContentResponse = execute::<SetUserAvatarError>(request)?.json()?;
 
     let params_avatar = SetAvatarUrlParameters { access_token };
     let body = SetAvatarUrlBody {
@@ -557,7 +754,7 @@ pub fn set_user_avatar(
     };
 
     let request = set_avatar_url(base, &params_avatar, &body, &uid)?;
-    HTTP_CLIENT.get_client().execute(request)?;
+    execute::<SetUserAvatarError>(request)?;
 
     Ok(avatar)
 }
@@ -592,6 +789,7 @@ pub fn get_user_info_async(
 #[derive(Debug)]
 pub enum UserSearchError {
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     ParseUrl(UrlError),
 }
 
@@ -601,6 +799,12 @@ impl From<ReqwestError> for UserSearchError {
     }
 }
 
+impl From<MatrixError> for UserSearchError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl From<UrlError> for UserSearchError {
     fn from(err: UrlError) -> Self {
         Self::ParseUrl(err)
@@ -621,7 +825,7 @@ pub fn search(
     };
 
     let request = user_directory(base, &params, &body)?;
-    let response: UserDirectoryResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: UserDirectoryResponse = execute::<UserSearchError>(request)?.json()?;
 
     response
         .results
@@ -634,6 +838,7 @@ pub fn search(
 #[derive(Debug)]
 pub enum GetUserAvatarError {
     Reqwest(ReqwestError),
+    Matrix(MatrixError),
     Download(MediaError),
     ParseUrl(UrlError),
 }
@@ -644,6 +849,12 @@ impl From<ReqwestError> for GetUserAvatarError {
     }
 }
 
+impl From<MatrixError> for GetUserAvatarError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
 impl From<MediaError> for GetUserAvatarError {
     fn from(err: MediaError) -> Self {
         Self::Download(err)
@@ -665,7 +876,7 @@ pub fn get_user_avatar(
 ) -> Result<(String, PathBuf), GetUserAvatarError> {
     let params = GetProfileParameters { access_token };
     let request = get_profile(base.clone(), &params, user_id)?;
-    let response: GetProfileResponse = HTTP_CLIENT.get_client().execute(request)?.json()?;
+    let response: GetProfileResponse = execute::<GetUserAvatarError>(request)?.json()?;
 
     let name = response
         .displayname
@@ -685,3 +896,172 @@ pub fn get_user_avatar(
 
     Ok((name, img))
 }
+
+#[derive(Debug)]
+pub struct PushRulesError(Error);
+
+impl<T: Into<Error>> From<T> for PushRulesError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for PushRulesError {}
+
+pub fn get_push_rules(base: Url, access_token: AccessToken) -> Result<Ruleset, PushRulesError> {
+    request_push_rules(base, access_token)
+}
+
+/// The push rules we end up with after changing them
+fn request_push_rules<E>(base: Url, access_token: AccessToken) -> Result<Ruleset, E>
+where
+    E: From<ReqwestError> + From<MatrixError>,
+{
+    let params = GetPushRulesParameters { access_token };
+    let request = get_pushrules(base, &params)?;
+    let response: GetPushRulesResponse = execute::<E>(request)?.json()?;
+
+    Ok(response.global)
+}
+
+#[derive(Debug)]
+pub enum RoomNotificationsError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for RoomNotificationsError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for RoomNotificationsError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for RoomNotificationsError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Couldn’t change the notification settings of the room");
+        show_request_error(error, self, response);
+    }
+}
+
+/// Replaces the push rules of the room with the one of `level`, returns the push rules
+/// we end up with
+pub fn set_room_notifications(
+    base: Url,
+    access_token: AccessToken,
+    room_id: RoomId,
+    level: RoomNotifications,
+) -> Result<Ruleset, RoomNotificationsError> {
+    let rule_id = room_id.to_string();
+    for kind in [RuleKind::Override, RuleKind::Room].iter() {
+        let params = DeletePushRuleParameters {
+            access_token: access_token.clone(),
+        };
+        let request = delete_pushrule(base.clone(), *kind, &rule_id, &params)?;
+        // There is a rule of one kind at most
+        match execute(request) {
+            Ok(_) => {}
+            Err(RoomNotificationsError::Response(err))
+                if err.status_code == StatusCode::NOT_FOUND => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    if let Some((kind, rule)) = level.rule(&room_id) {
+        let params = SetPushRuleParameters {
+            access_token: access_token.clone(),
+            before: None,
+            after: None,
+        };
+        let body = SetPushRuleBody {
+            actions: rule.actions,
+            conditions: rule.conditions,
+            pattern: rule.pattern,
+        };
+        let request = set_pushrule(base.clone(), kind, &rule.rule_id, &params, &body)?;
+        execute::<RoomNotificationsError>(request)?;
+    }
+
+    request_push_rules(base, access_token)
+}
+
+#[derive(Debug)]
+pub enum HighlightKeywordError {
+    Request(ReqwestError),
+    Response(MatrixError),
+}
+
+impl From<ReqwestError> for HighlightKeywordError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<MatrixError> for HighlightKeywordError {
+    fn from(err: MatrixError) -> Self {
+        Self::Response(err)
+    }
+}
+
+impl HandleError for HighlightKeywordError {
+    fn handle_error(&self) {
+        let response = match self {
+            Self::Response(response) => Some(response),
+            Self::Request(_) => None,
+        };
+        let error = i18n("Couldn’t change the highlight keywords");
+        show_request_error(error, self, response);
+    }
+}
+
+/// Keywords are content rules, returns the push rules we end up with
+pub fn add_highlight_keyword(
+    base: Url,
+    access_token: AccessToken,
+    keyword: String,
+) -> Result<Ruleset, HighlightKeywordError> {
+    let rule = push_rules::keyword_rule(&keyword);
+    let params = SetPushRuleParameters {
+        access_token: access_token.clone(),
+        before: None,
+        after: None,
+    };
+    let body = SetPushRuleBody {
+        actions: rule.actions,
+        conditions: rule.conditions,
+        pattern: rule.pattern,
+    };
+    let request = set_pushrule(
+        base.clone(),
+        RuleKind::Content,
+        &rule.rule_id,
+        &params,
+        &body,
+    )?;
+    execute::<HighlightKeywordError>(request)?;
+
+    request_push_rules(base, access_token)
+}
+
+pub fn remove_highlight_keyword(
+    base: Url,
+    access_token: AccessToken,
+    rule_id: String,
+) -> Result<Ruleset, HighlightKeywordError> {
+    let params = DeletePushRuleParameters {
+        access_token: access_token.clone(),
+    };
+    let request = delete_pushrule(base.clone(), RuleKind::Content, &rule_id, &params)?;
+    execute::<HighlightKeywordError>(request)?;
+
+    request_push_rules(base, access_token)
+}
@@ -0,0 +1,402 @@
+use chrono::prelude::*;
+use failure::Error;
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use super::state::FCache;
+use crate::types::Message;
+use fractal_api::identifiers::{EventId, RoomId, UserId};
+
+/// Words shorter than this aren't indexed, they would match almost every message
+const MIN_TERM_LEN: usize = 2;
+/// Longer words are cut, nobody searches for the whole of them
+const MAX_TERM_LEN: usize = 64;
+
+/// What to look for in the local message index, every field that is set must match
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchQuery {
+    pub text: String,
+    pub sender: Option<UserId>,
+    pub room: Option<RoomId>,
+    pub after: Option<DateTime<Local>>,
+    pub before: Option<DateTime<Local>>,
+}
+
+impl SearchQuery {
+    /// Build a query from what the user typed, the words `from:@user:server`,
+    /// `in:!room:server`, `after:YYYY-MM-DD` and `before:YYYY-MM-DD` set the filters and
+    /// everything else is the text to look for
+    pub fn parse(input: &str) -> Self {
+        let mut query = SearchQuery::default();
+        let mut text = vec![];
+
+        for word in input.split_whitespace() {
+            let mut parts = word.splitn(2, ':');
+            let filter = match (parts.next(), parts.next()) {
+                (Some("from"), Some(sender)) => UserId::try_from(sender)
+                    .ok()
+                    .map(|sender| query.sender = Some(sender)),
+                (Some("in"), Some(room)) => RoomId::try_from(room)
+                    .ok()
+                    .map(|room| query.room = Some(room)),
+                (Some("after"), Some(date)) => parse_day(date).map(|date| query.after = Some(date)),
+                (Some("before"), Some(date)) => {
+                    parse_day(date).map(|date| query.before = Some(date))
+                }
+                _ => None,
+            };
+
+            if filter.is_none() {
+                text.push(word);
+            }
+        }
+
+        query.text = text.join(" ");
+        query
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.trim().is_empty()
+            && self.sender.is_none()
+            && self.room.is_none()
+            && self.after.is_none()
+            && self.before.is_none()
+    }
+
+    /// The words to highlight in the messages found
+    pub fn highlights(&self) -> Vec<String> {
+        terms(&self.text)
+    }
+
+    /// Whether the message passes the filters of the query, the text is checked by the index
+    pub fn matches(&self, msg: &Message) -> bool {
+        self.sender.as_ref().map_or(true, |s| *s == msg.sender)
+            && self.room.as_ref().map_or(true, |r| *r == msg.room)
+            && self.after.map_or(true, |after| msg.date >= after)
+            && self.before.map_or(true, |before| msg.date < before)
+    }
+}
+
+fn parse_day(date: &str) -> Option<DateTime<Local>> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Local.from_local_datetime(&date.and_hms(0, 0, 0)).earliest()
+}
+
+/// Split a text in the lowercase words we store in the index
+pub fn terms(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_TERM_LEN)
+        .map(|word| word.to_lowercase().chars().take(MAX_TERM_LEN).collect())
+        .filter(|term: &String| seen.insert(term.clone()))
+        .collect()
+}
+
+fn latest_edit(conn: &Connection, event_id: &str) -> Result<Option<Message>, Error> {
+    let data: Option<String> = conn
+        .query_row(
+            "SELECT data FROM events WHERE replaces = ?1 AND redacted = 0
+             ORDER BY ts DESC LIMIT 1",
+            params![event_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    Ok(data.map(|data| serde_json::from_str(&data)).transpose()?)
+}
+
+/// Index the text of a stored message as it was last edited
+fn reindex(conn: &Connection, event_id: &str) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM search_terms WHERE event_id = ?1",
+        params![event_id],
+    )?;
+
+    let data: Option<String> = conn
+        .query_row(
+            "SELECT data FROM events
+             WHERE event_id = ?1 AND replaces IS NULL AND annotates IS NULL AND state = 0
+               AND redacted = 0",
+            params![event_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let data = match data {
+        Some(data) => data,
+        // We don't have it or it was redacted
+        None => return Ok(()),
+    };
+    let body = match latest_edit(conn, event_id)? {
+        Some(edit) => edit.body,
+        None => serde_json::from_str::<Message>(&data)?.body,
+    };
+
+    let mut stmt =
+        conn.prepare_cached("INSERT INTO search_terms (term, event_id) VALUES (?1, ?2)")?;
+    for term in terms(&body) {
+        stmt.execute(params![term, event_id])?;
+    }
+
+    Ok(())
+}
+
+/// Keep the index up to date with a message just written to the events table, edits
+/// change the words of the message they edit
+pub(super) fn index_event(conn: &Connection, msg: &Message) -> Result<(), Error> {
+    let event_id = match msg.id.as_ref() {
+        Some(event_id) => event_id,
+        None => return Ok(()),
+    };
+    let indexed = msg.replace.as_ref().unwrap_or(event_id);
+
+    reindex(conn, &indexed.to_string())
+}
+
+// Cache
+
+impl FCache {
+    /// Forget the text of a redacted message, we may not have it in memory anymore so
+    /// this can't wait for the rooms to be saved
+    pub fn redact_event(&self, event_id: &EventId) -> Result<(), Error> {
+        let cache = self.get_store();
+        let conn = cache.as_ref().unwrap();
+        let event_id = event_id.to_string();
+
+        let data: Option<String> = conn
+            .query_row(
+                "SELECT data FROM events WHERE event_id = ?1",
+                params![event_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let mut msg: Message = match data {
+            Some(data) => serde_json::from_str(&data)?,
+            // We never had it
+            None => return Ok(()),
+        };
+        msg.redacted = true;
+
+        conn.execute(
+            "UPDATE events SET redacted = 1, data = ?2 WHERE event_id = ?1",
+            params![event_id, serde_json::to_string(&msg)?],
+        )?;
+        index_event(conn, &msg)
+    }
+
+    /// Look for messages in the index, the newest first. The messages of encrypted rooms
+    /// aren't in it.
+    pub fn search_messages(
+        &self,
+        query: &SearchQuery,
+        limit: usize,
+    ) -> Result<Vec<Message>, Error> {
+        let cache = self.get_store();
+        let conn = cache.as_ref().unwrap();
+
+        let mut sql = String::from(
+            "SELECT event_id, data FROM events
+             WHERE replaces IS NULL AND annotates IS NULL AND state = 0 AND redacted = 0",
+        );
+        let mut args: Vec<Box<dyn ToSql>> = vec![];
+        if let Some(room) = query.room.as_ref() {
+            sql.push_str(" AND room_id = ?");
+            args.push(Box::new(room.to_string()));
+        }
+        if let Some(sender) = query.sender.as_ref() {
+            sql.push_str(" AND sender = ?");
+            args.push(Box::new(sender.to_string()));
+        }
+        if let Some(after) = query.after {
+            sql.push_str(" AND ts >= ?");
+            args.push(Box::new(after.timestamp_millis()));
+        }
+        if let Some(before) = query.before {
+            sql.push_str(" AND ts < ?");
+            args.push(Box::new(before.timestamp_millis()));
+        }
+
+        // Every word must be in the message, the last one could be incomplete
+        let terms = terms(&query.text);
+        for (i, term) in terms.iter().enumerate() {
+            if i == terms.len() - 1 {
+                sql.push_str(
+                    " AND event_id IN (SELECT event_id FROM search_terms WHERE term GLOB ?)",
+                );
+                args.push(Box::new(format!("{}*", term)));
+            } else {
+                sql.push_str(" AND event_id IN (SELECT event_id FROM search_terms WHERE term = ?)");
+                args.push(Box::new(term.clone()));
+            }
+        }
+
+        sql.push_str(" ORDER BY ts DESC LIMIT ?");
+        args.push(Box::new(limit as i64));
+
+        let args: Vec<&dyn ToSql> = args.iter().map(|arg| arg.as_ref()).collect();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map(&args, |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(event_id, data)| {
+                let mut msg: Message = serde_json::from_str(&data)?;
+                if let Some(edit) = latest_edit(conn, &event_id)? {
+                    msg.body = edit.body;
+                    msg.formatted_body = edit.formatted_body;
+                    msg.format = edit.format;
+                }
+                Ok(msg)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::AppState;
+    use crate::types::{Room, RoomList, RoomMembership, RoomTag};
+
+    fn message(room: &Room, id: &str, body: &str, ts: i64) -> Message {
+        let mut msg = Message::new(
+            room.id.clone(),
+            UserId::try_from("@alice:example.org").unwrap(),
+            body.to_string(),
+            "m.text".to_string(),
+            Some(EventId::try_from(id).unwrap()),
+        );
+        msg.date = Local.timestamp_millis(ts);
+        msg
+    }
+
+    #[test]
+    fn index_terms() {
+        assert_eq!(
+            terms("Hello, hello World! I'm at Café-Bar"),
+            vec!["hello", "world", "at", "café", "bar"]
+        );
+        assert!(terms("a b c ;-)").is_empty());
+    }
+
+    #[test]
+    fn index_parse_query() {
+        let query = SearchQuery::parse("from:@alice:example.org  release notes in:nope");
+        assert_eq!(query.text, "release notes in:nope");
+        assert_eq!(
+            query.sender,
+            Some(UserId::try_from("@alice:example.org").unwrap())
+        );
+        assert_eq!(query.room, None);
+
+        let query = SearchQuery::parse("in:!abc:example.org after:2020-03-01 before:2020-04-01");
+        assert!(query.text.is_empty());
+        assert!(!query.is_empty());
+        assert_eq!(
+            query.room,
+            Some(RoomId::try_from("!abc:example.org").unwrap())
+        );
+        assert_eq!(
+            query.after.map(|d| d.naive_local()),
+            Some(NaiveDate::from_ymd(2020, 3, 1).and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            query.before.map(|d| d.naive_local()),
+            Some(NaiveDate::from_ymd(2020, 4, 1).and_hms(0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn index_search_messages() {
+        let cache = FCache::in_memory();
+        let mut rooms = RoomList::new();
+
+        let mut public = Room::new(
+            RoomId::try_from("!public:example.org").unwrap(),
+            RoomMembership::Joined(RoomTag::None),
+        );
+        public.messages = vec![
+            message(&public, "$1:example.org", "The release notes are out", 1000),
+            message(&public, "$2:example.org", "Release party tonight", 2000),
+            message(&public, "$3:example.org", "Nothing to see", 3000),
+        ];
+        let mut edit = message(&public, "$4:example.org", "* Something to see", 4000);
+        edit.replace = public.messages[2].id.clone();
+        public.messages.push(edit);
+        rooms.insert(public.id.clone(), public);
+
+        let mut secret = Room::new(
+            RoomId::try_from("!secret:example.org").unwrap(),
+            RoomMembership::Joined(RoomTag::None),
+        );
+        secret.encrypted = true;
+        secret.messages = vec![message(&secret, "$5:example.org", "Secret release", 5000)];
+        rooms.insert(secret.id.clone(), secret);
+
+        let st = AppState {
+            since: None,
+            username: "alice".to_string(),
+            uid: UserId::try_from("@alice:example.org").unwrap(),
+            device_id: "DEVICE".into(),
+        };
+        cache.save(&rooms, st).unwrap();
+
+        let found = |input: &str| -> Vec<String> {
+            cache
+                .search_messages(&SearchQuery::parse(input), 10)
+                .unwrap()
+                .into_iter()
+                .map(|msg| msg.body)
+                .collect()
+        };
+
+        // The newest first, the last word can be the start of one
+        assert_eq!(
+            found("relea"),
+            vec!["Release party tonight", "The release notes are out"]
+        );
+        assert_eq!(found("release notes"), vec!["The release notes are out"]);
+        assert!(found("notes party").is_empty());
+        // Edits replace the words of the message they edit
+        assert_eq!(found("something"), vec!["* Something to see"]);
+        assert!(found("nothing").is_empty());
+        // Nothing of the encrypted room is kept
+        assert!(found("secret").is_empty());
+        assert!(found("in:!secret:example.org").is_empty());
+        let plaintext = |text: &str| -> i64 {
+            let store = cache.get_store();
+            store
+                .as_ref()
+                .unwrap()
+                .query_row(
+                    "SELECT count(*) FROM events WHERE data LIKE ?1",
+                    params![format!("%{}%", text)],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(plaintext("Secret release"), 0);
+
+        cache
+            .redact_event(&EventId::try_from("$1:example.org").unwrap())
+            .unwrap();
+        assert_eq!(found("release"), vec!["Release party tonight"]);
+
+        // Neither once a room turns on encryption
+        let public_id = RoomId::try_from("!public:example.org").unwrap();
+        rooms.get_mut(&public_id).unwrap().encrypted = true;
+        let st = AppState {
+            since: None,
+            username: "alice".to_string(),
+            uid: UserId::try_from("@alice:example.org").unwrap(),
+            device_id: "DEVICE".into(),
+        };
+        cache.save(&rooms, st).unwrap();
+        assert!(found("in:!public:example.org").is_empty());
+        assert_eq!(plaintext("Release party"), 0);
+    }
+}
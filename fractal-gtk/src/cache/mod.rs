@@ -10,7 +10,6 @@ use gtk::LabelExt;
 use serde::{Deserialize, Serialize};
 use std::thread;
 
-use crate::types::Room;
 use crate::types::RoomList;
 use failure::Error;
 use fractal_api::identifiers::{DeviceId, UserId};
@@ -19,8 +18,6 @@ use std::hash::Hash;
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-use crate::globals;
-
 /* includes for avatar download */
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
@@ -31,8 +28,14 @@ use crate::widgets::AvatarData;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+mod index;
+mod outbox;
 mod state;
+pub use self::index::SearchQuery;
+pub use self::outbox::OutboxState;
+pub use self::state::for_account;
 pub use self::state::get;
+pub use self::state::set_account;
 pub use self::state::AppState;
 pub use self::state::FCache;
 
@@ -95,37 +98,14 @@ pub fn store(
     uid: UserId,
     device_id: Box<DeviceId>,
 ) -> Result<(), Error> {
-    // don't store all messages in the cache
-    let mut cacherooms: Vec<Room> = vec![];
-    for r in rooms.values() {
-        let mut r = r.clone();
-        let skip = match r.messages.len() {
-            n if n > globals::CACHE_SIZE => n - globals::CACHE_SIZE,
-            _ => 0,
-        };
-        r.messages = r.messages.iter().skip(skip).cloned().collect();
-        // setting prev_batch to none because we're removing some messages so the
-        // prev_batch isn't valid now, it's not pointing to the stored last msg
-        r.prev_batch = None;
-        cacherooms.push(r);
-    }
-
     let st = AppState {
         since,
         username,
         uid,
         device_id,
     };
-    get().save_st(st)?;
-
-    // This is slow because we iterate over all room msgs
-    // in the future we shouldn't do that, we should remove the
-    // Vec<Msg> from the room and treat messages as first level
-    // cache objects with something like cache.get_msgs(room),
-    // cache.get_msg(room_id, msg_id) and cache.save_msg(msg)
-    get().save_rooms(cacherooms)?;
 
-    Ok(())
+    get().save(rooms, st)
 }
 
 pub fn load() -> Result<CacheData, Error> {
@@ -0,0 +1,208 @@
+use failure::err_msg;
+use failure::Error;
+use rusqlite::{params, NO_PARAMS};
+
+use super::state::FCache;
+use crate::types::Message;
+
+/// Where a message of the outbox is, sent messages leave the outbox
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutboxState {
+    /// Waiting for its turn or for the network to come back
+    Queued,
+    Sending,
+    /// The server rejected it, it waits for the user to retry or discard it
+    Failed,
+}
+
+impl OutboxState {
+    fn as_str(self) -> &'static str {
+        match self {
+            OutboxState::Queued => "queued",
+            OutboxState::Sending => "sending",
+            OutboxState::Failed => "failed",
+        }
+    }
+
+    fn from_str(state: &str) -> Result<Self, Error> {
+        match state {
+            "queued" => Ok(OutboxState::Queued),
+            "sending" => Ok(OutboxState::Sending),
+            "failed" => Ok(OutboxState::Failed),
+            _ => Err(err_msg("Unknown outbox state")),
+        }
+    }
+}
+
+// Cache
+
+impl FCache {
+    /// Keep a new message until it's sent, the transaction id is reused on every try so
+    /// the server doesn't get it twice
+    pub fn queue_message(&self, txn_id: &str, msg: &Message) -> Result<(), Error> {
+        let cache = self.get_store();
+        let conn = cache.as_ref().unwrap();
+
+        conn.execute(
+            "INSERT INTO outbox (txn_id, room_id, state, data) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                txn_id,
+                msg.room.to_string(),
+                OutboxState::Queued.as_str(),
+                serde_json::to_string(msg)?
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn update_outgoing(&self, txn_id: &str, msg: &Message) -> Result<(), Error> {
+        let cache = self.get_store();
+        let conn = cache.as_ref().unwrap();
+
+        conn.execute(
+            "UPDATE outbox SET data = ?2 WHERE txn_id = ?1",
+            params![txn_id, serde_json::to_string(msg)?],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_outgoing_state(&self, txn_id: &str, state: OutboxState) -> Result<(), Error> {
+        let cache = self.get_store();
+        let conn = cache.as_ref().unwrap();
+
+        conn.execute(
+            "UPDATE outbox SET state = ?2 WHERE txn_id = ?1",
+            params![txn_id, state.as_str()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn remove_outgoing(&self, txn_id: &str) -> Result<(), Error> {
+        let cache = self.get_store();
+        let conn = cache.as_ref().unwrap();
+
+        conn.execute("DELETE FROM outbox WHERE txn_id = ?1", params![txn_id])?;
+
+        Ok(())
+    }
+
+    /// The messages of the outbox, the oldest first. The ones we were sending come back
+    /// queued, we don't know if they arrived but sending them again with the same
+    /// transaction id is harmless.
+    pub fn get_outbox(&self) -> Result<Vec<(String, OutboxState, Message)>, Error> {
+        let cache = self.get_store();
+        let conn = cache.as_ref().unwrap();
+
+        let mut stmt = conn.prepare("SELECT txn_id, state, data FROM outbox ORDER BY rowid")?;
+        let rows = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(txn_id, state, data)| {
+                let state = match OutboxState::from_str(&state)? {
+                    OutboxState::Sending => OutboxState::Queued,
+                    state => state,
+                };
+                Ok((txn_id, state, serde_json::from_str(&data)?))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fractal_api::identifiers::{RoomId, UserId};
+    use std::convert::TryFrom;
+    use std::env;
+    use std::fs;
+
+    fn message(body: &str) -> Message {
+        Message::new(
+            RoomId::try_from("!room:example.org").unwrap(),
+            UserId::try_from("@alice:example.org").unwrap(),
+            body.to_string(),
+            "m.text".to_string(),
+            None,
+        )
+    }
+
+    fn outbox(cache: &FCache) -> Vec<(String, OutboxState, String)> {
+        cache
+            .get_outbox()
+            .unwrap()
+            .into_iter()
+            .map(|(txn_id, state, msg)| (txn_id, state, msg.body))
+            .collect()
+    }
+
+    #[test]
+    fn outbox_survives_restart() {
+        let dir = env::temp_dir().join(format!("fractal-outbox-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.db");
+        let _ = fs::remove_file(&path);
+
+        {
+            let cache = FCache::open(&path);
+            cache.queue_message("txn1", &message("First")).unwrap();
+            cache.queue_message("txn2", &message("Second")).unwrap();
+            cache.queue_message("txn3", &message("Third")).unwrap();
+            // A transaction id is only ever used by one message
+            assert!(cache.queue_message("txn1", &message("Other")).is_err());
+
+            cache
+                .set_outgoing_state("txn1", OutboxState::Sending)
+                .unwrap();
+            cache
+                .set_outgoing_state("txn2", OutboxState::Failed)
+                .unwrap();
+            cache
+                .update_outgoing("txn3", &message("Third, edited"))
+                .unwrap();
+        }
+
+        // The messages keep their order and transaction id, the one we were sending is
+        // sent again
+        let cache = FCache::open(&path);
+        assert_eq!(
+            outbox(&cache),
+            vec![
+                ("txn1".to_string(), OutboxState::Queued, "First".to_string()),
+                (
+                    "txn2".to_string(),
+                    OutboxState::Failed,
+                    "Second".to_string()
+                ),
+                (
+                    "txn3".to_string(),
+                    OutboxState::Queued,
+                    "Third, edited".to_string()
+                ),
+            ]
+        );
+
+        // Sent messages leave the outbox for good
+        cache.remove_outgoing("txn1").unwrap();
+        drop(cache);
+        let cache = FCache::open(&path);
+        let txn_ids: Vec<String> = outbox(&cache)
+            .into_iter()
+            .map(|(txn_id, _, _)| txn_id)
+            .collect();
+        assert_eq!(txn_ids, vec!["txn2", "txn3"]);
+
+        drop(cache);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,20 +1,106 @@
 use lazy_static::lazy_static;
-use mdl::Cache;
-use mdl::Model;
-use mdl::Store;
+use rusqlite::Error as SqliteError;
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, NO_PARAMS};
 use serde::{Deserialize, Serialize};
 
 use failure::err_msg;
 use failure::Error;
+use log::error;
 
-use std::cell::RefCell;
-use std::fs::remove_dir_all;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::{remove_dir_all, remove_file, rename};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
 use std::sync::{Arc, Mutex, MutexGuard};
 
+use super::index;
+use crate::globals;
+use crate::types::Member;
 use crate::types::Message;
 use crate::types::Room;
-use crate::util::cache_dir_path;
-use fractal_api::identifiers::{DeviceId, UserId};
+use crate::types::RoomList;
+use crate::util::{account_dir, cache_dir_path};
+use fractal_api::identifiers::{DeviceId, EventId, RoomId, UserId};
+
+const DB_NAME: &str = "cache.db";
+/// Where the cache was before we had a database, it's thrown away on start
+const OLD_DB_NAME: &str = "cache.mdl";
+
+/// Each migration takes the schema one version further, the version of a database
+/// is the number of migrations already run on it. Never change a migration that was
+/// already released, add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: Rooms with their members and timeline, and the message search index
+    "CREATE TABLE app_state (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        since TEXT,
+        username TEXT NOT NULL,
+        uid TEXT NOT NULL,
+        device_id TEXT NOT NULL
+    );
+    CREATE TABLE rooms (
+        room_id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE room_members (
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (room_id, user_id)
+    );
+    CREATE TABLE timeline_chunks (
+        chunk_id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        prev_batch TEXT
+    );
+    CREATE INDEX timeline_chunks_room ON timeline_chunks (room_id);
+    CREATE TABLE events (
+        event_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        chunk_id INTEGER NOT NULL REFERENCES timeline_chunks (chunk_id) ON DELETE CASCADE,
+        sender TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        replaces TEXT,
+        redacted INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX events_chunk ON events (chunk_id, ts);
+    CREATE INDEX events_room ON events (room_id, ts);
+    CREATE INDEX events_sender ON events (sender, ts);
+    CREATE INDEX events_replaces ON events (replaces);
+    CREATE TABLE search_terms (
+        term TEXT NOT NULL,
+        event_id TEXT NOT NULL REFERENCES events (event_id) ON DELETE CASCADE,
+        PRIMARY KEY (term, event_id)
+    ) WITHOUT ROWID;
+    CREATE INDEX search_terms_event ON search_terms (event_id);",
+    // 2: Messages waiting to be sent, in the order the user wrote them
+    "CREATE TABLE outbox (
+        txn_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        state TEXT NOT NULL,
+        data TEXT NOT NULL
+    );",
+    // 3: Reactions, they point to the message they are for
+    "ALTER TABLE events ADD COLUMN annotates TEXT;
+    CREATE INDEX events_annotates ON events (annotates);",
+    // 4: Changes of the room state shown in the timeline, they aren't searched
+    "ALTER TABLE events ADD COLUMN state INTEGER NOT NULL DEFAULT 0;",
+    // 5: Encrypted rooms aren't indexed, their text would be kept in the clear
+    "DELETE FROM search_terms WHERE event_id IN (
+        SELECT event_id FROM events WHERE room_id IN (
+            SELECT room_id FROM rooms WHERE data LIKE '%\"encrypted\":true%'
+        )
+    );",
+    // 6: Nor are their events, the decrypted messages are loaded from the server again
+    "DELETE FROM timeline_chunks WHERE room_id IN (
+        SELECT room_id FROM rooms WHERE data LIKE '%\"encrypted\":true%'
+    );",
+];
 
 // Models
 
@@ -29,95 +115,392 @@ pub struct AppState {
     pub device_id: Box<DeviceId>,
 }
 
-/// Backend Room model but without the list of messages
-/// The list of messages is stored in cache with the Msg
-/// struct and this struct defines a convenience method
-/// to get a Vec<Msg>
-#[derive(Serialize, Deserialize)]
-pub struct AppRoom {
-    pub room: RefCell<Room>,
+/// Fingerprints of what the database has for a room, they let us write only
+/// what changed since the last save
+#[derive(Default)]
+struct SavedRoom {
+    data: u64,
+    prev_batch: Option<String>,
+    members: HashMap<UserId, u64>,
+    events: HashMap<EventId, u64>,
 }
 
-/// Message stored in the cache
-#[derive(Serialize, Deserialize)]
-pub struct AppMsg {
-    pub msg: Message,
+fn checksum(data: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn serialize<T: Serialize>(value: &T) -> Result<(String, u64), Error> {
+    let data = serde_json::to_string(value)?;
+    let sum = checksum(&data);
+    Ok((data, sum))
+}
+
+/// The part of the room stored in the rooms table, the rest has tables of its own
+fn room_record(room: &Room) -> Room {
+    Room {
+        messages: vec![],
+        members: Default::default(),
+        // Don't store typing notifications
+        typing_users: vec![],
+        prev_batch: None,
+        ..room.clone()
+    }
+}
+
+/// Why a database couldn't be brought to the current schema
+#[derive(Debug)]
+enum MigrateError {
+    /// A newer version made it, what it has is left as it is
+    Newer,
+    Sqlite(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for MigrateError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Sqlite(err)
+    }
 }
 
-impl Model for AppState {
-    fn key(&self) -> String {
-        "state".to_string()
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Newer => write!(f, "The cache was made by a newer version"),
+            Self::Sqlite(err) => write!(f, "{}", err),
+        }
     }
 }
 
-impl AppRoom {
-    #[allow(dead_code)]
-    fn store_msgs<S: Store>(&self, store: &S) -> Result<(), Error> {
-        for msg in self.room.borrow().messages.iter() {
-            let m = AppMsg { msg: msg.clone() };
-            m.store(store)?;
+impl StdError for MigrateError {}
+
+impl MigrateError {
+    /// Whether there's nothing to do with the database anymore, unlike when it's only
+    /// locked by another process for example
+    fn is_unusable(&self) -> bool {
+        match self {
+            // The schema isn't what the migrations expect
+            Self::Sqlite(SqliteError::SqliteFailure(err, _)) => match err.code {
+                ErrorCode::Unknown | ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase => true,
+                _ => false,
+            },
+            _ => false,
         }
+    }
+}
 
-        Ok(())
+fn migrate(conn: &mut Connection) -> Result<(), MigrateError> {
+    // What we delete may be decrypted messages, it's overwritten
+    conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA secure_delete = ON")?;
+
+    let version: i64 = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+    let version = version as usize;
+    if version > MIGRATIONS.len() {
+        return Err(MigrateError::Newer);
     }
 
-    #[allow(dead_code)]
-    fn load_msgs<S: Store>(&mut self, store: &S) -> Result<(), Error> {
-        let key = format!("msg:{}", self.room.borrow().id);
-        let msgs: Vec<Message> = AppMsg::all(store, &key)?
-            .iter()
-            .map(|m| m.msg.clone())
-            .collect();
-        self.room.borrow_mut().messages = msgs;
+    let tx = conn.transaction()?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1))?;
+    }
+    tx.commit()?;
 
-        Ok(())
+    Ok(())
+}
+
+fn open_db(dir: &str) -> Result<Connection, Error> {
+    // There's nothing worth keeping in the old cache, the next sync fills the new one
+    let old_db = cache_dir_path(None, OLD_DB_NAME)?;
+    if old_db.exists() {
+        remove_dir_all(old_db)?;
+    }
+
+    /* Before we had several accounts the only one kept its database at the top, the
+     * first account we open takes it */
+    let path = cache_dir_path(Some(dir), DB_NAME)?;
+    let single_account_db = cache_dir_path(None, DB_NAME)?;
+    if single_account_db.exists() && !path.exists() {
+        rename(single_account_db, &path)?;
     }
 
-    #[allow(dead_code)]
-    fn clear_msgs(&self) {
-        self.room.borrow_mut().messages = vec![];
+    open_path(&path)
+}
+
+fn open_path(path: &Path) -> Result<Connection, Error> {
+    let mut conn = Connection::open(path)?;
+    match migrate(&mut conn) {
+        Ok(()) => Ok(conn),
+        // We start again with an empty one, the old one is kept aside
+        Err(err) if err.is_unusable() => {
+            error!("The cache can't be used, starting a new one: {:?}", err);
+            drop(conn);
+            rename(path, path.with_extension("db.broken"))?;
+            let mut conn = Connection::open(path)?;
+            migrate(&mut conn)?;
+            Ok(conn)
+        }
+        /* The database may hold messages waiting to be sent, it's never removed for a newer
+         * version or an error that may go away. Nothing is kept after this session then. */
+        Err(err) => {
+            error!(
+                "The cache can't be opened, it's only kept in memory: {:?}",
+                err
+            );
+            let mut conn = Connection::open_in_memory()?;
+            migrate(&mut conn)?;
+            Ok(conn)
+        }
+    }
+}
+
+/// The chunk where the new events of the room go, the one we have been filling since the
+/// last gap in the timeline
+fn latest_chunk(conn: &Connection, room_id: &RoomId) -> Result<i64, Error> {
+    let chunk_id: Option<i64> = conn.query_row(
+        "SELECT max(chunk_id) FROM timeline_chunks WHERE room_id = ?1",
+        params![room_id.to_string()],
+        |row| row.get(0),
+    )?;
+
+    match chunk_id {
+        Some(chunk_id) => Ok(chunk_id),
+        None => {
+            conn.execute(
+                "INSERT INTO timeline_chunks (room_id) VALUES (?1)",
+                params![room_id.to_string()],
+            )?;
+            Ok(conn.last_insert_rowid())
+        }
     }
 }
 
-impl Model for AppRoom {
-    fn key(&self) -> String {
-        format!("room:{}", self.room.borrow().id)
+fn save_room(
+    conn: &Connection,
+    room: &Room,
+    saved: Option<&SavedRoom>,
+) -> Result<SavedRoom, Error> {
+    let room_id = room.id.to_string();
+    let (data, sum) = serialize(&room_record(room))?;
+    if saved.map(|s| s.data) != Some(sum) {
+        conn.execute(
+            "INSERT OR REPLACE INTO rooms (room_id, data) VALUES (?1, ?2)",
+            params![room_id, data],
+        )?;
+    }
+
+    let mut members = HashMap::new();
+    for (uid, member) in room.members.iter() {
+        let (data, sum) = serialize(member)?;
+        if saved.and_then(|s| s.members.get(uid)) != Some(&sum) {
+            conn.execute(
+                "INSERT OR REPLACE INTO room_members (room_id, user_id, data) VALUES (?1, ?2, ?3)",
+                params![room_id, uid.to_string(), data],
+            )?;
+        }
+        members.insert(uid.clone(), sum);
+    }
+    for uid in saved
+        .iter()
+        .flat_map(|s| s.members.keys())
+        .filter(|uid| !members.contains_key(uid))
+    {
+        conn.execute(
+            "DELETE FROM room_members WHERE room_id = ?1 AND user_id = ?2",
+            params![room_id, uid.to_string()],
+        )?;
+    }
+
+    /* The decrypted messages of encrypted rooms would be kept in the clear, we load them
+     * from the server again instead. The room may have been encrypted since the last save. */
+    if room.encrypted {
+        if saved.map_or(true, |s| !s.events.is_empty()) {
+            conn.execute(
+                "DELETE FROM timeline_chunks WHERE room_id = ?1",
+                params![room_id],
+            )?;
+        }
+
+        return Ok(SavedRoom {
+            data: sum,
+            members,
+            ..Default::default()
+        });
+    }
+
+    /* New events go to the chunk of the next newer event we know of, so messages loaded
+     * from the past join the chunk they continue and the ones from sync the latest one */
+    let mut events = HashMap::new();
+    let mut newer_chunk: Option<i64> = None;
+    let mut newer_event: Option<&EventId> = None;
+    for msg in room.messages.iter().rev() {
+        // Messages we are still sending don't have an id yet
+        let event_id = match msg.id.as_ref() {
+            Some(event_id) => event_id,
+            None => continue,
+        };
+        let (data, sum) = serialize(msg)?;
+        events.insert(event_id.clone(), sum);
+        if saved.and_then(|s| s.events.get(event_id)) == Some(&sum) {
+            newer_chunk = None;
+            newer_event = Some(event_id);
+            continue;
+        }
+
+        let updated = conn.execute(
+            "UPDATE events SET redacted = ?2, data = ?3 WHERE event_id = ?1",
+            params![event_id.to_string(), msg.redacted, data],
+        )?;
+        if updated > 0 {
+            newer_chunk = None;
+            newer_event = Some(event_id);
+        } else {
+            let chunk_id = match (newer_chunk, newer_event) {
+                (Some(chunk_id), _) => chunk_id,
+                (None, Some(newer)) => conn.query_row(
+                    "SELECT chunk_id FROM events WHERE event_id = ?1",
+                    params![newer.to_string()],
+                    |row| row.get(0),
+                )?,
+                (None, None) => latest_chunk(conn, &room.id)?,
+            };
+            conn.execute(
+                "INSERT INTO events
+                 (event_id, room_id, chunk_id, sender, ts, replaces, annotates, state, redacted,
+                  data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    event_id.to_string(),
+                    room_id,
+                    chunk_id,
+                    msg.sender.to_string(),
+                    msg.date.timestamp_millis(),
+                    msg.replace.as_ref().map(ToString::to_string),
+                    msg.annotates.as_ref().map(ToString::to_string),
+                    msg.state_change.is_some(),
+                    msg.redacted,
+                    data,
+                ],
+            )?;
+            newer_chunk = Some(chunk_id);
+        }
+
+        index::index_event(conn, msg)?;
     }
+
+    // The token is only good for the chunk if nothing older than our oldest message is in it
+    let oldest = room.messages.iter().find(|msg| msg.id.is_some());
+    if let (Some(prev_batch), Some(oldest)) = (room.prev_batch.as_ref(), oldest) {
+        if saved.and_then(|s| s.prev_batch.as_ref()) != Some(prev_batch) {
+            conn.execute(
+                "UPDATE timeline_chunks SET prev_batch = ?1
+                 WHERE chunk_id = (SELECT chunk_id FROM events WHERE event_id = ?2)
+                 AND NOT EXISTS (SELECT 1 FROM events
+                                 WHERE chunk_id = timeline_chunks.chunk_id AND ts < ?3)",
+                params![
+                    prev_batch,
+                    oldest.id.as_ref().map(ToString::to_string),
+                    oldest.date.timestamp_millis(),
+                ],
+            )?;
+        }
+    }
+
+    Ok(SavedRoom {
+        data: sum,
+        prev_batch: room.prev_batch.clone(),
+        members,
+        events,
+    })
 }
 
-impl Model for AppMsg {
-    fn key(&self) -> String {
-        format!(
-            "msg:{}:{}",
-            self.msg.room,
-            self.msg
-                .id
-                .as_ref()
-                .map(|evid| evid.to_string())
-                .unwrap_or_default(),
+fn delete_room(conn: &Connection, room_id: &RoomId) -> Result<(), Error> {
+    let room_id = room_id.to_string();
+    conn.execute("DELETE FROM rooms WHERE room_id = ?1", params![room_id])?;
+    conn.execute(
+        "DELETE FROM room_members WHERE room_id = ?1",
+        params![room_id],
+    )?;
+    // The events and their search terms go with the chunks
+    conn.execute(
+        "DELETE FROM timeline_chunks WHERE room_id = ?1",
+        params![room_id],
+    )?;
+
+    Ok(())
+}
+
+fn load_room(conn: &Connection, data: &str) -> Result<(Room, SavedRoom), Error> {
+    let mut room: Room = serde_json::from_str(data)?;
+    let mut saved = SavedRoom {
+        data: checksum(data),
+        ..Default::default()
+    };
+    let room_id = room.id.to_string();
+
+    let mut stmt = conn.prepare("SELECT data FROM room_members WHERE room_id = ?1")?;
+    let members = stmt
+        .query_map(params![room_id], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for data in members {
+        let member: Member = serde_json::from_str(&data)?;
+        saved.members.insert(member.uid.clone(), checksum(&data));
+        room.members.insert(member.uid.clone(), member);
+    }
+
+    let chunk: Option<(i64, Option<String>)> = conn
+        .query_row(
+            "SELECT chunk_id, prev_batch FROM timeline_chunks
+             WHERE room_id = ?1 ORDER BY chunk_id DESC LIMIT 1",
+            params![room_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
         )
+        .optional()?;
+    if let Some((chunk_id, prev_batch)) = chunk {
+        // We load one more than we keep to know if there's anything before them
+        let mut stmt =
+            conn.prepare("SELECT data FROM events WHERE chunk_id = ?1 ORDER BY ts DESC LIMIT ?2")?;
+        let mut events = stmt
+            .query_map(params![chunk_id, (globals::CACHE_SIZE + 1) as i64], |row| {
+                row.get::<_, String>(0)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if events.len() > globals::CACHE_SIZE {
+            events.truncate(globals::CACHE_SIZE);
+        } else {
+            room.prev_batch = prev_batch.clone();
+            saved.prev_batch = prev_batch;
+        }
+
+        for data in events.iter().rev() {
+            let msg: Message = serde_json::from_str(data)?;
+            if let Some(event_id) = msg.id.clone() {
+                saved.events.insert(event_id, checksum(data));
+            }
+            room.messages.push(msg);
+        }
     }
+
+    Ok((room, saved))
 }
 
 // Cache
 
 #[derive(Clone)]
 pub struct FCache {
-    cache: Arc<Mutex<Option<Cache>>>,
+    /// Each account has its own database in this directory
+    dir: String,
+    cache: Arc<Mutex<Option<Connection>>>,
+    saved: Arc<Mutex<HashMap<RoomId, SavedRoom>>>,
 }
 
 impl FCache {
-    fn get_store(&self) -> MutexGuard<Option<Cache>> {
+    pub(super) fn get_store(&self) -> MutexGuard<Option<Connection>> {
         let mut guard = self.cache.lock().unwrap();
         if guard.is_none() {
-            let maybe_db_path = cache_dir_path(None, "cache.mdl").ok();
-            let db: String = maybe_db_path
-                .and_then(|p| p.to_str().map(Into::into))
-                .expect("Fatal error: Can't start the cache");
-            // TODO: Replace Cache with another library. Not expecting a proper
-            //       Path type for the path of the DB is bonkers.
-            let mdl_cache = Cache::new(&db).expect("Fatal error: Can't start the cache");
-            *guard = Some(mdl_cache);
+            let db = open_db(&self.dir).expect("Fatal error: Can't start the cache");
+            *guard = Some(db);
         }
         guard
     }
@@ -125,68 +508,218 @@ impl FCache {
     pub fn destroy(&self) -> Result<(), Error> {
         let mut guard = self.cache.lock().unwrap();
         guard.take();
+        self.saved.lock().unwrap().clear();
 
-        let fname = cache_dir_path(None, "cache.mdl")
+        let fname = cache_dir_path(Some(&self.dir), DB_NAME)
             .or_else(|_| Err(err_msg("Can't remove cache file")))?;
-        remove_dir_all(fname).or_else(|_| Err(err_msg("Can't remove cache file")))
+        remove_file(fname).or_else(|_| Err(err_msg("Can't remove cache file")))
     }
 
-    #[allow(dead_code)]
-    pub fn get_room(&self, id: &str) -> Result<Room, Error> {
+    /// Load the rooms with their latest messages, they are taken as already saved
+    pub fn get_rooms(&self) -> Result<Vec<Room>, Error> {
         let cache = self.get_store();
-        let r = AppRoom::get(cache.as_ref().unwrap(), id)?;
-        Ok(r.room.into_inner())
+        let conn = cache.as_ref().unwrap();
+
+        let mut stmt = conn.prepare("SELECT data FROM rooms")?;
+        let records = stmt
+            .query_map(NO_PARAMS, |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut rooms = vec![];
+        let mut saved = HashMap::new();
+        for data in records {
+            let (room, saved_room) = load_room(conn, &data)?;
+            saved.insert(room.id.clone(), saved_room);
+            rooms.push(room);
+        }
+        *self.saved.lock().unwrap() = saved;
+
+        Ok(rooms)
     }
 
-    pub fn get_rooms(&self) -> Result<Vec<Room>, Error> {
+    pub fn get_st(&self) -> Result<AppState, Error> {
         let cache = self.get_store();
-        let rooms = AppRoom::all(cache.as_ref().unwrap(), "room")?
-            .iter()
-            .map(|r| r.room.borrow().clone())
-            .collect();
-        Ok(rooms)
+        let conn = cache.as_ref().unwrap();
+
+        let (since, username, uid, device_id): (Option<String>, String, String, String) = conn
+            .query_row(
+                "SELECT since, username, uid, device_id FROM app_state WHERE id = 0",
+                NO_PARAMS,
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )?;
+
+        Ok(AppState {
+            since,
+            username,
+            uid: UserId::try_from(uid.as_str())?,
+            device_id: device_id.as_str().into(),
+        })
     }
 
-    pub fn save_rooms(&self, rooms: Vec<Room>) -> Result<(), Error> {
-        for r in rooms {
-            self.save_room(r)?;
+    /// Save what changed in the rooms since the last time together with the
+    /// state, so the "since" we store always matches the data it covers
+    pub fn save(&self, rooms: &RoomList, st: AppState) -> Result<(), Error> {
+        let mut cache = self.get_store();
+        let conn = cache.as_mut().unwrap();
+        let mut saved = self.saved.lock().unwrap();
+
+        let tx = conn.transaction()?;
+        let mut now_saved = HashMap::new();
+        for room in rooms.values() {
+            let saved_room = save_room(&tx, room, saved.get(&room.id))?;
+            now_saved.insert(room.id.clone(), saved_room);
+        }
+        for room_id in saved.keys().filter(|id| !rooms.contains_key(id)) {
+            delete_room(&tx, room_id)?;
         }
+        tx.execute(
+            "INSERT OR REPLACE INTO app_state (id, since, username, uid, device_id)
+             VALUES (0, ?1, ?2, ?3, ?4)",
+            params![
+                st.since,
+                st.username,
+                st.uid.to_string(),
+                st.device_id.to_string(),
+            ],
+        )?;
+        tx.commit()?;
+
+        *saved = now_saved;
+
         Ok(())
     }
 
-    pub fn save_room(&self, room: Room) -> Result<(), Error> {
+    /// There's a gap between the events we have of the room and the ones that are coming,
+    /// these go to a new chunk that can be filled back from prev_batch
+    pub fn start_timeline_chunk(
+        &self,
+        room_id: &RoomId,
+        prev_batch: Option<String>,
+    ) -> Result<(), Error> {
         let cache = self.get_store();
-        let mut stored_room = room;
-        // Don't store typing notifications
-        stored_room.typing_users.clear();
-        let approom = AppRoom {
-            room: RefCell::new(stored_room),
-        };
-        approom.store(cache.as_ref().unwrap())?;
+        let conn = cache.as_ref().unwrap();
+
+        // An empty chunk is as good as a new one
+        conn.execute(
+            "INSERT INTO timeline_chunks (room_id, prev_batch)
+             SELECT ?1, ?2 WHERE EXISTS (
+                 SELECT 1 FROM events WHERE chunk_id =
+                     (SELECT max(chunk_id) FROM timeline_chunks WHERE room_id = ?1)
+             )",
+            params![room_id.to_string(), prev_batch],
+        )?;
 
         Ok(())
     }
+}
 
-    pub fn get_st(&self) -> Result<AppState, Error> {
-        let cache = self.get_store();
-        AppState::get(cache.as_ref().unwrap(), "state")
+#[cfg(test)]
+impl FCache {
+    /// A cache that is only kept in memory
+    pub fn in_memory() -> Self {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        Self::with_connection(conn)
     }
 
-    pub fn save_st(&self, st: AppState) -> Result<(), Error> {
-        let cache = self.get_store();
-        st.store(cache.as_ref().unwrap())?;
+    /// A cache in the database at `path`, like the one of an account
+    pub fn open(path: &Path) -> Self {
+        Self::with_connection(open_path(path).unwrap())
+    }
 
-        Ok(())
+    fn with_connection(conn: Connection) -> Self {
+        FCache {
+            dir: String::new(),
+            cache: Arc::new(Mutex::new(Some(conn))),
+            saved: Arc::new(Mutex::new(HashMap::new())),
+        }
     }
 }
 
-// The cache object, it's the same for the whole process
+// The cache objects, one for each account we open in the process
 lazy_static! {
-    static ref CACHE: FCache = FCache {
-        cache: Arc::new(Mutex::new(None))
-    };
+    static ref CACHES: Mutex<HashMap<UserId, FCache>> = Mutex::new(HashMap::new());
+    static ref ACTIVE: Mutex<Option<UserId>> = Mutex::new(None);
 }
 
+/// The cache of the account we are showing
 pub fn get() -> FCache {
-    CACHE.clone()
+    let uid = ACTIVE.lock().unwrap().clone();
+    for_account(&uid.expect("There is no account to get the cache of"))
+}
+
+pub fn for_account(uid: &UserId) -> FCache {
+    CACHES
+        .lock()
+        .unwrap()
+        .entry(uid.clone())
+        .or_insert_with(|| FCache {
+            dir: account_dir(uid),
+            cache: Arc::new(Mutex::new(None)),
+            saved: Arc::new(Mutex::new(HashMap::new())),
+        })
+        .clone()
+}
+
+/// Makes `get` return the cache of this account from now on
+pub fn set_account(uid: &UserId) {
+    *ACTIVE.lock().unwrap() = Some(uid.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn cache_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        // Running them again on an up to date database does nothing
+        migrate(&mut conn).unwrap();
+
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+
+        conn.execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len() + 1))
+            .unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+
+    #[test]
+    fn cache_unusable_databases() {
+        let dir = env::temp_dir().join(format!("fractal-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let version = |conn: &Connection| -> i64 {
+            conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+                .unwrap()
+        };
+
+        // What isn't a database is kept aside
+        let path = dir.join("broken.db");
+        fs::write(&path, vec![b'x'; 4096]).unwrap();
+        let conn = open_path(&path).unwrap();
+        assert_eq!(version(&conn) as usize, MIGRATIONS.len());
+        assert_eq!(
+            fs::read(path.with_extension("db.broken")).unwrap(),
+            vec![b'x'; 4096]
+        );
+
+        // A newer version's database isn't touched
+        let path = dir.join("newer.db");
+        let _ = fs::remove_file(&path);
+        let newer = MIGRATIONS.len() as i64 + 1;
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(&format!("PRAGMA user_version = {}", newer))
+            .unwrap();
+        let conn = open_path(&path).unwrap();
+        assert_eq!(version(&conn) as usize, MIGRATIONS.len());
+        assert_eq!(version(&Connection::open(&path).unwrap()), newer);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,242 @@
+//! Encryption of the files sent to encrypted rooms, see
+//! https://matrix.org/docs/spec/client_server/r0.6.1#sending-encrypted-attachments
+//!
+//! Once received, the keys of a file travel in the fragment of its `mxc://`
+//! URL, so the widgets keep passing a single URL around and only the download
+//! has to know about them.
+
+use aes_ctr::stream_cipher::generic_array::GenericArray;
+use aes_ctr::stream_cipher::{NewStreamCipher, SyncStreamCipher};
+use aes_ctr::Aes256Ctr;
+use fractal_api::url::{form_urlencoded, Url};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::{json, Value as JsonValue};
+
+use super::cipher::sha256;
+use super::{decode_base64, encode_base64, CryptoError};
+
+const ALGORITHM: &str = "A256CTR";
+const VERSION: &str = "v2";
+
+/// The keys of an encrypted file
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttachmentKeys {
+    key: [u8; 32],
+    iv: [u8; 16],
+    sha256: [u8; 32],
+}
+
+/// Where to download an encrypted file, and how to decrypt it
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncryptedFile {
+    pub url: Url,
+    pub keys: AttachmentKeys,
+}
+
+/// Encrypts `data` in place with a new key
+pub fn encrypt_attachment(data: &mut [u8]) -> AttachmentKeys {
+    let mut key = [0; 32];
+    let mut iv = [0; 16];
+    OsRng.fill_bytes(&mut key);
+    // The lower half of the IV is the block counter, it starts at zero
+    OsRng.fill_bytes(&mut iv[..8]);
+
+    apply_keystream(&key, &iv, data);
+    AttachmentKeys {
+        key,
+        iv,
+        sha256: sha256(data),
+    }
+}
+
+/// Checks the hash of the downloaded `data` and decrypts it in place
+pub fn decrypt_attachment(data: &mut [u8], keys: &AttachmentKeys) -> Result<(), CryptoError> {
+    if sha256(data) != keys.sha256 {
+        return Err(CryptoError::BadMac);
+    }
+
+    apply_keystream(&keys.key, &keys.iv, data);
+    Ok(())
+}
+
+fn apply_keystream(key: &[u8; 32], iv: &[u8; 16], data: &mut [u8]) {
+    Aes256Ctr::new(GenericArray::from_slice(key), GenericArray::from_slice(iv))
+        .apply_keystream(data);
+}
+
+fn to_array<T: Default + AsMut<[u8]>>(input: &[u8]) -> Result<T, CryptoError> {
+    let mut output = T::default();
+    if input.len() != output.as_mut().len() {
+        return Err(CryptoError::BadMessage);
+    }
+    output.as_mut().copy_from_slice(input);
+    Ok(output)
+}
+
+impl AttachmentKeys {
+    fn from_encoded(key: &str, iv: &str, sha256: &str) -> Result<Self, CryptoError> {
+        let key = base64::decode_config(key.trim_end_matches('='), base64::URL_SAFE_NO_PAD)?;
+        Ok(Self {
+            key: to_array(&key)?,
+            iv: to_array(&decode_base64(iv)?)?,
+            sha256: to_array(&decode_base64(sha256)?)?,
+        })
+    }
+
+    /// The key is a JSON Web Key, encoded in unpadded base64url
+    fn encoded_key(&self) -> String {
+        base64::encode_config(&self.key, base64::URL_SAFE_NO_PAD)
+    }
+}
+
+impl EncryptedFile {
+    /// Reads the `file` of a message content, or the `thumbnail_file` of its info
+    pub fn from_json(file: &JsonValue) -> Result<Self, CryptoError> {
+        if file["v"] != VERSION {
+            return Err(CryptoError::BadMessage);
+        }
+        if file["key"]["alg"] != ALGORITHM {
+            let algorithm = file["key"]["alg"].as_str().unwrap_or_default();
+            return Err(CryptoError::UnsupportedAlgorithm(algorithm.to_string()));
+        }
+
+        let url = file["url"]
+            .as_str()
+            .and_then(|url| Url::parse(url).ok())
+            .filter(|url| url.scheme() == "mxc")
+            .ok_or(CryptoError::BadMessage)?;
+        let keys = AttachmentKeys::from_encoded(
+            file["key"]["k"].as_str().ok_or(CryptoError::BadMessage)?,
+            file["iv"].as_str().ok_or(CryptoError::BadMessage)?,
+            file["hashes"]["sha256"]
+                .as_str()
+                .ok_or(CryptoError::BadMessage)?,
+        )?;
+
+        Ok(Self { url, keys })
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        json!({
+            "url": self.url,
+            "key": {
+                "kty": "oct",
+                "key_ops": ["encrypt", "decrypt"],
+                "alg": ALGORITHM,
+                "k": self.keys.encoded_key(),
+                "ext": true,
+            },
+            "iv": encode_base64(&self.keys.iv),
+            "hashes": {
+                "sha256": encode_base64(&self.keys.sha256),
+            },
+            "v": VERSION,
+        })
+    }
+
+    /// The URL of the file with its keys in the fragment
+    pub fn media_url(&self) -> Url {
+        let fragment = form_urlencoded::Serializer::new(String::new())
+            .append_pair("k", &self.keys.encoded_key())
+            .append_pair("iv", &encode_base64(&self.keys.iv))
+            .append_pair("sha256", &encode_base64(&self.keys.sha256))
+            .finish();
+        let mut url = self.url.clone();
+        url.set_fragment(Some(&fragment));
+        url
+    }
+
+    /// Reads the keys back from a URL made by `media_url`, `None` if the file
+    /// isn't encrypted
+    pub fn from_media_url(url: &Url) -> Option<Self> {
+        let mut key = None;
+        let mut iv = None;
+        let mut sha256 = None;
+        for (name, value) in form_urlencoded::parse(url.fragment()?.as_bytes()) {
+            match name.as_ref() {
+                "k" => key = Some(value),
+                "iv" => iv = Some(value),
+                "sha256" => sha256 = Some(value),
+                _ => {}
+            }
+        }
+
+        let keys = AttachmentKeys::from_encoded(&key?, &iv?, &sha256?).ok()?;
+        let mut url = url.clone();
+        url.set_fragment(None);
+        Some(Self { url, keys })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attachment_roundtrip() {
+        let plaintext = b"The attachment of an encrypted room".to_vec();
+        let mut data = plaintext.clone();
+        let keys = encrypt_attachment(&mut data);
+        assert_ne!(data, plaintext);
+        assert_eq!(keys.iv[8..], [0; 8]);
+
+        let file = EncryptedFile {
+            url: Url::parse("mxc://example.org/abcdef").unwrap(),
+            keys,
+        };
+        let file = EncryptedFile::from_json(&file.to_json()).unwrap();
+        let media_url = file.media_url();
+        assert_eq!(media_url.path(), "/abcdef");
+        assert_eq!(
+            EncryptedFile::from_media_url(&media_url),
+            Some(file.clone())
+        );
+        assert_eq!(EncryptedFile::from_media_url(&file.url), None);
+
+        let mut tampered = data.clone();
+        tampered[0] ^= 1;
+        assert!(decrypt_attachment(&mut tampered, &file.keys).is_err());
+
+        decrypt_attachment(&mut data, &file.keys).unwrap();
+        assert_eq!(data, plaintext);
+    }
+
+    #[test]
+    fn attachment_known_answer() {
+        // From the AES-256-CTR vectors of NIST SP 800-38A, F.5.5
+        let file = EncryptedFile::from_json(&json!({
+            "url": "mxc://example.org/abcdef",
+            "key": {
+                "kty": "oct",
+                "key_ops": ["encrypt", "decrypt"],
+                "alg": "A256CTR",
+                "k": "YD3rEBXKcb4rc67whX13gR81LAc7YQjXLZgQowkU3_Q",
+                "ext": true,
+            },
+            "iv": "8PHy8/T19vf4+fr7/P3+/w",
+            "hashes": {
+                "sha256": "tkEAvV+cSsTWQNMQu3a21Y87/8yAiyTOaF9Bj4Es+VQ",
+            },
+            "v": "v2",
+        }))
+        .unwrap();
+
+        let mut data = [
+            0x60, 0x1e, 0xc3, 0x13, 0x77, 0x57, 0x89, 0xa5, 0xb7, 0xa7, 0xf5, 0x04, 0xbb, 0xf3,
+            0xd2, 0x28,
+        ];
+        decrypt_attachment(&mut data, &file.keys).unwrap();
+        assert_eq!(
+            data,
+            [
+                0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+                0x17, 0x2a,
+            ]
+        );
+
+        let mut json = file.to_json();
+        json["key"]["alg"] = json!("A128CTR");
+        assert!(EncryptedFile::from_json(&json).is_err());
+    }
+}
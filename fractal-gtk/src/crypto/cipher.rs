@@ -0,0 +1,132 @@
+use aes::block_cipher_trait::generic_array::GenericArray;
+use aes::block_cipher_trait::BlockCipher;
+use aes::Aes256;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use super::CryptoError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const MAC_LENGTH: usize = 8;
+const BLOCK_SIZE: usize = 16;
+
+pub fn hkdf_sha256(salt: Option<&[u8]>, input: &[u8], info: &[u8], output: &mut [u8]) {
+    Hkdf::<Sha256>::new(salt, input)
+        .expand(info, output)
+        .expect("HKDF output too long");
+}
+
+pub fn hmac_sha256(key: &[u8], input: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_varkey(key).expect("HMAC can take a key of any size");
+    mac.input(input);
+
+    let mut output = [0; 32];
+    output.copy_from_slice(&mac.result().code());
+    output
+}
+
+pub fn sha256(input: &[u8]) -> [u8; 32] {
+    let mut output = [0; 32];
+    output.copy_from_slice(&Sha256::digest(input));
+    output
+}
+
+/// The `aes_sha2` cipher shared by Olm and Megolm: AES-256 in CBC mode with
+/// PKCS#7 padding, authenticated with a truncated HMAC-SHA-256. The keys are
+/// derived from a single secret, using the protocol specific `info` string.
+pub struct Cipher {
+    aes_key: [u8; 32],
+    mac_key: [u8; 32],
+    iv: [u8; 16],
+}
+
+impl Cipher {
+    pub fn new(secret: &[u8], info: &[u8]) -> Self {
+        let mut derived = [0; 80];
+        hkdf_sha256(None, secret, info, &mut derived);
+
+        let mut cipher = Self {
+            aes_key: [0; 32],
+            mac_key: [0; 32],
+            iv: [0; 16],
+        };
+        cipher.aes_key.copy_from_slice(&derived[..32]);
+        cipher.mac_key.copy_from_slice(&derived[32..64]);
+        cipher.iv.copy_from_slice(&derived[64..]);
+        cipher
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let aes = Aes256::new(GenericArray::from_slice(&self.aes_key));
+        let padding = BLOCK_SIZE - plaintext.len() % BLOCK_SIZE;
+        let mut data = plaintext.to_vec();
+        data.extend(std::iter::repeat(padding as u8).take(padding));
+
+        let mut previous = self.iv;
+        for block in data.chunks_mut(BLOCK_SIZE) {
+            for (b, p) in block.iter_mut().zip(previous.iter()) {
+                *b ^= p;
+            }
+            aes.encrypt_block(GenericArray::from_mut_slice(block));
+            previous.copy_from_slice(block);
+        }
+
+        data
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if ciphertext.is_empty() || ciphertext.len() % BLOCK_SIZE != 0 {
+            return Err(CryptoError::BadMessage);
+        }
+
+        let aes = Aes256::new(GenericArray::from_slice(&self.aes_key));
+        let mut data = ciphertext.to_vec();
+        let mut previous = self.iv;
+        for block in data.chunks_mut(BLOCK_SIZE) {
+            let mut current = [0; BLOCK_SIZE];
+            current.copy_from_slice(block);
+            aes.decrypt_block(GenericArray::from_mut_slice(block));
+            for (b, p) in block.iter_mut().zip(previous.iter()) {
+                *b ^= p;
+            }
+            previous = current;
+        }
+
+        let padding = *data.last().unwrap_or(&0) as usize;
+        if padding == 0
+            || padding > BLOCK_SIZE
+            || data[data.len() - padding..]
+                .iter()
+                .any(|b| *b as usize != padding)
+        {
+            return Err(CryptoError::BadMessage);
+        }
+        data.truncate(data.len() - padding);
+
+        Ok(data)
+    }
+
+    pub fn mac(&self, message: &[u8]) -> [u8; MAC_LENGTH] {
+        let mut mac = [0; MAC_LENGTH];
+        mac.copy_from_slice(&hmac_sha256(&self.mac_key, message)[..MAC_LENGTH]);
+        mac
+    }
+
+    /// Checks the MAC appended at the end of `message`
+    pub fn verify_mac(&self, message: &[u8], mac: &[u8]) -> Result<(), CryptoError> {
+        let expected = self.mac(message);
+        // Compare the whole MAC so the comparison takes constant time
+        let diff = expected
+            .iter()
+            .zip(mac.iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+
+        if mac.len() == MAC_LENGTH && diff == 0 {
+            Ok(())
+        } else {
+            Err(CryptoError::BadMac)
+        }
+    }
+}
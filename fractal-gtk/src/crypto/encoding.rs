@@ -0,0 +1,87 @@
+//! The minimal subset of the protobuf wire format used by Olm and Megolm
+//! messages: a version byte followed by varint and length-delimited fields.
+
+use super::CryptoError;
+
+pub const VERSION: u8 = 3;
+
+pub enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub fn write_varint_field(out: &mut Vec<u8>, tag: u8, value: u64) {
+    out.push(tag);
+    write_varint(out, value);
+}
+
+pub fn write_bytes_field(out: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    out.push(tag);
+    write_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+fn read_varint(input: &[u8], pos: &mut usize) -> Result<u64, CryptoError> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *input.get(*pos).ok_or(CryptoError::BadMessage)?;
+        *pos += 1;
+        if shift >= 64 {
+            return Err(CryptoError::BadMessage);
+        }
+        value |= u64::from(byte & 0x7F) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Checks the version of a message and returns the list of its fields with
+/// their tags. Unknown fields are returned too, callers just ignore them.
+pub fn decode_fields(input: &[u8]) -> Result<Vec<(u64, Field<'_>)>, CryptoError> {
+    match input.first() {
+        Some(&VERSION) => {}
+        Some(&version) => return Err(CryptoError::UnknownVersion(version)),
+        None => return Err(CryptoError::BadMessage),
+    }
+
+    let mut fields = Vec::new();
+    let mut pos = 1;
+    while pos < input.len() {
+        let tag = read_varint(input, &mut pos)?;
+        let field = match tag & 0x07 {
+            0 => Field::Varint(read_varint(input, &mut pos)?),
+            2 => {
+                let len = read_varint(input, &mut pos)? as usize;
+                let end = pos.checked_add(len).ok_or(CryptoError::BadMessage)?;
+                let bytes = input.get(pos..end).ok_or(CryptoError::BadMessage)?;
+                pos = end;
+                Field::Bytes(bytes)
+            }
+            _ => return Err(CryptoError::BadMessage),
+        };
+        fields.push((tag, field));
+    }
+
+    Ok(fields)
+}
+
+pub fn to_key(bytes: &[u8]) -> Result<[u8; 32], CryptoError> {
+    if bytes.len() != 32 {
+        return Err(CryptoError::BadMessage);
+    }
+
+    let mut key = [0; 32];
+    key.copy_from_slice(bytes);
+    Ok(key)
+}
@@ -0,0 +1,1357 @@
+use fractal_api::events::room::{MembershipState, RoomEventContent};
+use fractal_api::events::ToDeviceEvent;
+use fractal_api::identifiers::{DeviceId, RoomId, UserId};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::megolm::{InboundGroupSession, OutboundGroupSession};
+use super::olm::{Account, OlmMessage, PreKeyMessage, Session, MAX_ONE_TIME_KEYS};
+use super::{
+    canonical_json, decode_base64, decode_key, encode_base64, verify_json, CryptoError,
+    MEGOLM_ALGORITHM, OLM_ALGORITHM,
+};
+
+pub static ONE_TIME_KEY_ALGORITHM: &str = "signed_curve25519";
+
+// Outbound group sessions are replaced after a week or 100 messages, the
+// default rotation of the spec
+const ROTATION_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const ROTATION_PERIOD_MSGS: u64 = 100;
+
+/// The identity keys of a device, as published by its owner
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Device {
+    pub curve25519_key: String,
+    pub ed25519_key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OutboundSession {
+    session: OutboundGroupSession,
+    creation_time: u64,
+    shared_with: HashMap<UserId, HashSet<Box<DeviceId>>>,
+}
+
+impl OutboundSession {
+    fn is_expired(&self, rotation: &Rotation) -> bool {
+        let age = Duration::from_secs(now().saturating_sub(self.creation_time));
+        age.as_millis() >= u128::from(rotation.period_ms)
+            || u64::from(self.session.message_index()) >= rotation.period_msgs
+    }
+}
+
+/// When the outbound group session of a room has to be replaced, from the
+/// `m.room.encryption` event of the room
+#[derive(Clone, Serialize, Deserialize)]
+struct Rotation {
+    period_ms: u64,
+    period_msgs: u64,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            period_ms: ROTATION_PERIOD.as_millis() as u64,
+            period_msgs: ROTATION_PERIOD_MSGS,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct InboundSession {
+    sender_key: String,
+    // The owner and the signing key of the device that shared the session,
+    // unknown for the sessions stored before we kept them
+    #[serde(default)]
+    sender: Option<UserId>,
+    #[serde(default)]
+    ed25519_key: Option<String>,
+    session: InboundGroupSession,
+    // The events decrypted with each message index, so an index can't be
+    // used again by a replayed message
+    #[serde(default)]
+    decrypted: HashMap<u32, DecryptedEvent>,
+}
+
+#[derive(PartialEq, Serialize, Deserialize)]
+struct DecryptedEvent {
+    event_id: String,
+    origin_server_ts: i64,
+}
+
+/// Keeps the whole encryption state of this device: its keys, the Olm
+/// sessions with other devices, the Megolm sessions of every room and the
+/// device lists of the users we share encrypted rooms with.
+#[derive(Serialize, Deserialize)]
+pub struct OlmMachine {
+    user_id: UserId,
+    device_id: Box<DeviceId>,
+    account: Account,
+    uploaded_device_keys: bool,
+    one_time_key_count: Option<u64>,
+    // Olm sessions by the curve25519 key of the other device
+    sessions: HashMap<String, Vec<Session>>,
+    // Megolm sessions by room and session ID
+    inbound_group_sessions: HashMap<RoomId, HashMap<String, InboundSession>>,
+    outbound_group_sessions: HashMap<RoomId, OutboundSession>,
+    #[serde(default)]
+    rotations: HashMap<RoomId, Rotation>,
+    devices: HashMap<UserId, HashMap<Box<DeviceId>, Device>>,
+    tracked_users: HashSet<UserId>,
+    outdated_users: HashSet<UserId>,
+}
+
+impl OlmMachine {
+    pub fn new(user_id: UserId, device_id: Box<DeviceId>) -> Self {
+        Self {
+            user_id,
+            device_id,
+            account: Account::new(),
+            uploaded_device_keys: false,
+            one_time_key_count: None,
+            sessions: HashMap::new(),
+            inbound_group_sessions: HashMap::new(),
+            outbound_group_sessions: HashMap::new(),
+            rotations: HashMap::new(),
+            devices: HashMap::new(),
+            tracked_users: HashSet::new(),
+            outdated_users: HashSet::new(),
+        }
+    }
+
+    /// Loads the state stored at `path`, or creates a new one if there's no
+    /// stored state for this device. A state that can't be read is an error,
+    /// replacing it would lose the identity keys and every session.
+    pub fn load(
+        path: &Path,
+        user_id: UserId,
+        device_id: Box<DeviceId>,
+    ) -> Result<Self, CryptoError> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                info!("No encryption keys stored yet, creating new ones");
+                return Ok(Self::new(user_id, device_id));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let machine: Self = serde_json::from_slice(&data)?;
+        if machine.user_id == user_id && machine.device_id == device_id {
+            Ok(machine)
+        } else {
+            // The device these keys belonged to doesn't exist anymore
+            info!("Stored encryption keys belong to another device, creating new ones");
+            Ok(Self::new(user_id, device_id))
+        }
+    }
+
+    /// Writes the state to `path`, only readable by the user since it
+    /// contains private keys
+    pub fn store(&self, path: &Path) -> Result<(), CryptoError> {
+        let data = serde_json::to_vec(self)?;
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    pub fn identity_keys(&self) -> Device {
+        Device {
+            curve25519_key: self.account.curve25519_key(),
+            ed25519_key: self.account.ed25519_key(),
+        }
+    }
+
+    fn sign_json(&self, value: &mut JsonValue) {
+        let signature = self.account.sign(canonical_json(value).as_bytes());
+        value["signatures"] = json!({
+            self.user_id.to_string(): {
+                format!("ed25519:{}", self.device_id): signature,
+            }
+        });
+    }
+
+    fn device_keys(&self) -> JsonValue {
+        let mut device_keys = json!({
+            "user_id": self.user_id,
+            "device_id": self.device_id,
+            "algorithms": [OLM_ALGORITHM, MEGOLM_ALGORITHM],
+            "keys": {
+                format!("curve25519:{}", self.device_id): self.account.curve25519_key(),
+                format!("ed25519:{}", self.device_id): self.account.ed25519_key(),
+            },
+        });
+        self.sign_json(&mut device_keys);
+
+        device_keys
+    }
+
+    pub fn should_upload_keys(&self) -> bool {
+        !self.uploaded_device_keys
+            || self
+                .one_time_key_count
+                .map_or(true, |count| count < (MAX_ONE_TIME_KEYS / 2) as u64)
+    }
+
+    /// Returns the device keys, if they weren't uploaded yet, and enough new
+    /// one-time keys to have half of the maximum published on the server
+    pub fn keys_for_upload(&mut self) -> (Option<JsonValue>, HashMap<String, JsonValue>) {
+        let device_keys = Some(self.device_keys()).filter(|_| !self.uploaded_device_keys);
+
+        let published = self.one_time_key_count.unwrap_or(0) as usize;
+        let unpublished = self.account.unpublished_one_time_keys().len();
+        let wanted = MAX_ONE_TIME_KEYS / 2;
+        if published + unpublished < wanted {
+            self.account
+                .generate_one_time_keys(wanted - published - unpublished);
+        }
+
+        let one_time_keys = self
+            .account
+            .unpublished_one_time_keys()
+            .into_iter()
+            .map(|(key_id, key)| {
+                let mut value = json!({ "key": key });
+                self.sign_json(&mut value);
+                (format!("{}:{}", ONE_TIME_KEY_ALGORITHM, key_id), value)
+            })
+            .collect();
+
+        (device_keys, one_time_keys)
+    }
+
+    pub fn receive_keys_upload_response(&mut self, one_time_key_counts: &HashMap<String, u64>) {
+        self.uploaded_device_keys = true;
+        self.account.mark_keys_as_published();
+        self.update_one_time_key_count(one_time_key_counts);
+    }
+
+    pub fn update_one_time_key_count(&mut self, one_time_key_counts: &HashMap<String, u64>) {
+        self.one_time_key_count = Some(
+            one_time_key_counts
+                .get(ONE_TIME_KEY_ALGORITHM)
+                .copied()
+                .unwrap_or(0),
+        );
+    }
+
+    /// Starts tracking the devices of the given users, the new ones will be
+    /// returned by `users_for_key_query`
+    pub fn update_tracked_users<'a, I: IntoIterator<Item = &'a UserId>>(&mut self, users: I) {
+        for user in users {
+            if self.tracked_users.insert(user.clone()) {
+                self.outdated_users.insert(user.clone());
+            }
+        }
+    }
+
+    pub fn receive_device_list_changes(&mut self, changed: &[UserId], left: &[UserId]) {
+        for user in changed {
+            if self.tracked_users.contains(user) {
+                self.outdated_users.insert(user.clone());
+            }
+        }
+        for user in left {
+            self.tracked_users.remove(user);
+            self.outdated_users.remove(user);
+            self.devices.remove(user);
+            // We don't share any room with them anymore
+            self.discard_group_sessions_shared_with(user, |_| true);
+        }
+    }
+
+    /// Keeps track of the state events of a room that affect its outbound
+    /// group session: members leaving and the rotation settings
+    pub fn receive_room_state_event(&mut self, room_id: &RoomId, content: &RoomEventContent) {
+        match content {
+            RoomEventContent::Member(member) => {
+                if let MembershipState::Leave | MembershipState::Ban = member.membership {
+                    self.discard_group_session(room_id);
+                }
+            }
+            RoomEventContent::Encryption(encryption) => {
+                let default = Rotation::default();
+                let rotation = Rotation {
+                    period_ms: encryption.rotation_period_ms.unwrap_or(default.period_ms),
+                    period_msgs: encryption
+                        .rotation_period_msgs
+                        .unwrap_or(default.period_msgs),
+                };
+                self.rotations.insert(room_id.clone(), rotation);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn users_for_key_query(&self) -> Vec<UserId> {
+        self.outdated_users.iter().cloned().collect()
+    }
+
+    /// Updates the devices of the users from the response to a key query.
+    /// Devices whose keys aren't properly signed are ignored.
+    pub fn receive_keys_query_response(
+        &mut self,
+        device_keys: &HashMap<UserId, HashMap<Box<DeviceId>, JsonValue>>,
+    ) {
+        for (user_id, user_devices) in device_keys {
+            let old_devices = self.devices.remove(user_id).unwrap_or_default();
+            let devices = user_devices
+                .iter()
+                .filter_map(|(device_id, keys)| {
+                    let device = parse_device_keys(user_id, device_id, keys)
+                        .map_err(|err| {
+                            warn!("Invalid keys for {} {}: {:?}", user_id, device_id, err)
+                        })
+                        .ok()?;
+
+                    // The signing key of a device can't change
+                    match old_devices.get(device_id) {
+                        Some(old) if old.ed25519_key != device.ed25519_key => {
+                            warn!("The ed25519 key of {} {} has changed", user_id, device_id);
+                            None
+                        }
+                        _ => Some((device_id.clone(), device)),
+                    }
+                })
+                .collect::<HashMap<_, _>>();
+
+            // Removed devices mustn't get the keys of the next messages
+            self.discard_group_sessions_shared_with(user_id, |device_id| {
+                !devices.contains_key(device_id)
+            });
+
+            self.devices.insert(user_id.clone(), devices);
+            self.outdated_users.remove(user_id);
+        }
+    }
+
+    /// The devices of `users` we don't have an Olm session with, in the form
+    /// expected by the claim endpoint
+    pub fn missing_sessions<'a, I: IntoIterator<Item = &'a UserId>>(
+        &self,
+        users: I,
+    ) -> HashMap<UserId, HashMap<Box<DeviceId>, String>> {
+        users
+            .into_iter()
+            .filter_map(|user_id| {
+                let devices: HashMap<_, _> = self
+                    .devices
+                    .get(user_id)?
+                    .iter()
+                    .filter(|(device_id, device)| {
+                        !self.is_own_device(user_id, device_id)
+                            && !self.sessions.contains_key(&device.curve25519_key)
+                    })
+                    .map(|(device_id, _)| (device_id.clone(), ONE_TIME_KEY_ALGORITHM.to_string()))
+                    .collect();
+
+                Some((user_id.clone(), devices)).filter(|(_, d)| !d.is_empty())
+            })
+            .collect()
+    }
+
+    /// Creates Olm sessions with the one-time keys claimed for other devices
+    pub fn receive_keys_claim_response(
+        &mut self,
+        one_time_keys: &HashMap<UserId, HashMap<Box<DeviceId>, HashMap<String, JsonValue>>>,
+    ) {
+        for (user_id, user_devices) in one_time_keys {
+            for (device_id, keys) in user_devices {
+                let device = match self.devices.get(user_id).and_then(|d| d.get(device_id)) {
+                    Some(device) => device.clone(),
+                    None => continue,
+                };
+
+                for key in keys.values() {
+                    let session = verify_json(
+                        &device.ed25519_key,
+                        &user_id.to_string(),
+                        &format!("ed25519:{}", device_id),
+                        key,
+                    )
+                    .and_then(|_| {
+                        let one_time_key = key["key"].as_str().ok_or(CryptoError::BadMessage)?;
+                        Ok(self.account.create_outbound_session(
+                            &decode_key(&device.curve25519_key)?,
+                            &decode_key(one_time_key)?,
+                        ))
+                    });
+
+                    match session {
+                        Ok(session) => {
+                            self.sessions
+                                .entry(device.curve25519_key.clone())
+                                .or_default()
+                                .push(session);
+                        }
+                        Err(err) => {
+                            warn!(
+                                "Invalid one-time key for {} {}: {:?}",
+                                user_id, device_id, err
+                            )
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_own_device(&self, user_id: &UserId, device_id: &DeviceId) -> bool {
+        *user_id == self.user_id && *device_id == *self.device_id
+    }
+
+    /// Handles the to-device events of a sync, storing the room keys sent to
+    /// us. Returns the decrypted events.
+    pub fn receive_to_device_events(&mut self, events: &[ToDeviceEvent]) -> Vec<JsonValue> {
+        events
+            .iter()
+            .filter(|ev| ev.event_type == "m.room.encrypted")
+            .filter_map(|ev| {
+                self.decrypt_to_device_event(ev)
+                    .map_err(|err| warn!("Couldn't decrypt to-device event: {:?}", err))
+                    .ok()
+            })
+            .collect()
+    }
+
+    fn decrypt_to_device_event(&mut self, event: &ToDeviceEvent) -> Result<JsonValue, CryptoError> {
+        let content = &event.content;
+        let algorithm = content["algorithm"].as_str().unwrap_or_default();
+        if algorithm != OLM_ALGORITHM {
+            return Err(CryptoError::UnsupportedAlgorithm(algorithm.to_string()));
+        }
+
+        let sender_key = content["sender_key"]
+            .as_str()
+            .ok_or(CryptoError::BadMessage)?;
+        let ciphertext = &content["ciphertext"][self.account.curve25519_key()];
+        let message = OlmMessage::from_parts(
+            ciphertext["type"].as_u64().ok_or(CryptoError::BadMessage)?,
+            decode_base64(ciphertext["body"].as_str().ok_or(CryptoError::BadMessage)?)?,
+        )?;
+
+        let plaintext = self.decrypt_olm_message(sender_key, &message)?;
+        let payload: JsonValue = serde_json::from_slice(&plaintext)?;
+
+        // Make sure the message was meant to us and not forwarded by
+        // someone else
+        if payload["sender"] != json!(event.sender)
+            || payload["recipient"] != json!(self.user_id)
+            || payload["recipient_keys"]["ed25519"] != json!(self.account.ed25519_key())
+        {
+            return Err(CryptoError::MismatchedPayload);
+        }
+
+        if payload["type"] == "m.room_key" {
+            // The key must come from the device that owns the Olm session
+            let ed25519_key = payload["keys"]["ed25519"]
+                .as_str()
+                .ok_or(CryptoError::BadMessage)?;
+            let device = self
+                .devices
+                .get(&event.sender)
+                .and_then(|devices| {
+                    devices
+                        .values()
+                        .find(|device| device.curve25519_key == sender_key)
+                })
+                .ok_or(CryptoError::UnknownDevice)?;
+            if device.ed25519_key != ed25519_key {
+                return Err(CryptoError::MismatchedPayload);
+            }
+
+            let device = device.clone();
+            self.receive_room_key(&event.sender, &device, &payload["content"])?;
+        }
+
+        Ok(payload)
+    }
+
+    fn decrypt_olm_message(
+        &mut self,
+        sender_key: &str,
+        message: &OlmMessage,
+    ) -> Result<Vec<u8>, CryptoError> {
+        let sessions = self.sessions.entry(sender_key.to_string()).or_default();
+        for session in sessions.iter_mut() {
+            if let Ok(plaintext) = session.decrypt(message) {
+                return Ok(plaintext);
+            }
+        }
+
+        // Only pre-key messages can start a new session
+        match message {
+            OlmMessage::PreKey(body) => {
+                let message = PreKeyMessage::decode(body)?;
+                if encode_base64(&message.identity_key) != sender_key {
+                    return Err(CryptoError::MismatchedPayload);
+                }
+                let (session, plaintext) = self.account.create_inbound_session(&message)?;
+                info!(
+                    "Created Olm session {} with {}",
+                    session.session_id(),
+                    sender_key
+                );
+                sessions.push(session);
+                Ok(plaintext)
+            }
+            OlmMessage::Normal(_) => Err(CryptoError::MissingSession),
+        }
+    }
+
+    fn receive_room_key(
+        &mut self,
+        sender: &UserId,
+        device: &Device,
+        content: &JsonValue,
+    ) -> Result<(), CryptoError> {
+        let algorithm = content["algorithm"].as_str().unwrap_or_default();
+        if algorithm != MEGOLM_ALGORITHM {
+            return Err(CryptoError::UnsupportedAlgorithm(algorithm.to_string()));
+        }
+
+        let room_id: RoomId = serde_json::from_value(content["room_id"].clone())?;
+        let session_id = content["session_id"]
+            .as_str()
+            .ok_or(CryptoError::BadMessage)?;
+        let session_key = content["session_key"]
+            .as_str()
+            .ok_or(CryptoError::BadMessage)?;
+
+        let session = InboundGroupSession::new(session_key)?;
+        if session.session_id() != session_id {
+            return Err(CryptoError::MismatchedPayload);
+        }
+
+        let sessions = self.inbound_group_sessions.entry(room_id).or_default();
+        match sessions.get_mut(session_id) {
+            // The session ID is a public key, only its owner can share it
+            Some(known)
+                if known.sender_key != device.curve25519_key
+                    || known
+                        .ed25519_key
+                        .as_ref()
+                        .map_or(false, |key| *key != device.ed25519_key) =>
+            {
+                return Err(CryptoError::MismatchedPayload);
+            }
+            // Keep the copy that can decrypt the most messages
+            Some(known) if session.first_known_index() < known.session.first_known_index() => {
+                known.session = session;
+            }
+            Some(_) => {}
+            None => {
+                sessions.insert(
+                    session_id.to_string(),
+                    InboundSession {
+                        sender_key: device.curve25519_key.clone(),
+                        sender: Some(sender.clone()),
+                        ed25519_key: Some(device.ed25519_key.clone()),
+                        session,
+                        decrypted: HashMap::new(),
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decrypts an `m.room.encrypted` event of a room timeline. The decrypted
+    /// event keeps the envelope of the original one, with the type and the
+    /// content from the encrypted payload.
+    pub fn decrypt_room_event(
+        &mut self,
+        room_id: &RoomId,
+        event: &JsonValue,
+    ) -> Result<JsonValue, CryptoError> {
+        let content = &event["content"];
+        let algorithm = content["algorithm"].as_str().unwrap_or_default();
+        if algorithm != MEGOLM_ALGORITHM {
+            return Err(CryptoError::UnsupportedAlgorithm(algorithm.to_string()));
+        }
+
+        let session_id = content["session_id"]
+            .as_str()
+            .ok_or(CryptoError::BadMessage)?;
+        let ciphertext = content["ciphertext"]
+            .as_str()
+            .ok_or(CryptoError::BadMessage)?;
+        let inbound = self
+            .inbound_group_sessions
+            .get_mut(room_id)
+            .and_then(|sessions| sessions.get_mut(session_id))
+            .ok_or(CryptoError::MissingSession)?;
+        if content["sender_key"] != json!(inbound.sender_key)
+            || inbound
+                .sender
+                .as_ref()
+                .map_or(false, |sender| event["sender"] != json!(sender))
+        {
+            return Err(CryptoError::MismatchedPayload);
+        }
+
+        let (plaintext, index) = inbound.session.decrypt(ciphertext)?;
+        let payload: JsonValue = serde_json::from_slice(&plaintext)?;
+        if payload["room_id"] != json!(room_id) {
+            return Err(CryptoError::MismatchedPayload);
+        }
+
+        // Decrypting the same event again is fine, but another event can't
+        // reuse a message index
+        let decrypted_event = DecryptedEvent {
+            event_id: event["event_id"].as_str().unwrap_or_default().to_string(),
+            origin_server_ts: event["origin_server_ts"].as_i64().unwrap_or_default(),
+        };
+        match inbound.decrypted.get(&index) {
+            Some(known) if *known != decrypted_event => {
+                warn!(
+                    "Message index {} of session {} was already used by {}",
+                    index, session_id, known.event_id
+                );
+                return Err(CryptoError::ReplayedMessageIndex(index));
+            }
+            Some(_) => {}
+            None => {
+                inbound.decrypted.insert(index, decrypted_event);
+            }
+        }
+
+        let mut decrypted = event.clone();
+        decrypted["type"] = payload["type"].clone();
+        decrypted["content"] = payload["content"].clone();
+        // The relation is sent in the clear so the server can aggregate it
+        if let Some(relates_to) = content.get("m.relates_to") {
+            decrypted["content"]["m.relates_to"] = relates_to.clone();
+        }
+        Ok(decrypted)
+    }
+
+    /// Whether a new group session has to be shared before sending a message
+    /// to the room
+    pub fn should_share_group_session(&self, room_id: &RoomId) -> bool {
+        let rotation = self.rotations.get(room_id).cloned().unwrap_or_default();
+        self.outbound_group_sessions
+            .get(room_id)
+            .map_or(true, |outbound| outbound.is_expired(&rotation))
+    }
+
+    /// Creates the outbound group session of the room if needed and encrypts
+    /// its key for every device of `users` it wasn't shared with yet. Returns
+    /// the to-device messages to send, by user and device.
+    pub fn share_group_session(
+        &mut self,
+        room_id: &RoomId,
+        users: &[UserId],
+    ) -> Result<HashMap<UserId, HashMap<Box<DeviceId>, JsonValue>>, CryptoError> {
+        // Someone left the room since the session was shared
+        let left = self
+            .outbound_group_sessions
+            .get(room_id)
+            .map_or(false, |outbound| {
+                outbound
+                    .shared_with
+                    .keys()
+                    .any(|user_id| !users.contains(user_id))
+            });
+        if left {
+            self.discard_group_session(room_id);
+        }
+
+        if self.should_share_group_session(room_id) {
+            let session = OutboundGroupSession::new();
+            // We also need the session to decrypt our own messages
+            let own_key = self.identity_keys();
+            let inbound = InboundGroupSession::new(&session.session_key())?;
+            self.inbound_group_sessions
+                .entry(room_id.clone())
+                .or_default()
+                .insert(
+                    session.session_id(),
+                    InboundSession {
+                        sender_key: own_key.curve25519_key,
+                        sender: Some(self.user_id.clone()),
+                        ed25519_key: Some(own_key.ed25519_key),
+                        session: inbound,
+                        decrypted: HashMap::new(),
+                    },
+                );
+            self.outbound_group_sessions.insert(
+                room_id.clone(),
+                OutboundSession {
+                    session,
+                    creation_time: now(),
+                    shared_with: HashMap::new(),
+                },
+            );
+        }
+
+        let outbound = &self.outbound_group_sessions[room_id];
+        let room_key = json!({
+            "algorithm": MEGOLM_ALGORITHM,
+            "room_id": room_id,
+            "session_id": outbound.session.session_id(),
+            "session_key": outbound.session.session_key(),
+        });
+
+        let recipients: Vec<(UserId, Box<DeviceId>, Device)> = users
+            .iter()
+            .filter_map(|user_id| Some((user_id, self.devices.get(user_id)?)))
+            .flat_map(|(user_id, devices)| {
+                devices.iter().map(move |(device_id, device)| {
+                    (user_id.clone(), device_id.clone(), device.clone())
+                })
+            })
+            .filter(|(user_id, device_id, _)| {
+                !self.is_own_device(user_id, device_id)
+                    && !outbound
+                        .shared_with
+                        .get(user_id)
+                        .map_or(false, |d| d.contains(device_id))
+            })
+            .collect();
+
+        let mut messages: HashMap<UserId, HashMap<Box<DeviceId>, JsonValue>> = HashMap::new();
+        for (user_id, device_id, device) in recipients {
+            match self.encrypt_olm(&user_id, &device, "m.room_key", &room_key) {
+                Ok(content) => {
+                    messages
+                        .entry(user_id.clone())
+                        .or_default()
+                        .insert(device_id.clone(), content);
+                    if let Some(outbound) = self.outbound_group_sessions.get_mut(room_id) {
+                        outbound
+                            .shared_with
+                            .entry(user_id)
+                            .or_default()
+                            .insert(device_id);
+                    }
+                }
+                Err(err) => warn!(
+                    "Can't share room key with {} {}: {:?}",
+                    user_id, device_id, err
+                ),
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// Forgets the outbound group session of the room, a new one will be
+    /// created for the next message
+    pub fn discard_group_session(&mut self, room_id: &RoomId) {
+        if self.outbound_group_sessions.remove(room_id).is_some() {
+            info!("Discarded the outbound group session of {}", room_id);
+        }
+    }
+
+    /// Discards the outbound group sessions shared with a device of
+    /// `user_id` matching `filter`
+    fn discard_group_sessions_shared_with<F>(&mut self, user_id: &UserId, filter: F)
+    where
+        F: Fn(&DeviceId) -> bool,
+    {
+        let rooms: Vec<RoomId> = self
+            .outbound_group_sessions
+            .iter()
+            .filter(|(_, outbound)| {
+                outbound
+                    .shared_with
+                    .get(user_id)
+                    .map_or(false, |devices| devices.iter().any(|d| filter(d)))
+            })
+            .map(|(room_id, _)| room_id.clone())
+            .collect();
+
+        for room_id in rooms {
+            self.discard_group_session(&room_id);
+        }
+    }
+
+    fn encrypt_olm(
+        &mut self,
+        user_id: &UserId,
+        device: &Device,
+        event_type: &str,
+        content: &JsonValue,
+    ) -> Result<JsonValue, CryptoError> {
+        let payload = json!({
+            "type": event_type,
+            "content": content,
+            "sender": self.user_id,
+            "sender_device": self.device_id,
+            "keys": { "ed25519": self.account.ed25519_key() },
+            "recipient": user_id,
+            "recipient_keys": { "ed25519": device.ed25519_key },
+        });
+
+        // The newest session is the one the other device is more likely to
+        // still have
+        let session = self
+            .sessions
+            .get_mut(&device.curve25519_key)
+            .and_then(|sessions| sessions.last_mut())
+            .ok_or(CryptoError::MissingSession)?;
+        let message = session.encrypt(payload.to_string().as_bytes());
+
+        Ok(json!({
+            "algorithm": OLM_ALGORITHM,
+            "sender_key": self.account.curve25519_key(),
+            "ciphertext": {
+                device.curve25519_key.clone(): {
+                    "type": message.message_type(),
+                    "body": encode_base64(message.body()),
+                }
+            },
+        }))
+    }
+
+    /// Encrypts the content of an event for the room, the group session must
+    /// have been shared first
+    pub fn encrypt_room_event(
+        &mut self,
+        room_id: &RoomId,
+        event_type: &str,
+        content: &JsonValue,
+    ) -> Result<JsonValue, CryptoError> {
+        let sender_key = self.account.curve25519_key();
+        let outbound = self
+            .outbound_group_sessions
+            .get_mut(room_id)
+            .ok_or(CryptoError::MissingSession)?;
+
+        // Relations stay in the clear, servers need them for aggregations
+        let mut content = content.clone();
+        let relates_to = content
+            .as_object_mut()
+            .and_then(|content| content.remove("m.relates_to"));
+        let payload = json!({
+            "type": event_type,
+            "content": content,
+            "room_id": room_id,
+        });
+        let ciphertext = outbound.session.encrypt(payload.to_string().as_bytes());
+
+        let mut encrypted = json!({
+            "algorithm": MEGOLM_ALGORITHM,
+            "sender_key": sender_key,
+            "ciphertext": ciphertext,
+            "session_id": outbound.session.session_id(),
+            "device_id": self.device_id,
+        });
+        if let Some(relates_to) = relates_to {
+            encrypted["m.relates_to"] = relates_to;
+        }
+        Ok(encrypted)
+    }
+}
+
+fn parse_device_keys(
+    user_id: &UserId,
+    device_id: &DeviceId,
+    keys: &JsonValue,
+) -> Result<Device, CryptoError> {
+    if keys["user_id"] != json!(user_id) || keys["device_id"] != json!(device_id) {
+        return Err(CryptoError::MismatchedPayload);
+    }
+
+    let ed25519_key = keys["keys"][format!("ed25519:{}", device_id)]
+        .as_str()
+        .ok_or(CryptoError::BadMessage)?;
+    let curve25519_key = keys["keys"][format!("curve25519:{}", device_id)]
+        .as_str()
+        .ok_or(CryptoError::BadMessage)?;
+    verify_json(
+        ed25519_key,
+        &user_id.to_string(),
+        &format!("ed25519:{}", device_id),
+        keys,
+    )?;
+
+    Ok(Device {
+        curve25519_key: curve25519_key.to_string(),
+        ed25519_key: ed25519_key.to_string(),
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn user_id(id: &str) -> UserId {
+        UserId::try_from(id).unwrap()
+    }
+
+    fn room_id() -> RoomId {
+        RoomId::try_from("!test:example.org").unwrap()
+    }
+
+    /// Plays the part of the server for the key upload, query and claim of
+    /// `alice`, so she can share a group session with `bob`
+    fn connect(alice: &mut OlmMachine, bob: &mut OlmMachine) {
+        let (device_keys, mut one_time_keys) = bob.keys_for_upload();
+        let device_keys = device_keys.expect("New devices must upload their keys");
+        let mut counts = HashMap::new();
+        counts.insert(
+            ONE_TIME_KEY_ALGORITHM.to_string(),
+            one_time_keys.len() as u64,
+        );
+        bob.receive_keys_upload_response(&counts);
+        assert!(!bob.should_upload_keys());
+
+        let bob_id = bob.user_id.clone();
+        let bob_device: Box<DeviceId> = bob.device_id.clone();
+
+        alice.update_tracked_users(vec![&bob_id]);
+        assert_eq!(alice.users_for_key_query(), vec![bob_id.clone()]);
+        let mut query_response = HashMap::new();
+        query_response.insert(
+            bob_id.clone(),
+            vec![(bob_device.clone(), device_keys)]
+                .into_iter()
+                .collect(),
+        );
+        alice.receive_keys_query_response(&query_response);
+        assert!(alice.users_for_key_query().is_empty());
+
+        let missing = alice.missing_sessions(vec![&bob_id]);
+        assert_eq!(missing[&bob_id][&bob_device], ONE_TIME_KEY_ALGORITHM);
+        let key_id = one_time_keys.keys().next().cloned().unwrap();
+        let key = one_time_keys.remove(&key_id).unwrap();
+        let mut claim_response = HashMap::new();
+        claim_response.insert(
+            bob_id.clone(),
+            vec![(bob_device, vec![(key_id, key)].into_iter().collect())]
+                .into_iter()
+                .collect(),
+        );
+        alice.receive_keys_claim_response(&claim_response);
+        assert!(alice.missing_sessions(vec![&bob_id]).is_empty());
+    }
+
+    /// Lets `machine` know the keys of the device of `other`, as if it had
+    /// queried them
+    fn learn_device(machine: &mut OlmMachine, other: &OlmMachine) {
+        machine.update_tracked_users(vec![&other.user_id]);
+        let mut query_response = HashMap::new();
+        query_response.insert(
+            other.user_id.clone(),
+            vec![(other.device_id.clone(), other.device_keys())]
+                .into_iter()
+                .collect(),
+        );
+        machine.receive_keys_query_response(&query_response);
+    }
+
+    fn to_device_events(
+        sender: &UserId,
+        messages: HashMap<UserId, HashMap<Box<DeviceId>, JsonValue>>,
+    ) -> Vec<ToDeviceEvent> {
+        messages
+            .into_iter()
+            .flat_map(|(_, devices)| devices.into_iter())
+            .map(|(_, content)| {
+                serde_json::from_value(json!({
+                    "type": "m.room.encrypted",
+                    "sender": sender,
+                    "content": content,
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn room_event(sender: &UserId, content: JsonValue) -> JsonValue {
+        json!({
+            "type": "m.room.encrypted",
+            "event_id": "$event:example.org",
+            "sender": sender,
+            "origin_server_ts": 1_590_000_000_000u64,
+            "content": content,
+        })
+    }
+
+    #[test]
+    fn encrypted_room_event_roundtrip() {
+        let alice_id = user_id("@alice:example.org");
+        let bob_id = user_id("@bob:example.org");
+        let mut alice = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let mut bob = OlmMachine::new(bob_id.clone(), "BOBDEVICE".into());
+        connect(&mut alice, &mut bob);
+        learn_device(&mut bob, &alice);
+
+        let room_id = room_id();
+        let members = vec![alice_id.clone(), bob_id.clone()];
+        assert!(alice.should_share_group_session(&room_id));
+        let messages = alice.share_group_session(&room_id, &members).unwrap();
+        assert!(!alice.should_share_group_session(&room_id));
+        // Already shared, nothing more to send
+        assert!(alice
+            .share_group_session(&room_id, &members)
+            .unwrap()
+            .is_empty());
+
+        let decrypted = bob.receive_to_device_events(&to_device_events(&alice_id, messages));
+        assert_eq!(decrypted.len(), 1);
+        assert_eq!(decrypted[0]["type"], "m.room_key");
+
+        let content = json!({ "msgtype": "m.text", "body": "Hello Bob" });
+        let encrypted = alice
+            .encrypt_room_event(&room_id, "m.room.message", &content)
+            .unwrap();
+        let event = room_event(&alice_id, encrypted);
+
+        for machine in &mut [&mut bob, &mut alice] {
+            let decrypted = machine.decrypt_room_event(&room_id, &event).unwrap();
+            assert_eq!(decrypted["type"], "m.room.message");
+            assert_eq!(decrypted["content"], content);
+            assert_eq!(decrypted["event_id"], event["event_id"]);
+        }
+
+        // The event can't be replayed in another room
+        let other_room = RoomId::try_from("!other:example.org").unwrap();
+        assert!(bob.decrypt_room_event(&other_room, &event).is_err());
+    }
+
+    #[test]
+    fn encrypted_room_event_relation() {
+        let alice_id = user_id("@alice:example.org");
+        let bob_id = user_id("@bob:example.org");
+        let mut alice = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let mut bob = OlmMachine::new(bob_id.clone(), "BOBDEVICE".into());
+        connect(&mut alice, &mut bob);
+        learn_device(&mut bob, &alice);
+
+        let room_id = room_id();
+        let members = vec![alice_id.clone(), bob_id];
+        let messages = alice.share_group_session(&room_id, &members).unwrap();
+        bob.receive_to_device_events(&to_device_events(&alice_id, messages));
+
+        let relates_to = json!({
+            "rel_type": "m.annotation",
+            "event_id": "$target:example.org",
+            "key": "👍",
+        });
+        let content = json!({ "m.relates_to": relates_to });
+        let encrypted = alice
+            .encrypt_room_event(&room_id, "m.reaction", &content)
+            .unwrap();
+        assert_eq!(encrypted["m.relates_to"], relates_to);
+
+        let mut event = room_event(&alice_id, encrypted);
+        let decrypted = bob.decrypt_room_event(&room_id, &event).unwrap();
+        assert_eq!(decrypted["type"], "m.reaction");
+        assert_eq!(decrypted["content"], content);
+
+        // Only the relation in the clear is used
+        event["content"]["m.relates_to"]["event_id"] = json!("$other:example.org");
+        let decrypted = bob.decrypt_room_event(&room_id, &event).unwrap();
+        assert_eq!(
+            decrypted["content"]["m.relates_to"]["event_id"],
+            "$other:example.org"
+        );
+    }
+
+    fn outbound_session_id(machine: &OlmMachine, room_id: &RoomId) -> String {
+        machine.outbound_group_sessions[room_id]
+            .session
+            .session_id()
+    }
+
+    #[test]
+    fn new_group_session_after_a_leave() {
+        let alice_id = user_id("@alice:example.org");
+        let bob_id = user_id("@bob:example.org");
+        let mut alice = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let mut bob = OlmMachine::new(bob_id.clone(), "BOBDEVICE".into());
+        connect(&mut alice, &mut bob);
+
+        let room_id = room_id();
+        let members = vec![alice_id.clone(), bob_id.clone()];
+        alice.share_group_session(&room_id, &members).unwrap();
+        let first = outbound_session_id(&alice, &room_id);
+
+        // Bob isn't in the list of members anymore
+        alice
+            .share_group_session(&room_id, &[alice_id.clone()])
+            .unwrap();
+        let second = outbound_session_id(&alice, &room_id);
+        assert_ne!(first, second);
+
+        // Bob's leave event comes in the sync
+        alice.share_group_session(&room_id, &members).unwrap();
+        assert_eq!(outbound_session_id(&alice, &room_id), second);
+        let leave: RoomEventContent =
+            serde_json::from_value::<fractal_api::events::StateEvent>(json!({
+                "type": "m.room.member",
+                "sender": bob_id,
+                "state_key": bob_id,
+                "content": { "membership": "leave" },
+            }))
+            .unwrap()
+            .content;
+        alice.receive_room_state_event(&room_id, &leave);
+        assert!(alice.should_share_group_session(&room_id));
+        alice.share_group_session(&room_id, &[alice_id]).unwrap();
+        assert_ne!(outbound_session_id(&alice, &room_id), second);
+    }
+
+    #[test]
+    fn new_group_session_after_a_device_removal() {
+        let alice_id = user_id("@alice:example.org");
+        let bob_id = user_id("@bob:example.org");
+        let mut alice = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let mut bob = OlmMachine::new(bob_id.clone(), "BOBDEVICE".into());
+        connect(&mut alice, &mut bob);
+
+        let room_id = room_id();
+        alice
+            .share_group_session(&room_id, &[alice_id, bob_id.clone()])
+            .unwrap();
+        assert!(!alice.should_share_group_session(&room_id));
+
+        // Bob logged out of his only device
+        alice.receive_device_list_changes(&[bob_id.clone()], &[]);
+        assert_eq!(alice.users_for_key_query(), vec![bob_id.clone()]);
+        let mut query_response = HashMap::new();
+        query_response.insert(bob_id, HashMap::new());
+        alice.receive_keys_query_response(&query_response);
+        assert!(alice.should_share_group_session(&room_id));
+    }
+
+    #[test]
+    fn group_session_rotation_settings() {
+        let alice_id = user_id("@alice:example.org");
+        let mut alice = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let room_id = room_id();
+
+        let encryption: RoomEventContent =
+            serde_json::from_value::<fractal_api::events::StateEvent>(json!({
+                "type": "m.room.encryption",
+                "sender": alice_id,
+                "state_key": "",
+                "content": {
+                    "algorithm": MEGOLM_ALGORITHM,
+                    "rotation_period_msgs": 2,
+                },
+            }))
+            .unwrap()
+            .content;
+        alice.receive_room_state_event(&room_id, &encryption);
+
+        alice
+            .share_group_session(&room_id, &[alice_id.clone()])
+            .unwrap();
+        let first = outbound_session_id(&alice, &room_id);
+        let content = json!({ "msgtype": "m.text", "body": "Hello" });
+        for _ in 0..2 {
+            assert!(!alice.should_share_group_session(&room_id));
+            alice
+                .encrypt_room_event(&room_id, "m.room.message", &content)
+                .unwrap();
+        }
+        assert!(alice.should_share_group_session(&room_id));
+        alice.share_group_session(&room_id, &[alice_id]).unwrap();
+        assert_ne!(outbound_session_id(&alice, &room_id), first);
+
+        // The session is also too old when the period is over
+        alice.rotations.get_mut(&room_id).unwrap().period_ms = 0;
+        assert!(alice.should_share_group_session(&room_id));
+    }
+
+    #[test]
+    fn rejects_to_device_events_for_someone_else() {
+        let alice_id = user_id("@alice:example.org");
+        let mut alice = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let mut bob = OlmMachine::new(user_id("@bob:example.org"), "BOBDEVICE".into());
+        connect(&mut alice, &mut bob);
+
+        let messages = alice
+            .share_group_session(&room_id(), &[bob.user_id.clone()])
+            .unwrap();
+        // Pretend the message comes from someone else
+        let mallory = user_id("@mallory:example.org");
+        assert!(bob
+            .receive_to_device_events(&to_device_events(&mallory, messages))
+            .is_empty());
+    }
+
+    #[test]
+    fn rejects_replayed_message_indices() {
+        let alice_id = user_id("@alice:example.org");
+        let bob_id = user_id("@bob:example.org");
+        let mut alice = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let mut bob = OlmMachine::new(bob_id.clone(), "BOBDEVICE".into());
+        connect(&mut alice, &mut bob);
+        learn_device(&mut bob, &alice);
+
+        let room_id = room_id();
+        let messages = alice
+            .share_group_session(&room_id, &[alice_id.clone(), bob_id])
+            .unwrap();
+        bob.receive_to_device_events(&to_device_events(&alice_id, messages));
+
+        let content = json!({ "msgtype": "m.text", "body": "Only once" });
+        let encrypted = alice
+            .encrypt_room_event(&room_id, "m.room.message", &content)
+            .unwrap();
+        let event = room_event(&alice_id, encrypted);
+        assert!(bob.decrypt_room_event(&room_id, &event).is_ok());
+        // The same event can be decrypted again, when it's loaded twice
+        assert!(bob.decrypt_room_event(&room_id, &event).is_ok());
+
+        let mut replayed = event.clone();
+        replayed["event_id"] = json!("$replayed:example.org");
+        match bob.decrypt_room_event(&room_id, &replayed) {
+            Err(CryptoError::ReplayedMessageIndex(0)) => {}
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Someone else can't pass the message as theirs
+        let mut forged = event;
+        forged["sender"] = json!("@mallory:example.org");
+        assert!(bob.decrypt_room_event(&room_id, &forged).is_err());
+    }
+
+    #[test]
+    fn rejects_room_keys_from_unknown_devices() {
+        let alice_id = user_id("@alice:example.org");
+        let bob_id = user_id("@bob:example.org");
+        let mut alice = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let mut bob = OlmMachine::new(bob_id.clone(), "BOBDEVICE".into());
+        connect(&mut alice, &mut bob);
+
+        let room_id = room_id();
+        let members = vec![alice_id.clone(), bob_id];
+        let messages = alice.share_group_session(&room_id, &members).unwrap();
+        // Bob doesn't know the keys of Alice's device
+        let events = to_device_events(&alice_id, messages);
+        match bob.decrypt_to_device_event(&events[0]) {
+            Err(CryptoError::UnknownDevice) => {}
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Another device claims the curve25519 key of Alice's device
+        let mallory = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let mut keys = mallory.device_keys();
+        keys["keys"]["curve25519:ALICEDEVICE"] = json!(alice.account.curve25519_key());
+        mallory.sign_json(&mut keys);
+        bob.update_tracked_users(vec![&alice_id]);
+        let mut query_response = HashMap::new();
+        query_response.insert(
+            alice_id.clone(),
+            vec![(Box::<DeviceId>::from("ALICEDEVICE"), keys)]
+                .into_iter()
+                .collect(),
+        );
+        bob.receive_keys_query_response(&query_response);
+
+        alice.discard_group_session(&room_id);
+        let messages = alice.share_group_session(&room_id, &members).unwrap();
+        let events = to_device_events(&alice_id, messages);
+        match bob.decrypt_to_device_event(&events[0]) {
+            Err(CryptoError::MismatchedPayload) => {}
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(bob.inbound_group_sessions.is_empty());
+    }
+
+    #[test]
+    fn undecryptable_room_events() {
+        let mut bob = OlmMachine::new(user_id("@bob:example.org"), "BOBDEVICE".into());
+        let sender = user_id("@alice:example.org");
+
+        let unknown_session = room_event(
+            &sender,
+            json!({
+                "algorithm": MEGOLM_ALGORITHM,
+                "sender_key": "MVZyDkOS9pNwRj5A0rEz7qd1dMKlu5RLbjlFhYLwjSA",
+                "ciphertext": "AwgAEnCf6blPBnlBVcvHAgqwHrBOQEfyU2ocRU",
+                "session_id": "5ZdOYkVwZ3zVDJXiqbDCZRRhCWPw6tU/SeVZTXXuEvs",
+                "device_id": "ALICEDEVICE",
+            }),
+        );
+        match bob.decrypt_room_event(&room_id(), &unknown_session) {
+            Err(CryptoError::MissingSession) => {}
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        let unknown_algorithm = room_event(&sender, json!({ "algorithm": "m.unknown" }));
+        match bob.decrypt_room_event(&room_id(), &unknown_algorithm) {
+            Err(CryptoError::UnsupportedAlgorithm(_)) => {}
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn store_and_load() {
+        let path = std::env::temp_dir().join(format!("fractal-crypto-test-{}.json", now()));
+        let alice_id = user_id("@alice:example.org");
+        let mut alice = OlmMachine::new(alice_id.clone(), "ALICEDEVICE".into());
+        let mut bob = OlmMachine::new(user_id("@bob:example.org"), "BOBDEVICE".into());
+        connect(&mut alice, &mut bob);
+        alice
+            .share_group_session(&room_id(), &[bob.user_id.clone()])
+            .unwrap();
+
+        alice.store(&path).unwrap();
+        let mut loaded = OlmMachine::load(&path, alice_id.clone(), "ALICEDEVICE".into()).unwrap();
+        assert_eq!(
+            loaded.identity_keys().curve25519_key,
+            alice.identity_keys().curve25519_key
+        );
+        assert!(!loaded.should_share_group_session(&room_id()));
+        let content = json!({ "msgtype": "m.text", "body": "Still here" });
+        let encrypted = loaded
+            .encrypt_room_event(&room_id(), "m.room.message", &content)
+            .unwrap();
+        let decrypted = loaded
+            .decrypt_room_event(&room_id(), &room_event(&alice_id, encrypted))
+            .unwrap();
+        assert_eq!(decrypted["content"], content);
+
+        // The keys of another device are never reused
+        let other = OlmMachine::load(&path, alice_id.clone(), "OTHERDEVICE".into()).unwrap();
+        assert_ne!(
+            other.identity_keys().curve25519_key,
+            alice.identity_keys().curve25519_key
+        );
+
+        // A corrupted state isn't replaced by new keys
+        fs::write(&path, b"{").unwrap();
+        assert!(OlmMachine::load(&path, alice_id.clone(), "ALICEDEVICE".into()).is_err());
+
+        fs::remove_file(&path).unwrap();
+        let new = OlmMachine::load(&path, alice_id, "ALICEDEVICE".into()).unwrap();
+        assert!(new.should_upload_keys());
+    }
+}
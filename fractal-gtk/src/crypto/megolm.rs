@@ -0,0 +1,430 @@
+//! The Megolm ratchet, used to encrypt the messages sent to a room. Each
+//! sender has its own outbound session, whose key is shared with the other
+//! devices of the room over Olm.
+
+use ed25519_dalek::{Keypair, PublicKey as Ed25519PublicKey, SecretKey, Signer};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use super::cipher::{hmac_sha256, Cipher, MAC_LENGTH};
+use super::encoding::{decode_fields, write_bytes_field, write_varint_field, Field, VERSION};
+use super::{decode_base64, encode_base64, verify_signature, CryptoError};
+
+const SESSION_KEY_VERSION: u8 = 2;
+const SIGNATURE_LENGTH: usize = 64;
+const SESSION_KEY_LENGTH: usize = 1 + 4 + 128 + 32 + SIGNATURE_LENGTH;
+const EXPORT_VERSION: u8 = 1;
+const EXPORT_LENGTH: usize = 1 + 4 + 128 + 32;
+
+const MESSAGE_INDEX_TAG: u8 = 0x08;
+const CIPHERTEXT_TAG: u8 = 0x12;
+
+/// The four parts of the ratchet, R(0) changes every 2^24 messages and R(3)
+/// on every message
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Ratchet {
+    parts: [[u8; 32]; 4],
+    counter: u32,
+}
+
+impl Ratchet {
+    fn new(parts: [[u8; 32]; 4], counter: u32) -> Self {
+        Self { parts, counter }
+    }
+
+    fn generate() -> Self {
+        let mut parts = [[0; 32]; 4];
+        for part in parts.iter_mut() {
+            OsRng.fill_bytes(part);
+        }
+
+        Self::new(parts, 0)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.parts.concat()
+    }
+
+    fn from_bytes(bytes: &[u8], counter: u32) -> Self {
+        let mut parts = [[0; 32]; 4];
+        for (part, bytes) in parts.iter_mut().zip(bytes.chunks(32)) {
+            part.copy_from_slice(bytes);
+        }
+
+        Self::new(parts, counter)
+    }
+
+    fn rehash_part(&mut self, from: usize, to: usize) {
+        self.parts[to] = hmac_sha256(&self.parts[from], &[to as u8]);
+    }
+
+    fn advance(&mut self) {
+        self.counter = self.counter.wrapping_add(1);
+
+        // Find the most significant part that has to change
+        let mut mask: u32 = 0x00FF_FFFF;
+        let mut h = 0;
+        while h < 4 {
+            if self.counter & mask == 0 {
+                break;
+            }
+            h += 1;
+            mask >>= 8;
+        }
+
+        // R(h) is updated last, the other parts are derived from its old value
+        for i in (h..4).rev() {
+            self.rehash_part(h, i);
+        }
+    }
+
+    fn advance_to(&mut self, index: u32) {
+        for j in 0..4 {
+            let shift = (3 - j) * 8;
+            let mask = (!0u32).checked_shl(shift as u32).unwrap_or(0);
+
+            let mut steps = (index >> shift).wrapping_sub(self.counter >> shift) & 0xFF;
+            if steps == 0 {
+                // The counter wrapped around
+                if index < self.counter {
+                    steps = 0x100;
+                } else {
+                    continue;
+                }
+            }
+
+            while steps > 1 {
+                self.rehash_part(j, j);
+                steps -= 1;
+            }
+
+            for k in (j..4).rev() {
+                self.rehash_part(j, k);
+            }
+            self.counter = index & mask;
+        }
+    }
+
+    fn cipher(&self) -> Cipher {
+        Cipher::new(&self.to_bytes(), b"MEGOLM_KEYS")
+    }
+}
+
+struct Message {
+    index: u32,
+    ciphertext: Vec<u8>,
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![VERSION];
+        write_varint_field(&mut out, MESSAGE_INDEX_TAG, u64::from(self.index));
+        write_bytes_field(&mut out, CIPHERTEXT_TAG, &self.ciphertext);
+        out
+    }
+
+    fn decode(input: &[u8]) -> Result<Self, CryptoError> {
+        let mut index = None;
+        let mut ciphertext = None;
+        for (tag, field) in decode_fields(input)? {
+            match (tag as u8, field) {
+                (MESSAGE_INDEX_TAG, Field::Varint(v)) => index = Some(v as u32),
+                (CIPHERTEXT_TAG, Field::Bytes(b)) => ciphertext = Some(b.to_vec()),
+                _ => {}
+            }
+        }
+
+        match (index, ciphertext) {
+            (Some(index), Some(ciphertext)) => Ok(Self { index, ciphertext }),
+            _ => Err(CryptoError::BadMessage),
+        }
+    }
+}
+
+/// The session used to encrypt our messages in a room
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OutboundGroupSession {
+    ratchet: Ratchet,
+    signing_secret: [u8; 32],
+}
+
+impl OutboundGroupSession {
+    pub fn new() -> Self {
+        let mut signing_secret = [0; 32];
+        OsRng.fill_bytes(&mut signing_secret);
+
+        Self {
+            ratchet: Ratchet::generate(),
+            signing_secret,
+        }
+    }
+
+    fn keypair(&self) -> Keypair {
+        let secret =
+            SecretKey::from_bytes(&self.signing_secret).expect("ed25519 secrets are 32 bytes");
+        let public = Ed25519PublicKey::from(&secret);
+
+        Keypair { secret, public }
+    }
+
+    /// The ID of a group session is its public signing key
+    pub fn session_id(&self) -> String {
+        encode_base64(self.keypair().public.as_bytes())
+    }
+
+    /// The index of the next message that will be encrypted
+    pub fn message_index(&self) -> u32 {
+        self.ratchet.counter
+    }
+
+    /// The key the other devices need to decrypt the next messages of this
+    /// session
+    pub fn session_key(&self) -> String {
+        let keypair = self.keypair();
+
+        let mut key = Vec::with_capacity(SESSION_KEY_LENGTH);
+        key.push(SESSION_KEY_VERSION);
+        key.extend_from_slice(&self.ratchet.counter.to_be_bytes());
+        key.extend_from_slice(&self.ratchet.to_bytes());
+        key.extend_from_slice(keypair.public.as_bytes());
+        let signature = keypair.sign(&key).to_bytes();
+        key.extend_from_slice(&signature);
+
+        encode_base64(&key)
+    }
+
+    /// Encrypts `plaintext` and returns the base64 encoded message
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> String {
+        let cipher = self.ratchet.cipher();
+        let mut message = Message {
+            index: self.ratchet.counter,
+            ciphertext: cipher.encrypt(plaintext),
+        }
+        .encode();
+        let mac = cipher.mac(&message);
+        message.extend_from_slice(&mac);
+        let signature = self.keypair().sign(&message).to_bytes();
+        message.extend_from_slice(&signature);
+
+        self.ratchet.advance();
+
+        encode_base64(&message)
+    }
+}
+
+impl Default for OutboundGroupSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The session used to decrypt the messages of another device in a room
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InboundGroupSession {
+    initial_ratchet: Ratchet,
+    latest_ratchet: Ratchet,
+    signing_key: String,
+}
+
+impl InboundGroupSession {
+    /// Creates a session from the key shared in a `m.room_key` event
+    pub fn new(session_key: &str) -> Result<Self, CryptoError> {
+        let key = decode_base64(session_key)?;
+        if key.len() != SESSION_KEY_LENGTH {
+            return Err(CryptoError::BadMessage);
+        }
+        if key[0] != SESSION_KEY_VERSION {
+            return Err(CryptoError::UnknownVersion(key[0]));
+        }
+
+        let (signed, signature) = key.split_at(SESSION_KEY_LENGTH - SIGNATURE_LENGTH);
+        let signing_key = encode_base64(&signed[133..]);
+        verify_signature(&signing_key, signed, &encode_base64(signature))?;
+
+        Ok(Self::from_key(&key, signing_key))
+    }
+
+    /// Creates a session from a key exported by libolm. Unlike the shared session
+    /// keys, exported keys aren't signed.
+    #[allow(dead_code)]
+    pub fn import(exported: &str) -> Result<Self, CryptoError> {
+        let key = decode_base64(exported)?;
+        if key.len() != EXPORT_LENGTH {
+            return Err(CryptoError::BadMessage);
+        }
+        if key[0] != EXPORT_VERSION {
+            return Err(CryptoError::UnknownVersion(key[0]));
+        }
+
+        let signing_key = encode_base64(&key[133..]);
+        Ok(Self::from_key(&key, signing_key))
+    }
+
+    /// Reads the message index and the ratchet, which come first in both formats
+    fn from_key(key: &[u8], signing_key: String) -> Self {
+        let mut counter = [0; 4];
+        counter.copy_from_slice(&key[1..5]);
+        let ratchet = Ratchet::from_bytes(&key[5..133], u32::from_be_bytes(counter));
+
+        Self {
+            initial_ratchet: ratchet.clone(),
+            latest_ratchet: ratchet,
+            signing_key,
+        }
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.signing_key
+    }
+
+    /// The index of the first message that can be decrypted
+    pub fn first_known_index(&self) -> u32 {
+        self.initial_ratchet.counter
+    }
+
+    /// Decrypts a base64 encoded message, returning the plaintext and the
+    /// index of the message
+    pub fn decrypt(&mut self, message: &str) -> Result<(Vec<u8>, u32), CryptoError> {
+        let input = decode_base64(message)?;
+        if input.len() < MAC_LENGTH + SIGNATURE_LENGTH {
+            return Err(CryptoError::BadMessage);
+        }
+
+        let (signed, signature) = input.split_at(input.len() - SIGNATURE_LENGTH);
+        verify_signature(&self.signing_key, signed, &encode_base64(signature))?;
+        let (body, mac) = signed.split_at(signed.len() - MAC_LENGTH);
+        let message = Message::decode(body)?;
+
+        if message.index < self.initial_ratchet.counter {
+            return Err(CryptoError::UnknownMessageIndex(message.index));
+        }
+
+        // Ratchets can only go forward, keep the latest one around so we don't
+        // have to start over from the initial one for every message
+        let use_latest = message.index >= self.latest_ratchet.counter;
+        let mut ratchet = if use_latest {
+            self.latest_ratchet.clone()
+        } else {
+            self.initial_ratchet.clone()
+        };
+        ratchet.advance_to(message.index);
+
+        let cipher = ratchet.cipher();
+        cipher.verify_mac(body, mac)?;
+        let plaintext = cipher.decrypt(&message.ciphertext)?;
+
+        if use_latest {
+            self.latest_ratchet = ratchet;
+        }
+
+        Ok((plaintext, message.index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn megolm_advance_to_matches_advance() {
+        let mut step_by_step = Ratchet::generate();
+        let mut jump = step_by_step.clone();
+
+        for _ in 0..300 {
+            step_by_step.advance();
+        }
+        jump.advance_to(300);
+
+        assert_eq!(step_by_step.counter, jump.counter);
+        assert_eq!(step_by_step.to_bytes(), jump.to_bytes());
+
+        // Crossing the boundary of R(2)
+        let mut step_by_step = Ratchet::new(step_by_step.parts, 0xFFFF);
+        let mut jump = step_by_step.clone();
+        step_by_step.advance();
+        step_by_step.advance();
+        jump.advance_to(0x10001);
+
+        assert_eq!(step_by_step.to_bytes(), jump.to_bytes());
+    }
+
+    #[test]
+    fn megolm_session_roundtrip() {
+        let mut outbound = OutboundGroupSession::new();
+        let first = outbound.encrypt(b"first");
+
+        // Sessions shared after the first message can't decrypt it
+        let mut inbound = InboundGroupSession::new(&outbound.session_key()).unwrap();
+        assert_eq!(inbound.session_id(), outbound.session_id());
+        assert_eq!(inbound.first_known_index(), 1);
+        assert!(inbound.decrypt(&first).is_err());
+
+        let second = outbound.encrypt(b"second");
+        let third = outbound.encrypt(b"third");
+
+        assert_eq!(inbound.decrypt(&third).unwrap(), (b"third".to_vec(), 2));
+        assert_eq!(inbound.decrypt(&second).unwrap(), (b"second".to_vec(), 1));
+        assert_eq!(inbound.decrypt(&third).unwrap(), (b"third".to_vec(), 2));
+    }
+
+    // The known answers were made with vodozemac, the Olm implementation of
+    // matrix.org that is tested against libolm
+    const SESSION_ID: &str = "Lj6B42M6uDczMPBvG36z0XWOZ/EbcSwhNPc9O83O6So";
+    const FIRST: &str =
+        "AwgAEhCji8OYAQQftbNhAPGHPD+35h1UvGuDo42/og+q9xr5zGISF0L1djcp+Iw7D1i5/Lrz1trE4F3\
+                         HQE5uQzbOzKA8CfJl0je0xuUuKxWLx8aM3hRQ1iTGPpkB";
+    const SECOND: &str =
+        "AwgBEhBE98L823ikC8DUlXetr76wmfmOx8dr/IZDoytKAMyyoItZ/D9wLiwbsxvbwt88pnO4Up7H2g5\
+                          2/YMEE4MvU654K0rHpzo4IAEOK72cwL4UZK3oa8ooJpMC";
+    const THIRD: &str =
+        "AwgCEhDh5YTmFCw5V0mxs4IYr1jS2Bs6bssXToP/FKkyXR2TXfxYTpYWaEGAGSnaVrnoAZSug+dWC7g\
+                         w7NjfAlTK1qk6xolZ7chwxv+R5OBRkBvPljyd5BeQ1r4I";
+
+    #[test]
+    fn megolm_known_session_key() {
+        let mut inbound = InboundGroupSession::new(
+            "AgAAAABYK921xzTU49NSAg/vtoB5MW8qCejUqiw8xFe1BELyPNfaOg0uef+Jlcjb5WZRPNvJr6c2R2KpQPC3/C5\
+             0DG4pIDLRgzvJ3jKrlGKooBDmvdrHQyFg29ntEPl4ntg9Ky3AwZ2SqYcBeQly9BrjGErL8nACoUB6vH7iNY85jX\
+             nZly4+geNjOrg3MzDwbxt+s9F1jmfxG3EsITT3PTvNzukqZF6WMS882UDgD0TyQdSVZKFkvYvoPRRaLfZZeUyaU\
+             R8RaM1RSd6z7Fjh3gZ5dsmGylmv+owR1WlIm5AuFFxNAA",
+        )
+        .unwrap();
+        assert_eq!(inbound.session_id(), SESSION_ID);
+        assert_eq!(inbound.first_known_index(), 0);
+
+        assert_eq!(inbound.decrypt(THIRD).unwrap(), (b"third".to_vec(), 2));
+        assert_eq!(inbound.decrypt(FIRST).unwrap(), (b"first".to_vec(), 0));
+        assert_eq!(inbound.decrypt(SECOND).unwrap(), (b"second".to_vec(), 1));
+    }
+
+    #[test]
+    fn megolm_known_export() {
+        let mut inbound = InboundGroupSession::import(
+            "AQAAAAFYK921xzTU49NSAg/vtoB5MW8qCejUqiw8xFe1BELyPNfaOg0uef+Jlcjb5WZRPNvJr6c2R2KpQPC3/C5\
+             0DG4pIDLRgzvJ3jKrlGKooBDmvdrHQyFg29ntEPl4ntg9Ky1gVtwu7OsW1M86r8lXgtk+j84hHuF7nlzvf8Nx+2\
+             dINi4+geNjOrg3MzDwbxt+s9F1jmfxG3EsITT3PTvNzukq",
+        )
+        .unwrap();
+        assert_eq!(inbound.session_id(), SESSION_ID);
+        assert_eq!(inbound.first_known_index(), 1);
+
+        assert!(inbound.decrypt(FIRST).is_err());
+        assert_eq!(inbound.decrypt(SECOND).unwrap(), (b"second".to_vec(), 1));
+        assert_eq!(inbound.decrypt(THIRD).unwrap(), (b"third".to_vec(), 2));
+    }
+
+    #[test]
+    fn megolm_rejects_forged_messages() {
+        let mut outbound = OutboundGroupSession::new();
+        let mut inbound = InboundGroupSession::new(&outbound.session_key()).unwrap();
+
+        // Signed by another session
+        let mut other = OutboundGroupSession::new();
+        assert!(inbound.decrypt(&other.encrypt(b"forged")).is_err());
+
+        let mut message = decode_base64(&outbound.encrypt(b"genuine")).unwrap();
+        message[3] ^= 0x01;
+        assert!(inbound.decrypt(&encode_base64(&message)).is_err());
+    }
+}
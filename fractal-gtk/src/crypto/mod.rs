@@ -0,0 +1,114 @@
+//! End-to-end encryption, following the Olm and Megolm specifications:
+//! https://gitlab.matrix.org/matrix-org/olm/-/tree/master/docs
+//!
+//! `olm` and `megolm` implement the cryptographic ratchets, while the
+//! `OlmMachine` glues them to the Matrix events and keeps track of the
+//! devices of the users we share rooms with.
+
+pub mod attachment;
+pub mod cipher;
+mod encoding;
+mod machine;
+pub mod megolm;
+pub mod olm;
+
+use ed25519_dalek::{PublicKey as Ed25519PublicKey, Signature, Verifier};
+use serde_json::Value as JsonValue;
+use std::convert::TryFrom;
+use std::io::Error as IoError;
+use std::sync::{Arc, Mutex};
+
+pub use self::machine::OlmMachine;
+
+pub type SharedOlmMachine = Arc<Mutex<OlmMachine>>;
+
+pub static OLM_ALGORITHM: &str = "m.olm.v1.curve25519-aes-sha2";
+pub static MEGOLM_ALGORITHM: &str = "m.megolm.v1.aes-sha2";
+
+#[derive(Debug)]
+pub enum CryptoError {
+    BadMessage,
+    BadMac,
+    BadSignature,
+    UnknownVersion(u8),
+    UnsupportedAlgorithm(String),
+    MissingSession,
+    UnknownDevice,
+    UnknownMessageIndex(u32),
+    ReplayedMessageIndex(u32),
+    MismatchedPayload,
+    Json(serde_json::Error),
+    Io(IoError),
+}
+
+impl From<serde_json::Error> for CryptoError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl From<IoError> for CryptoError {
+    fn from(err: IoError) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<base64::DecodeError> for CryptoError {
+    fn from(_: base64::DecodeError) -> Self {
+        Self::BadMessage
+    }
+}
+
+/// Keys and ciphertexts are exchanged as unpadded base64
+pub fn encode_base64(input: &[u8]) -> String {
+    base64::encode_config(input, base64::STANDARD_NO_PAD)
+}
+
+pub fn decode_base64(input: &str) -> Result<Vec<u8>, CryptoError> {
+    base64::decode_config(input.trim_end_matches('='), base64::STANDARD_NO_PAD).map_err(Into::into)
+}
+
+pub fn decode_key(input: &str) -> Result<[u8; 32], CryptoError> {
+    encoding::to_key(&decode_base64(input)?)
+}
+
+/// The representation of a JSON object that gets signed, see
+/// https://matrix.org/docs/spec/appendices#signing-json
+pub fn canonical_json(value: &JsonValue) -> String {
+    let mut value = value.clone();
+    if let Some(object) = value.as_object_mut() {
+        object.remove("signatures");
+        object.remove("unsigned");
+    }
+
+    // Objects are backed by a BTreeMap, so the keys are already sorted
+    value.to_string()
+}
+
+/// Checks the signature made by `key_id` of `user_id` on a signed JSON object
+pub fn verify_json(
+    signing_key: &str,
+    user_id: &str,
+    key_id: &str,
+    value: &JsonValue,
+) -> Result<(), CryptoError> {
+    let signature = value["signatures"][user_id][key_id]
+        .as_str()
+        .ok_or(CryptoError::BadSignature)?;
+
+    verify_signature(signing_key, canonical_json(value).as_bytes(), signature)
+}
+
+pub fn verify_signature(
+    signing_key: &str,
+    message: &[u8],
+    signature: &str,
+) -> Result<(), CryptoError> {
+    let key = Ed25519PublicKey::from_bytes(&decode_base64(signing_key)?)
+        .map_err(|_| CryptoError::BadSignature)?;
+    let signature = Signature::try_from(decode_base64(signature)?.as_slice())
+        .map_err(|_| CryptoError::BadSignature)?;
+
+    key.verify(message, &signature)
+        .map_err(|_| CryptoError::BadSignature)
+}
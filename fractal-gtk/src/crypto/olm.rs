@@ -0,0 +1,829 @@
+//! The Olm double ratchet, used to encrypt the to-device messages exchanged
+//! between two devices.
+
+use ed25519_dalek::{Keypair, PublicKey as Ed25519PublicKey, SecretKey, Signer};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::cipher::{hkdf_sha256, hmac_sha256, sha256, Cipher, MAC_LENGTH};
+use super::encoding::{
+    decode_fields, to_key, write_bytes_field, write_varint_field, Field, VERSION,
+};
+use super::{encode_base64, CryptoError};
+
+pub const MAX_ONE_TIME_KEYS: usize = 100;
+const MAX_RECEIVER_CHAINS: usize = 5;
+const MAX_SKIPPED_MESSAGE_KEYS: usize = 40;
+const MAX_MESSAGE_GAP: u32 = 2000;
+
+const RATCHET_KEY_TAG: u8 = 0x0A;
+const COUNTER_TAG: u8 = 0x10;
+const CIPHERTEXT_TAG: u8 = 0x22;
+const ONE_TIME_KEY_TAG: u8 = 0x0A;
+const BASE_KEY_TAG: u8 = 0x12;
+const IDENTITY_KEY_TAG: u8 = 0x1A;
+const MESSAGE_TAG: u8 = 0x22;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Curve25519KeyPair {
+    secret: [u8; 32],
+    public: [u8; 32],
+}
+
+impl Curve25519KeyPair {
+    pub fn generate() -> Self {
+        Self::from_secret(StaticSecret::new(&mut OsRng).to_bytes())
+    }
+
+    fn from_secret(secret: [u8; 32]) -> Self {
+        let public = PublicKey::from(&StaticSecret::from(secret));
+
+        Self {
+            secret,
+            public: *public.as_bytes(),
+        }
+    }
+
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.public
+    }
+
+    fn diffie_hellman(&self, their_key: &[u8; 32]) -> [u8; 32] {
+        let secret = StaticSecret::from(self.secret);
+        *secret
+            .diffie_hellman(&PublicKey::from(*their_key))
+            .as_bytes()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct OneTimeKey {
+    id: u32,
+    key: Curve25519KeyPair,
+    published: bool,
+}
+
+/// The long-term identity of this device and its one-time keys
+#[derive(Serialize, Deserialize)]
+pub struct Account {
+    ed25519_secret: [u8; 32],
+    curve25519: Curve25519KeyPair,
+    one_time_keys: Vec<OneTimeKey>,
+    next_one_time_key_id: u32,
+}
+
+impl Account {
+    pub fn new() -> Self {
+        let mut ed25519_secret = [0; 32];
+        OsRng.fill_bytes(&mut ed25519_secret);
+
+        Self {
+            ed25519_secret,
+            curve25519: Curve25519KeyPair::generate(),
+            one_time_keys: Vec::new(),
+            next_one_time_key_id: 0,
+        }
+    }
+
+    fn keypair(&self) -> Keypair {
+        let secret =
+            SecretKey::from_bytes(&self.ed25519_secret).expect("ed25519 secrets are 32 bytes");
+        let public = Ed25519PublicKey::from(&secret);
+
+        Keypair { secret, public }
+    }
+
+    pub fn curve25519_key(&self) -> String {
+        encode_base64(self.curve25519.public_key())
+    }
+
+    pub fn ed25519_key(&self) -> String {
+        encode_base64(self.keypair().public.as_bytes())
+    }
+
+    /// Signs `message` with the ed25519 key of the device
+    pub fn sign(&self, message: &[u8]) -> String {
+        encode_base64(&self.keypair().sign(message).to_bytes())
+    }
+
+    pub fn generate_one_time_keys(&mut self, count: usize) {
+        for _ in 0..count {
+            self.next_one_time_key_id += 1;
+            self.one_time_keys.push(OneTimeKey {
+                id: self.next_one_time_key_id,
+                key: Curve25519KeyPair::generate(),
+                published: false,
+            });
+        }
+
+        // Forget the oldest keys, they are likely to never be used
+        if self.one_time_keys.len() > MAX_ONE_TIME_KEYS {
+            let extra = self.one_time_keys.len() - MAX_ONE_TIME_KEYS;
+            self.one_time_keys.drain(..extra);
+        }
+    }
+
+    /// Returns the one-time keys that weren't uploaded yet, as pairs of key
+    /// ID and public key
+    pub fn unpublished_one_time_keys(&self) -> Vec<(String, String)> {
+        self.one_time_keys
+            .iter()
+            .filter(|k| !k.published)
+            .map(|k| {
+                (
+                    encode_base64(&k.id.to_be_bytes()),
+                    encode_base64(k.key.public_key()),
+                )
+            })
+            .collect()
+    }
+
+    pub fn mark_keys_as_published(&mut self) {
+        for key in self.one_time_keys.iter_mut() {
+            key.published = true;
+        }
+    }
+
+    /// Starts a session with a device, using one of the one-time keys it
+    /// published
+    pub fn create_outbound_session(
+        &self,
+        their_identity_key: &[u8; 32],
+        their_one_time_key: &[u8; 32],
+    ) -> Session {
+        self.create_outbound_session_with_keys(
+            their_identity_key,
+            their_one_time_key,
+            Curve25519KeyPair::generate(),
+            Curve25519KeyPair::generate(),
+        )
+    }
+
+    fn create_outbound_session_with_keys(
+        &self,
+        their_identity_key: &[u8; 32],
+        their_one_time_key: &[u8; 32],
+        base_key: Curve25519KeyPair,
+        ratchet_key: Curve25519KeyPair,
+    ) -> Session {
+        let mut shared_secret = Vec::with_capacity(96);
+        shared_secret.extend_from_slice(&self.curve25519.diffie_hellman(their_one_time_key));
+        shared_secret.extend_from_slice(&base_key.diffie_hellman(their_identity_key));
+        shared_secret.extend_from_slice(&base_key.diffie_hellman(their_one_time_key));
+
+        let (root_key, chain_key) = derive_root_keys(&shared_secret);
+
+        Session {
+            alice_identity_key: *self.curve25519.public_key(),
+            alice_base_key: *base_key.public_key(),
+            bob_one_time_key: *their_one_time_key,
+            received_message: false,
+            root_key,
+            sender_chain: Some(SenderChain {
+                ratchet_key,
+                chain_key: ChainKey {
+                    key: chain_key,
+                    index: 0,
+                },
+            }),
+            receiver_chains: Vec::new(),
+            skipped_message_keys: Vec::new(),
+        }
+    }
+
+    /// Creates the session started by a pre-key message and decrypts it. The
+    /// one-time key used by the other device is removed, so it can't be
+    /// used again.
+    pub fn create_inbound_session(
+        &mut self,
+        message: &PreKeyMessage,
+    ) -> Result<(Session, Vec<u8>), CryptoError> {
+        let pos = self
+            .one_time_keys
+            .iter()
+            .position(|k| *k.key.public_key() == message.one_time_key)
+            .ok_or(CryptoError::MissingSession)?;
+        let one_time_key = &self.one_time_keys[pos].key;
+
+        let mut shared_secret = Vec::with_capacity(96);
+        shared_secret.extend_from_slice(&one_time_key.diffie_hellman(&message.identity_key));
+        shared_secret.extend_from_slice(&self.curve25519.diffie_hellman(&message.base_key));
+        shared_secret.extend_from_slice(&one_time_key.diffie_hellman(&message.base_key));
+
+        let (root_key, chain_key) = derive_root_keys(&shared_secret);
+        let their_ratchet_key = Message::decode(&message.message)?.ratchet_key;
+
+        let mut session = Session {
+            alice_identity_key: message.identity_key,
+            alice_base_key: message.base_key,
+            bob_one_time_key: message.one_time_key,
+            received_message: false,
+            root_key,
+            sender_chain: None,
+            receiver_chains: vec![ReceiverChain {
+                ratchet_key: their_ratchet_key,
+                chain_key: ChainKey {
+                    key: chain_key,
+                    index: 0,
+                },
+            }],
+            skipped_message_keys: Vec::new(),
+        };
+        let plaintext = session.decrypt_message(&message.message)?;
+        self.one_time_keys.remove(pos);
+
+        Ok((session, plaintext))
+    }
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn derive_root_keys(shared_secret: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut derived = [0; 64];
+    hkdf_sha256(None, shared_secret, b"OLM_ROOT", &mut derived);
+    split_keys(&derived)
+}
+
+fn split_keys(derived: &[u8; 64]) -> ([u8; 32], [u8; 32]) {
+    let mut root_key = [0; 32];
+    let mut chain_key = [0; 32];
+    root_key.copy_from_slice(&derived[..32]);
+    chain_key.copy_from_slice(&derived[32..]);
+    (root_key, chain_key)
+}
+
+/// Advances the root key with a new Diffie-Hellman exchange, returning the
+/// new root key and the key of the new chain
+fn ratchet_root_key(
+    root_key: &[u8; 32],
+    our_key: &Curve25519KeyPair,
+    their_key: &[u8; 32],
+) -> ([u8; 32], [u8; 32]) {
+    let mut derived = [0; 64];
+    hkdf_sha256(
+        Some(root_key),
+        &our_key.diffie_hellman(their_key),
+        b"OLM_RATCHET",
+        &mut derived,
+    );
+    split_keys(&derived)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ChainKey {
+    key: [u8; 32],
+    index: u32,
+}
+
+impl ChainKey {
+    fn message_key(&self) -> MessageKey {
+        MessageKey {
+            key: hmac_sha256(&self.key, &[0x01]),
+            index: self.index,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.key = hmac_sha256(&self.key, &[0x02]);
+        self.index += 1;
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct MessageKey {
+    key: [u8; 32],
+    index: u32,
+}
+
+impl MessageKey {
+    fn cipher(&self) -> Cipher {
+        Cipher::new(&self.key, b"OLM_KEYS")
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SenderChain {
+    ratchet_key: Curve25519KeyPair,
+    chain_key: ChainKey,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ReceiverChain {
+    ratchet_key: [u8; 32],
+    chain_key: ChainKey,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SkippedMessageKey {
+    ratchet_key: [u8; 32],
+    message_key: MessageKey,
+}
+
+/// A message encrypted by a session
+pub enum OlmMessage {
+    PreKey(Vec<u8>),
+    Normal(Vec<u8>),
+}
+
+impl OlmMessage {
+    pub fn from_parts(message_type: u64, body: Vec<u8>) -> Result<Self, CryptoError> {
+        match message_type {
+            0 => Ok(OlmMessage::PreKey(body)),
+            1 => Ok(OlmMessage::Normal(body)),
+            _ => Err(CryptoError::BadMessage),
+        }
+    }
+
+    pub fn message_type(&self) -> u64 {
+        match self {
+            OlmMessage::PreKey(_) => 0,
+            OlmMessage::Normal(_) => 1,
+        }
+    }
+
+    pub fn body(&self) -> &[u8] {
+        match self {
+            OlmMessage::PreKey(body) | OlmMessage::Normal(body) => body,
+        }
+    }
+}
+
+struct Message {
+    ratchet_key: [u8; 32],
+    counter: u32,
+    ciphertext: Vec<u8>,
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![VERSION];
+        write_bytes_field(&mut out, RATCHET_KEY_TAG, &self.ratchet_key);
+        write_varint_field(&mut out, COUNTER_TAG, u64::from(self.counter));
+        write_bytes_field(&mut out, CIPHERTEXT_TAG, &self.ciphertext);
+        out
+    }
+
+    /// Decodes a message followed by its MAC
+    fn decode(input: &[u8]) -> Result<Self, CryptoError> {
+        if input.len() < MAC_LENGTH {
+            return Err(CryptoError::BadMessage);
+        }
+
+        let mut ratchet_key = None;
+        let mut counter = None;
+        let mut ciphertext = None;
+        for (tag, field) in decode_fields(&input[..input.len() - MAC_LENGTH])? {
+            match (tag as u8, field) {
+                (RATCHET_KEY_TAG, Field::Bytes(b)) => ratchet_key = Some(to_key(b)?),
+                (COUNTER_TAG, Field::Varint(v)) => counter = Some(v as u32),
+                (CIPHERTEXT_TAG, Field::Bytes(b)) => ciphertext = Some(b.to_vec()),
+                _ => {}
+            }
+        }
+
+        match (ratchet_key, counter, ciphertext) {
+            (Some(ratchet_key), Some(counter), Some(ciphertext)) => Ok(Self {
+                ratchet_key,
+                counter,
+                ciphertext,
+            }),
+            _ => Err(CryptoError::BadMessage),
+        }
+    }
+}
+
+/// The first messages of a session, which carry what's needed to create
+/// the session on the receiving side
+pub struct PreKeyMessage {
+    pub one_time_key: [u8; 32],
+    pub base_key: [u8; 32],
+    pub identity_key: [u8; 32],
+    message: Vec<u8>,
+}
+
+impl PreKeyMessage {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![VERSION];
+        write_bytes_field(&mut out, ONE_TIME_KEY_TAG, &self.one_time_key);
+        write_bytes_field(&mut out, BASE_KEY_TAG, &self.base_key);
+        write_bytes_field(&mut out, IDENTITY_KEY_TAG, &self.identity_key);
+        write_bytes_field(&mut out, MESSAGE_TAG, &self.message);
+        out
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, CryptoError> {
+        let mut one_time_key = None;
+        let mut base_key = None;
+        let mut identity_key = None;
+        let mut message = None;
+        for (tag, field) in decode_fields(input)? {
+            match (tag as u8, field) {
+                (ONE_TIME_KEY_TAG, Field::Bytes(b)) => one_time_key = Some(to_key(b)?),
+                (BASE_KEY_TAG, Field::Bytes(b)) => base_key = Some(to_key(b)?),
+                (IDENTITY_KEY_TAG, Field::Bytes(b)) => identity_key = Some(to_key(b)?),
+                (MESSAGE_TAG, Field::Bytes(b)) => message = Some(b.to_vec()),
+                _ => {}
+            }
+        }
+
+        match (one_time_key, base_key, identity_key, message) {
+            (Some(one_time_key), Some(base_key), Some(identity_key), Some(message)) => Ok(Self {
+                one_time_key,
+                base_key,
+                identity_key,
+                message,
+            }),
+            _ => Err(CryptoError::BadMessage),
+        }
+    }
+}
+
+/// An Olm session between two devices. Alice is the device that created the
+/// session and Bob the one that received the first pre-key message.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    alice_identity_key: [u8; 32],
+    alice_base_key: [u8; 32],
+    bob_one_time_key: [u8; 32],
+    // Until the other side answers, every message must be a pre-key message
+    received_message: bool,
+    root_key: [u8; 32],
+    sender_chain: Option<SenderChain>,
+    // The newest chain comes first
+    receiver_chains: Vec<ReceiverChain>,
+    skipped_message_keys: Vec<SkippedMessageKey>,
+}
+
+impl Session {
+    pub fn session_id(&self) -> String {
+        let mut input = Vec::with_capacity(96);
+        input.extend_from_slice(&self.alice_identity_key);
+        input.extend_from_slice(&self.alice_base_key);
+        input.extend_from_slice(&self.bob_one_time_key);
+
+        encode_base64(&sha256(&input))
+    }
+
+    /// Whether the pre-key message was sent to create this session
+    pub fn matches_inbound_session(&self, message: &PreKeyMessage) -> bool {
+        self.alice_identity_key == message.identity_key
+            && self.alice_base_key == message.base_key
+            && self.bob_one_time_key == message.one_time_key
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> OlmMessage {
+        let root_key = self.root_key;
+        let receiver_chains = &self.receiver_chains;
+        let mut new_root_key = None;
+        let sender_chain = self.sender_chain.get_or_insert_with(|| {
+            // The first message since the last message we received, we have to
+            // start a new chain with a new ratchet key
+            let ratchet_key = Curve25519KeyPair::generate();
+            let their_key = &receiver_chains
+                .first()
+                .expect("Sessions without sender chain always have a receiver chain")
+                .ratchet_key;
+            let (root_key, chain_key) = ratchet_root_key(&root_key, &ratchet_key, their_key);
+            new_root_key = Some(root_key);
+
+            SenderChain {
+                ratchet_key,
+                chain_key: ChainKey {
+                    key: chain_key,
+                    index: 0,
+                },
+            }
+        });
+        if let Some(root_key) = new_root_key {
+            self.root_key = root_key;
+        }
+
+        let message_key = sender_chain.chain_key.message_key();
+        sender_chain.chain_key.advance();
+
+        let cipher = message_key.cipher();
+        let mut message = Message {
+            ratchet_key: *sender_chain.ratchet_key.public_key(),
+            counter: message_key.index,
+            ciphertext: cipher.encrypt(plaintext),
+        }
+        .encode();
+        let mac = cipher.mac(&message);
+        message.extend_from_slice(&mac);
+
+        if self.received_message {
+            OlmMessage::Normal(message)
+        } else {
+            OlmMessage::PreKey(
+                PreKeyMessage {
+                    one_time_key: self.bob_one_time_key,
+                    base_key: self.alice_base_key,
+                    identity_key: self.alice_identity_key,
+                    message,
+                }
+                .encode(),
+            )
+        }
+    }
+
+    pub fn decrypt(&mut self, message: &OlmMessage) -> Result<Vec<u8>, CryptoError> {
+        match message {
+            OlmMessage::Normal(body) => self.decrypt_message(body),
+            OlmMessage::PreKey(body) => {
+                let message = PreKeyMessage::decode(body)?;
+                if !self.matches_inbound_session(&message) {
+                    return Err(CryptoError::MissingSession);
+                }
+                self.decrypt_message(&message.message)
+            }
+        }
+    }
+
+    /// Decrypts a normal message. The state of the session is only modified
+    /// if the message is authentic.
+    fn decrypt_message(&mut self, input: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let message = Message::decode(input)?;
+        let (body, mac) = input.split_at(input.len() - MAC_LENGTH);
+
+        let chain_pos = self
+            .receiver_chains
+            .iter()
+            .position(|c| c.ratchet_key == message.ratchet_key);
+
+        let (chain_key, new_root_key) = match chain_pos {
+            Some(pos) => {
+                let chain_key = &self.receiver_chains[pos].chain_key;
+                if message.counter < chain_key.index {
+                    return self.decrypt_with_skipped_key(&message, body, mac);
+                }
+                (chain_key.clone(), None)
+            }
+            None => {
+                // The other side started a new chain after receiving one of
+                // our messages
+                let sender_chain = self
+                    .sender_chain
+                    .as_ref()
+                    .ok_or(CryptoError::MissingSession)?;
+                let (root_key, chain_key) = ratchet_root_key(
+                    &self.root_key,
+                    &sender_chain.ratchet_key,
+                    &message.ratchet_key,
+                );
+                let chain_key = ChainKey {
+                    key: chain_key,
+                    index: 0,
+                };
+                (chain_key, Some(root_key))
+            }
+        };
+
+        if message.counter - chain_key.index > MAX_MESSAGE_GAP {
+            return Err(CryptoError::BadMessage);
+        }
+
+        let mut chain_key = chain_key;
+        let mut skipped = Vec::new();
+        while chain_key.index < message.counter {
+            skipped.push(SkippedMessageKey {
+                ratchet_key: message.ratchet_key,
+                message_key: chain_key.message_key(),
+            });
+            chain_key.advance();
+        }
+
+        let cipher = chain_key.message_key().cipher();
+        cipher.verify_mac(body, mac)?;
+        let plaintext = cipher.decrypt(&message.ciphertext)?;
+        chain_key.advance();
+
+        match chain_pos {
+            Some(pos) => self.receiver_chains[pos].chain_key = chain_key,
+            None => {
+                self.receiver_chains.insert(
+                    0,
+                    ReceiverChain {
+                        ratchet_key: message.ratchet_key,
+                        chain_key,
+                    },
+                );
+                self.receiver_chains.truncate(MAX_RECEIVER_CHAINS);
+                if let Some(root_key) = new_root_key {
+                    self.root_key = root_key;
+                }
+                // Our next message will start a new chain
+                self.sender_chain = None;
+            }
+        }
+
+        self.skipped_message_keys.extend(skipped);
+        if self.skipped_message_keys.len() > MAX_SKIPPED_MESSAGE_KEYS {
+            let extra = self.skipped_message_keys.len() - MAX_SKIPPED_MESSAGE_KEYS;
+            self.skipped_message_keys.drain(..extra);
+        }
+        self.received_message = true;
+
+        Ok(plaintext)
+    }
+
+    fn decrypt_with_skipped_key(
+        &mut self,
+        message: &Message,
+        body: &[u8],
+        mac: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let pos = self
+            .skipped_message_keys
+            .iter()
+            .position(|k| {
+                k.ratchet_key == message.ratchet_key && k.message_key.index == message.counter
+            })
+            .ok_or(CryptoError::UnknownMessageIndex(message.counter))?;
+
+        let cipher = self.skipped_message_keys[pos].message_key.cipher();
+        cipher.verify_mac(body, mac)?;
+        let plaintext = cipher.decrypt(&message.ciphertext)?;
+        self.skipped_message_keys.remove(pos);
+
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{decode_base64, decode_key};
+    use super::*;
+
+    fn create_sessions() -> (Account, Session, Account, Session) {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        bob.generate_one_time_keys(1);
+
+        let bob_one_time_key = *bob.one_time_keys[0].key.public_key();
+        let mut alice_session =
+            alice.create_outbound_session(bob.curve25519.public_key(), &bob_one_time_key);
+
+        let message = alice_session.encrypt(b"Hello Bob");
+        let prekey = match message {
+            OlmMessage::PreKey(ref body) => PreKeyMessage::decode(body).unwrap(),
+            OlmMessage::Normal(_) => panic!("The first message must be a pre-key message"),
+        };
+        let (bob_session, plaintext) = bob.create_inbound_session(&prekey).unwrap();
+        assert_eq!(plaintext, b"Hello Bob");
+        assert_eq!(alice_session.session_id(), bob_session.session_id());
+        assert!(bob.one_time_keys.is_empty());
+
+        (alice, alice_session, bob, bob_session)
+    }
+
+    #[test]
+    fn olm_session_roundtrip() {
+        let (_, mut alice_session, _, mut bob_session) = create_sessions();
+
+        let message = bob_session.encrypt(b"Hello Alice");
+        assert_eq!(message.message_type(), 1);
+        assert_eq!(alice_session.decrypt(&message).unwrap(), b"Hello Alice");
+
+        // Alice got an answer, so her next messages are normal messages and
+        // start a new chain
+        for text in &["one", "two", "three"] {
+            let message = alice_session.encrypt(text.as_bytes());
+            assert_eq!(message.message_type(), 1);
+            assert_eq!(bob_session.decrypt(&message).unwrap(), text.as_bytes());
+        }
+    }
+
+    #[test]
+    fn olm_out_of_order_messages() {
+        let (_, mut alice_session, _, mut bob_session) = create_sessions();
+
+        let first = bob_session.encrypt(b"first");
+        let second = bob_session.encrypt(b"second");
+        let third = bob_session.encrypt(b"third");
+
+        assert_eq!(alice_session.decrypt(&third).unwrap(), b"third");
+        assert_eq!(alice_session.decrypt(&first).unwrap(), b"first");
+        assert_eq!(alice_session.decrypt(&second).unwrap(), b"second");
+        // Message keys can't be used twice
+        assert!(alice_session.decrypt(&second).is_err());
+    }
+
+    // The known answers were made with vodozemac, the Olm implementation of
+    // matrix.org that is tested against libolm
+    const BOB_IDENTITY_SECRET: &str = "kYX9dlwXJXWHXFkpQ9HvgBC2ZWLBkgA+pymDVFsjdIo";
+    const BOB_ONE_TIME_SECRET: &str = "L8/8hcD2FlCLG5fsPKI4nYqgFK1lNnjlqP0TIBUynOc";
+
+    fn known_account(identity_secret: &str) -> Account {
+        Account {
+            ed25519_secret: [0; 32],
+            curve25519: Curve25519KeyPair::from_secret(decode_key(identity_secret).unwrap()),
+            one_time_keys: Vec::new(),
+            next_one_time_key_id: 0,
+        }
+    }
+
+    fn known_bob() -> Account {
+        let mut bob = known_account(BOB_IDENTITY_SECRET);
+        bob.one_time_keys.push(OneTimeKey {
+            id: 1,
+            key: Curve25519KeyPair::from_secret(decode_key(BOB_ONE_TIME_SECRET).unwrap()),
+            published: true,
+        });
+        bob.next_one_time_key_id = 1;
+        bob
+    }
+
+    #[test]
+    fn olm_known_prekey_message() {
+        let mut bob = known_bob();
+        assert_eq!(
+            bob.curve25519_key(),
+            "ENGYNY3eoE5b0Voz0KwUAvleY/kt+9hFk53O7r3rLjw"
+        );
+        assert_eq!(
+            encode_base64(bob.one_time_keys[0].key.public_key()),
+            "UNQ97WV353GKUaSnY+xpjYq58y+gZUriPZUbGzkmNjM"
+        );
+
+        let body = decode_base64(
+            "AwogUNQ97WV353GKUaSnY+xpjYq58y+gZUriPZUbGzkmNjMSIBDdDzilrMLtsGaN8+JvMOGnB+wWQ4DsWJqNm\
+             LslfEN2GiB2NWkd2X3/Z8nyb8EYomHT1SOnDCPB8Oob8Eu9qQsXFiJPAwogowokZR2sXudMMpp0J7Ujhg0Q9\
+             fC4VNw30Im02aK7CwMQACIgEoNr2/2end+UY/pByGshUUUmH07mtb7Xw4LyIA3XGeGIgSQaSt6xMw",
+        )
+        .unwrap();
+        let prekey = PreKeyMessage::decode(&body).unwrap();
+        assert_eq!(
+            encode_base64(&prekey.identity_key),
+            "djVpHdl9/2fJ8m/BGKJh09UjpwwjwfDqG/BLvakLFxY"
+        );
+
+        let (session, plaintext) = bob.create_inbound_session(&prekey).unwrap();
+        assert_eq!(plaintext, b"Hello Bob, from vodozemac");
+        assert_eq!(
+            session.session_id(),
+            "7xNHxU7Btjp8WNorUurDaCMH3yUmjnc7UEEbmDazNx0"
+        );
+    }
+
+    #[test]
+    fn olm_known_normal_message() {
+        let bob = known_bob();
+        let alice = known_account("Ifiw+lmjwMl5kCQh3RiFx3dffcEK+y34+eY1+Y7Hnkg");
+        let mut session = alice.create_outbound_session_with_keys(
+            bob.curve25519.public_key(),
+            bob.one_time_keys[0].key.public_key(),
+            Curve25519KeyPair::from_secret(
+                decode_key("4k3XvnslsLdfv3AA1tLWYPjRD6Ks4k5n8/RZ6I/oFkU").unwrap(),
+            ),
+            Curve25519KeyPair::from_secret(
+                decode_key("q0TyY7c5juL8OkBgLOsoP8z4HOBmy+3YcY8CW7/kWxg").unwrap(),
+            ),
+        );
+
+        // The pre-key message vodozemac answered to
+        let message = session.encrypt(b"Hello Bob");
+        assert_eq!(
+            encode_base64(message.body()),
+            "AwogUNQ97WV353GKUaSnY+xpjYq58y+gZUriPZUbGzkmNjMSIIRVDyD048FHpvzykET0WazzhjbVRneHM0lg7\
+             xQXbqkNGiBdQ1YWIHx/46gJd2EPwBjSKnnXwHZFkRMmMGYwElKrDyI/AwogxCso7t1ToMvdTNGvBqv/HuvvoR\
+             CPToEqvaxhnzEYMHIQACIQJzeEIqE1rspHigNNXcDWBbp9PItBmL4D"
+        );
+        assert_eq!(
+            session.session_id(),
+            "4p3dZY+R9lT3xXzn5P0MO66ACcqOUm60sUC8+/nPRno"
+        );
+
+        let answer = OlmMessage::Normal(
+            decode_base64(
+                "AwogVJNd9bJbiMoYrzTOAaWTMvo1OdLXrByFZptmSyQB4gUQACIgcl0uW2wZr6EnhHPhtYyXutptiRyAh\
+                 hX5hf2vtNnZeOCGh02i2YlvKA",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            session.decrypt(&answer).unwrap(),
+            b"Hello Alice, from vodozemac"
+        );
+    }
+
+    #[test]
+    fn olm_tampered_message() {
+        let (_, mut alice_session, _, mut bob_session) = create_sessions();
+
+        let message = bob_session.encrypt(b"Hello Alice");
+        let mut body = message.body().to_vec();
+        let last = body.len() - MAC_LENGTH - 1;
+        body[last] ^= 0x01;
+
+        let tampered = OlmMessage::Normal(body);
+        assert!(alice_session.decrypt(&tampered).is_err());
+        // The session is still usable with the genuine message
+        assert_eq!(alice_session.decrypt(&message).unwrap(), b"Hello Alice");
+    }
+}
@@ -1,12 +1,4 @@
-use serde::Deserialize;
-
-#[derive(Clone, Debug, Deserialize)]
-pub struct StandardErrorResponse {
-    pub errcode: String,
-    pub error: String,
-}
-
-type MatrixErrorCode = String;
+use fractal_api::error::MatrixError;
 
 #[macro_export]
 macro_rules! derror {
@@ -23,8 +15,7 @@ macro_rules! derror {
 pub enum Error {
     BackendError,
     ReqwestError(fractal_api::reqwest::Error),
-    NetworkError(fractal_api::reqwest::StatusCode),
-    MatrixError(MatrixErrorCode, String),
+    MatrixError(MatrixError),
 }
 
 impl From<fractal_api::reqwest::Error> for Error {
@@ -33,9 +24,9 @@ impl From<fractal_api::reqwest::Error> for Error {
     }
 }
 
-impl From<StandardErrorResponse> for Error {
-    fn from(resp: StandardErrorResponse) -> Error {
-        Error::MatrixError(resp.errcode, resp.error)
+impl From<MatrixError> for Error {
+    fn from(err: MatrixError) -> Error {
+        Error::MatrixError(err)
     }
 }
 
@@ -7,6 +7,8 @@ use std::path::PathBuf;
 pub static TIMEOUT: u64 = 80;
 pub static PAGE_LIMIT: i32 = 40;
 pub static ROOM_DIRECTORY_LIMIT: i32 = 20;
+pub static SEARCH_CONTEXT_LIMIT: u64 = 10;
+pub static LOCAL_SEARCH_LIMIT: usize = 100;
 pub static DEVICE_NAME: &str = "Fractal";
 
 pub static CACHE_SIZE: usize = 40;
@@ -15,7 +17,6 @@ pub static USERLIST_ICON_SIZE: i32 = 30;
 pub static PILL_ICON_SIZE: i32 = 18;
 pub static MINUTES_TO_SPLIT_MSGS: i64 = 30;
 pub static PLACEHOLDER_TEXT: &str = "Matrix username, email or phone number";
-pub static RIOT_REGISTER_URL: &str = "https://riot.im/app/#/register";
 
 pub static MAX_IMAGE_SIZE: (i32, i32) = (600, 400);
 pub static MAX_STICKER_SIZE: (i32, i32) = (200, 130);
@@ -34,4 +35,9 @@ lazy_static! {
         .map(ProjectDirs::cache_dir)
         .map(Into::into)
         .unwrap_or_else(|| std::env::temp_dir().join("fractal"));
+    pub static ref DATA_PATH: PathBuf = ProjectDirs::from("org", "GNOME", "Fractal")
+        .as_ref()
+        .map(ProjectDirs::data_dir)
+        .map(Into::into)
+        .unwrap_or_else(|| std::env::temp_dir().join("fractal"));
 }
@@ -1,6 +1,7 @@
 mod backend;
 mod client;
 mod config;
+mod crypto;
 mod error;
 mod globals;
 mod i18n;
@@ -0,0 +1,268 @@
+use fractal_api::identifiers::RoomIdOrAliasId;
+use std::convert::TryFrom;
+
+/// The power level `/op` gives when none is given, the one of moderators
+pub const DEFAULT_OP_LEVEL: i64 = 50;
+
+/// What a command expects after its name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arguments {
+    None,
+    /// The rest of the line
+    Text,
+    OptionalText,
+    Room,
+    User,
+    /// A user and, optionally, the reason why
+    UserAndReason,
+    /// A user and, optionally, their new power level
+    UserAndLevel,
+    /// The name of a command, optionally
+    Command,
+}
+
+/// A command of the message composer
+#[derive(Debug, PartialEq)]
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub args: Arguments,
+}
+
+const fn command(name: &'static str, args: Arguments) -> CommandInfo {
+    CommandInfo { name, args }
+}
+
+/// Every command we understand, in the order they are listed in the help
+pub const COMMANDS: &[CommandInfo] = &[
+    command("me", Arguments::Text),
+    command("shrug", Arguments::OptionalText),
+    command("plain", Arguments::Text),
+    command("html", Arguments::Text),
+    command("notice", Arguments::Text),
+    command("join", Arguments::Room),
+    command("leave", Arguments::None),
+    command("invite", Arguments::User),
+    command("kick", Arguments::UserAndReason),
+    command("ban", Arguments::UserAndReason),
+    command("unban", Arguments::User),
+    command("nick", Arguments::Text),
+    command("topic", Arguments::Text),
+    command("roomname", Arguments::Text),
+    command("op", Arguments::UserAndLevel),
+    command("deop", Arguments::User),
+    command("query", Arguments::User),
+    command("ignore", Arguments::User),
+    command("devtools", Arguments::None),
+    command("help", Arguments::Command),
+];
+
+/// A command typed in the message composer, users are kept as typed, they can be a
+/// Matrix ID or the name of a member of the room
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Me(String),
+    Shrug(String),
+    Plain(String),
+    Html(String),
+    Notice(String),
+    Join(RoomIdOrAliasId),
+    Leave,
+    Invite(String),
+    Kick(String, Option<String>),
+    Ban(String, Option<String>),
+    Unban(String),
+    Nick(String),
+    Topic(String),
+    RoomName(String),
+    Op(String, i64),
+    Deop(String),
+    Query(String),
+    Ignore(String),
+    Devtools,
+    Help(Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    Unknown(String),
+    /// The arguments don't match what the command expects
+    Usage(&'static CommandInfo),
+    InvalidRoom(String),
+    InvalidPowerLevel(String),
+}
+
+impl CommandInfo {
+    pub fn find(name: &str) -> Option<&'static CommandInfo> {
+        COMMANDS.iter().find(|info| info.name == name)
+    }
+
+    /// The commands whose name starts with `prefix`
+    pub fn completions(prefix: &str) -> Vec<&'static CommandInfo> {
+        COMMANDS
+            .iter()
+            .filter(|info| info.name.starts_with(prefix))
+            .collect()
+    }
+}
+
+impl Command {
+    /// Returns None if the input isn't a command, text starting with `//` is sent as a
+    /// message starting with `/`
+    pub fn parse(input: &str) -> Option<Result<Self, CommandError>> {
+        let input = input.trim();
+        if !input.starts_with('/') || input.starts_with("//") {
+            return None;
+        }
+
+        let mut parts = input[1..].splitn(2, char::is_whitespace);
+        let name = parts.next().unwrap_or_default();
+        let args = parts.next().unwrap_or_default().trim();
+
+        Some(match CommandInfo::find(name) {
+            Some(info) => parse_args(info, args),
+            None => Err(CommandError::Unknown(name.to_string())),
+        })
+    }
+}
+
+fn parse_args(info: &'static CommandInfo, args: &str) -> Result<Command, CommandError> {
+    let mut words = args.splitn(2, char::is_whitespace);
+    let first = words.next().unwrap_or_default().to_string();
+    let rest = words
+        .next()
+        .map(str::trim)
+        .filter(|rest| !rest.is_empty())
+        .map(String::from);
+    let text = args.to_string();
+
+    let valid = match info.args {
+        Arguments::None => args.is_empty(),
+        Arguments::Text | Arguments::UserAndReason | Arguments::UserAndLevel => !args.is_empty(),
+        Arguments::OptionalText => true,
+        Arguments::Room | Arguments::User => !args.is_empty() && rest.is_none(),
+        Arguments::Command => rest.is_none(),
+    };
+    if !valid {
+        return Err(CommandError::Usage(info));
+    }
+
+    let command = match info.name {
+        "me" => Command::Me(text),
+        "shrug" => Command::Shrug(text),
+        "plain" => Command::Plain(text),
+        "html" => Command::Html(text),
+        "notice" => Command::Notice(text),
+        "join" => match RoomIdOrAliasId::try_from(first.as_str()) {
+            Ok(room) => Command::Join(room),
+            Err(_) => return Err(CommandError::InvalidRoom(first)),
+        },
+        "leave" => Command::Leave,
+        "invite" => Command::Invite(first),
+        "kick" => Command::Kick(first, rest),
+        "ban" => Command::Ban(first, rest),
+        "unban" => Command::Unban(first),
+        "nick" => Command::Nick(text),
+        "topic" => Command::Topic(text),
+        "roomname" => Command::RoomName(text),
+        "op" => {
+            let level = match rest {
+                Some(level) => level
+                    .parse()
+                    .map_err(|_| CommandError::InvalidPowerLevel(level))?,
+                None => DEFAULT_OP_LEVEL,
+            };
+            Command::Op(first, level)
+        }
+        "deop" => Command::Deop(first),
+        "query" => Command::Query(first),
+        "ignore" => Command::Ignore(first),
+        "devtools" => Command::Devtools,
+        "help" => Command::Help(
+            Some(first.trim_start_matches('/').to_string()).filter(|name| !name.is_empty()),
+        ),
+        name => return Err(CommandError::Unknown(name.to_string())),
+    };
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_commands() {
+        assert_eq!(Command::parse("hello /me"), None);
+        assert_eq!(Command::parse("//me is a path"), None);
+    }
+
+    #[test]
+    fn command_arguments() {
+        assert_eq!(
+            Command::parse("/me  waves  "),
+            Some(Ok(Command::Me(String::from("waves"))))
+        );
+        assert_eq!(
+            Command::parse("/kick @bob:example.org too  loud"),
+            Some(Ok(Command::Kick(
+                String::from("@bob:example.org"),
+                Some(String::from("too  loud"))
+            )))
+        );
+        assert_eq!(
+            Command::parse("/op bob"),
+            Some(Ok(Command::Op(String::from("bob"), DEFAULT_OP_LEVEL)))
+        );
+        assert_eq!(
+            Command::parse("/op bob lots"),
+            Some(Err(CommandError::InvalidPowerLevel(String::from("lots"))))
+        );
+        assert_eq!(
+            Command::parse("/join #fractal:gnome.org"),
+            Some(Ok(Command::Join(
+                RoomIdOrAliasId::try_from("#fractal:gnome.org").unwrap()
+            )))
+        );
+        assert_eq!(
+            Command::parse("/help /ban"),
+            Some(Ok(Command::Help(Some(String::from("ban")))))
+        );
+    }
+
+    #[test]
+    fn command_errors() {
+        assert_eq!(
+            Command::parse("/dance"),
+            Some(Err(CommandError::Unknown(String::from("dance"))))
+        );
+        assert_eq!(
+            Command::parse("/invite"),
+            Some(Err(CommandError::Usage(
+                CommandInfo::find("invite").unwrap()
+            )))
+        );
+        assert_eq!(
+            Command::parse("/leave now"),
+            Some(Err(CommandError::Usage(
+                CommandInfo::find("leave").unwrap()
+            )))
+        );
+        assert_eq!(
+            Command::parse("/join nowhere"),
+            Some(Err(CommandError::InvalidRoom(String::from("nowhere"))))
+        );
+    }
+
+    #[test]
+    fn every_command_parses() {
+        for info in COMMANDS {
+            let args = match info.args {
+                Arguments::None | Arguments::Command => "",
+                Arguments::Room => "!abc:example.org",
+                _ => "@bob:example.org",
+            };
+            let input = format!("/{} {}", info.name, args);
+            assert!(matches!(Command::parse(&input), Some(Ok(_))), "{}", input);
+        }
+    }
+}
@@ -1,5 +1,5 @@
+use fractal_api::events::room::RoomEventContent;
 use fractal_api::identifiers::{EventId, RoomId, UserId};
-use serde_json::Value as JsonValue;
 
 #[derive(Debug, Clone)]
 pub struct Event {
@@ -8,7 +8,7 @@ pub struct Event {
     pub room: RoomId,
     pub id: String,
     pub redacts: Option<EventId>,
-    pub content: JsonValue,
+    pub content: RoomEventContent,
 }
 
 impl PartialEq for Event {
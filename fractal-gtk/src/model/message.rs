@@ -5,13 +5,19 @@ use fractal_api::{
     identifiers::{Error as IdError, EventId, RoomId, UserId},
     url::Url,
 };
+use html2pango::html_escape;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::path::PathBuf;
 
+use super::state_change::StateChange;
+use crate::crypto::attachment::EncryptedFile;
+
 //FIXME make properties private
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
@@ -37,8 +43,15 @@ pub struct Message {
     pub redacted: bool,
     // The event ID of the message this is in reply to.
     pub in_reply_to: Option<EventId>,
+    // The sender and the text of the message this is in reply to, as quoted by the
+    // reply fallback, for when we don't have the message itself.
+    pub reply_quote: Option<(UserId, String)>,
     // The event ID of the message this replaces.
     pub replace: Option<EventId>,
+    // The event ID of the message this reaction is for, the key of the reaction is the body.
+    pub annotates: Option<EventId>,
+    // What changed in the room, for the state events we show in the room history.
+    pub state_change: Option<StateChange>,
     // This can be used for the client to add more values to the message on sending
     // for example for images attachment the "info" field can be attached as
     // Some(json!({"info": {"h": 296, "w": 296, "mimetype": "image/png", "orientation": 0, "size": 8796}});
@@ -87,27 +100,43 @@ impl Message {
             receipt: HashMap::new(),
             redacted: false,
             in_reply_to: None,
+            reply_quote: None,
             replace: None,
+            annotates: None,
+            state_change: None,
             extra_content: None,
         }
     }
 
-    /// Generates an unique transaction id for this message
-    /// The txn_id is generated using the md5sum of a concatenation of the message room id, the
-    /// message body and the date.
+    /// Generates a new unique transaction id
+    /// A message keeps the same one until it's sent, so if we send it more than once the
+    /// server knows it's the same message.
     ///
-    /// https://matrix.org/docs/spec/client_server/r0.3.0.html#put-matrix-client-r0-rooms-roomid-send-eventtype-txnid
-    pub fn get_txn_id(&self) -> String {
-        let msg_str = format!("{}{}{}", self.room, self.body, self.date);
-        let digest = md5::compute(msg_str.as_bytes());
-        format!("{:x}", digest)
+    /// https://matrix.org/docs/spec/client_server/r0.6.1#put-matrix-client-r0-rooms-roomid-send-eventtype-txnid
+    pub fn new_txn_id() -> String {
+        let suffix: String = thread_rng().sample_iter(&Alphanumeric).take(16).collect();
+        format!("{}.{}", Local::now().timestamp_millis(), suffix)
     }
 
     /// List all supported types. By default a message map a m.room.message event, but there's
     /// other events that we want to show in the message history so we map other event types to our
     /// Message struct, like stickers
-    pub fn types() -> [&'static str; 2] {
-        ["m.room.message", "m.sticker"]
+    pub fn types() -> [&'static str; 3] {
+        ["m.room.message", "m.sticker", "m.reaction"]
+    }
+
+    /// The events we ask the server for to fill the room history, encrypted events become
+    /// one of the others once we decrypt them
+    pub fn timeline_types() -> Vec<&'static str> {
+        let mut types = Message::types().to_vec();
+        types.push("m.room.encrypted");
+        types.extend_from_slice(&StateChange::types());
+        types
+    }
+
+    /// Whether this is a change of the room state rather than something said in it
+    pub fn is_state_event(&self) -> bool {
+        StateChange::types().contains(&self.mtype.as_str())
     }
 
     /// Helper function to use in iterator filter of a matrix.org json response to filter supported
@@ -115,7 +144,7 @@ impl Message {
     pub fn supported_event(ev: &&JsonValue) -> bool {
         let type_ = ev["type"].as_str().unwrap_or_default();
 
-        for t in Message::types().iter() {
+        for t in Message::types().iter().chain(StateChange::types().iter()) {
             if t == &type_ {
                 return true;
             }
@@ -160,7 +189,10 @@ impl Message {
             receipt: HashMap::new(),
             redacted,
             in_reply_to: None,
+            reply_quote: None,
             replace: None,
+            annotates: None,
+            state_change: None,
             extra_content: None,
         };
 
@@ -168,7 +200,8 @@ impl Message {
         match type_ {
             "m.room.message" => message.parse_m_room_message(c),
             "m.sticker" => message.parse_m_sticker(c),
-            _ => {}
+            "m.reaction" => message.parse_m_reaction(c),
+            _ => message.state_change = StateChange::parse(msg),
         };
 
         Ok(message)
@@ -194,11 +227,8 @@ impl Message {
 
         match mtype.as_str() {
             "m.image" | "m.file" | "m.video" | "m.audio" => {
-                self.url = c["url"].as_str().map(Url::parse).and_then(Result::ok);
-                self.thumb = c["info"]["thumbnail_url"]
-                    .as_str()
-                    .map(Url::parse)
-                    .and_then(Result::ok)
+                self.url = media_url(&c["url"], &c["file"]);
+                self.thumb = media_url(&c["info"]["thumbnail_url"], &c["info"]["thumbnail_file"])
                     .or_else(|| Some(self.url.clone()?));
             }
             "m.text" => {
@@ -212,21 +242,84 @@ impl Message {
         };
 
         self.mtype = mtype;
-        self.body = body;
-        self.formatted_body = formatted_body;
+        if self.in_reply_to.is_some() {
+            let (quote, body) = split_reply_fallback(&body);
+            self.reply_quote = quote.as_deref().and_then(parse_reply_quote);
+            self.body = body;
+            self.formatted_body = formatted_body.as_deref().map(strip_reply_fallback_html);
+        } else {
+            self.body = body;
+            self.formatted_body = formatted_body;
+        }
         self.format = format;
     }
 
+    /// Builds the plain text and HTML bodies of this message as a reply to `original`, with
+    /// the quote of the original for the clients that don't support rich replies
+    ///
+    /// https://matrix.org/docs/spec/client_server/r0.6.1#fallbacks-for-rich-replies
+    pub fn reply_fallbacks(&self, original: &Message) -> (String, String) {
+        let sender = original.sender.to_string();
+        let mut lines = original.body.lines();
+        let first = lines.next().unwrap_or_default();
+        let mut body = if original.mtype == "m.emote" {
+            format!("> * <{}> {}", sender, first)
+        } else {
+            format!("> <{}> {}", sender, first)
+        };
+        for line in lines {
+            body.push_str("\n> ");
+            body.push_str(line);
+        }
+        body.push_str("\n\n");
+        body.push_str(&self.body);
+
+        let original_html = match (original.format.as_deref(), &original.formatted_body) {
+            (Some("org.matrix.custom.html"), Some(html)) => html.clone(),
+            _ => html_escape(&original.body).replace('\n', "<br>"),
+        };
+        let html = match (self.format.as_deref(), &self.formatted_body) {
+            (Some("org.matrix.custom.html"), Some(html)) => html.clone(),
+            _ => html_escape(&self.body).replace('\n', "<br>"),
+        };
+        let formatted_body = format!(
+            "<mx-reply><blockquote>\
+             <a href=\"https://matrix.to/#/{room}/{event}\">In reply to</a> \
+             <a href=\"https://matrix.to/#/{sender}\">{sender}</a><br>{original}\
+             </blockquote></mx-reply>{html}",
+            room = original.room,
+            event = original
+                .id
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            sender = sender,
+            original = original_html,
+            html = html,
+        );
+
+        (body, formatted_body)
+    }
+
     fn parse_m_sticker(&mut self, c: &JsonValue) {
-        self.url = c["url"].as_str().map(Url::parse).and_then(Result::ok);
-        self.thumb = c["info"]["thumbnail_url"]
-            .as_str()
-            .map(Url::parse)
-            .and_then(Result::ok)
+        self.url = media_url(&c["url"], &c["file"]);
+        self.thumb = media_url(&c["info"]["thumbnail_url"], &c["info"]["thumbnail_file"])
             .or_else(|| Some(self.url.clone()?));
         self.body = c["body"].as_str().map(String::from).unwrap_or_default();
     }
 
+    fn parse_m_reaction(&mut self, c: &JsonValue) {
+        if c["m.relates_to"]["rel_type"].as_str() == Some("m.annotation") {
+            self.annotates = c["m.relates_to"]["event_id"]
+                .as_str()
+                .and_then(|evid| evid.try_into().ok());
+            self.body = c["m.relates_to"]["key"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or_default();
+        }
+    }
+
     /// Create a vec of Message from a json event list
     ///
     /// * `roomid` - The messages room id
@@ -248,3 +341,114 @@ impl Message {
         self.receipt = receipt;
     }
 }
+
+/// The URL of a file, from its `file` if it's encrypted, see `EncryptedFile::media_url`
+fn media_url(url: &JsonValue, file: &JsonValue) -> Option<Url> {
+    match EncryptedFile::from_json(file) {
+        Ok(file) => Some(file.media_url()),
+        Err(_) => url.as_str().map(Url::parse).and_then(Result::ok),
+    }
+}
+
+/// Splits the quote clients add at the beginning of a reply from the text of the reply,
+/// the quote is every line starting with "> " up to the first empty line
+fn split_reply_fallback(body: &str) -> (Option<String>, String) {
+    let mut quote = vec![];
+    let mut lines = body.lines();
+    loop {
+        match lines.next() {
+            Some(line) if line.starts_with("> ") => quote.push(&line[2..]),
+            Some(">") => quote.push(""),
+            Some("") if !quote.is_empty() => break,
+            _ => return (None, body.to_string()),
+        }
+    }
+
+    (Some(quote.join("\n")), lines.collect::<Vec<_>>().join("\n"))
+}
+
+/// Gets the sender and the text from a quote like "<@alice:example.org> hello"
+fn parse_reply_quote(quote: &str) -> Option<(UserId, String)> {
+    let quote = if quote.starts_with("* ") {
+        &quote[2..]
+    } else {
+        quote
+    };
+    if !quote.starts_with('<') {
+        return None;
+    }
+    let end = quote.find('>')?;
+    let sender = UserId::try_from(&quote[1..end]).ok()?;
+
+    Some((sender, quote[end + 1..].trim().to_string()))
+}
+
+fn strip_reply_fallback_html(html: &str) -> String {
+    const END_TAG: &str = "</mx-reply>";
+    match (html.find("<mx-reply>"), html.find(END_TAG)) {
+        (Some(start), Some(end)) if start < end => {
+            format!("{}{}", &html[..start], &html[end + END_TAG.len()..])
+        }
+        _ => html.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_fallback_is_stripped() {
+        let (quote, body) =
+            split_reply_fallback("> <@alice:example.org> hello\n> there\n\nhi Alice\nbye");
+        assert_eq!(body, "hi Alice\nbye");
+        assert_eq!(
+            quote.as_deref().and_then(parse_reply_quote),
+            Some((
+                UserId::try_from("@alice:example.org").unwrap(),
+                String::from("hello\nthere")
+            ))
+        );
+
+        let (quote, body) = split_reply_fallback("> just a quote\nand some text");
+        assert_eq!(quote, None);
+        assert_eq!(body, "> just a quote\nand some text");
+
+        assert_eq!(
+            strip_reply_fallback_html(
+                "<mx-reply><blockquote><a href=\"#\">In reply to</a></blockquote></mx-reply><b>hi</b>"
+            ),
+            "<b>hi</b>"
+        );
+    }
+
+    #[test]
+    fn encrypted_media_url() {
+        let file = serde_json::json!({
+            "url": "mxc://example.org/abcdef",
+            "key": {
+                "kty": "oct",
+                "key_ops": ["encrypt", "decrypt"],
+                "alg": "A256CTR",
+                "k": "YD3rEBXKcb4rc67whX13gR81LAc7YQjXLZgQowkU3_Q",
+                "ext": true,
+            },
+            "iv": "8PHy8/T19vf4+fr7/P3+/w",
+            "hashes": {
+                "sha256": "tkEAvV+cSsTWQNMQu3a21Y87/8yAiyTOaF9Bj4Es+VQ",
+            },
+            "v": "v2",
+        });
+        let url = media_url(&JsonValue::Null, &file).unwrap();
+        assert_eq!(
+            EncryptedFile::from_media_url(&url).map(|file| file.to_json()),
+            Some(file)
+        );
+
+        let url = serde_json::json!("mxc://example.org/ghijkl");
+        assert_eq!(
+            media_url(&url, &JsonValue::Null).as_ref().map(Url::as_str),
+            Some("mxc://example.org/ghijkl")
+        );
+    }
+}
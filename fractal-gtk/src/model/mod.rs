@@ -1,5 +1,8 @@
+pub mod command;
 pub mod event;
 pub mod fileinfo;
 pub mod member;
 pub mod message;
+pub mod push_rules;
 pub mod room;
+pub mod state_change;
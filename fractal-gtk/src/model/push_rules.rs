@@ -0,0 +1,399 @@
+use fractal_api::events::room::PowerLevelsEventContent;
+use fractal_api::identifiers::{RoomId, UserId};
+use fractal_api::r0::pushrules::{Action, PushCondition, PushRule, RuleKind, Ruleset};
+use regex::RegexBuilder;
+use serde_json::{json, Value as JsonValue};
+use std::convert::TryFrom;
+
+/// What the push rules decided to do with an event
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Notification {
+    pub notify: bool,
+    pub highlight: bool,
+    pub sound: bool,
+}
+
+/// What the conditions of the push rules need to know about the room of an event
+#[derive(Debug, Clone, Default)]
+pub struct PushContext<'a> {
+    pub display_name: Option<&'a str>,
+    pub member_count: usize,
+    pub power_levels: Option<&'a PowerLevelsEventContent>,
+}
+
+/// How much a room notifies us, each level is backed by a push rule whose ID is the one
+/// of the room
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoomNotifications {
+    All,
+    /// A room rule that doesn't notify, mentions are caught by override and content rules
+    MentionsOnly,
+    /// An override rule that doesn't notify, it comes before every mention rule
+    Mute,
+}
+
+impl RoomNotifications {
+    pub fn of_room(ruleset: &Ruleset, room_id: &RoomId) -> Self {
+        let room_id = room_id.to_string();
+        let silences = |rule: Option<&PushRule>| {
+            rule.map_or(false, |rule| {
+                rule.enabled && rule.actions.contains(&Action::DontNotify)
+            })
+        };
+
+        if silences(ruleset.find(RuleKind::Override, &room_id)) {
+            RoomNotifications::Mute
+        } else if silences(ruleset.find(RuleKind::Room, &room_id)) {
+            RoomNotifications::MentionsOnly
+        } else {
+            RoomNotifications::All
+        }
+    }
+
+    /// The rule this level needs, `All` doesn't need any
+    pub fn rule(self, room_id: &RoomId) -> Option<(RuleKind, PushRule)> {
+        let kind = match self {
+            RoomNotifications::All => return None,
+            RoomNotifications::MentionsOnly => RuleKind::Room,
+            RoomNotifications::Mute => RuleKind::Override,
+        };
+        let conditions = match kind {
+            RuleKind::Override => Some(vec![PushCondition::EventMatch {
+                key: String::from("room_id"),
+                pattern: room_id.to_string(),
+            }]),
+            _ => None,
+        };
+        let rule = PushRule {
+            rule_id: room_id.to_string(),
+            default: false,
+            enabled: true,
+            actions: vec![Action::DontNotify],
+            conditions,
+            pattern: None,
+        };
+
+        Some((kind, rule))
+    }
+}
+
+/// The content rules the user added, to be notified of messages with these words in them
+pub fn highlight_keywords(ruleset: &Ruleset) -> impl Iterator<Item = &PushRule> {
+    ruleset
+        .content
+        .iter()
+        .filter(|rule| !rule.default && rule.pattern.is_some())
+}
+
+/// Keywords notify and highlight like mentions do
+pub fn keyword_rule(keyword: &str) -> PushRule {
+    PushRule {
+        rule_id: keyword.to_string(),
+        default: false,
+        enabled: true,
+        actions: vec![
+            Action::Notify,
+            Action::SetTweak {
+                set_tweak: String::from("sound"),
+                value: Some(json!("default")),
+            },
+            Action::SetTweak {
+                set_tweak: String::from("highlight"),
+                value: None,
+            },
+        ],
+        conditions: None,
+        pattern: Some(keyword.to_string()),
+    }
+}
+
+/// Finds the first enabled rule matching the event and applies its actions, events matching
+/// no rule don't notify
+pub fn evaluate(ruleset: &Ruleset, event: &JsonValue, ctx: &PushContext) -> Notification {
+    RuleKind::ALL
+        .iter()
+        .flat_map(|kind| ruleset.rules(*kind).iter().map(move |rule| (*kind, rule)))
+        .find(|(kind, rule)| rule.enabled && rule_matches(*kind, rule, event, ctx))
+        .map(|(_, rule)| apply_actions(&rule.actions))
+        .unwrap_or_default()
+}
+
+fn apply_actions(actions: &[Action]) -> Notification {
+    let mut notification = Notification::default();
+    for action in actions {
+        match action {
+            Action::Notify => notification.notify = true,
+            Action::DontNotify => notification.notify = false,
+            Action::Coalesce => notification.notify = true,
+            Action::SetTweak { set_tweak, value } if set_tweak == "highlight" => {
+                notification.highlight = value.as_ref().map_or(true, |v| v == true);
+            }
+            Action::SetTweak { set_tweak, value } if set_tweak == "sound" => {
+                notification.sound = value.is_some();
+            }
+            _ => {}
+        }
+    }
+    notification
+}
+
+fn rule_matches(kind: RuleKind, rule: &PushRule, event: &JsonValue, ctx: &PushContext) -> bool {
+    match kind {
+        RuleKind::Override | RuleKind::Underride => rule
+            .conditions
+            .iter()
+            .flatten()
+            .all(|condition| condition_matches(condition, event, ctx)),
+        RuleKind::Content => rule
+            .pattern
+            .as_ref()
+            .map_or(false, |pattern| event_match(event, "content.body", pattern)),
+        RuleKind::Room => event["room_id"].as_str() == Some(&rule.rule_id),
+        RuleKind::Sender => event["sender"].as_str() == Some(&rule.rule_id),
+    }
+}
+
+fn condition_matches(condition: &PushCondition, event: &JsonValue, ctx: &PushContext) -> bool {
+    match condition {
+        PushCondition::EventMatch { key, pattern } => event_match(event, key, pattern),
+        PushCondition::ContainsDisplayName => {
+            let body = event["content"]["body"].as_str();
+            match (body, ctx.display_name) {
+                (Some(body), Some(name)) if !name.is_empty() => {
+                    matches_glob(body, &regex::escape(name), true)
+                }
+                _ => false,
+            }
+        }
+        PushCondition::RoomMemberCount { is } => member_count_matches(is, ctx.member_count),
+        PushCondition::SenderNotificationPermission { key } => {
+            let levels = match ctx.power_levels {
+                Some(levels) => levels,
+                None => return false,
+            };
+            let sender_level = event["sender"]
+                .as_str()
+                .and_then(|sender| UserId::try_from(sender).ok())
+                .and_then(|sender| levels.users.get(&sender).copied())
+                .unwrap_or(levels.users_default);
+            let required = levels.notifications.get(key).copied().unwrap_or(50);
+            sender_level >= required
+        }
+        PushCondition::Unknown => false,
+    }
+}
+
+/// `key` is a dot-separated path to a string field of the event, the body of messages is
+/// matched by words and other fields as a whole
+fn event_match(event: &JsonValue, key: &str, pattern: &str) -> bool {
+    let value = key
+        .split('.')
+        .try_fold(event, |value, field| value.get(field))
+        .and_then(JsonValue::as_str);
+
+    match value {
+        Some(value) => matches_glob(value, &glob_to_regex(pattern), key == "content.body"),
+        None => false,
+    }
+}
+
+/* Globs only know about `*` and `?` */
+fn glob_to_regex(glob: &str) -> String {
+    glob.chars()
+        .map(|c| match c {
+            '*' => String::from(".*"),
+            '?' => String::from("."),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect()
+}
+
+fn matches_glob(value: &str, pattern: &str, words: bool) -> bool {
+    let pattern = if words {
+        format!(r"(^|\W)({})(\W|$)", pattern)
+    } else {
+        format!("^({})$", pattern)
+    };
+
+    RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .dot_matches_new_line(true)
+        .build()
+        .map_or(false, |re| re.is_match(value))
+}
+
+fn member_count_matches(is: &str, count: usize) -> bool {
+    let number_start = is.find(|c: char| c.is_ascii_digit()).unwrap_or(is.len());
+    let (op, number) = is.split_at(number_start);
+    let number: usize = match number.parse() {
+        Ok(number) => number,
+        Err(_) => return false,
+    };
+
+    match op {
+        "" | "==" => count == number,
+        "<" => count < number,
+        ">" => count > number,
+        "<=" => count <= number,
+        ">=" => count >= number,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ruleset() -> Ruleset {
+        serde_json::from_value(json!({
+            "override": [{
+                "rule_id": ".m.rule.suppress_notices",
+                "default": true,
+                "enabled": true,
+                "conditions": [
+                    {"kind": "event_match", "key": "content.msgtype", "pattern": "m.notice"}
+                ],
+                "actions": ["dont_notify"]
+            }, {
+                "rule_id": ".m.rule.contains_display_name",
+                "default": true,
+                "enabled": true,
+                "conditions": [{"kind": "contains_display_name"}],
+                "actions": ["notify", {"set_tweak": "highlight"}]
+            }],
+            "content": [{
+                "rule_id": ".m.rule.contains_user_name",
+                "default": true,
+                "enabled": true,
+                "pattern": "alice",
+                "actions": ["notify", {"set_tweak": "highlight"}]
+            }],
+            "room": [{
+                "rule_id": "!quiet:example.org",
+                "default": false,
+                "enabled": true,
+                "actions": ["dont_notify"]
+            }],
+            "underride": [{
+                "rule_id": ".m.rule.room_one_to_one",
+                "default": true,
+                "enabled": true,
+                "conditions": [
+                    {"kind": "room_member_count", "is": "2"},
+                    {"kind": "event_match", "key": "type", "pattern": "m.room.message"}
+                ],
+                "actions": ["notify", {"set_tweak": "highlight", "value": false}]
+            }, {
+                "rule_id": ".m.rule.unknown",
+                "default": true,
+                "enabled": true,
+                "conditions": [{"kind": "org.example.future"}],
+                "actions": ["notify"]
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn message(room: &str, body: &str) -> JsonValue {
+        json!({
+            "type": "m.room.message",
+            "room_id": room,
+            "sender": "@bob:example.org",
+            "content": {"msgtype": "m.text", "body": body}
+        })
+    }
+
+    #[test]
+    fn rules_by_kind() {
+        let ruleset = ruleset();
+        let ctx = PushContext {
+            display_name: Some("Ali Baba"),
+            member_count: 2,
+            power_levels: None,
+        };
+        let mention = Notification {
+            notify: true,
+            highlight: true,
+            sound: false,
+        };
+        let direct = Notification {
+            notify: true,
+            highlight: false,
+            sound: false,
+        };
+
+        let msg = message("!dm:example.org", "hi ALI BABA!");
+        assert_eq!(evaluate(&ruleset, &msg, &ctx), mention);
+        let msg = message("!quiet:example.org", "alice?");
+        assert_eq!(evaluate(&ruleset, &msg, &ctx), mention);
+        let msg = message("!quiet:example.org", "malice");
+        assert_eq!(evaluate(&ruleset, &msg, &ctx), Notification::default());
+        let msg = message("!dm:example.org", "malice");
+        assert_eq!(evaluate(&ruleset, &msg, &ctx), direct);
+
+        let ctx = PushContext {
+            member_count: 3,
+            ..ctx
+        };
+        assert_eq!(evaluate(&ruleset, &msg, &ctx), Notification::default());
+    }
+
+    #[test]
+    fn globs() {
+        let event = json!({"type": "m.room.message", "content": {"body": "Coffee time"}});
+        assert!(event_match(&event, "type", "m.room.*"));
+        assert!(!event_match(&event, "type", "m.room"));
+        assert!(event_match(&event, "content.body", "cof?ee"));
+        assert!(!event_match(&event, "content.body", "cof"));
+        assert!(!event_match(&event, "content.missing", "*"));
+    }
+
+    #[test]
+    fn member_counts() {
+        assert!(member_count_matches("2", 2));
+        assert!(member_count_matches("==2", 2));
+        assert!(member_count_matches("<=2", 1));
+        assert!(!member_count_matches(">2", 2));
+        assert!(!member_count_matches("lots", 2));
+    }
+
+    #[test]
+    fn room_levels() {
+        let mut ruleset = ruleset();
+        let room_id = RoomId::try_from("!quiet:example.org").unwrap();
+        assert_eq!(
+            RoomNotifications::of_room(&ruleset, &room_id),
+            RoomNotifications::MentionsOnly
+        );
+
+        let (kind, rule) = RoomNotifications::Mute.rule(&room_id).unwrap();
+        ruleset.rules_mut(kind).insert(0, rule);
+        assert_eq!(
+            RoomNotifications::of_room(&ruleset, &room_id),
+            RoomNotifications::Mute
+        );
+        let msg = message("!quiet:example.org", "alice?");
+        let ctx = PushContext::default();
+        assert_eq!(evaluate(&ruleset, &msg, &ctx), Notification::default());
+    }
+
+    #[test]
+    fn keywords() {
+        let mut ruleset = ruleset();
+        ruleset.content.push(keyword_rule("coffee"));
+        let keywords: Vec<_> = highlight_keywords(&ruleset)
+            .map(|rule| rule.rule_id.as_str())
+            .collect();
+        assert_eq!(keywords, vec!["coffee"]);
+
+        // Mentions-only rooms still notify about keywords
+        let msg = message("!quiet:example.org", "Coffee anyone?");
+        let expected = Notification {
+            notify: true,
+            highlight: true,
+            sound: true,
+        };
+        assert_eq!(evaluate(&ruleset, &msg, &PushContext::default()), expected);
+    }
+}
@@ -1,10 +1,15 @@
-use serde_json::Value as JsonValue;
-
 use crate::backend::user::get_user_avatar;
 use crate::model::member::Member;
 use crate::model::member::MemberList;
 use crate::model::message::Message;
 use either::Either;
+use fractal_api::events::account_data::AccountDataEventContent;
+use fractal_api::events::ephemeral::{EphemeralEventContent, ReceiptEventContent};
+use fractal_api::events::room::{
+    CanonicalAliasEventContent, GuestAccess, HistoryVisibility, JoinRule, MembershipState,
+    PowerLevelsEventContent, PreviousRoom, RoomEventContent,
+};
+use fractal_api::events::{AccountDataEvent, RoomEvent, StateEvent, StrippedStateEvent};
 use fractal_api::identifiers::{Error as IdError, EventId, RoomId, UserId};
 use fractal_api::r0::directory::post_public_rooms::Chunk as PublicRoomsChunk;
 use fractal_api::r0::sync::sync_events::Response as SyncResponse;
@@ -13,7 +18,7 @@ use fractal_api::url::{ParseError as UrlError, Url};
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -81,6 +86,13 @@ pub enum RoomTag {
     Custom(String),
 }
 
+/// What a member can do to other members, depending on the power levels of the room
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Moderation {
+    Kick,
+    Ban,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Room {
     pub id: RoomId,
@@ -100,11 +112,36 @@ pub struct Room {
     pub prev_batch: Option<String>,
     pub typing_users: Vec<Member>,
     pub language: Option<String>,
+    #[serde(default)]
+    pub encrypted: bool,
 
     /// Hashmap with the room users power levels
     /// the key will be the userid and the value will be the level
     pub admins: HashMap<UserId, i32>,
     pub default_power_level: i32,
+    /// The content of the `m.room.power_levels` event, if we got one
+    #[serde(default)]
+    pub power_levels: Option<PowerLevelsEventContent>,
+    /// The settings of the room, None until we get their state event
+    #[serde(default)]
+    pub canonical_alias: Option<CanonicalAliasEventContent>,
+    #[serde(default)]
+    pub join_rule: Option<JoinRule>,
+    #[serde(default)]
+    pub history_visibility: Option<HistoryVisibility>,
+    #[serde(default)]
+    pub guest_access: Option<GuestAccess>,
+    #[serde(default)]
+    pub room_version: Option<String>,
+    /// The room this one continues after an upgrade
+    #[serde(default)]
+    pub predecessor: Option<PreviousRoom>,
+    /// The room that continues this one after an upgrade
+    #[serde(default)]
+    pub replacement: Option<RoomId>,
+    /// The room `prev_batch` belongs to when the history went on in the predecessor
+    #[serde(skip)]
+    pub prev_batch_room: Option<RoomId>,
 }
 
 impl Room {
@@ -127,8 +164,18 @@ impl Room {
             prev_batch: Default::default(),
             typing_users: Default::default(),
             language: Default::default(),
+            encrypted: Default::default(),
             admins: Default::default(),
             default_power_level: -1,
+            power_levels: None,
+            canonical_alias: None,
+            join_rule: None,
+            history_visibility: None,
+            guest_access: None,
+            room_version: None,
+            predecessor: None,
+            replacement: None,
+            prev_batch_room: None,
         }
     }
 
@@ -152,52 +199,67 @@ impl Room {
             let dataevs = &room.account_data.events;
             let room_tag = dataevs
                 .iter()
-                .filter(|x| x["type"] == "m.tag")
-                .find_map(|tag| tag["content"]["tags"]["m.favourite"].as_object())
+                .find_map(|ev| match &ev.content {
+                    AccountDataEventContent::Tag(c) => c.tags.get("m.favourite"),
+                    _ => None,
+                })
                 .and(Some(RoomTag::Favourite))
                 .unwrap_or(RoomTag::None);
             let room_lang = dataevs
                 .iter()
-                .filter(|x| x["type"] == "org.gnome.fractal.language")
-                .find_map(|entry| entry["content"]["input_language"].as_str())
+                .find_map(|ev| match &ev.content {
+                    AccountDataEventContent::Custom(c)
+                        if c.event_type == "org.gnome.fractal.language" =>
+                    {
+                        c.content["input_language"].as_str()
+                    }
+                    _ => None,
+                })
                 .map(|lang| lang.to_string());
 
             let mut r = Self {
                 name: calculate_room_name(stevents, &user_id),
-                avatar: evc(stevents, "m.room.avatar", "url")
-                    .and_then(|ref url| Url::parse(url).ok()),
-                alias: evc(stevents, "m.room.canonical_alias", "alias"),
-                topic: evc(stevents, "m.room.topic", "topic"),
+                avatar: get_avatar(stevents),
+                alias: get_canonical_alias(stevents),
+                topic: get_topic(stevents),
                 direct: direct.contains(&k),
                 notifications: room.unread_notifications.notification_count,
                 highlight: room.unread_notifications.highlight_count,
                 prev_batch: timeline.prev_batch.clone(),
-                messages: Message::from_json_events_iter(&k, timeline.events.iter())?,
-                admins: get_admins(stevents)?,
-                default_power_level: get_default_power_level(stevents),
+                messages: Message::from_json_events_iter(
+                    &k,
+                    timeline.events.iter().map(RoomEvent::json),
+                )?,
                 members: stevents
                     .iter()
-                    .filter(|x| x["type"] == "m.room.member")
                     .filter_map(parse_room_member)
                     .map(|m| (m.uid.clone(), m))
                     .collect(),
                 language: room_lang,
+                encrypted: is_encrypted(stevents)
+                    || timeline
+                        .events
+                        .iter()
+                        .any(|ev| is_encryption_event(&ev.content)),
                 ..Self::new(k.clone(), RoomMembership::Joined(room_tag))
             };
 
-            r.add_receipt_from_json(
-                ephemeral
-                    .events
-                    .iter()
-                    .filter(|ev| ev["type"] == "m.receipt")
-                    .collect(),
-            );
+            // The timeline is more recent than the state
+            let state = stevents.iter().map(|ev| &ev.content);
+            for content in state.chain(timeline.events.iter().map(|ev| &ev.content)) {
+                r.apply_state(content);
+            }
+
+            for ev in ephemeral.events.iter() {
+                if let EphemeralEventContent::Receipt(receipts) = &ev.content {
+                    r.add_receipts(receipts);
+                }
+            }
             // Adding fully read to the receipts events
-            if let Some(ev) = dataevs
-                .iter()
-                .find(|x| x["type"] == "m.fully_read")
-                .and_then(|fread| fread["content"]["event_id"].as_str()?.try_into().ok())
-            {
+            if let Some(ev) = dataevs.iter().find_map(|ev| match &ev.content {
+                AccountDataEventContent::FullyRead(c) => Some(c.event_id.clone()),
+                _ => None,
+            }) {
                 r.add_receipt_from_fully_read(user_id.clone(), ev);
             }
 
@@ -206,9 +268,12 @@ impl Room {
 
         let left_rooms = response.rooms.leave.iter().map(|(k, room)| {
             let r = if let Some(last_event) = room.timeline.events.last() {
-                let leave_id = UserId::try_from(last_event["sender"].as_str().unwrap_or_default())?;
+                let leave_id = last_event.sender.clone();
                 if leave_id != user_id {
-                    let kick_reason = &last_event["content"]["reason"];
+                    let kick_reason = match &last_event.content {
+                        RoomEventContent::Member(c) => c.reason.clone(),
+                        _ => None,
+                    };
                     if let Ok((kicker_alias, kicker_avatar)) =
                         get_user_avatar(baseu.clone(), access_token.clone(), &leave_id)
                     {
@@ -217,10 +282,7 @@ impl Room {
                             avatar: Some(Either::Right(kicker_avatar)),
                             uid: leave_id,
                         };
-                        let reason = Reason::Kicked(
-                            String::from(kick_reason.as_str().unwrap_or_default()),
-                            kicker,
-                        );
+                        let reason = Reason::Kicked(kick_reason.unwrap_or_default(), kicker);
                         Self::new(k.clone(), RoomMembership::Left(reason))
                     } else {
                         Self::new(k.clone(), RoomMembership::Left(Reason::None))
@@ -241,21 +303,19 @@ impl Room {
             .iter()
             .map(|(k, room)| {
                 let stevents = &room.invite_state.events;
-                let alias_avatar: Result<Option<(String, PathBuf)>, IdError> = stevents
+                let alias_avatar: Option<(String, PathBuf)> = stevents
                     .iter()
-                    .find(|x| {
-                        x["content"]["membership"] == "invite"
-                            && x["state_key"] == user_id.to_string().as_str()
+                    .find(|ev| match &ev.content {
+                        RoomEventContent::Member(c) => {
+                            c.membership == MembershipState::Invite
+                                && ev.state_key == user_id.to_string()
+                        }
+                        _ => false,
                     })
-                    .map_or(Ok(None), |ev| {
-                        Ok(get_user_avatar(
-                            baseu.clone(),
-                            access_token.clone(),
-                            &UserId::try_from(ev["sender"].as_str().unwrap_or_default())?,
-                        )
-                        .ok())
+                    .and_then(|ev| {
+                        get_user_avatar(baseu.clone(), access_token.clone(), &ev.sender).ok()
                     });
-                if let Some((alias, avatar)) = alias_avatar? {
+                if let Some((alias, avatar)) = alias_avatar {
                     let inv_sender = Member {
                         alias: Some(alias),
                         avatar: Some(Either::Right(avatar)),
@@ -264,10 +324,9 @@ impl Room {
 
                     Ok(Some(Self {
                         name: calculate_room_name(stevents, &user_id),
-                        avatar: evc(stevents, "m.room.avatar", "url")
-                            .and_then(|ref url| Url::parse(url).ok()),
-                        alias: evc(stevents, "m.room.canonical_alias", "alias"),
-                        topic: evc(stevents, "m.room.topic", "topic"),
+                        avatar: get_avatar(stevents),
+                        alias: get_canonical_alias(stevents),
+                        topic: get_topic(stevents),
                         direct: direct.contains(&k),
                         ..Self::new(k.clone(), RoomMembership::Invited(inv_sender))
                     }))
@@ -283,35 +342,22 @@ impl Room {
             .collect()
     }
 
-    pub fn add_receipt_from_json(&mut self, mut events: Vec<&JsonValue>) {
-        let receipts: HashMap<EventId, HashMap<UserId, i64>> = events
-            .pop()
-            .and_then(|ev| ev["content"].as_object())
-            .into_iter()
-            .flatten()
-            .filter_map(|(mid, obj)| {
-                let event_id = mid.as_str().try_into().ok()?;
-                let receipts = obj["m.read"]
-                    .as_object()?
+    pub fn add_receipts(&mut self, receipts: &ReceiptEventContent) {
+        for msg in self.messages.iter_mut() {
+            if let Some(r) = msg.id.as_ref().and_then(|evid| receipts.get(evid)) {
+                let receipt = r
+                    .read
                     .iter()
-                    .map(|(uid, ts)| {
-                        debug!("Value of timestamp 'ts': {}", ts);
-                        let ts = ts["ts"].as_i64().unwrap_or(0);
+                    .map(|(uid, receipt)| {
+                        debug!("Value of timestamp 'ts': {:?}", receipt.ts);
+                        let ts = receipt.ts.unwrap_or(0);
                         if ts == 0 {
                             info!("Possibly malformed timestamp, working around synapse bug 4898");
                         };
-                        Ok((UserId::try_from(uid.as_str())?, ts))
+                        (uid.clone(), ts)
                     })
-                    .collect::<Result<HashMap<UserId, i64>, IdError>>()
-                    .ok()?;
-
-                Some((event_id, receipts))
-            })
-            .collect();
-
-        for msg in self.messages.iter_mut() {
-            if let Some(r) = msg.id.as_ref().and_then(|evid| receipts.get(evid)) {
-                msg.set_receipt(r.clone());
+                    .collect();
+                msg.set_receipt(receipt);
             }
         }
     }
@@ -325,6 +371,112 @@ impl Room {
             .filter(|msg| msg.id == event_id)
             .map(|msg| msg.receipt.insert(uid.clone(), 0));
     }
+
+    /// Keeps the settings of the room up to date with a state event, the events must be
+    /// applied from the oldest one
+    pub fn apply_state(&mut self, content: &RoomEventContent) {
+        match content {
+            RoomEventContent::PowerLevels(c) => self.set_power_levels(c.clone()),
+            RoomEventContent::CanonicalAlias(c) => self.set_canonical_alias(c.clone()),
+            RoomEventContent::JoinRules(c) => self.join_rule = Some(c.join_rule.clone()),
+            RoomEventContent::HistoryVisibility(c) => {
+                self.set_history_visibility(c.history_visibility.clone())
+            }
+            RoomEventContent::GuestAccess(c) => self.set_guest_access(c.guest_access.clone()),
+            RoomEventContent::Create(c) => {
+                // Rooms created without a version are in version 1
+                self.room_version = Some(c.room_version.clone().unwrap_or_else(|| "1".into()));
+                self.predecessor = c.predecessor.clone();
+            }
+            RoomEventContent::Tombstone(c) => self.replacement = Some(c.replacement_room.clone()),
+            _ => {}
+        }
+    }
+
+    /// Takes the settings a sync brought for the room, the ones it has no event for are kept
+    pub fn update_state(&mut self, room: &Room) {
+        if let Some(levels) = room.power_levels.clone() {
+            self.set_power_levels(levels);
+        }
+        if let Some(aliases) = room.canonical_alias.clone() {
+            self.set_canonical_alias(aliases);
+        }
+        if let Some(join_rule) = room.join_rule.clone() {
+            self.join_rule = Some(join_rule);
+        }
+        if let Some(visibility) = room.history_visibility.clone() {
+            self.set_history_visibility(visibility);
+        }
+        if let Some(guest_access) = room.guest_access.clone() {
+            self.set_guest_access(guest_access);
+        }
+        if let Some(version) = room.room_version.clone() {
+            self.room_version = Some(version);
+            self.predecessor = room.predecessor.clone();
+        }
+        if let Some(replacement) = room.replacement.clone() {
+            self.replacement = Some(replacement);
+        }
+    }
+
+    pub fn set_canonical_alias(&mut self, aliases: CanonicalAliasEventContent) {
+        self.alias = aliases.alias.clone();
+        self.canonical_alias = Some(aliases);
+    }
+
+    pub fn set_history_visibility(&mut self, visibility: HistoryVisibility) {
+        self.world_readable = visibility == HistoryVisibility::WorldReadable;
+        self.history_visibility = Some(visibility);
+    }
+
+    pub fn set_guest_access(&mut self, guest_access: GuestAccess) {
+        self.guest_can_join = guest_access == GuestAccess::CanJoin;
+        self.guest_access = Some(guest_access);
+    }
+
+    pub fn set_power_levels(&mut self, levels: PowerLevelsEventContent) {
+        self.admins = levels
+            .users
+            .iter()
+            .map(|(uid, level)| (uid.clone(), *level as i32))
+            .collect();
+        self.default_power_level = levels.users_default as i32;
+        self.power_levels = Some(levels);
+    }
+
+    pub fn power_level(&self, uid: &UserId) -> i64 {
+        self.power_levels.as_ref().map_or(0, |pl| {
+            pl.users.get(uid).copied().unwrap_or(pl.users_default)
+        })
+    }
+
+    /// Whether the user can send state events of this type. When we don't know the power
+    /// levels of the room we let the server decide.
+    pub fn can_send_state(&self, uid: &UserId, event_type: &str) -> bool {
+        self.power_levels.as_ref().map_or(true, |pl| {
+            let required = pl
+                .events
+                .get(event_type)
+                .copied()
+                .unwrap_or(pl.state_default);
+            self.power_level(uid) >= required
+        })
+    }
+
+    /// Whether the user can do this to `target`, who must have a lower power level
+    pub fn can_moderate(&self, uid: &UserId, target: &UserId, action: Moderation) -> bool {
+        let pl = match self.power_levels.as_ref() {
+            Some(pl) => pl,
+            None => return true,
+        };
+        let level = self.power_level(uid);
+        let required = match action {
+            Moderation::Kick => pl.kick,
+            Moderation::Ban => pl.ban,
+        };
+
+        uid != target && level > self.power_level(target) && level >= required
+    }
 }
 
 impl TryFrom<PublicRoomsChunk> for Room {
@@ -356,121 +508,183 @@ impl PartialEq for Room {
 
 pub type RoomList = HashMap<RoomId, Room>;
 
-fn evc(events: &[JsonValue], t: &str, field: &str) -> Option<String> {
-    events
-        .iter()
-        .find(|x| x["type"] == t)
-        .and_then(|js| js["content"][field].as_str())
-        .map(Into::into)
+/// Common fields of the full and the stripped state events
+trait RoomState {
+    fn sender(&self) -> &UserId;
+    fn state_key(&self) -> &str;
+    fn content(&self) -> &RoomEventContent;
 }
 
-fn get_admins(stevents: &[JsonValue]) -> Result<HashMap<UserId, i32>, IdError> {
-    stevents
+impl RoomState for StateEvent {
+    fn sender(&self) -> &UserId {
+        &self.sender
+    }
+
+    fn state_key(&self) -> &str {
+        &self.state_key
+    }
+
+    fn content(&self) -> &RoomEventContent {
+        &self.content
+    }
+}
+
+impl RoomState for StrippedStateEvent {
+    fn sender(&self) -> &UserId {
+        &self.sender
+    }
+
+    fn state_key(&self) -> &str {
+        &self.state_key
+    }
+
+    fn content(&self) -> &RoomEventContent {
+        &self.content
+    }
+}
+
+fn get_avatar<T: RoomState>(events: &[T]) -> Option<Url> {
+    events
         .iter()
-        .filter(|x| x["type"] == "m.room.power_levels")
-        .filter_map(|ev| ev["content"]["users"].as_object())
-        .flatten()
-        .map(|(k, v)| {
-            Ok((
-                UserId::try_from(k.as_str())?,
-                v.as_i64().map(|v| v as i32).unwrap_or_default(),
-            ))
+        .find_map(|ev| match ev.content() {
+            RoomEventContent::Avatar(c) => c.url.as_ref(),
+            _ => None,
         })
-        .collect()
+        .and_then(|url| Url::parse(url).ok())
 }
 
-fn get_default_power_level(stevents: &[JsonValue]) -> i32 {
-    stevents
-        .iter()
-        .filter(|x| x["type"] == "m.room.power_levels")
-        .filter_map(|ev| ev["content"]["users_default"].as_i64())
-        .last()
-        .unwrap_or(-1) as i32
+fn get_canonical_alias<T: RoomState>(events: &[T]) -> Option<String> {
+    events.iter().find_map(|ev| match ev.content() {
+        RoomEventContent::CanonicalAlias(c) => c.alias.clone(),
+        _ => None,
+    })
 }
 
-fn calculate_room_name(events: &[JsonValue], user_id: &UserId) -> Option<String> {
+fn get_topic<T: RoomState>(events: &[T]) -> Option<String> {
+    events.iter().find_map(|ev| match ev.content() {
+        RoomEventContent::Topic(c) => Some(c.topic.clone()),
+        _ => None,
+    })
+}
+
+fn is_encryption_event(content: &RoomEventContent) -> bool {
+    match content {
+        RoomEventContent::Encryption(_) => true,
+        _ => false,
+    }
+}
+
+/// Encryption can't be disabled once it's enabled in a room
+fn is_encrypted<T: RoomState>(events: &[T]) -> bool {
+    events.iter().any(|ev| is_encryption_event(ev.content()))
+}
+
+fn calculate_room_name<T: RoomState>(events: &[T], user_id: &UserId) -> Option<String> {
     let userid = user_id.to_string();
     // looking for "m.room.name" event
     if let Some(name) = events
         .iter()
-        .find(|x| x["type"] == "m.room.name")
-        .and_then(|name| name["content"]["name"].as_str())
+        .find_map(|ev| match ev.content() {
+            RoomEventContent::Name(c) => Some(&c.name),
+            _ => None,
+        })
         .filter(|name| !name.is_empty())
-        .map(Into::into)
+        .cloned()
     {
         return Some(name);
     }
 
     // looking for "m.room.canonical_alias" event
-    if let Some(name) = events
-        .iter()
-        .find(|x| x["type"] == "m.room.canonical_alias")
-        .and_then(|name| name["content"]["alias"].as_str())
-        .map(Into::into)
-    {
+    if let Some(name) = get_canonical_alias(events) {
         return Some(name);
     }
 
     // we look for members that aren't me
-    let members: Vec<&str> = events
+    let members: Vec<String> = events
         .iter()
-        .filter(|x| {
-            x["type"] == "m.room.member"
-                && ((x["content"]["membership"] == "join" && x["sender"] != userid.as_str())
-                    || (x["content"]["membership"] == "invite"
-                        && x["state_key"] != userid.as_str()))
+        .filter_map(|ev| match ev.content() {
+            RoomEventContent::Member(c)
+                if (c.membership == MembershipState::Join && *ev.sender() != *user_id)
+                    || (c.membership == MembershipState::Invite
+                        && ev.state_key() != userid.as_str()) =>
+            {
+                Some(
+                    c.displayname
+                        .clone()
+                        .unwrap_or_else(|| ev.sender().to_string()),
+                )
+            }
+            _ => None,
         })
         .take(3)
-        .map(|m| {
-            let sender = m["sender"].as_str().unwrap_or("NONAMED");
-            m["content"]["displayname"].as_str().unwrap_or(sender)
-        })
         .collect();
 
     match members.len() {
         // we don't have information to calculate the name
         0 => None,
-        1 => Some(members[0].to_string()),
+        1 => Some(members[0].clone()),
         2 => Some(format!("{} and {}", members[0], members[1])),
         _ => Some(format!("{} and Others", members[0])),
     }
 }
 
-fn parse_room_member(msg: &JsonValue) -> Option<Member> {
-    let c = &msg["content"];
-    let _ = c["membership"].as_str().filter(|&m| m == "join")?;
+fn parse_room_member(ev: &StateEvent) -> Option<Member> {
+    let c = match &ev.content {
+        RoomEventContent::Member(c) if c.membership == MembershipState::Join => c,
+        _ => return None,
+    };
 
     Some(Member {
-        uid: msg["sender"].as_str().unwrap_or_default().try_into().ok()?,
-        alias: c["displayname"].as_str().map(String::from),
-        avatar: c["avatar_url"]
-            .as_str()
-            .map(Url::parse)
+        uid: ev.sender.clone(),
+        alias: c.displayname.clone(),
+        avatar: c
+            .avatar_url
+            .as_ref()
+            .map(|url| Url::parse(url))
             .and_then(Result::ok)
             .map(Either::Left),
     })
 }
 
-fn parse_m_direct(events: &[JsonValue]) -> HashMap<UserId, Vec<RoomId>> {
+fn parse_m_direct(events: &[AccountDataEvent]) -> HashMap<UserId, Vec<RoomId>> {
     events
         .iter()
-        .find(|x| x["type"] == "m.direct")
-        .and_then(|js| js["content"].as_object())
-        .cloned()
-        .unwrap_or_default()
-        .iter()
-        // Synapse sometimes sends an object with the key "[object Object]"
-        // instead of a user ID, so we have to skip those invalid objects
-        // in the array in order to avoid discarding everything
-        .filter_map(|(uid, rid)| {
-            let value = rid
-                .as_array()
-                .unwrap_or(&vec![])
-                .iter()
-                .map(|rid| RoomId::try_from(rid.as_str().unwrap_or_default()))
-                .collect::<Result<Vec<RoomId>, IdError>>()
-                .ok()?;
-            Some((UserId::try_from(uid.as_str()).ok()?, value))
+        .find_map(|ev| match &ev.content {
+            AccountDataEventContent::Direct(c) => Some(c.0.clone()),
+            _ => None,
         })
-        .collect()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_levels_permissions() {
+        let room_id = RoomId::try_from("!room:example.org").unwrap();
+        let admin = UserId::try_from("@admin:example.org").unwrap();
+        let moderator = UserId::try_from("@moderator:example.org").unwrap();
+        let member = UserId::try_from("@member:example.org").unwrap();
+        let mut room = Room::new(room_id, RoomMembership::Joined(RoomTag::None));
+
+        // Without power levels the server decides
+        assert!(room.can_moderate(&member, &admin, Moderation::Ban));
+
+        let mut levels = PowerLevelsEventContent::default();
+        levels.users.insert(admin.clone(), 100);
+        levels.users.insert(moderator.clone(), 50);
+        levels.ban = 75;
+        levels.events.insert(String::from("m.room.name"), 0);
+        room.set_power_levels(levels);
+
+        assert_eq!(room.admins.get(&moderator), Some(&50));
+        assert!(room.can_moderate(&moderator, &member, Moderation::Kick));
+        assert!(!room.can_moderate(&moderator, &member, Moderation::Ban));
+        assert!(!room.can_moderate(&moderator, &admin, Moderation::Kick));
+        assert!(!room.can_moderate(&admin, &admin, Moderation::Kick));
+        assert!(room.can_send_state(&member, "m.room.name"));
+        assert!(!room.can_send_state(&member, "m.room.topic"));
+        assert!(room.can_send_state(&moderator, "m.room.topic"));
+    }
 }
@@ -0,0 +1,220 @@
+use fractal_api::identifiers::UserId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+/// What happened to a member of a room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MembershipChange {
+    Joined,
+    Left,
+    Invited,
+    InviteRejected,
+    InviteRevoked,
+    Kicked,
+    Banned,
+    Unbanned,
+    /// The member changed their display name, this is the one they had before
+    Renamed(Option<String>),
+    AvatarChanged,
+}
+
+/// A change of the state of a room, we show them in the room history with the messages
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StateChange {
+    Member {
+        target: UserId,
+        /// The display name of the member after the change, or before it if they left
+        name: Option<String>,
+        change: MembershipChange,
+        reason: Option<String>,
+    },
+    /// The new name of the room, empty if it was removed
+    Name(String),
+    /// The new topic of the room, empty if it was removed
+    Topic(String),
+    Avatar,
+    /// The members whose power level changed, with their new level
+    PowerLevels(Vec<(UserId, i64)>),
+}
+
+impl StateChange {
+    /// The state events we show in the room history
+    pub fn types() -> [&'static str; 5] {
+        [
+            "m.room.member",
+            "m.room.name",
+            "m.room.topic",
+            "m.room.avatar",
+            "m.room.power_levels",
+        ]
+    }
+
+    /// Parses a state event of the timeline, the previous content in `unsigned` tells us
+    /// what changed. Returns None if nothing worth showing changed.
+    pub fn parse(event: &JsonValue) -> Option<Self> {
+        let content = &event["content"];
+        let prev_content = &event["unsigned"]["prev_content"];
+
+        match event["type"].as_str()? {
+            "m.room.member" => parse_member(event, content, prev_content),
+            "m.room.name" => Some(StateChange::Name(
+                content["name"].as_str().unwrap_or_default().to_string(),
+            )),
+            "m.room.topic" => Some(StateChange::Topic(
+                content["topic"].as_str().unwrap_or_default().to_string(),
+            )),
+            "m.room.avatar" => Some(StateChange::Avatar),
+            "m.room.power_levels" => Some(StateChange::PowerLevels(power_level_changes(
+                content,
+                prev_content,
+            ))),
+            _ => None,
+        }
+    }
+
+    pub fn is_membership(&self) -> bool {
+        match self {
+            StateChange::Member { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+fn parse_member(
+    event: &JsonValue,
+    content: &JsonValue,
+    prev_content: &JsonValue,
+) -> Option<StateChange> {
+    let target = UserId::try_from(event["state_key"].as_str()?).ok()?;
+    let by_target = event["sender"].as_str() == Some(target.to_string().as_str());
+    let membership = content["membership"].as_str()?;
+    let prev_membership = prev_content["membership"].as_str().unwrap_or("leave");
+    let displayname = content["displayname"].as_str();
+    let prev_displayname = prev_content["displayname"].as_str();
+
+    let change = match (membership, prev_membership) {
+        ("join", "join") if displayname != prev_displayname => {
+            MembershipChange::Renamed(prev_displayname.map(String::from))
+        }
+        ("join", "join") if content["avatar_url"] != prev_content["avatar_url"] => {
+            MembershipChange::AvatarChanged
+        }
+        ("join", "join") | ("leave", "leave") => return None,
+        ("join", _) => MembershipChange::Joined,
+        ("invite", _) => MembershipChange::Invited,
+        ("leave", "invite") if by_target => MembershipChange::InviteRejected,
+        ("leave", "invite") => MembershipChange::InviteRevoked,
+        ("leave", "ban") => MembershipChange::Unbanned,
+        ("leave", _) if by_target => MembershipChange::Left,
+        ("leave", _) => MembershipChange::Kicked,
+        ("ban", _) => MembershipChange::Banned,
+        _ => return None,
+    };
+
+    Some(StateChange::Member {
+        target,
+        name: displayname.or(prev_displayname).map(String::from),
+        change,
+        reason: content["reason"].as_str().map(String::from),
+    })
+}
+
+fn power_level_changes(content: &JsonValue, prev_content: &JsonValue) -> Vec<(UserId, i64)> {
+    let level = |content: &JsonValue, uid: &str| {
+        content["users"][uid]
+            .as_i64()
+            .or_else(|| content["users_default"].as_i64())
+            .unwrap_or_default()
+    };
+
+    let users: BTreeSet<&str> = content["users"]
+        .as_object()
+        .into_iter()
+        .chain(prev_content["users"].as_object())
+        .flat_map(|users| users.keys().map(String::as_str))
+        .collect();
+
+    users
+        .into_iter()
+        .filter(|uid| level(content, uid) != level(prev_content, uid))
+        .filter_map(|uid| Some((UserId::try_from(uid).ok()?, level(content, uid))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn member_event(sender: &str, content: JsonValue, prev_content: JsonValue) -> JsonValue {
+        json!({
+            "type": "m.room.member",
+            "sender": sender,
+            "state_key": "@bob:example.org",
+            "content": content,
+            "unsigned": { "prev_content": prev_content },
+        })
+    }
+
+    fn change_of(event: &JsonValue) -> Option<MembershipChange> {
+        match StateChange::parse(event)? {
+            StateChange::Member { change, .. } => Some(change),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn membership_changes() {
+        let joined = json!({ "membership": "join", "displayname": "Bob" });
+        let invited = json!({ "membership": "invite" });
+        let left = json!({ "membership": "leave" });
+
+        let ev = member_event("@bob:example.org", joined.clone(), json!(null));
+        assert_eq!(change_of(&ev), Some(MembershipChange::Joined));
+        let ev = member_event("@bob:example.org", left.clone(), joined.clone());
+        assert_eq!(change_of(&ev), Some(MembershipChange::Left));
+        let ev = member_event("@alice:example.org", left.clone(), joined.clone());
+        assert_eq!(change_of(&ev), Some(MembershipChange::Kicked));
+        let ev = member_event("@bob:example.org", left.clone(), invited.clone());
+        assert_eq!(change_of(&ev), Some(MembershipChange::InviteRejected));
+        let ev = member_event("@alice:example.org", left, invited);
+        assert_eq!(change_of(&ev), Some(MembershipChange::InviteRevoked));
+
+        let renamed = json!({ "membership": "join", "displayname": "Robert" });
+        let ev = member_event("@bob:example.org", renamed, joined.clone());
+        assert_eq!(
+            change_of(&ev),
+            Some(MembershipChange::Renamed(Some(String::from("Bob"))))
+        );
+        let ev = member_event("@bob:example.org", joined.clone(), joined);
+        assert_eq!(change_of(&ev), None);
+    }
+
+    #[test]
+    fn power_level_diff() {
+        let event = json!({
+            "type": "m.room.power_levels",
+            "sender": "@alice:example.org",
+            "state_key": "",
+            "content": {
+                "users": { "@alice:example.org": 100, "@bob:example.org": 50 },
+                "users_default": 0,
+            },
+            "unsigned": {
+                "prev_content": {
+                    "users": { "@alice:example.org": 100, "@carol:example.org": 50 },
+                },
+            },
+        });
+
+        assert_eq!(
+            StateChange::parse(&event),
+            Some(StateChange::PowerLevels(vec![
+                (UserId::try_from("@bob:example.org").unwrap(), 50),
+                (UserId::try_from("@carol:example.org").unwrap(), 0),
+            ]))
+        );
+    }
+}
@@ -3,12 +3,22 @@ use fractal_api::identifiers::{Error as IdError, UserId};
 use fractal_api::r0::AccessToken;
 use fractal_api::url::ParseError;
 use fractal_api::url::Url;
+use std::io::Error as IoError;
+
+use crate::util;
 
 #[derive(Debug)]
 pub enum Error {
     SecretServiceError,
     UrlParseError(ParseError),
     IdParseError(IdError),
+    IoError(IoError),
+    JsonError(serde_json::Error),
+    /// Nothing stored in the credentials file
+    NotFound,
+    /// The credentials file is encrypted and we don't have the passphrase yet
+    Locked,
+    BadPassphrase,
 }
 
 impl From<ParseError> for Error {
@@ -23,11 +33,111 @@ impl From<IdError> for Error {
     }
 }
 
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::JsonError(err)
+    }
+}
+
 derror!(secret_service::SsError, Error::SecretServiceError);
 
+/// Where the password and the tokens are kept, chosen with the `password-storage` setting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StorageType {
+    SecretService,
+    PlainText,
+}
+
+impl StorageType {
+    pub fn current() -> Self {
+        util::get_password_storage_schema()
+    }
+
+    pub fn other(self) -> Self {
+        match self {
+            StorageType::SecretService => StorageType::PlainText,
+            StorageType::PlainText => StorageType::SecretService,
+        }
+    }
+
+    fn delete_pass(self, key: &str) -> Result<(), Error> {
+        match self {
+            StorageType::SecretService => ss_storage::delete_pass(key),
+            StorageType::PlainText => plain_storage::delete_pass(),
+        }
+    }
+
+    fn store_pass(
+        self,
+        username: String,
+        password: String,
+        server: Url,
+        identity: Url,
+    ) -> Result<(), Error> {
+        match self {
+            StorageType::SecretService => {
+                ss_storage::store_pass(username, password, server, identity)
+            }
+            StorageType::PlainText => {
+                plain_storage::store_pass(username, password, server, identity)
+            }
+        }
+    }
+
+    fn get_pass(self) -> Result<(String, String, Url, Url), Error> {
+        match self {
+            StorageType::SecretService => ss_storage::get_pass(),
+            StorageType::PlainText => plain_storage::get_pass(),
+        }
+    }
+
+    fn store_token(
+        self,
+        uid: UserId,
+        token: AccessToken,
+        server: Url,
+        identity: Url,
+    ) -> Result<(), Error> {
+        match self {
+            StorageType::SecretService => ss_storage::store_token(uid, token, server, identity),
+            StorageType::PlainText => plain_storage::store_token(uid, token, server, identity),
+        }
+    }
+
+    fn get_tokens(self) -> Result<Vec<(AccessToken, UserId, Url, Url)>, Error> {
+        match self {
+            StorageType::SecretService => ss_storage::get_tokens(),
+            StorageType::PlainText => plain_storage::get_tokens(),
+        }
+    }
+
+    fn delete_token(self, uid: &UserId) -> Result<(), Error> {
+        match self {
+            StorageType::SecretService => ss_storage::delete_token(uid),
+            StorageType::PlainText => plain_storage::delete_token(uid),
+        }
+    }
+
+    /// Errors count as nothing stored, the Secret Service may not be running at all
+    fn has_credentials(self) -> bool {
+        match self {
+            StorageType::SecretService => {
+                self.get_pass().is_ok() || self.get_tokens().map_or(false, |t| !t.is_empty())
+            }
+            StorageType::PlainText => plain_storage::exists(),
+        }
+    }
+}
+
 pub trait PasswordStorage {
     fn delete_pass(&self, key: &str) -> Result<(), Error> {
-        ss_storage::delete_pass(key)
+        StorageType::current().delete_pass(key)
     }
 
     fn store_pass(
@@ -37,22 +147,78 @@ pub trait PasswordStorage {
         server: Url,
         identity: Url,
     ) -> Result<(), Error> {
-        ss_storage::store_pass(username, password, server, identity)
+        StorageType::current().store_pass(username, password, server, identity)
     }
 
     fn get_pass(&self) -> Result<(String, String, Url, Url), Error> {
-        ss_storage::get_pass()
+        StorageType::current().get_pass()
     }
 
-    fn store_token(&self, uid: UserId, token: AccessToken) -> Result<(), Error> {
-        ss_storage::store_token(uid, token)
+    fn store_token(
+        &self,
+        uid: UserId,
+        token: AccessToken,
+        server: Url,
+        identity: Url,
+    ) -> Result<(), Error> {
+        StorageType::current().store_token(uid, token, server, identity)
     }
 
-    fn get_token(&self) -> Result<(Option<AccessToken>, UserId), Error> {
-        ss_storage::get_token()
+    fn get_tokens(&self) -> Result<Vec<(AccessToken, UserId, Url, Url)>, Error> {
+        StorageType::current().get_tokens()
+    }
+
+    fn delete_token(&self, uid: &UserId) -> Result<(), Error> {
+        StorageType::current().delete_token(uid)
     }
 }
 
+/// Moves the password and the tokens from `from` to `to`, so switching the storage doesn't
+/// log us out. Nothing is moved when `to` already has credentials of its own. Returns
+/// whether something was moved.
+pub fn migrate(from: StorageType, to: StorageType) -> Result<bool, Error> {
+    if from == to || to.has_credentials() || !from.has_credentials() {
+        return Ok(false);
+    }
+
+    let tokens = from.get_tokens()?;
+    let pass = from.get_pass().ok();
+    for (token, uid, server, identity) in tokens.iter().cloned() {
+        to.store_token(uid, token, server, identity)?;
+    }
+    if let Some((username, password, server, identity)) = pass {
+        to.store_pass(username, password, server, identity)?;
+    }
+
+    // Only once everything is in the new storage
+    for (_, uid, _, _) in tokens {
+        from.delete_token(&uid)?;
+    }
+    from.delete_pass("fractal")?;
+
+    Ok(true)
+}
+
+/// Whether the credentials file is encrypted and we still have to ask for its passphrase
+pub fn is_locked() -> bool {
+    plain_storage::is_locked()
+}
+
+/// Checks the passphrase of the credentials file and keeps it for this session
+pub fn unlock(passphrase: String) -> Result<(), Error> {
+    plain_storage::unlock(passphrase)
+}
+
+/// Removes the credentials file, for when its passphrase is lost
+pub fn forget() -> Result<(), Error> {
+    plain_storage::forget()
+}
+
+/// Encrypts the credentials file with a new passphrase, or decrypts it with `None`
+pub fn set_passphrase(passphrase: Option<String>) -> Result<(), Error> {
+    plain_storage::set_passphrase(passphrase)
+}
+
 mod ss_storage {
     use super::Error;
     use fractal_api::identifiers::UserId;
@@ -82,56 +248,101 @@ mod ss_storage {
         Ok(())
     }
 
-    pub fn store_token(uid: UserId, token: AccessToken) -> Result<(), Error> {
+    /// Each account has its own token, told apart by the uid
+    pub fn delete_token(uid: &UserId) -> Result<(), Error> {
+        let ss = SecretService::new(EncryptionType::Dh)?;
+        let collection = ss.get_default_collection()?;
+        let uid = uid.to_string();
+
+        let allpass = collection.get_all_items()?;
+        let tokens = allpass.iter().filter(|x| {
+            x.get_label().unwrap_or_default() == "fractal-token"
+                && x.get_attributes()
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|attr| attr.0 == "uid" && attr.1 == uid)
+        });
+        for p in tokens {
+            p.unlock()?;
+            p.delete()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn store_token(
+        uid: UserId,
+        token: AccessToken,
+        server: Url,
+        identity: Url,
+    ) -> Result<(), Error> {
         let ss = SecretService::new(EncryptionType::Dh)?;
         let collection = ss.get_default_collection()?;
         let key = "fractal-token";
 
         // deleting previous items
-        delete_pass(key)?;
+        delete_token(&uid)?;
 
         // create new item
         collection.unlock()?;
         collection.create_item(
-            key,                             // label
-            vec![("uid", &uid.to_string())], // properties
-            token.to_string().as_bytes(),    // secret
-            true,                            // replace item with same attributes
-            "text/plain",                    // secret content type
+            key, // label
+            vec![
+                ("uid", &uid.to_string()),
+                ("server", server.as_str()),
+                ("identity", identity.as_str()),
+            ], // properties
+            token.to_string().as_bytes(), // secret
+            true, // replace item with same attributes
+            "text/plain", // secret content type
         )?;
 
         Ok(())
     }
 
-    pub fn get_token() -> Result<(Option<AccessToken>, UserId), Error> {
+    pub fn get_tokens() -> Result<Vec<(AccessToken, UserId, Url, Url)>, Error> {
         let ss = SecretService::new(EncryptionType::Dh)?;
         let collection = ss.get_default_collection()?;
         let allpass = collection.get_all_items()?;
         let key = "fractal-token";
 
-        let passwd = allpass
+        let mut tokens = vec![];
+        for p in allpass
             .iter()
-            .find(|x| x.get_label().unwrap_or_default() == key);
-
-        if passwd.is_none() {
-            return Err(Error::SecretServiceError);
+            .filter(|x| x.get_label().unwrap_or_default() == key)
+        {
+            p.unlock()?;
+            let attrs = p.get_attributes()?;
+            let secret = p.get_secret()?;
+            let token = match String::from_utf8(secret).ok().filter(|tk| !tk.is_empty()) {
+                Some(token) => AccessToken::from(token),
+                None => continue,
+            };
+
+            let attr = attrs
+                .iter()
+                .find(|x| x.0 == "uid")
+                .ok_or(Error::SecretServiceError)?;
+            let uid = UserId::try_from(attr.1.as_str())?;
+
+            let server = attrs.iter().find(|x| x.0 == "server");
+            let identity = attrs.iter().find(|x| x.0 == "identity");
+            let (server, identity) = match (server, identity) {
+                (Some(server), Some(identity)) => {
+                    (Url::parse(&server.1)?, Url::parse(&identity.1)?)
+                }
+                /* The token of the only account we had before didn't have the servers,
+                 * they were with the password */
+                _ => {
+                    let (_, _, server, identity) = get_pass()?;
+                    (server, identity)
+                }
+            };
+
+            tokens.push((token, uid, server, identity));
         }
 
-        let p = passwd.unwrap();
-        p.unlock()?;
-        let attrs = p.get_attributes()?;
-        let secret = p.get_secret()?;
-        let token = Some(String::from_utf8(secret).unwrap())
-            .filter(|tk| !tk.is_empty())
-            .map(AccessToken::from);
-
-        let attr = attrs
-            .iter()
-            .find(|x| x.0 == "uid")
-            .ok_or(Error::SecretServiceError)?;
-        let uid = UserId::try_from(attr.1.as_str())?;
-
-        Ok((token, uid))
+        Ok(tokens)
     }
 
     pub fn store_pass(
@@ -257,3 +468,444 @@ mod ss_storage {
         Ok(tup)
     }
 }
+
+/// Credentials kept in a file only we can read, for when there's no Secret Service. With a
+/// passphrase the file is encrypted, otherwise anyone with access to our account can read it.
+mod plain_storage {
+    use super::Error;
+    use aes_ctr::stream_cipher::generic_array::GenericArray;
+    use aes_ctr::stream_cipher::{NewStreamCipher, SyncStreamCipher};
+    use aes_ctr::Aes256Ctr;
+    use fractal_api::identifiers::UserId;
+    use fractal_api::r0::AccessToken;
+    use fractal_api::url::Url;
+    use hmac::{Hmac, Mac};
+    use lazy_static::lazy_static;
+    use rand::rngs::OsRng;
+    use rand::RngCore;
+    use serde::{Deserialize, Serialize};
+    use sha2::Sha256;
+    use std::convert::TryFrom;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    use crate::crypto::{decode_base64, encode_base64};
+    use crate::globals;
+
+    type HmacSha256 = Hmac<Sha256>;
+
+    const ITERATIONS: u32 = 100_000;
+    /// A file asking for fewer would make the passphrase easier to guess, one asking for
+    /// many more would keep us busy forever
+    const MIN_ITERATIONS: u32 = ITERATIONS;
+    const MAX_ITERATIONS: u32 = 100 * ITERATIONS;
+
+    lazy_static! {
+        static ref PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    pub struct Credentials {
+        pub password: Option<StoredPassword>,
+        pub tokens: Vec<StoredToken>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct StoredPassword {
+        pub username: String,
+        pub password: String,
+        pub server: String,
+        pub identity: String,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct StoredToken {
+        pub uid: String,
+        pub token: String,
+        pub server: String,
+        pub identity: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(tag = "format", rename_all = "snake_case")]
+    enum CredentialsFile {
+        Plain {
+            credentials: Credentials,
+        },
+        /// The JSON of the credentials, encrypted with AES-256-CTR and authenticated with
+        /// HMAC-SHA-256, both keys derived from the passphrase with PBKDF2
+        Encrypted {
+            salt: String,
+            iterations: u32,
+            iv: String,
+            ciphertext: String,
+            mac: String,
+        },
+    }
+
+    /// The AES and HMAC keys for the passphrase
+    fn derive_keys(passphrase: &str, salt: &[u8], iterations: u32) -> ([u8; 32], [u8; 32]) {
+        let mut derived = [0; 64];
+        pbkdf2::pbkdf2::<HmacSha256>(
+            passphrase.as_bytes(),
+            salt,
+            iterations as usize,
+            &mut derived,
+        );
+
+        let mut aes_key = [0; 32];
+        let mut mac_key = [0; 32];
+        aes_key.copy_from_slice(&derived[..32]);
+        mac_key.copy_from_slice(&derived[32..]);
+        (aes_key, mac_key)
+    }
+
+    /// The MAC covers the IV too, a changed one would decrypt to something else
+    fn mac(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_varkey(key).expect("HMAC can take a key of any size");
+        mac.input(iv);
+        mac.input(ciphertext);
+        mac
+    }
+
+    /// AES-256-CTR, the same operation encrypts and decrypts
+    fn apply_keystream(key: &[u8; 32], iv: &[u8], data: &mut [u8]) -> Result<(), Error> {
+        if iv.len() != 16 {
+            return Err(Error::BadPassphrase);
+        }
+        Aes256Ctr::new(GenericArray::from_slice(key), GenericArray::from_slice(iv))
+            .apply_keystream(data);
+        Ok(())
+    }
+
+    fn path() -> PathBuf {
+        globals::DATA_PATH.join("credentials.json")
+    }
+
+    fn passphrase() -> Option<String> {
+        PASSPHRASE.lock().unwrap().clone()
+    }
+
+    pub fn exists() -> bool {
+        path().exists()
+    }
+
+    pub fn is_locked() -> bool {
+        passphrase().is_none() && is_encrypted(&path())
+    }
+
+    pub fn unlock(passphrase: String) -> Result<(), Error> {
+        load(&path(), Some(&passphrase))?;
+        *PASSPHRASE.lock().unwrap() = Some(passphrase);
+        Ok(())
+    }
+
+    pub fn set_passphrase(passphrase: Option<String>) -> Result<(), Error> {
+        let credentials = read()?;
+        save(&path(), &credentials, passphrase.as_deref())?;
+        *PASSPHRASE.lock().unwrap() = passphrase;
+        Ok(())
+    }
+
+    pub fn forget() -> Result<(), Error> {
+        if exists() {
+            fs::remove_file(path())?;
+        }
+        *PASSPHRASE.lock().unwrap() = None;
+        Ok(())
+    }
+
+    pub fn is_encrypted(path: &Path) -> bool {
+        fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .map_or(false, |file| match file {
+                CredentialsFile::Encrypted { .. } => true,
+                CredentialsFile::Plain { .. } => false,
+            })
+    }
+
+    /// A missing file is the same as one without credentials
+    pub fn load(path: &Path, passphrase: Option<&str>) -> Result<Credentials, Error> {
+        if !path.exists() {
+            return Ok(Default::default());
+        }
+
+        let data = fs::read(path)?;
+        match serde_json::from_slice(&data)? {
+            CredentialsFile::Plain { credentials } => Ok(credentials),
+            CredentialsFile::Encrypted {
+                salt,
+                iterations,
+                iv,
+                ciphertext,
+                mac: tag,
+            } => {
+                let passphrase = passphrase.ok_or(Error::Locked)?;
+                if !(MIN_ITERATIONS..=MAX_ITERATIONS).contains(&iterations) {
+                    return Err(Error::BadPassphrase);
+                }
+                let salt = decode_base64(&salt).or(Err(Error::BadPassphrase))?;
+                let iv = decode_base64(&iv).or(Err(Error::BadPassphrase))?;
+                let mut data = decode_base64(&ciphertext).or(Err(Error::BadPassphrase))?;
+                let tag = decode_base64(&tag).or(Err(Error::BadPassphrase))?;
+
+                let (aes_key, mac_key) = derive_keys(passphrase, &salt, iterations);
+                // The whole tag is checked, in constant time
+                mac(&mac_key, &iv, &data)
+                    .verify(&tag)
+                    .or(Err(Error::BadPassphrase))?;
+                apply_keystream(&aes_key, &iv, &mut data)?;
+                Ok(serde_json::from_slice(&data)?)
+            }
+        }
+    }
+
+    /// Replaces the file, only readable by us. Without credentials the file goes away.
+    pub fn save(
+        path: &Path,
+        credentials: &Credentials,
+        passphrase: Option<&str>,
+    ) -> Result<(), Error> {
+        if credentials.password.is_none() && credentials.tokens.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+
+        let file = match passphrase {
+            Some(passphrase) => {
+                let mut salt = [0; 16];
+                let mut iv = [0; 16];
+                OsRng.fill_bytes(&mut salt);
+                OsRng.fill_bytes(&mut iv);
+                let (aes_key, mac_key) = derive_keys(passphrase, &salt, ITERATIONS);
+                let mut data = serde_json::to_vec(credentials)?;
+                apply_keystream(&aes_key, &iv, &mut data)?;
+                let tag = mac(&mac_key, &iv, &data).result().code();
+
+                CredentialsFile::Encrypted {
+                    salt: encode_base64(&salt),
+                    iterations: ITERATIONS,
+                    iv: encode_base64(&iv),
+                    ciphertext: encode_base64(&data),
+                    mac: encode_base64(&tag),
+                }
+            }
+            None => CredentialsFile::Plain {
+                credentials: credentials.clone(),
+            },
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Written aside and renamed, so a crash doesn't leave us with half the credentials
+        let tmp_path = path.with_extension("json.tmp");
+        let mut tmp = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+        // The mode only applies to new files
+        tmp.set_permissions(fs::Permissions::from_mode(0o600))?;
+        tmp.write_all(&serde_json::to_vec(&file)?)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    fn read() -> Result<Credentials, Error> {
+        load(&path(), passphrase().as_deref())
+    }
+
+    fn write(credentials: &Credentials) -> Result<(), Error> {
+        save(&path(), credentials, passphrase().as_deref())
+    }
+
+    pub fn delete_pass() -> Result<(), Error> {
+        let mut credentials = read()?;
+        credentials.password = None;
+        write(&credentials)
+    }
+
+    pub fn store_pass(
+        username: String,
+        password: String,
+        server: Url,
+        identity: Url,
+    ) -> Result<(), Error> {
+        let mut credentials = read()?;
+        credentials.password = Some(StoredPassword {
+            username,
+            password,
+            server: server.to_string(),
+            identity: identity.to_string(),
+        });
+        write(&credentials)
+    }
+
+    pub fn get_pass() -> Result<(String, String, Url, Url), Error> {
+        let pass = read()?.password.ok_or(Error::NotFound)?;
+        Ok((
+            pass.username,
+            pass.password,
+            Url::parse(&pass.server)?,
+            Url::parse(&pass.identity)?,
+        ))
+    }
+
+    pub fn delete_token(uid: &UserId) -> Result<(), Error> {
+        let mut credentials = read()?;
+        let uid = uid.to_string();
+        credentials.tokens.retain(|token| token.uid != uid);
+        write(&credentials)
+    }
+
+    pub fn store_token(
+        uid: UserId,
+        token: AccessToken,
+        server: Url,
+        identity: Url,
+    ) -> Result<(), Error> {
+        let mut credentials = read()?;
+        let uid = uid.to_string();
+        credentials.tokens.retain(|token| token.uid != uid);
+        credentials.tokens.push(StoredToken {
+            uid,
+            token: token.to_string(),
+            server: server.to_string(),
+            identity: identity.to_string(),
+        });
+        write(&credentials)
+    }
+
+    pub fn get_tokens() -> Result<Vec<(AccessToken, UserId, Url, Url)>, Error> {
+        read()?
+            .tokens
+            .into_iter()
+            .map(|token| {
+                Ok((
+                    AccessToken::from(token.token),
+                    UserId::try_from(token.uid.as_str())?,
+                    Url::parse(&token.server)?,
+                    Url::parse(&token.identity)?,
+                ))
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::env;
+
+        fn test_path(name: &str) -> PathBuf {
+            let dir = env::temp_dir().join(format!("fractal-passwd-{}", std::process::id()));
+            let path = dir.join(name);
+            let _ = fs::remove_file(&path);
+            path
+        }
+
+        fn credentials() -> Credentials {
+            Credentials {
+                password: Some(StoredPassword {
+                    username: String::from("alice"),
+                    password: String::from("hunter2"),
+                    server: String::from("https://matrix.example.org/"),
+                    identity: String::from("https://vector.im/"),
+                }),
+                tokens: vec![StoredToken {
+                    uid: String::from("@alice:example.org"),
+                    token: String::from("syt_token"),
+                    server: String::from("https://matrix.example.org/"),
+                    identity: String::from("https://vector.im/"),
+                }],
+            }
+        }
+
+        #[test]
+        fn plain_file_roundtrip() {
+            let path = test_path("plain.json");
+            assert_eq!(load(&path, None).unwrap(), Credentials::default());
+
+            save(&path, &credentials(), None).unwrap();
+            assert!(!is_encrypted(&path));
+            assert_eq!(load(&path, None).unwrap(), credentials());
+
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+
+            save(&path, &Credentials::default(), None).unwrap();
+            assert!(!path.exists());
+        }
+
+        #[test]
+        fn encrypted_file_roundtrip() {
+            let path = test_path("encrypted.json");
+            save(&path, &credentials(), Some("correct horse")).unwrap();
+            assert!(is_encrypted(&path));
+
+            let data = fs::read_to_string(&path).unwrap();
+            assert!(!data.contains("hunter2"));
+            assert!(!data.contains("syt_token"));
+
+            assert!(matches!(load(&path, None), Err(Error::Locked)));
+            assert!(matches!(
+                load(&path, Some("battery staple")),
+                Err(Error::BadPassphrase)
+            ));
+            assert_eq!(load(&path, Some("correct horse")).unwrap(), credentials());
+
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn encrypted_file_tampering() {
+            let path = test_path("tampered.json");
+            save(&path, &credentials(), Some("correct horse")).unwrap();
+            let file: serde_json::Value =
+                serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+
+            let tampered = |field: &str, value: serde_json::Value| {
+                let mut file = file.clone();
+                file[field] = value;
+                fs::write(&path, serde_json::to_vec(&file).unwrap()).unwrap();
+                load(&path, Some("correct horse"))
+            };
+
+            // A file that asks for a weaker key isn't trusted
+            assert!(matches!(
+                tampered("iterations", 1.into()),
+                Err(Error::BadPassphrase)
+            ));
+            assert!(matches!(
+                tampered("iterations", u32::MAX.into()),
+                Err(Error::BadPassphrase)
+            ));
+            // Every bit of the tag counts
+            let mut tag = decode_base64(file["mac"].as_str().unwrap()).unwrap();
+            assert_eq!(tag.len(), 32);
+            tag[31] ^= 1;
+            assert!(matches!(
+                tampered("mac", encode_base64(&tag).into()),
+                Err(Error::BadPassphrase)
+            ));
+            let mut iv = decode_base64(file["iv"].as_str().unwrap()).unwrap();
+            iv[0] ^= 1;
+            assert!(matches!(
+                tampered("iv", encode_base64(&iv).into()),
+                Err(Error::BadPassphrase)
+            ));
+            assert_eq!(tampered("mac", file["mac"].clone()).unwrap(), credentials());
+
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -1,11 +1,20 @@
+pub use crate::model::command::Command;
+pub use crate::model::command::CommandError;
+pub use crate::model::command::CommandInfo;
 pub use crate::model::event::Event;
 pub use crate::model::fileinfo::ExtraContent;
 pub use crate::model::fileinfo::Info;
 pub use crate::model::member::Member;
 pub use crate::model::member::MemberList;
 pub use crate::model::message::Message;
+pub use crate::model::push_rules::Notification;
+pub use crate::model::push_rules::PushContext;
+pub use crate::model::push_rules::RoomNotifications;
+pub use crate::model::room::Moderation;
 pub use crate::model::room::Reason;
 pub use crate::model::room::Room;
 pub use crate::model::room::RoomList;
 pub use crate::model::room::RoomMembership;
 pub use crate::model::room::RoomTag;
+pub use crate::model::state_change::MembershipChange;
+pub use crate::model::state_change::StateChange;
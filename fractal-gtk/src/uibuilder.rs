@@ -56,6 +56,23 @@ impl UI {
         sventry_disabled.set_line_wrap(true);
         sventry_disabled.set_line_wrap_mode(pango::WrapMode::WordChar);
         sventry_stack.add_named(&sventry_disabled, "Disabled Entry");
+        let replaced_room = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+        let replaced_label = gtk::Label::new(Some(&i18n(
+            "This room has been replaced and is no longer active",
+        )));
+        replaced_label.set_xalign(0.0);
+        replaced_label.set_line_wrap(true);
+        replaced_label.set_line_wrap_mode(pango::WrapMode::WordChar);
+        replaced_label.get_style_context().add_class("dim-label");
+        let replaced_button = gtk::Button::new_with_label(&i18n("Go to the New Room"));
+        replaced_button.set_action_name(Some("app.open-replacement-room"));
+        replaced_button.set_valign(gtk::Align::Center);
+        replaced_room.set_margin_start(12);
+        replaced_room.set_margin_end(12);
+        replaced_room.pack_start(&replaced_label, true, true, 0);
+        replaced_room.pack_start(&replaced_button, false, false, 0);
+        replaced_room.show_all();
+        sventry_stack.add_named(&replaced_room, "Replaced Room");
 
         let sventry_box = Box::new(sventry_stack.clone());
         let parent: gtk::Box = builder.get_object("room_parent").unwrap();
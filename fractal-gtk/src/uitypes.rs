@@ -29,10 +29,34 @@ pub struct MessageContent {
     pub msg: Message,
     pub highlights: Vec<String>,
     pub redactable: bool,
+    pub editable: bool,
     pub last_viewed: bool,
+    pub reactions: Vec<ReactionGroup>,
+    pub reply: Option<ReplyHeader>,
+    /* The membership changes shown together in this row, the oldest first. It's empty if the
+     * row shows a single message */
+    pub collapsed: Vec<Message>,
     pub widget: Option<widgets::MessageBox>,
 }
 
+/* The reactions to a message with the same key */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReactionGroup {
+    pub key: String,
+    pub count: usize,
+    /* Our own reaction, we redact it to take it back */
+    pub ours: Option<EventId>,
+}
+
+/* What we show of the message another one replies to */
+#[derive(Debug, Clone)]
+pub struct ReplyHeader {
+    pub event_id: EventId,
+    /* None if we don't know the message nor its sender */
+    pub sender_name: Option<String>,
+    pub snippet: String,
+}
+
 /* To-Do: this should be moved to a file collecting all structs used in the UI */
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum RowType {
@@ -47,4 +71,6 @@ pub enum RowType {
     Video,
     File,
     Emoji,
+    /* A change of the room state, e.g. someone joined or the topic changed */
+    State,
 }
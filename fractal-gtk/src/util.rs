@@ -1,6 +1,8 @@
 use crate::globals::CACHE_PATH;
+use crate::passwd::StorageType;
 use failure::format_err;
 use failure::Error as FailError;
+use fractal_api::identifiers::UserId;
 use gdk::prelude::*;
 use gdk_pixbuf::Pixbuf;
 use gio::{Settings, SettingsExt, SettingsSchemaSource};
@@ -21,6 +23,12 @@ pub fn cache_dir_path(dir: Option<&str>, name: &str) -> Result<PathBuf, IoError>
     Ok(path.join(name))
 }
 
+/// The directory that keeps the files of an account apart from the other ones
+pub fn account_dir(uid: &UserId) -> String {
+    // Historical user IDs can have slashes
+    uid.to_string().replace('/', "_")
+}
+
 pub fn get_pixbuf_data(pb: &Pixbuf) -> Result<Vec<u8>, FailError> {
     let image = cairo::ImageSurface::create(cairo::Format::ARgb32, pb.get_width(), pb.get_height())
         .or_else(|_| Err(format_err!("Cairo Error")))?;
@@ -60,6 +68,44 @@ pub fn set_markdown_schema(md: bool) {
     }
 }
 
+pub fn get_state_events_schema() -> bool {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .map(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            settings.get_boolean("show-state-events")
+        })
+        .unwrap_or(true)
+}
+
+pub fn get_password_storage_schema() -> StorageType {
+    let plain_text = SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .map_or(false, |_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            // The values of the org.gnome.Fractal.PasswordStorage enum
+            settings.get_enum("password-storage") == 1
+        });
+
+    if plain_text {
+        StorageType::PlainText
+    } else {
+        StorageType::SecretService
+    }
+}
+
+pub fn set_state_events_schema(show: bool) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_boolean("show-state-events", show) {
+            error!("Can't save whether room state changes are shown: {:?}", err);
+        }
+    }
+}
+
 macro_rules! unwrap_or_unit_return {
     ($x:expr) => {
         match $x {
@@ -8,11 +8,18 @@ use std::sync::{Arc, Mutex};
 use gtk::prelude::*;
 use gtk::TextTag;
 
-use crate::types::Member;
+use crate::types::{CommandInfo, Member};
 
+use crate::appop::command;
 use crate::appop::AppOp;
 use crate::widgets;
 
+/// What the popover offers to complete
+pub enum Completion {
+    Member(Member),
+    Command(&'static CommandInfo),
+}
+
 pub struct Autocomplete {
     entry: sourceview4::View,
     listbox: gtk::ListBox,
@@ -261,7 +268,12 @@ impl Autocomplete {
                             let first = p1.iter().collect::<String>();
                             if own.borrow().popover_position.is_none() {
                                 if !is_tab {
-                                    if let Some(at_pos) = first.rfind('@') {
+                                    // The name of a command at the start of the message
+                                    if first.starts_with('/')
+                                        && !first.contains(char::is_whitespace)
+                                    {
+                                        own.borrow_mut().popover_position = Some(0);
+                                    } else if let Some(at_pos) = first.rfind('@') {
                                         own.borrow_mut().popover_position = Some(at_pos as i32);
                                     }
                                 } else if let Some(space_pos) =
@@ -331,7 +343,9 @@ impl Autocomplete {
             /* highlight member inside the entry */
             /* we need to set the highlight here the first time
              * because the ui changes from others are blocked as long we hold the look */
-            if let Some(buffer) = self.entry.get_buffer() {
+            // Commands aren't highlighted
+            let buffer = self.entry.get_buffer().filter(|_| !alias.starts_with('/'));
+            if let Some(buffer) = buffer {
                 self.highlighted_entry.push(alias);
 
                 let start_iter = buffer.get_start_iter();
@@ -418,7 +432,7 @@ impl Autocomplete {
 
     pub fn autocomplete_show_popover(
         &mut self,
-        list: Vec<Member>,
+        list: Vec<Completion>,
     ) -> HashMap<String, gtk::EventBox> {
         for ch in self.listbox.get_children().iter() {
             self.listbox.remove(ch);
@@ -427,19 +441,21 @@ impl Autocomplete {
         let mut widget_list: HashMap<String, gtk::EventBox> = HashMap::new();
 
         if !list.is_empty() {
-            for m in list.iter() {
-                let alias = &m
-                    .alias
-                    .clone()
-                    .unwrap_or_default()
-                    .trim_end_matches(" (IRC)")
-                    .to_owned();
-                let widget;
-                {
-                    let guard = self.op.lock().unwrap();
-                    let mb = widgets::MemberBox::new(&m, &guard);
-                    widget = mb.widget(true);
-                }
+            for completion in list.iter() {
+                let (alias, widget) = match completion {
+                    Completion::Member(m) => {
+                        let alias = m
+                            .alias
+                            .clone()
+                            .unwrap_or_default()
+                            .trim_end_matches(" (IRC)")
+                            .to_owned();
+                        let guard = self.op.lock().unwrap();
+                        let mb = widgets::MemberBox::new(&m, &guard);
+                        (alias, mb.widget(true))
+                    }
+                    Completion::Command(info) => (format!("/{} ", info.name), command_widget(info)),
+                };
 
                 let w = widget.clone();
                 let a = alias.clone();
@@ -468,8 +484,8 @@ impl Autocomplete {
         widget_list
     }
 
-    pub fn autocomplete(&self, text: Option<String>, pos: i32) -> Vec<Member> {
-        let mut list: Vec<Member> = vec![];
+    pub fn autocomplete(&self, text: Option<String>, pos: i32) -> Vec<Completion> {
+        let mut list: Vec<Completion> = vec![];
         let guard = self.op.lock().unwrap();
         let rooms = &guard.rooms;
         match text {
@@ -481,6 +497,14 @@ impl Autocomplete {
                         let end = pos as usize;
                         txt.get(start..end)
                     };
+                    // The name of a command at the start of the message
+                    let command = last.filter(|last| at_pos == 0 && last.starts_with('/'));
+                    if let Some(command) = command {
+                        return CommandInfo::completions(&command[1..])
+                            .into_iter()
+                            .map(Completion::Command)
+                            .collect();
+                    }
                     if let Some(last) = last {
                         info!("Matching string '{}'", last);
                         /*remove @ from string*/
@@ -498,7 +522,7 @@ impl Autocomplete {
                                     let alias = &m.alias.clone().unwrap_or_default().to_lowercase();
                                     let uid = m.uid.localpart().to_lowercase();
                                     if alias.starts_with(&w) || uid.starts_with(&w) {
-                                        list.push(m.clone());
+                                        list.push(Completion::Member(m.clone()));
                                         count += 1;
                                         /* Search only for 5 matching users */
                                         if count > 4 {
@@ -515,3 +539,26 @@ impl Autocomplete {
         list
     }
 }
+
+fn command_widget(info: &CommandInfo) -> gtk::EventBox {
+    let event_box = gtk::EventBox::new();
+    let w = gtk::Box::new(gtk::Orientation::Vertical, 0);
+
+    let synopsis = gtk::Label::new(Some(command::synopsis(info).as_str()));
+    synopsis.set_halign(gtk::Align::Start);
+    synopsis.get_style_context().add_class("member");
+
+    let description = gtk::Label::new(Some(command::description(info).as_str()));
+    description.set_halign(gtk::Align::Start);
+    description.set_ellipsize(pango::EllipsizeMode::End);
+    description.get_style_context().add_class("member-uid");
+
+    w.pack_start(&synopsis, false, false, 0);
+    w.pack_start(&description, false, false, 0);
+    w.set_margin_start(6);
+    w.set_margin_end(6);
+    event_box.add(&w);
+    event_box.show_all();
+
+    event_box
+}
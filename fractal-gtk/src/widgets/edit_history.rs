@@ -0,0 +1,80 @@
+use chrono::prelude::*;
+use gtk::prelude::*;
+use html2pango::html_escape;
+
+use crate::i18n::i18n;
+
+/* The versions of an edited message, the original first */
+pub struct EditHistoryDialog {
+    dialog: gtk::Dialog,
+}
+
+impl EditHistoryDialog {
+    pub fn new(parent: &gtk::Window, history: &[(DateTime<Local>, String)]) -> EditHistoryDialog {
+        let dialog = gtk::Dialog::new();
+        dialog.set_title(&i18n("Edit History"));
+        dialog.set_transient_for(Some(parent));
+        dialog.set_modal(true);
+        dialog.set_destroy_with_parent(true);
+        dialog.set_default_size(400, 300);
+        dialog.add_button(&i18n("Close"), gtk::ResponseType::Close);
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::None);
+        for (i, (date, body)) in history.iter().enumerate() {
+            list.add(&build_version(i == 0, date, body));
+        }
+
+        let scroll = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+        scroll.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+        scroll.set_vexpand(true);
+        scroll.add(&list);
+        dialog.get_content_area().add(&scroll);
+
+        dialog.connect_response(|dialog, _| dialog.destroy());
+
+        EditHistoryDialog { dialog }
+    }
+
+    pub fn show(&self) {
+        self.dialog.show_all();
+    }
+}
+
+fn build_version(original: bool, date: &DateTime<Local>, body: &str) -> gtk::ListBoxRow {
+    let row = gtk::ListBoxRow::new();
+    let version_box = gtk::Box::new(gtk::Orientation::Vertical, 3);
+    version_box.set_margin_top(6);
+    version_box.set_margin_bottom(6);
+    version_box.set_margin_start(12);
+    version_box.set_margin_end(12);
+
+    let date = date.format(&i18n("%x %R")).to_string();
+    let title = if original {
+        format!("{} · {}", i18n("Original"), date.trim())
+    } else {
+        date.trim().to_string()
+    };
+    let date_label = gtk::Label::new(None);
+    date_label.set_markup(&format!(
+        "<span alpha=\"60%\">{}</span>",
+        html_escape(&title)
+    ));
+    date_label.set_halign(gtk::Align::Start);
+    date_label.get_style_context().add_class("timestamp");
+
+    let body_label = gtk::Label::new(Some(body));
+    body_label.set_line_wrap(true);
+    body_label.set_line_wrap_mode(pango::WrapMode::WordChar);
+    body_label.set_selectable(true);
+    body_label.set_halign(gtk::Align::Start);
+    body_label.set_xalign(0.0);
+
+    version_box.add(&date_label);
+    version_box.add(&body_label);
+    row.set_selectable(false);
+    row.set_activatable(false);
+    row.add(&version_box);
+
+    row
+}
@@ -1,8 +1,13 @@
 use fractal_api::url::Url;
 use gio::prelude::*;
 use glib::clone;
+use glib::source::Continue;
 use gtk::prelude::*;
 use log::info;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::TryRecvError;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
 
 use crate::actions;
 use crate::actions::global::AppState;
@@ -13,6 +18,9 @@ use crate::i18n::i18n;
 use crate::widgets::ErrorDialog;
 
 use crate::backend::register::get_well_known;
+use crate::backend::register::phone_country;
+use crate::backend::register::{check_username_availability, UsernameAvailabilityError};
+use crate::backend::register::{get_login_flows, GetLoginFlowsError};
 
 use std::sync::{Arc, Mutex};
 
@@ -25,9 +33,32 @@ pub struct LoginWidget {
     pub password_entry: gtk::Entry,
     server_err_label: gtk::Label,
     credentials_err_label: gtk::Label,
+    login_methods: LoginMethods,
+    registration: RegistrationForm,
     actions: gio::SimpleActionGroup,
 }
 
+/// The parts of the credentials page for each way to log in
+#[derive(Debug, Clone)]
+struct LoginMethods {
+    password_widgets: Vec<gtk::Widget>,
+    sso_button: gtk::Button,
+    login: gio::SimpleAction,
+}
+
+#[derive(Debug, Clone)]
+struct RegistrationForm {
+    username_entry: gtk::Entry,
+    username_label: gtk::Label,
+    password_entry: gtk::Entry,
+    password_strength: gtk::LevelBar,
+    password_hint: gtk::Label,
+    confirm_entry: gtk::Entry,
+    email_entry: gtk::Entry,
+    phone_entry: gtk::Entry,
+    err_label: gtk::Label,
+}
+
 impl LoginWidget {
     pub fn new(op: &Arc<Mutex<AppOp>>) -> Self {
         let widget = Self::default();
@@ -36,13 +67,15 @@ impl LoginWidget {
         let username_entry = &widget.username_entry;
         let password_entry = &widget.password_entry;
         let err_label = &widget.credentials_err_label;
+        let register_username_entry = &widget.registration.username_entry;
 
         // Grab the focus for each state
         widget
             .container
             .connect_property_visible_child_name_notify(clone!(
             @weak server_entry as server,
-            @weak username_entry as username
+            @weak username_entry as username,
+            @weak register_username_entry
             => move |container| {
                 let state: LoginState = container
                     .get_visible_child_name()
@@ -53,10 +86,47 @@ impl LoginWidget {
                 match state {
                     LoginState::ServerChooser => server.grab_focus(),
                     LoginState::Credentials => username.grab_focus(),
+                    LoginState::Registration => register_username_entry.grab_focus(),
                     _ => (),
                 }
             }));
 
+        // Show the ways to log in the server supports
+        let login_methods = widget.login_methods.clone();
+        widget.container.connect_property_visible_child_name_notify(
+            clone!(@weak server_entry => move |container| {
+                if container.get_visible_child_name().map_or(false, |name| name == "credentials") {
+                    login_methods.update(&server_entry);
+                }
+            }),
+        );
+
+        let sso = widget
+            .actions
+            .lookup_action("sso")
+            .expect("Could not find 'sso' action for LoginWidget")
+            .downcast::<gio::SimpleAction>()
+            .expect("Could not cast action 'sso' to SimpleAction");
+
+        sso.connect_activate(clone!(@strong op, @weak server_entry => move |_, _| {
+            let server = server_entry
+                .get_text()
+                .map_or(String::new(), |gstr| gstr.to_string());
+
+            if let Some((homeserver_url, idserver)) = server_urls(&server) {
+                op.lock().unwrap().set_state(AppState::Loading);
+                op.lock().unwrap().since = None;
+                op.lock().unwrap().connect_sso(homeserver_url, idserver);
+            } else {
+                let msg = i18n("Malformed server URL");
+                ErrorDialog::new(false, &msg);
+            }
+        }));
+
+        widget
+            .registration
+            .connect(op, &widget.actions, server_entry);
+
         let op = op.clone();
 
         let login = widget
@@ -81,44 +151,15 @@ impl LoginWidget {
                     .get_text()
                     .map_or(String::new(), |gstr| gstr.to_string());
 
-                let txt = String::from(txt).trim().to_string();
-                let txt = if txt.starts_with("http://") || txt.starts_with("https://") {
-                    txt
-                } else {
-                    format!("https://{}", &txt)
-                };
-                let txt = if !txt.ends_with('/') { txt + "/" } else { txt };
-
                 if !password.is_empty() && !username.is_empty() {
-                    // take the user's homeserver value if the
-                    // well-known request fails
-                    let homeserver_url = if let Ok(hs_url) = Url::parse(&txt) {
-                        hs_url
+                    let (homeserver_url, idserver) = if let Some(urls) = server_urls(&txt) {
+                        urls
                     } else {
                         let msg = i18n("Malformed server URL");
                         ErrorDialog::new(false, &msg);
                         return;
                     };
 
-                    let (homeserver_url, idserver) = get_well_known(homeserver_url.clone())
-                        .and_then(|response| {
-                            let hs_url = Url::parse(&response.homeserver.base_url)?;
-                            let ids = response
-                                .identity_server
-                                .as_ref()
-                                .map(|ids| Url::parse(&ids.base_url))
-                                .transpose()?
-                                .unwrap_or(globals::DEFAULT_IDENTITYSERVER.clone());
-                            info!("Got well-known response from {}: {:#?}", &txt, response);
-
-                            Ok((hs_url, ids))
-                        })
-                        .map_err(|e| {
-                            info!("Failed to .well-known request: {:#?}", e);
-                            e
-                        })
-                        .unwrap_or((homeserver_url, globals::DEFAULT_IDENTITYSERVER.clone()));
-
                     err_label.hide();
                     op.lock().unwrap().set_state(AppState::Loading);
                     op.lock().unwrap().since = None;
@@ -170,6 +211,39 @@ impl Default for LoginWidget {
 
         let actions = actions::Login::new(&container, &headers, &server_entry, &server_err_label);
 
+        let login_methods = LoginMethods {
+            password_widgets: vec![
+                builder
+                    .get_object::<gtk::Widget>("credentials_username_label")
+                    .unwrap(),
+                builder
+                    .get_object::<gtk::Widget>("credentials_username_box")
+                    .unwrap(),
+                builder
+                    .get_object::<gtk::Widget>("credentials_password_label")
+                    .unwrap(),
+                builder.get_object::<gtk::Widget>("password_entry").unwrap(),
+            ],
+            sso_button: builder.get_object("credentials_sso_button").unwrap(),
+            login: actions
+                .lookup_action("login")
+                .expect("Could not find 'login' action for LoginWidget")
+                .downcast::<gio::SimpleAction>()
+                .expect("Could not cast action 'login' to SimpleAction"),
+        };
+
+        let registration = RegistrationForm {
+            username_entry: builder.get_object("register_username_entry").unwrap(),
+            username_label: builder.get_object("register_username_label").unwrap(),
+            password_entry: builder.get_object("register_password_entry").unwrap(),
+            password_strength: builder.get_object("register_password_strength").unwrap(),
+            password_hint: builder.get_object("register_password_hint").unwrap(),
+            confirm_entry: builder.get_object("register_confirm_entry").unwrap(),
+            email_entry: builder.get_object("register_email_entry").unwrap(),
+            phone_entry: builder.get_object("register_phone_entry").unwrap(),
+            err_label: builder.get_object("register_err_label").unwrap(),
+        };
+
         container.show_all();
         headers.show_all();
 
@@ -181,7 +255,329 @@ impl Default for LoginWidget {
             password_entry,
             server_err_label,
             credentials_err_label,
+            login_methods,
+            registration,
             actions,
         }
     }
 }
+
+impl LoginMethods {
+    fn update(&self, server_entry: &gtk::Entry) {
+        let server = server_entry
+            .get_text()
+            .map_or(String::new(), |gstr| gstr.to_string());
+
+        // The password is the default until the server tells otherwise
+        self.show(true, false);
+
+        let (tx, rx): (
+            Sender<Result<Vec<String>, GetLoginFlowsError>>,
+            Receiver<Result<Vec<String>, GetLoginFlowsError>>,
+        ) = channel();
+        let checked = server.clone();
+        thread::spawn(move || {
+            if let Some((homeserver_url, _)) = server_urls(&checked) {
+                let _ = tx.send(get_login_flows(homeserver_url));
+            }
+        });
+
+        let methods = self.clone();
+        gtk::timeout_add(
+            50,
+            clone!(@weak server_entry => @default-return Continue(false), move || {
+                match rx.try_recv() {
+                    Err(TryRecvError::Empty) => return Continue(true),
+                    Err(TryRecvError::Disconnected) => {}
+                    // The user may have chosen another server in the meantime
+                    Ok(_) if server_entry.get_text().map_or(true, |text| text.as_str() != server) => {}
+                    Ok(Ok(flows)) => {
+                        let password = flows.iter().any(|flow| flow == "m.login.password");
+                        let sso = flows.iter().any(|flow| flow == "m.login.sso");
+                        methods.show(password || !sso, sso);
+                    }
+                    Ok(Err(err)) => info!("Failed to get the login flows: {:?}", err),
+                }
+
+                Continue(false)
+            }),
+        );
+    }
+
+    fn show(&self, password: bool, sso: bool) {
+        for widget in self.password_widgets.iter() {
+            widget.set_visible(password);
+        }
+        self.sso_button.set_visible(sso);
+        self.login.set_enabled(password);
+    }
+}
+
+impl RegistrationForm {
+    fn connect(
+        &self,
+        op: &Arc<Mutex<AppOp>>,
+        actions: &gio::SimpleActionGroup,
+        server_entry: &gtk::Entry,
+    ) {
+        let register = actions
+            .lookup_action("register")
+            .expect("Could not find 'register' action for LoginWidget")
+            .downcast::<gio::SimpleAction>()
+            .expect("Could not cast action 'register' to SimpleAction");
+
+        let form = self.clone();
+        register.connect_activate(clone!(@strong op, @weak server_entry => move |_, _| {
+            form.register(&op, &server_entry);
+        }));
+
+        let form = self.clone();
+        self.username_entry.connect_focus_out_event(
+            clone!(@weak server_entry => @default-return Inhibit(false), move |_, _| {
+                form.check_username(&server_entry);
+                Inhibit(false)
+            }),
+        );
+
+        let form = self.clone();
+        self.password_entry
+            .connect_property_text_notify(move |_| form.update_password_strength());
+
+        // Go through the form with the enter key
+        let entries = [
+            &self.username_entry,
+            &self.password_entry,
+            &self.confirm_entry,
+            &self.email_entry,
+            &self.phone_entry,
+        ];
+        for pair in entries.windows(2) {
+            let next = pair[1].clone();
+            pair[0].connect_activate(move |_| next.grab_focus());
+        }
+        self.phone_entry
+            .connect_activate(move |_| register.activate(None));
+    }
+
+    fn register(&self, op: &Arc<Mutex<AppOp>>, server_entry: &gtk::Entry) {
+        let text = |entry: &gtk::Entry| {
+            entry
+                .get_text()
+                .map_or(String::new(), |gstr| gstr.trim().to_string())
+        };
+        let username = text(&self.username_entry);
+        let password = self
+            .password_entry
+            .get_text()
+            .map_or(String::new(), |gstr| gstr.to_string());
+        let confirm = self
+            .confirm_entry
+            .get_text()
+            .map_or(String::new(), |gstr| gstr.to_string());
+        let email = Some(text(&self.email_entry)).filter(|email| !email.is_empty());
+        let phone_number = Some(text(&self.phone_entry)).filter(|phone| !phone.is_empty());
+
+        let error = if username.is_empty() || password.is_empty() {
+            Some(i18n("The user name and the password may not be empty."))
+        } else if password != confirm {
+            Some(i18n("The passwords do not match."))
+        } else if email
+            .as_ref()
+            .map_or(false, |email| !globals::EMAIL_RE.is_match(email))
+        {
+            Some(i18n("Please enter a valid email address."))
+        } else if phone_number
+            .as_ref()
+            .map_or(false, |phone| phone_country(phone).is_none())
+        {
+            Some(i18n(
+                "Please enter the phone number with your country code, e.g. +44.",
+            ))
+        } else {
+            None
+        };
+
+        if let Some(error) = error {
+            self.err_label.set_text(&error);
+            self.err_label.show();
+            return;
+        }
+
+        let server = text(server_entry);
+        let (homeserver_url, idserver) = if let Some(urls) = server_urls(&server) {
+            urls
+        } else {
+            let msg = i18n("Malformed server URL");
+            ErrorDialog::new(false, &msg);
+            return;
+        };
+
+        self.err_label.hide();
+        op.lock().unwrap().set_state(AppState::Loading);
+        op.lock().unwrap().since = None;
+        op.lock().unwrap().register(
+            username,
+            password,
+            email,
+            phone_number,
+            homeserver_url,
+            idserver,
+        );
+    }
+
+    fn check_username(&self, server_entry: &gtk::Entry) {
+        let username = self
+            .username_entry
+            .get_text()
+            .map_or(String::new(), |gstr| gstr.trim().to_string());
+        let server = server_entry
+            .get_text()
+            .map_or(String::new(), |gstr| gstr.to_string());
+        if username.is_empty() {
+            self.username_label.hide();
+            return;
+        }
+
+        self.set_username_status(&i18n("Checking availability…"), false);
+
+        let (tx, rx): (
+            Sender<Result<(), UsernameAvailabilityError>>,
+            Receiver<Result<(), UsernameAvailabilityError>>,
+        ) = channel();
+        let checked = username.clone();
+        thread::spawn(move || {
+            if let Some((homeserver_url, _)) = server_urls(&server) {
+                let _ = tx.send(check_username_availability(homeserver_url, checked));
+            }
+        });
+
+        let form = self.clone();
+        gtk::timeout_add(50, move || match rx.try_recv() {
+            Err(TryRecvError::Empty) => Continue(true),
+            Err(TryRecvError::Disconnected) => {
+                form.username_label.hide();
+                Continue(false)
+            }
+            Ok(result) => {
+                // The user may have typed another name in the meantime
+                let current = form
+                    .username_entry
+                    .get_text()
+                    .map_or(String::new(), |gstr| gstr.trim().to_string());
+                if current != username {
+                    return Continue(false);
+                }
+
+                match result {
+                    Ok(()) => form.set_username_status(&i18n("This user name is available"), false),
+                    Err(UsernameAvailabilityError::InUse) => {
+                        form.set_username_status(&i18n("This user name is already taken"), true)
+                    }
+                    Err(UsernameAvailabilityError::Invalid(error)) => {
+                        let error = error.unwrap_or_else(|| i18n("This user name is not valid"));
+                        form.set_username_status(&error, true)
+                    }
+                    Err(UsernameAvailabilityError::Reqwest(err)) => {
+                        info!(
+                            "Failed to check the availability of {}: {:?}",
+                            username, err
+                        );
+                        form.username_label.hide();
+                    }
+                }
+
+                Continue(false)
+            }
+        });
+    }
+
+    fn set_username_status(&self, text: &str, error: bool) {
+        let context = self.username_label.get_style_context();
+        if error {
+            context.remove_class("dim-label");
+            context.add_class("error-label");
+        } else {
+            context.remove_class("error-label");
+            context.add_class("dim-label");
+        }
+
+        self.username_label.set_text(text);
+        self.username_label.show();
+    }
+
+    fn update_password_strength(&self) {
+        let password = self
+            .password_entry
+            .get_text()
+            .map_or(String::new(), |gstr| gstr.to_string());
+        let strength = password_strength(&password);
+
+        let hint = match strength {
+            0 => String::new(),
+            1 => i18n("Too short, use at least 8 characters"),
+            2 => i18n("Weak password"),
+            3 => i18n("Fair password"),
+            4 => i18n("Good password"),
+            _ => i18n("Strong password"),
+        };
+
+        self.password_strength.set_value(f64::from(strength));
+        self.password_hint.set_text(&hint);
+    }
+}
+
+/// Rates a password from 0 to 5 from its length and the kinds of characters
+/// it uses
+fn password_strength(password: &str) -> u32 {
+    let length = password.chars().count();
+    if length == 0 {
+        return 0;
+    } else if length < 8 {
+        return 1;
+    }
+
+    let has = |f: fn(char) -> bool| password.chars().any(f) as u32;
+    let kinds = has(char::is_lowercase)
+        + has(char::is_uppercase)
+        + has(char::is_numeric)
+        + has(|c| !c.is_alphanumeric());
+    let long = (length >= 12) as u32 + (length >= 16) as u32;
+
+    (1 + long + kinds.saturating_sub(1)).min(5)
+}
+
+/// Returns the homeserver and identity server of a provider entered by the
+/// user. The homeserver it gives is used if there is no well-known
+/// information.
+fn server_urls(server: &str) -> Option<(Url, Url)> {
+    let txt = server.trim().to_string();
+    let txt = if txt.starts_with("http://") || txt.starts_with("https://") {
+        txt
+    } else {
+        format!("https://{}", &txt)
+    };
+    let txt = if !txt.ends_with('/') { txt + "/" } else { txt };
+
+    let homeserver_url = Url::parse(&txt).ok()?;
+
+    let urls = get_well_known(homeserver_url.clone())
+        .and_then(|response| {
+            let hs_url = Url::parse(&response.homeserver.base_url)?;
+            let ids = response
+                .identity_server
+                .as_ref()
+                .map(|ids| Url::parse(&ids.base_url))
+                .transpose()?
+                .unwrap_or(globals::DEFAULT_IDENTITYSERVER.clone());
+            info!("Got well-known response from {}: {:#?}", &txt, response);
+
+            Ok((hs_url, ids))
+        })
+        .map_err(|e| {
+            info!("Failed to .well-known request: {:#?}", e);
+            e
+        })
+        .unwrap_or((homeserver_url, globals::DEFAULT_IDENTITYSERVER.clone()));
+
+    Some(urls)
+}
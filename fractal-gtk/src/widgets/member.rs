@@ -1,6 +1,9 @@
+use fractal_api::identifiers::UserId;
 use gtk::prelude::*;
 
-use crate::types::Member;
+use crate::app::App;
+use crate::i18n::i18n;
+use crate::types::{Member, Moderation};
 
 use crate::appop::AppOp;
 
@@ -120,3 +123,45 @@ impl<'a> MemberBox<'a> {
         pill
     }
 }
+
+/// Context menu of a member of the active room, with the moderation actions. The ones we don't
+/// have the power level for are disabled.
+pub struct MemberMenu {
+    popover: gtk::Popover,
+}
+
+impl MemberMenu {
+    pub fn new(uid: &UserId, allowed: &[Moderation]) -> MemberMenu {
+        let popover = gtk::Popover::new(None::<&gtk::Widget>);
+        let b = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        b.set_margin_top(6);
+        b.set_margin_bottom(6);
+
+        let actions = [
+            (Moderation::Kick, i18n("Remove From Room…")),
+            (Moderation::Ban, i18n("Ban From Room…")),
+        ];
+        for (action, label) in actions.iter() {
+            let button = gtk::ModelButton::new();
+            button.set_property_text(Some(label.as_str()));
+            button.set_sensitive(allowed.contains(action));
+            let uid = uid.clone();
+            let action = *action;
+            button.connect_clicked(move |_| {
+                let uid = uid.clone();
+                APPOP!(ask_moderation_reason, (uid, action));
+            });
+            b.pack_start(&button, false, false, 0);
+        }
+
+        b.show_all();
+        popover.add(&b);
+        MemberMenu { popover }
+    }
+
+    pub fn popup<W: IsA<gtk::Widget>>(&self, widget: &W) {
+        self.popover.set_relative_to(Some(widget));
+        self.popover.set_position(gtk::PositionType::Bottom);
+        self.popover.popup();
+    }
+}
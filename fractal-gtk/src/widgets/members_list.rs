@@ -8,7 +8,7 @@ use glib::signal;
 use gtk::prelude::*;
 
 use crate::i18n::i18n;
-use crate::types::Member;
+use crate::types::{Member, Moderation};
 use crate::widgets;
 use crate::widgets::avatar::{AvatarBadgeColor, AvatarExt};
 
@@ -19,12 +19,15 @@ pub struct MembersList {
     error: gtk::Label,
     members: Vec<Member>,
     admins: HashMap<UserId, i32>,
+    /// What we can do to each member, from their context menu
+    allowed: HashMap<UserId, Vec<Moderation>>,
 }
 
 impl MembersList {
     pub fn new(
         members: Vec<Member>,
         admins: HashMap<UserId, i32>,
+        allowed: HashMap<UserId, Vec<Moderation>>,
         search_entry: gtk::SearchEntry,
     ) -> MembersList {
         MembersList {
@@ -33,6 +36,7 @@ impl MembersList {
             members,
             search_entry,
             admins,
+            allowed,
         }
     }
 
@@ -46,6 +50,7 @@ impl MembersList {
             self.container.clone(),
             self.members.clone(),
             self.admins.clone(),
+            &self.allowed,
         );
         self.error.get_style_context().add_class("no_member_search");
         self.error.set_text(&i18n("No matching members found"));
@@ -114,11 +119,29 @@ impl MembersList {
     }
 }
 
-fn create_row(member: Member, power_level: Option<i32>) -> Option<gtk::ListBoxRow> {
+fn create_row(
+    member: Member,
+    power_level: Option<i32>,
+    allowed: Vec<Moderation>,
+) -> Option<gtk::ListBoxRow> {
     let row = gtk::ListBoxRow::new();
     row.connect_draw(clone!(@strong member => move |w, _| {
         if w.get_child().is_none() {
-            w.add(&load_row_content(member.clone(), power_level));
+            let content = load_row_content(member.clone(), power_level);
+            let event_box = gtk::EventBox::new();
+            let uid = member.uid.clone();
+            let allowed = allowed.clone();
+            event_box.connect_button_press_event(move |w, e| {
+                if e.get_button() == 3 {
+                    widgets::MemberMenu::new(&uid, &allowed).popup(w);
+                    gtk::Inhibit(true)
+                } else {
+                    gtk::Inhibit(false)
+                }
+            });
+            event_box.add(&content);
+            event_box.show();
+            w.add(&event_box);
         }
         gtk::Inhibit(false)
     }));
@@ -207,11 +230,13 @@ fn add_rows(
     container: gtk::ListBox,
     members: Vec<Member>,
     admins: HashMap<UserId, i32>,
+    allowed: &HashMap<UserId, Vec<Moderation>>,
 ) -> Option<usize> {
     /* Load just enough members to fill atleast the visible list */
     for member in members.iter() {
         let admin = admins.get(&member.uid).copied();
-        container.insert(&create_row(member.clone(), admin)?, -1);
+        let actions = allowed.get(&member.uid).cloned().unwrap_or_default();
+        container.insert(&create_row(member.clone(), admin, actions)?, -1);
     }
     None
 }
@@ -1,4 +1,4 @@
-use crate::i18n::i18n;
+use crate::i18n::{i18n, i18n_k};
 use itertools::Itertools;
 
 use crate::appop::UserInfoCache;
@@ -17,9 +17,12 @@ use crate::util::markup_text;
 use crate::cache::download_to_cache;
 use crate::cache::download_to_cache_username;
 use crate::cache::download_to_cache_username_emote;
+use crate::cache::OutboxState;
 
+use crate::app::App;
 use crate::globals;
 use crate::uitypes::MessageContent as Message;
+use crate::uitypes::ReplyHeader;
 use crate::uitypes::RowType;
 use crate::widgets;
 use crate::widgets::message_menu::MessageMenu;
@@ -104,6 +107,10 @@ impl MessageBox {
                 upload_attachment_msg.add(&gtk::Label::new(Some(i18n("Uploading file.").as_str())));
                 upload_attachment_msg
             }
+            RowType::State => {
+                self.header = false;
+                self.state_widget(msg)
+            }
             _ if has_header => {
                 self.row.set_margin_top(12);
                 self.header = true;
@@ -118,7 +125,9 @@ impl MessageBox {
         self.eventbox.add(&w);
         self.row.add(&self.eventbox);
         self.row.show_all();
-        self.connect_right_click_menu(msg, None);
+        if msg.mtype != RowType::State {
+            self.connect_right_click_menu(msg, None);
+        }
     }
 
     pub fn get_listbox_row(&self) -> &gtk::ListBoxRow {
@@ -130,15 +139,62 @@ impl MessageBox {
         thread_pool: ThreadPool,
         user_info_cache: UserInfoCache,
         msg: &Message,
+        txn_id: &str,
+        state: OutboxState,
     ) -> MessageBox {
         self.create(thread_pool, user_info_cache, msg, true, true);
         {
             let w = self.get_listbox_row();
             w.get_style_context().add_class("msg-tmp");
+
+            let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+            w.remove(&self.eventbox);
+            content.add(&self.eventbox);
+            content.add(&self.build_outbox_state(txn_id, state));
+            w.add(&content);
+            w.show_all();
         }
         self
     }
 
+    /* The state of a message we haven't sent yet, with what the user can do with it */
+    fn build_outbox_state(&self, txn_id: &str, state: OutboxState) -> gtk::Box {
+        let state_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        state_box.set_margin_start(50);
+        state_box.get_style_context().add_class("msg-state");
+
+        let (text, can_retry, can_discard) = match state {
+            OutboxState::Queued => (i18n("Waiting for the network"), false, true),
+            OutboxState::Sending => (i18n("Sending…"), false, false),
+            OutboxState::Failed => (i18n("Couldn’t send this message"), true, true),
+        };
+        let label = gtk::Label::new(Some(text.as_str()));
+        label.set_halign(gtk::Align::Start);
+        label.get_style_context().add_class("dim-label");
+        if state == OutboxState::Failed {
+            label.get_style_context().add_class("error");
+        }
+        state_box.pack_start(&label, false, false, 0);
+
+        let data = glib::Variant::from(txn_id);
+        if can_retry {
+            let retry = gtk::Button::new_with_label(i18n("Retry").as_str());
+            retry.set_relief(gtk::ReliefStyle::None);
+            retry.set_action_name(Some("app.retry-message"));
+            retry.set_action_target_value(Some(&data));
+            state_box.pack_start(&retry, false, false, 0);
+        }
+        if can_discard {
+            let discard = gtk::Button::new_with_label(i18n("Discard").as_str());
+            discard.set_relief(gtk::ReliefStyle::None);
+            discard.set_action_name(Some("app.discard-message"));
+            discard.set_action_target_value(Some(&data));
+            state_box.pack_start(&discard, false, false, 0);
+        }
+
+        state_box
+    }
+
     pub fn update_header(
         &mut self,
         thread_pool: ThreadPool,
@@ -199,6 +255,30 @@ impl MessageBox {
         msg_widget
     }
 
+    fn state_widget(&self, msg: &Message) -> gtk::Box {
+        // msg
+        // +--------+-------------+------+
+        // |        | description | date |
+        // +--------+-------------+------+
+        let msg_widget = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        msg_widget.set_margin_start(50);
+        msg_widget.set_margin_top(3);
+        msg_widget.set_margin_bottom(3);
+
+        let description = gtk::Label::new(Some(&msg.body));
+        description.set_line_wrap(true);
+        description.set_line_wrap_mode(pango::WrapMode::WordChar);
+        description.set_xalign(0.0);
+        description.set_halign(gtk::Align::Start);
+        description.get_style_context().add_class("state-change");
+        let date = self.build_room_msg_date(&msg.date);
+
+        msg_widget.pack_start(&description, true, true, 0);
+        msg_widget.pack_start(&date, false, false, 0);
+
+        msg_widget
+    }
+
     fn build_room_msg_content(
         &mut self,
         thread_pool: ThreadPool,
@@ -209,6 +289,8 @@ impl MessageBox {
         // +---------+
         // | info    |
         // +---------+
+        // | reply   |
+        // +---------+
         // | body_bx |
         // +---------+
         let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
@@ -220,12 +302,82 @@ impl MessageBox {
             content.pack_start(&info, false, false, 0);
         }
 
+        if let Some(reply) = msg.reply.as_ref() {
+            content.pack_start(&self.build_room_msg_reply(reply), false, false, 0);
+        }
+
         let body_bx = self.build_room_msg_body_bx(thread_pool, msg);
         content.pack_start(&body_bx, true, true, 0);
 
+        if !msg.reactions.is_empty() {
+            content.pack_start(&self.build_room_msg_reactions(msg), false, false, 0);
+        }
+
         content
     }
 
+    /* The message this one replies to, clicking it shows that message */
+    fn build_room_msg_reply(&self, reply: &ReplyHeader) -> gtk::Button {
+        let title = match reply.sender_name.as_ref() {
+            Some(name) => i18n_k("In reply to {name}", &[("name", name)]),
+            None => i18n("In reply to a message"),
+        };
+        let label = gtk::Label::new(None);
+        label.set_markup(&format!(
+            "<b>{}</b> {}",
+            markup_text(&title),
+            markup_text(&reply.snippet)
+        ));
+        label.set_ellipsize(pango::EllipsizeMode::End);
+        label.set_halign(gtk::Align::Start);
+
+        let button = gtk::Button::new();
+        button.add(&label);
+        button.set_relief(gtk::ReliefStyle::None);
+        button.set_halign(gtk::Align::Start);
+        button.get_style_context().add_class("reply-header");
+
+        let data = glib::Variant::from(reply.event_id.to_string());
+        button.set_action_name(Some("message.open_reply"));
+        button.set_action_target_value(Some(&data));
+
+        button
+    }
+
+    fn build_room_msg_reactions(&self, msg: &Message) -> gtk::FlowBox {
+        let reactions = gtk::FlowBox::new();
+        reactions.set_selection_mode(gtk::SelectionMode::None);
+        reactions.set_homogeneous(false);
+        reactions.set_column_spacing(6);
+        reactions.set_row_spacing(6);
+        reactions.set_max_children_per_line(20);
+        reactions.set_margin_top(3);
+        reactions.set_halign(gtk::Align::Start);
+
+        // Reactions are for the original message, not for its edits
+        let target = msg.msg.replace.clone().or_else(|| msg.id.clone());
+        for reaction in msg.reactions.iter() {
+            let chip = gtk::Button::new_with_label(&format!("{} {}", reaction.key, reaction.count));
+            chip.get_style_context().add_class("reaction");
+            if reaction.ours.is_some() {
+                chip.get_style_context().add_class("reaction-ours");
+            }
+
+            if let Some(target) = target.clone() {
+                let key = reaction.key.clone();
+                chip.connect_clicked(move |_| {
+                    let target = target.clone();
+                    let key = key.clone();
+                    APPOP!(toggle_reaction, (target, key));
+                });
+            }
+
+            reactions.add(&chip);
+        }
+
+        reactions
+    }
+
     fn build_room_msg_body_bx(&mut self, thread_pool: ThreadPool, msg: &Message) -> gtk::Box {
         // body_bx
         // +------+-----------+
@@ -246,16 +398,21 @@ impl MessageBox {
         body_bx.pack_start(&body, true, true, 0);
 
         if let Some(replace_date) = msg.replace_date {
-            let edit_mark = gtk::Image::new_from_icon_name(
-                Some("document-edit-symbolic"),
-                gtk::IconSize::Button,
-            );
+            let edit_mark = gtk::Button::new_with_label(&i18n("(edited)"));
+            edit_mark.set_relief(gtk::ReliefStyle::None);
             edit_mark.get_style_context().add_class("edit-mark");
             edit_mark.set_valign(gtk::Align::End);
 
             let edit_tooltip = replace_date.format(&i18n("Last edited %c")).to_string();
             edit_mark.set_tooltip_text(Some(&edit_tooltip));
 
+            // The history is kept by the original message
+            if let Some(evid) = msg.msg.replace.as_ref().or_else(|| msg.id.as_ref()) {
+                let data = glib::Variant::from(evid.to_string());
+                edit_mark.set_action_name(Some("message.edit_history"));
+                edit_mark.set_action_target_value(Some(&data));
+            }
+
             body_bx.pack_start(&edit_mark, false, false, 0);
         }
         body_bx
@@ -565,7 +722,7 @@ impl MessageBox {
 
             let evid = msg.id.as_ref();
             let redactable = msg.redactable;
-            let menu = MessageMenu::new(evid, &RowType::Video, &redactable, None, None);
+            let menu = MessageMenu::new(evid, &RowType::Video, &redactable, &false, None, None);
             menu_button.set_popover(Some(&menu.get_popover()));
 
             bx.pack_start(&overlay, true, true, 0);
@@ -699,6 +856,7 @@ impl MessageBox {
     fn connect_right_click_menu(&self, msg: &Message, label: Option<&gtk::Label>) -> Option<()> {
         let mtype = msg.mtype;
         let redactable = msg.redactable;
+        let editable = msg.editable;
         let widget = if let Some(l) = label {
             l.upcast_ref::<gtk::Widget>()
         } else {
@@ -710,7 +868,14 @@ impl MessageBox {
         widget.connect_button_press_event(
             clone!(@weak eventbox => @default-return Inhibit(false), move |w, e| {
                 if e.get_button() == 3 {
-                    MessageMenu::new(id.as_ref(), &mtype, &redactable, Some(&eventbox), Some(w));
+                    MessageMenu::new(
+                        id.as_ref(),
+                        &mtype,
+                        &redactable,
+                        &editable,
+                        Some(&eventbox),
+                        Some(w),
+                    );
                     Inhibit(true)
                 } else {
                     Inhibit(false)
@@ -725,6 +890,7 @@ impl MessageBox {
                     id.as_ref(),
                     &mtype,
                     &redactable,
+                    &editable,
                     Some(&eventbox),
                     Some(&widget),
                 );
@@ -2,12 +2,18 @@ use fractal_api::identifiers::EventId;
 use gdk::prelude::*;
 use gtk::prelude::*;
 
+use crate::app::App;
 use crate::uitypes::RowType;
 
+/* The reactions offered in the menu, any other one can be sent as a message */
+const QUICK_REACTIONS: &[&str] = &["👍", "❤️", "😄", "🎉", "😮", "😢"];
+
 #[derive(Clone)]
 struct Widgets {
     popover: gtk::Popover,
+    quick_reactions_box: gtk::Box,
     reply_button: gtk::ModelButton,
+    edit_button: gtk::ModelButton,
     open_with_button: gtk::ModelButton,
     save_image_as_button: gtk::ModelButton,
     save_video_as_button: gtk::ModelButton,
@@ -20,7 +26,12 @@ struct Widgets {
 }
 
 impl Widgets {
-    pub fn new(id: Option<&EventId>, mtype: &RowType, redactable: &bool) -> Widgets {
+    pub fn new(
+        id: Option<&EventId>,
+        mtype: &RowType,
+        redactable: &bool,
+        editable: &bool,
+    ) -> Widgets {
         let builder = gtk::Builder::new();
         builder
             .add_from_resource("/org/gnome/Fractal/ui/message_menu.ui")
@@ -30,10 +41,18 @@ impl Widgets {
             .get_object("message_menu_popover")
             .expect("Can't find message_menu_popover in ui file.");
 
+        let quick_reactions_box: gtk::Box = builder
+            .get_object("quick_reactions_box")
+            .expect("Can't find quick_reactions_box in ui file.");
+
         let reply_button: gtk::ModelButton = builder
             .get_object("reply_button")
             .expect("Can't find reply_button in ui file.");
 
+        let edit_button: gtk::ModelButton = builder
+            .get_object("edit_button")
+            .expect("Can't find edit_button in ui file.");
+
         let open_with_button: gtk::ModelButton = builder
             .get_object("open_with_button")
             .expect("Can't find open_with_button in ui file.");
@@ -73,6 +92,7 @@ impl Widgets {
         /* Set visibility of buttons */
         copy_selected_button.hide();
         delete_message_button.set_visible(*redactable);
+        edit_button.set_visible(*editable);
         menu_separator.set_visible(*redactable);
         open_with_button.set_visible(mtype == &RowType::Image || mtype == &RowType::Video);
         save_image_as_button.set_visible(mtype == &RowType::Image);
@@ -83,6 +103,7 @@ impl Widgets {
         let evid = id.map(|evid| evid.to_string()).unwrap_or_default();
         let data = glib::Variant::from(evid);
         reply_button.set_action_target_value(Some(&data));
+        edit_button.set_action_target_value(Some(&data));
         open_with_button.set_action_target_value(Some(&data));
         view_source_button.set_action_target_value(Some(&data));
         delete_message_button.set_action_target_value(Some(&data));
@@ -94,7 +115,9 @@ impl Widgets {
 
         Widgets {
             popover,
+            quick_reactions_box,
             reply_button,
+            edit_button,
             open_with_button,
             save_image_as_button,
             save_video_as_button,
@@ -125,17 +148,21 @@ impl MessageMenu {
         id: Option<&EventId>,
         mtype: &RowType,
         redactable: &bool,
+        editable: &bool,
         widget: Option<&gtk::EventBox>,
         label: Option<&gtk::Widget>,
     ) -> MessageMenu {
         let menu = MessageMenu {
-            widgets: Widgets::new(id, mtype, redactable),
+            widgets: Widgets::new(id, mtype, redactable, editable),
         };
         /* Copy selected text works a little different then the other actions, because it need the
          * label */
         if let Some(label) = label {
             menu.connect_copy_selected_text(label);
         }
+        if let Some(id) = id {
+            menu.add_quick_reactions(id);
+        }
         if let Some(widget) = widget {
             menu.show(widget);
         }
@@ -167,6 +194,23 @@ impl MessageMenu {
             });
     }
 
+    fn add_quick_reactions(&self, id: &EventId) {
+        for key in QUICK_REACTIONS {
+            let button = gtk::Button::new_with_label(key);
+            button.set_relief(gtk::ReliefStyle::None);
+            let popover = self.widgets.popover.clone();
+            let id = id.clone();
+            button.connect_clicked(move |_| {
+                let target = id.clone();
+                let key = key.to_string();
+                popover.popdown();
+                APPOP!(toggle_reaction, (target, key));
+            });
+            self.widgets.quick_reactions_box.add(&button);
+        }
+        self.widgets.quick_reactions_box.show_all();
+    }
+
     /* This should also be a action, but for some reason we need to set again the selection on the
      * label after the click event */
     fn connect_copy_selected_text(&self, w: &gtk::Widget) -> Option<()> {
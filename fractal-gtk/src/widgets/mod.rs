@@ -2,6 +2,7 @@ mod address;
 mod autocomplete;
 pub mod avatar;
 mod divider;
+mod edit_history;
 pub mod error_dialog;
 pub mod file_dialog;
 pub mod image;
@@ -13,12 +14,14 @@ mod member;
 pub mod members_list;
 mod message;
 pub mod message_menu;
+mod power_levels;
 mod room;
 pub mod room_history;
 pub mod room_settings;
 mod roomlist;
 mod roomrow;
 mod scroll_widget;
+mod search_result;
 mod source_dialog;
 mod sourceview_entry;
 
@@ -30,6 +33,7 @@ pub use self::avatar::AvatarBadgeColor;
 pub use self::avatar::AvatarData;
 pub use self::avatar::AvatarExt;
 pub use self::divider::NewMessageDivider;
+pub use self::edit_history::EditHistoryDialog;
 pub use self::error_dialog as ErrorDialog;
 pub use self::file_dialog as FileDialog;
 pub use self::inline_player::AudioPlayerWidget;
@@ -40,8 +44,10 @@ pub use self::kicked_dialog::KickedDialog;
 pub use self::login::LoginWidget;
 pub use self::media_viewer::MediaViewer;
 pub use self::member::MemberBox;
+pub use self::member::MemberMenu;
 pub use self::members_list::MembersList;
 pub use self::message::MessageBox;
+pub use self::power_levels::PowerLevelsEditor;
 pub use self::room::RoomBox;
 pub use self::room_history::RoomHistory;
 pub use self::room_settings::RoomSettings;
@@ -50,5 +56,6 @@ pub use self::roomrow::RoomRow;
 pub use self::scroll_widget::page_down;
 pub use self::scroll_widget::page_up;
 pub use self::scroll_widget::ScrollWidget;
+pub use self::search_result::SearchResultBox;
 pub use self::source_dialog::SourceDialog;
 pub use self::sourceview_entry::SVEntry;
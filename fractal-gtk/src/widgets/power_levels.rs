@@ -0,0 +1,295 @@
+use fractal_api::events::room::PowerLevelsEventContent;
+use fractal_api::identifiers::UserId;
+use glib::clone;
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use crate::i18n::i18n;
+use crate::types::Room;
+
+/* The part of the power levels event a spin button edits */
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    UsersDefault,
+    User(UserId),
+    Invite,
+    Kick,
+    Ban,
+    Redact,
+    EventsDefault,
+    StateDefault,
+    Event(String),
+}
+
+fn event_description(event_type: &str) -> String {
+    match event_type {
+        "m.room.name" => i18n("Change the room name"),
+        "m.room.topic" => i18n("Change the topic"),
+        "m.room.avatar" => i18n("Change the room avatar"),
+        "m.room.canonical_alias" => i18n("Change the main address"),
+        "m.room.history_visibility" => i18n("Change who can read the history"),
+        "m.room.join_rules" => i18n("Change who can join"),
+        "m.room.power_levels" => i18n("Change permissions"),
+        "m.room.encryption" => i18n("Enable encryption"),
+        "m.room.server_acl" => i18n("Change the allowed servers"),
+        "m.room.tombstone" => i18n("Upgrade the room"),
+        event_type => event_type.to_string(),
+    }
+}
+
+/// Editor of the `m.room.power_levels` event of a room. Levels higher than ours can't be
+/// changed, nor can we give a level higher than ours.
+#[derive(Debug, Clone)]
+pub struct PowerLevelsEditor {
+    container: gtk::Box,
+    users_list: gtk::ListBox,
+    save_button: gtk::Button,
+    levels: PowerLevelsEventContent,
+    own_level: i64,
+    editable: bool,
+    fields: Rc<RefCell<Vec<(Field, gtk::SpinButton)>>>,
+}
+
+impl PowerLevelsEditor {
+    /// Returns None if we don't know the power levels of the room
+    pub fn new(room: &Room, uid: &UserId, save_button: gtk::Button) -> Option<PowerLevelsEditor> {
+        let levels = room.power_levels.clone()?;
+        let editor = PowerLevelsEditor {
+            container: gtk::Box::new(gtk::Orientation::Vertical, 6),
+            users_list: gtk::ListBox::new(),
+            save_button,
+            own_level: room.power_level(uid),
+            editable: room.can_send_state(uid, "m.room.power_levels"),
+            levels,
+            fields: Default::default(),
+        };
+
+        editor.create_users_list(room, uid);
+        editor.create_actions_list();
+        editor.save_button.set_visible(editor.editable);
+        editor.save_button.set_sensitive(false);
+        editor.container.show_all();
+
+        Some(editor)
+    }
+
+    pub fn widget(&self) -> &gtk::Box {
+        &self.container
+    }
+
+    /// The edited power levels
+    pub fn levels(&self) -> PowerLevelsEventContent {
+        let mut levels = self.levels.clone();
+        for (field, spin) in self.fields.borrow().iter() {
+            let value = spin.get_value_as_int() as i64;
+            match field {
+                Field::UsersDefault => levels.users_default = value,
+                Field::User(uid) => {
+                    levels.users.insert(uid.clone(), value);
+                }
+                Field::Invite => levels.invite = value,
+                Field::Kick => levels.kick = value,
+                Field::Ban => levels.ban = value,
+                Field::Redact => levels.redact = value,
+                Field::EventsDefault => levels.events_default = value,
+                Field::StateDefault => levels.state_default = value,
+                Field::Event(event_type) => {
+                    levels.events.insert(event_type.clone(), value);
+                }
+            }
+        }
+        levels
+    }
+
+    fn create_users_list(&self, room: &Room, uid: &UserId) {
+        self.add_heading(&i18n("Members"));
+        self.add_list(&self.users_list);
+
+        self.add_row(
+            &self.users_list,
+            Field::UsersDefault,
+            &i18n("Default level"),
+            None,
+            self.levels.users_default,
+            true,
+        );
+
+        let mut users: Vec<(&UserId, &i64)> = self.levels.users.iter().collect();
+        users.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        for (user, level) in users {
+            let name = room.members.get(user).map(|member| member.get_alias());
+            // We can lower our own level, but not change the one of our peers
+            let changeable = user == uid || *level < self.own_level;
+            self.add_user_row(user, name, *level, changeable);
+        }
+
+        if self.editable {
+            self.add_new_user_row();
+        }
+    }
+
+    fn create_actions_list(&self) {
+        let list = gtk::ListBox::new();
+        self.add_heading(&i18n("Level needed to"));
+        self.add_list(&list);
+
+        let actions = vec![
+            (
+                Field::EventsDefault,
+                i18n("Send messages"),
+                self.levels.events_default,
+            ),
+            (Field::Invite, i18n("Invite users"), self.levels.invite),
+            (Field::Kick, i18n("Remove users"), self.levels.kick),
+            (Field::Ban, i18n("Ban users"), self.levels.ban),
+            (
+                Field::Redact,
+                i18n("Delete messages of others"),
+                self.levels.redact,
+            ),
+            (
+                Field::StateDefault,
+                i18n("Change the settings"),
+                self.levels.state_default,
+            ),
+        ];
+        for (field, description, level) in actions {
+            self.add_row(&list, field, &description, None, level, true);
+        }
+
+        let mut events: Vec<(&String, &i64)> = self.levels.events.iter().collect();
+        events.sort();
+        for (event_type, level) in events {
+            let description = event_description(event_type);
+            let subtitle = Some(event_type.as_str()).filter(|t| *t != description);
+            let field = Field::Event(event_type.clone());
+            self.add_row(&list, field, &description, subtitle, *level, true);
+        }
+    }
+
+    fn add_heading(&self, text: &str) {
+        let label = gtk::Label::new(Some(text));
+        label.set_xalign(0.);
+        label.set_margin_top(6);
+        label.get_style_context().add_class("dim-label");
+        self.container.pack_start(&label, false, false, 0);
+    }
+
+    fn add_list(&self, list: &gtk::ListBox) {
+        let frame = gtk::Frame::new(None);
+        frame.set_shadow_type(gtk::ShadowType::In);
+        list.set_selection_mode(gtk::SelectionMode::None);
+        list.get_style_context().add_class("list-with-separator");
+        frame.add(list);
+        self.container.pack_start(&frame, false, false, 0);
+    }
+
+    fn add_user_row(&self, uid: &UserId, name: Option<String>, level: i64, changeable: bool) {
+        let uid_str = uid.to_string();
+        let (title, subtitle) = match name.as_ref() {
+            Some(name) => (name.as_str(), Some(uid_str.as_str())),
+            None => (uid_str.as_str(), None),
+        };
+        let field = Field::User(uid.clone());
+        self.add_row(&self.users_list, field, title, subtitle, level, changeable);
+    }
+
+    /* The last row of the users list, to give a level to somebody who doesn't have one yet */
+    fn add_new_user_row(&self) {
+        let row = gtk::ListBoxRow::new();
+        let b = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+        let entry = gtk::Entry::new();
+        let button = gtk::Button::new_with_label(&i18n("Add"));
+
+        entry.set_placeholder_text(Some(i18n("Matrix ID").as_str()));
+        entry.set_hexpand(true);
+        b.set_margin_start(12);
+        b.set_margin_end(12);
+        b.set_margin_top(6);
+        b.set_margin_bottom(6);
+        b.pack_start(&entry, true, true, 0);
+        b.pack_start(&button, false, false, 0);
+        row.add(&b);
+        row.set_selectable(false);
+        self.users_list.insert(&row, -1);
+
+        let this = self.clone();
+        button.connect_clicked(clone!(@weak entry, @weak row => move |_| {
+            let text = entry.get_text().map(|t| t.to_string()).unwrap_or_default();
+            let uid = match UserId::try_from(text.trim()) {
+                Ok(uid) => uid,
+                Err(_) => {
+                    entry.get_style_context().add_class("error");
+                    return;
+                }
+            };
+            entry.get_style_context().remove_class("error");
+            entry.set_text("");
+
+            let exists = this
+                .fields
+                .borrow()
+                .iter()
+                .any(|(field, _)| *field == Field::User(uid.clone()));
+            if !exists {
+                this.add_user_row(&uid, None, this.levels.users_default, true);
+                // Keep the entry at the end of the list
+                this.users_list.remove(&row);
+                this.users_list.insert(&row, -1);
+                this.save_button.set_sensitive(true);
+            }
+        }));
+        entry.connect_activate(clone!(@weak button => move |_| button.clicked()));
+    }
+
+    fn add_row(
+        &self,
+        list: &gtk::ListBox,
+        field: Field,
+        title: &str,
+        subtitle: Option<&str>,
+        level: i64,
+        changeable: bool,
+    ) {
+        let row = gtk::ListBoxRow::new();
+        let b = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+        let labels = gtk::Box::new(gtk::Orientation::Vertical, 0);
+
+        let title = gtk::Label::new(Some(title));
+        title.set_xalign(0.);
+        title.set_ellipsize(pango::EllipsizeMode::End);
+        labels.pack_start(&title, true, true, 0);
+        if let Some(subtitle) = subtitle {
+            let subtitle = gtk::Label::new(Some(subtitle));
+            subtitle.set_xalign(0.);
+            subtitle.set_ellipsize(pango::EllipsizeMode::End);
+            let style = subtitle.get_style_context();
+            style.add_class("small-font");
+            style.add_class("dim-label");
+            labels.pack_start(&subtitle, true, true, 0);
+        }
+
+        let changeable = self.editable && changeable && level <= self.own_level;
+        let max = if changeable { self.own_level } else { level };
+        let spin = gtk::SpinButton::new_with_range(level.min(0) as f64, max as f64, 1.);
+        spin.set_value(level as f64);
+        spin.set_valign(gtk::Align::Center);
+        spin.set_sensitive(changeable);
+        let button = self.save_button.clone();
+        spin.connect_value_changed(move |_| button.set_sensitive(true));
+
+        b.set_margin_start(12);
+        b.set_margin_end(12);
+        b.set_margin_top(6);
+        b.set_margin_bottom(6);
+        b.pack_start(&labels, true, true, 0);
+        b.pack_start(&spin, false, false, 0);
+        row.add(&b);
+        row.set_selectable(false);
+        row.show_all();
+        list.insert(&row, -1);
+        self.fields.borrow_mut().push((field, spin));
+    }
+}
@@ -6,18 +6,21 @@ use fragile::Fragile;
 use log::warn;
 use std::cell::RefCell;
 use std::collections::VecDeque;
+use std::mem;
 use std::rc::Rc;
 
 use crate::appop::{AppOp, UserInfoCache};
-use crate::i18n::i18n;
+use crate::i18n::{i18n, ni18n_k};
+use crate::types::{MembershipChange, Message, StateChange};
 use crate::uitypes::MessageContent;
+use crate::uitypes::ReactionGroup;
 use crate::uitypes::RowType;
 
 use crate::backend::ThreadPool;
 use crate::globals;
 use crate::widgets;
 use crate::widgets::{PlayerExt, VideoPlayerWidget};
-use fractal_api::identifiers::RoomId;
+use fractal_api::identifiers::{EventId, RoomId, UserId};
 use fractal_api::r0::AccessToken;
 use fractal_api::url::Url;
 use gio::ActionMapExt;
@@ -503,16 +506,46 @@ impl RoomHistory {
                         let new_divider_index = rows.borrow().list.len() - 1;
                         rows.borrow_mut().new_divider_index = Some(new_divider_index);
                     }
-                    item.widget = Some(create_row(
-                        thread_pool.clone(),
-                        user_info_cache.clone(),
-                        item.clone(),
-                        has_header,
-                        server_url.clone(),
-                        access_token.clone(),
-                        &rows,
-                    ));
-                    rows.borrow_mut().add_top(Element::Message(item));
+                    let collapse_with = match rows.borrow().list.back() {
+                        Some(Element::Message(ref first))
+                            if !item.last_viewed && is_membership_row(&item) =>
+                        {
+                            first
+                                .widget
+                                .clone()
+                                .filter(|_| is_membership_row(first))
+                                .map(|widget| (collapse_membership(first, &item, false), widget))
+                        }
+                        _ => None,
+                    };
+                    if let Some((mut collapsed, widget)) = collapse_with {
+                        collapsed.widget = Some(create_row(
+                            thread_pool.clone(),
+                            user_info_cache.clone(),
+                            collapsed.clone(),
+                            false,
+                            server_url.clone(),
+                            access_token.clone(),
+                            &rows,
+                        ));
+                        let index = rows.borrow().list.len() - 1;
+                        rows.borrow_mut().replace_item(
+                            index,
+                            widget.get_listbox_row(),
+                            Element::Message(collapsed),
+                        );
+                    } else {
+                        item.widget = Some(create_row(
+                            thread_pool.clone(),
+                            user_info_cache.clone(),
+                            item.clone(),
+                            has_header,
+                            server_url.clone(),
+                            access_token.clone(),
+                            &rows,
+                        ));
+                        rows.borrow_mut().add_top(Element::Message(item));
+                    }
                     if let Some(day_divider) = day_divider {
                         rows.borrow_mut().add_top(day_divider);
                     }
@@ -569,6 +602,29 @@ impl RoomHistory {
             }
         };
 
+        if day_divider.is_none() && !item.last_viewed && is_membership_row(&item) {
+            let collapse_with = match rows.list.front() {
+                Some(Element::Message(ref last)) if is_membership_row(last) => last
+                    .widget
+                    .clone()
+                    .map(|widget| (collapse_membership(last, &item, true), widget)),
+                _ => None,
+            };
+            if let Some((mut collapsed, widget)) = collapse_with {
+                collapsed.widget = Some(create_row(
+                    thread_pool,
+                    user_info_cache,
+                    collapsed.clone(),
+                    false,
+                    self.server_url.clone(),
+                    self.access_token.clone(),
+                    &self.rows,
+                ));
+                rows.replace_item(0, widget.get_listbox_row(), Element::Message(collapsed));
+                return None;
+            }
+        }
+
         if item.last_viewed {
             let divider = Element::NewDivider(List::create_new_message_divider(self.rows.clone()));
             rows.add_bottom(divider);
@@ -630,6 +686,45 @@ impl RoomHistory {
         None
     }
 
+    /* Show the reactions of a message again, the row is built from scratch */
+    pub fn update_reactions(
+        &mut self,
+        thread_pool: ThreadPool,
+        user_info_cache: UserInfoCache,
+        target: &EventId,
+        reactions: Vec<ReactionGroup>,
+    ) -> Option<()> {
+        let mut rows = self.rows.borrow_mut();
+
+        let (i, msg) = rows.list.iter().enumerate().find_map(|(i, e)| match e {
+            Element::Message(ref itermessage)
+                if itermessage.id.as_ref() == Some(target)
+                    || itermessage.msg.replace.as_ref() == Some(target) =>
+            {
+                Some((i, itermessage))
+            }
+            _ => None,
+        })?;
+        if msg.reactions == reactions {
+            return None;
+        }
+
+        let msg_widget = msg.widget.clone()?;
+        let mut item = msg.clone();
+        item.reactions = reactions;
+        item.widget = Some(create_row(
+            thread_pool,
+            user_info_cache,
+            item.clone(),
+            msg_widget.header,
+            self.server_url.clone(),
+            self.access_token.clone(),
+            &self.rows,
+        ));
+        rows.replace_item(i, msg_widget.get_listbox_row(), Element::Message(item));
+        None
+    }
+
     pub fn remove_message(
         &mut self,
         thread_pool: ThreadPool,
@@ -726,6 +821,34 @@ impl RoomHistory {
         let scrolled_window = self.rows.borrow().view.get_scrolled_window();
         widgets::page_down(scrolled_window);
     }
+
+    /* Scroll to the row of a message, returns false if it isn't in the history */
+    pub fn scroll_to_message(&self, event_id: &EventId) -> bool {
+        let rows = self.rows.borrow();
+        let row = rows.list.iter().find_map(|element| match element {
+            Element::Message(ref item)
+                if item.id.as_ref() == Some(event_id)
+                    || item.msg.replace.as_ref() == Some(event_id) =>
+            {
+                Some(element.get_listbox_row())
+            }
+            _ => None,
+        });
+        let row = match row {
+            Some(row) => row,
+            None => return false,
+        };
+
+        let scrolled_window = rows.view.get_scrolled_window();
+        if let Some((_, y)) = row.translate_coordinates(&rows.listbox, 0, 0) {
+            if let Some(adj) = scrolled_window.get_vadjustment() {
+                adj.set_value(f64::from(y));
+            }
+        }
+        row.grab_focus();
+
+        true
+    }
 }
 
 /* This function creates the content for a Row based on the content of msg */
@@ -768,9 +891,126 @@ fn create_row(
     mb
 }
 
+fn is_membership_row(row: &MessageContent) -> bool {
+    row.mtype == RowType::State
+        && row
+            .msg
+            .state_change
+            .as_ref()
+            .map_or(false, StateChange::is_membership)
+}
+
+/* Show a membership change in the row of the ones next to it, `newer` tells if it goes after
+ * them */
+fn collapse_membership(row: &MessageContent, item: &MessageContent, newer: bool) -> MessageContent {
+    let mut changes = if row.collapsed.is_empty() {
+        vec![row.msg.clone()]
+    } else {
+        row.collapsed.clone()
+    };
+    if newer {
+        changes.push(item.msg.clone());
+    } else {
+        changes.insert(0, item.msg.clone());
+    }
+
+    let mut collapsed = row.clone();
+    collapsed.body = membership_summary(&changes);
+    collapsed.collapsed = changes;
+    collapsed.widget = None;
+    collapsed
+}
+
+/* Counts the members for each kind of change, e.g. "3 users joined, 1 user left" */
+fn membership_summary(changes: &[Message]) -> String {
+    let mut kinds: Vec<(&MembershipChange, Vec<&UserId>)> = vec![];
+    for msg in changes {
+        if let Some(StateChange::Member { target, change, .. }) = msg.state_change.as_ref() {
+            let kind = kinds
+                .iter_mut()
+                .find(|(c, _)| mem::discriminant(*c) == mem::discriminant(change));
+            match kind {
+                Some((_, targets)) if !targets.contains(&target) => targets.push(target),
+                Some(_) => {}
+                None => kinds.push((change, vec![target])),
+            }
+        }
+    }
+
+    kinds
+        .into_iter()
+        .map(|(change, targets)| {
+            let n = targets.len() as u32;
+            let count = n.to_string();
+            let args = [("count", count.as_str())];
+            match change {
+                MembershipChange::Joined => {
+                    ni18n_k("{count} user joined", "{count} users joined", n, &args)
+                }
+                MembershipChange::Left => {
+                    ni18n_k("{count} user left", "{count} users left", n, &args)
+                }
+                MembershipChange::Invited => ni18n_k(
+                    "{count} user was invited",
+                    "{count} users were invited",
+                    n,
+                    &args,
+                ),
+                MembershipChange::InviteRejected => ni18n_k(
+                    "{count} user rejected the invitation",
+                    "{count} users rejected the invitation",
+                    n,
+                    &args,
+                ),
+                MembershipChange::InviteRevoked => ni18n_k(
+                    "{count} invitation was revoked",
+                    "{count} invitations were revoked",
+                    n,
+                    &args,
+                ),
+                MembershipChange::Kicked => ni18n_k(
+                    "{count} user was kicked",
+                    "{count} users were kicked",
+                    n,
+                    &args,
+                ),
+                MembershipChange::Banned => ni18n_k(
+                    "{count} user was banned",
+                    "{count} users were banned",
+                    n,
+                    &args,
+                ),
+                MembershipChange::Unbanned => ni18n_k(
+                    "{count} user was unbanned",
+                    "{count} users were unbanned",
+                    n,
+                    &args,
+                ),
+                MembershipChange::Renamed(_) => ni18n_k(
+                    "{count} user changed their name",
+                    "{count} users changed their name",
+                    n,
+                    &args,
+                ),
+                MembershipChange::AvatarChanged => ni18n_k(
+                    "{count} user changed their avatar",
+                    "{count} users changed their avatar",
+                    n,
+                    &args,
+                ),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 /* returns if two messages should have only a single header or not */
 fn should_group_message(msg: &MessageContent, prev: &MessageContent) -> bool {
-    if msg.sender == prev.sender && !prev.msg.redacted {
+    if msg.sender == prev.sender
+        && !prev.msg.redacted
+        && msg.mtype != RowType::State
+        && prev.mtype != RowType::State
+    {
         let diff = msg.date.signed_duration_since(prev.date);
         let minutes = diff.num_minutes();
         minutes < globals::MINUTES_TO_SPLIT_MSGS
@@ -1,12 +1,18 @@
-use crate::backend::{room, HandleError};
-use fractal_api::identifiers::UserId;
+use crate::backend::{room, user, HandleError};
+use fractal_api::events::room::{
+    CanonicalAliasEventContent, GuestAccess, GuestAccessEventContent, HistoryVisibility,
+    HistoryVisibilityEventContent, JoinRule, JoinRulesEventContent,
+};
+use fractal_api::identifiers::{RoomAliasId, RoomId, UserId};
 use fractal_api::r0::AccessToken;
 use glib::clone;
+use serde_json::json;
 use std::cell::RefCell;
+use std::convert::TryFrom;
 use std::rc::Rc;
 use std::thread;
 
-use crate::i18n::ni18n_f;
+use crate::i18n::{i18n, i18n_k, ni18n_f};
 use fractal_api::url::Url;
 use gio::prelude::*;
 use gtk::prelude::*;
@@ -15,11 +21,14 @@ use crate::actions;
 use crate::actions::{ButtonState, StateExt};
 use crate::app::App;
 use crate::types::Member;
+use crate::types::Moderation;
 use crate::types::Room;
+use crate::types::RoomNotifications;
 use crate::util::markup_text;
 use crate::widgets;
 use crate::widgets::avatar::AvatarExt;
 use crate::widgets::members_list::MembersList;
+use crate::widgets::PowerLevelsEditor;
 
 #[derive(Debug, Clone)]
 pub struct RoomSettings {
@@ -28,6 +37,9 @@ pub struct RoomSettings {
     uid: UserId,
     builder: gtk::Builder,
     members_list: Option<MembersList>,
+    power_levels: Option<PowerLevelsEditor>,
+    history_checks: Vec<(HistoryVisibility, gtk::Image)>,
+    notification_checks: Vec<(RoomNotifications, gtk::Image)>,
     server_url: Url,
     access_token: AccessToken,
 }
@@ -59,6 +71,9 @@ impl RoomSettings {
             uid,
             builder,
             members_list: None,
+            power_levels: None,
+            history_checks: Vec::new(),
+            notification_checks: Vec::new(),
             server_url,
             access_token,
         }
@@ -122,6 +137,18 @@ impl RoomSettings {
             .builder
             .get_object::<gtk::Button>("room_settings_avatar_button")
             .expect("Can't find room_settings_avatar_button in ui file.");
+        let power_levels_btn = self
+            .builder
+            .get_object::<gtk::Button>("room_settings_power_levels_button")
+            .expect("Can't find room_settings_power_levels_button in ui file.");
+        let history_list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_history_visibility_list")
+            .expect("Can't find room_settings_history_visibility_list in ui file.");
+        let notifications_list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_notifications_list")
+            .expect("Can't find room_settings_notifications_list in ui file.");
 
         let this: Rc<RefCell<RoomSettings>> = Rc::new(RefCell::new(self.clone()));
 
@@ -162,6 +189,48 @@ impl RoomSettings {
             this.borrow_mut().update_room_topic();
         }));
 
+        power_levels_btn.connect_clicked(clone!(@strong this => move |_| {
+            this.borrow().update_power_levels();
+        }));
+
+        history_list.connect_row_activated(clone!(@strong this => move |_, row| {
+            this.borrow().update_history_visibility(row.get_index());
+        }));
+
+        notifications_list.connect_row_activated(clone!(@strong this => move |_, row| {
+            this.borrow().update_notifications_level(row.get_index());
+        }));
+
+        let switches: [(&str, fn(&RoomSettings, bool)); 3] = [
+            (
+                "room_settings_guests_switch",
+                RoomSettings::update_guest_access,
+            ),
+            (
+                "room_settings_join_rule_switch",
+                RoomSettings::update_join_rule,
+            ),
+            (
+                "room_settings_publish_switch",
+                RoomSettings::update_published,
+            ),
+        ];
+        for (id, update) in switches.iter() {
+            let switch = self
+                .builder
+                .get_object::<gtk::Switch>(id)
+                .expect("Can't find switch in ui file.");
+            let update = *update;
+            // The state only follows once the server accepted the change
+            switch.connect_state_set(clone!(@strong this => move |w, active| {
+                let changed = active != w.get_state();
+                if changed {
+                    update(&this.borrow(), active);
+                }
+                Inhibit(changed)
+            }));
+        }
+
         if let Some(action) = self.actions.lookup_action("change-avatar") {
             action.bind_button_state(&avatar_btn);
             let data = glib::Variant::from(&self.room.id.to_string());
@@ -191,9 +260,7 @@ impl RoomSettings {
         let mut is_room = true;
         let mut is_group = false;
         let members: Vec<Member> = self.room.members.values().cloned().collect();
-        let power = self.room.admins.get(&self.uid).copied().unwrap_or(0);
-
-        let edit = power >= 50 && !self.room.direct;
+        let edit = self.can_change("m.room.join_rules");
 
         let description = if self.room.direct {
             is_room = false;
@@ -215,19 +282,30 @@ impl RoomSettings {
             ))
         };
 
-        self.room_settings_show_avatar(edit);
-        self.room_settings_show_room_name(name, edit);
-        self.room_settings_show_room_topic(topic, is_room, edit);
+        self.room_settings_show_avatar(self.can_change("m.room.avatar"));
+        self.room_settings_show_room_name(name, self.can_change("m.room.name"));
+        self.room_settings_show_room_topic(topic, is_room, self.can_change("m.room.topic"));
         self.room_settings_show_room_type(description);
         self.room_settings_show_members(members);
+        self.room_settings_show_power_levels();
+        self.room_settings_show_access();
+        self.room_settings_show_history_visibility();
+        self.room_settings_show_notifications();
+        self.room_settings_show_version();
+        self.show_aliases(&self.room.canonical_alias.clone().unwrap_or_default());
 
         /* admin parts */
         self.room_settings_show_group_room(is_room || is_group);
         self.room_settings_show_admin_groupe(is_group && edit);
-        self.room_settings_show_admin_room(is_room && edit);
+        self.room_settings_show_admin_room(is_room);
         self.room_settings_hide_not_implemented_widgets();
     }
 
+    /* Direct chats keep the settings they were created with */
+    fn can_change(&self, event_type: &str) -> bool {
+        !self.room.direct && self.room.can_send_state(&self.uid, event_type)
+    }
+
     /* returns the uid of the fisrt member in the room, ignoring the current user */
     fn get_direct_partner_uid(&self, members: Vec<Member>) -> Option<UserId> {
         members
@@ -335,20 +413,14 @@ impl RoomSettings {
     }
 
     pub fn room_settings_show_group_room(&self, show: bool) -> Option<()> {
-        let notify = self
-            .builder
-            .get_object::<gtk::Frame>("room_settings_notification_sounds")
-            .expect("Can't find room_settings_notification_sounds in ui file.");
         let invite = self
             .builder
             .get_object::<gtk::Button>("room_settings_invite")
             .expect("Can't find room_settings_invite in ui file.");
 
         if show {
-            notify.show();
             invite.show();
         } else {
-            notify.hide();
             invite.hide();
         }
 
@@ -379,13 +451,19 @@ impl RoomSettings {
             .builder
             .get_object::<gtk::Frame>("room_settings_join")
             .expect("Can't find room_settings_join in ui file.");
+        let history = self
+            .builder
+            .get_object::<gtk::Frame>("room_settings_history_visibility")
+            .expect("Can't find room_settings_history_visibility in ui file.");
 
         if show {
             room.show();
             join.show();
+            history.show();
         } else {
             room.hide();
             join.hide();
+            history.hide();
         }
 
         None
@@ -604,10 +682,6 @@ impl RoomSettings {
     }
 
     fn room_settings_hide_not_implemented_widgets(&self) -> Option<()> {
-        let notification = self
-            .builder
-            .get_object::<gtk::Frame>("room_settings_notification_sounds")
-            .expect("Can't find room_settings_notification_sounds in ui file.");
         let media = self
             .builder
             .get_object::<gtk::Frame>("room_settings_media")
@@ -616,24 +690,8 @@ impl RoomSettings {
             .builder
             .get_object::<gtk::Frame>("room_settings_notification_switch")
             .expect("Can't find room_settings_notification_switch in ui file.");
-        let history = self
-            .builder
-            .get_object::<gtk::Frame>("room_settings_history_visibility")
-            .expect("Can't find room_settings_history_visibility in ui file.");
-        let join = self
-            .builder
-            .get_object::<gtk::Frame>("room_settings_join")
-            .expect("Can't find room_settings_join in ui file.");
-        let room = self
-            .builder
-            .get_object::<gtk::Frame>("room_settings_room_visibility")
-            .expect("Can't find room_settings_room_visibility in ui file.");
-        notification.hide();
         media.hide();
         switch.hide();
-        history.hide();
-        room.hide();
-        join.hide();
 
         None
     }
@@ -663,10 +721,665 @@ impl RoomSettings {
             )
             .as_str(),
         );
-        let list = widgets::MembersList::new(members, self.room.admins.clone(), entry);
+        let allowed = members
+            .iter()
+            .map(|member| {
+                let actions = [Moderation::Kick, Moderation::Ban]
+                    .iter()
+                    .copied()
+                    .filter(|action| self.room.can_moderate(&self.uid, &member.uid, *action))
+                    .collect();
+                (member.uid.clone(), actions)
+            })
+            .collect();
+        let list = widgets::MembersList::new(members, self.room.admins.clone(), allowed, entry);
         let w = list.create()?;
         b.add(&w);
         self.members_list = Some(list);
         None
     }
+
+    fn room_settings_show_power_levels(&mut self) {
+        let frame = self
+            .builder
+            .get_object::<gtk::Frame>("room_settings_permissions")
+            .expect("Can't find room_settings_permissions in ui file.");
+        let container = self
+            .builder
+            .get_object::<gtk::Box>("room_settings_power_levels")
+            .expect("Can't find room_settings_power_levels in ui file.");
+        let button = self
+            .builder
+            .get_object::<gtk::Button>("room_settings_power_levels_button")
+            .expect("Can't find room_settings_power_levels_button in ui file.");
+        for w in container.get_children().iter() {
+            container.remove(w);
+        }
+
+        // Direct chats don't need them, and we may not have received them yet
+        let editor = Some(&self.room)
+            .filter(|room| !room.direct)
+            .and_then(|room| PowerLevelsEditor::new(room, &self.uid, button));
+        match editor.as_ref() {
+            Some(editor) => {
+                container.add(editor.widget());
+                frame.show();
+            }
+            None => frame.hide(),
+        }
+        self.power_levels = editor;
+    }
+
+    pub fn update_power_levels(&self) -> Option<()> {
+        let button = self
+            .builder
+            .get_object::<gtk::Button>("room_settings_power_levels_button")
+            .expect("Can't find room_settings_power_levels_button in ui file.");
+        let levels = self.power_levels.as_ref()?.levels();
+
+        button.set_sensitive(false);
+
+        let server = self.server_url.clone();
+        let access_token = self.access_token.clone();
+        let room_id = self.room.id.clone();
+        thread::spawn(move || {
+            let query =
+                room::set_power_levels(server, access_token, room_id.clone(), levels.clone());
+            match query {
+                Ok(_) => {
+                    APPOP!(set_room_power_levels, (room_id, levels));
+                }
+                Err(err) => {
+                    err.handle_error();
+                    APPOP!(show_power_levels_error);
+                }
+            }
+        });
+
+        None
+    }
+
+    /* The button stays insensitive after saving, until the levels are changed again */
+    pub fn show_power_levels_error(&self) {
+        let button = self
+            .builder
+            .get_object::<gtk::Button>("room_settings_power_levels_button")
+            .expect("Can't find room_settings_power_levels_button in ui file.");
+        button.set_sensitive(true);
+    }
+
+    fn room_settings_show_version(&self) {
+        let frame = self
+            .builder
+            .get_object::<gtk::Frame>("room_settings_version")
+            .expect("Can't find room_settings_version in ui file.");
+        let label = self
+            .builder
+            .get_object::<gtk::Label>("room_settings_version_label")
+            .expect("Can't find room_settings_version_label in ui file.");
+        let button = self
+            .builder
+            .get_object::<gtk::Button>("room_settings_upgrade_button")
+            .expect("Can't find room_settings_upgrade_button in ui file.");
+
+        // Direct chats don't need to know about versions
+        let version = match self.room.room_version.as_ref() {
+            Some(version) if !self.room.direct => version,
+            _ => {
+                frame.hide();
+                return;
+            }
+        };
+
+        let text = if self.room.replacement.is_some() {
+            i18n_k(
+                "This room uses version {version}, it has been replaced by a new room",
+                &[("version", version)],
+            )
+        } else {
+            i18n_k("This room uses version {version}", &[("version", version)])
+        };
+        label.set_text(&text);
+
+        let data = glib::Variant::from(&self.room.id.to_string());
+        button.set_action_target_value(Some(&data));
+        button.set_action_name(Some("room-settings.upgrade"));
+        button.set_visible(self.room.replacement.is_none() && self.can_change("m.room.tombstone"));
+        frame.show();
+    }
+
+    fn room_settings_show_access(&self) {
+        let guests = self
+            .builder
+            .get_object::<gtk::Switch>("room_settings_guests_switch")
+            .expect("Can't find room_settings_guests_switch in ui file.");
+        let join_rule = self
+            .builder
+            .get_object::<gtk::Switch>("room_settings_join_rule_switch")
+            .expect("Can't find room_settings_join_rule_switch in ui file.");
+        let publish = self
+            .builder
+            .get_object::<gtk::Switch>("room_settings_publish_switch")
+            .expect("Can't find room_settings_publish_switch in ui file.");
+
+        guests.set_active(self.room.guest_access == Some(GuestAccess::CanJoin));
+        guests.set_sensitive(self.can_change("m.room.guest_access"));
+        join_rule.set_active(self.room.join_rule == Some(JoinRule::Public));
+        join_rule.set_sensitive(self.can_change("m.room.join_rules"));
+
+        // The directory isn't part of the room state, we have to ask the server
+        publish.set_sensitive(false);
+        let server = self.server_url.clone();
+        let room_id = self.room.id.clone();
+        thread::spawn(move || match room::get_room_visibility(server, room_id) {
+            Ok((room_id, published)) => {
+                APPOP!(show_room_published, (room_id, published));
+            }
+            Err(err) => {
+                err.handle_error();
+            }
+        });
+    }
+
+    pub fn show_room_published(&self, published: bool) {
+        let publish = self
+            .builder
+            .get_object::<gtk::Switch>("room_settings_publish_switch")
+            .expect("Can't find room_settings_publish_switch in ui file.");
+        publish.set_state(published);
+        publish.set_sensitive(self.can_change("m.room.canonical_alias"));
+    }
+
+    /* Called once the server answered, with the state the switch has to show */
+    pub fn show_switch_state(&self, id: &str, active: bool) {
+        let switch = self
+            .builder
+            .get_object::<gtk::Switch>(id)
+            .expect("Can't find switch in ui file.");
+        switch.set_state(active);
+    }
+
+    fn update_switch<F>(&self, id: &'static str, active: bool, query: F)
+    where
+        F: FnOnce(Url, AccessToken, RoomId) -> Result<(), room::RoomSettingsError> + Send + 'static,
+    {
+        let server = self.server_url.clone();
+        let access_token = self.access_token.clone();
+        let room_id = self.room.id.clone();
+        thread::spawn(move || {
+            let active = match query(server, access_token, room_id) {
+                Ok(_) => active,
+                Err(err) => {
+                    err.handle_error();
+                    !active
+                }
+            };
+            APPOP!(show_room_settings_switch, (id, active));
+        });
+    }
+
+    pub fn update_guest_access(&self, can_join: bool) {
+        let guest_access = if can_join {
+            GuestAccess::CanJoin
+        } else {
+            GuestAccess::Forbidden
+        };
+        let content = json!(GuestAccessEventContent { guest_access });
+        self.update_switch(
+            "room_settings_guests_switch",
+            can_join,
+            move |server, access_token, room_id| {
+                room::set_room_state(
+                    server,
+                    access_token,
+                    room_id,
+                    "m.room.guest_access",
+                    content,
+                )
+            },
+        );
+    }
+
+    pub fn update_join_rule(&self, public: bool) {
+        let join_rule = if public {
+            JoinRule::Public
+        } else {
+            JoinRule::Invite
+        };
+        let content = json!(JoinRulesEventContent { join_rule });
+        self.update_switch(
+            "room_settings_join_rule_switch",
+            public,
+            move |server, access_token, room_id| {
+                room::set_room_state(server, access_token, room_id, "m.room.join_rules", content)
+            },
+        );
+    }
+
+    pub fn update_published(&self, published: bool) {
+        self.update_switch(
+            "room_settings_publish_switch",
+            published,
+            move |server, access_token, room_id| {
+                room::set_room_visibility(server, access_token, room_id, published)
+            },
+        );
+    }
+
+    fn room_settings_show_history_visibility(&mut self) {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_history_visibility_list")
+            .expect("Can't find room_settings_history_visibility_list in ui file.");
+        for w in list.get_children().iter() {
+            list.remove(w);
+        }
+
+        let options = vec![
+            (
+                HistoryVisibility::WorldReadable,
+                i18n("Anyone, even without joining"),
+            ),
+            (HistoryVisibility::Shared, i18n("Members, all room history")),
+            (
+                HistoryVisibility::Invited,
+                i18n("Members, history after they were invited"),
+            ),
+            (
+                HistoryVisibility::Joined,
+                i18n("Members, history after they joined"),
+            ),
+        ];
+        self.history_checks = options
+            .into_iter()
+            .map(|(visibility, description)| (visibility, add_check_row(&list, &description)))
+            .collect();
+
+        self.show_history_visibility(self.room.history_visibility.clone());
+    }
+
+    pub fn show_history_visibility(&self, visibility: Option<HistoryVisibility>) {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_history_visibility_list")
+            .expect("Can't find room_settings_history_visibility_list in ui file.");
+        for (option, check) in self.history_checks.iter() {
+            check.set_visible(visibility.as_ref() == Some(option));
+        }
+        list.set_sensitive(self.can_change("m.room.history_visibility"));
+    }
+
+    pub fn update_history_visibility(&self, index: i32) -> Option<()> {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_history_visibility_list")
+            .expect("Can't find room_settings_history_visibility_list in ui file.");
+        let (visibility, check) = self.history_checks.get(index as usize)?;
+        if check.get_visible() {
+            return None;
+        }
+        let visibility = visibility.clone();
+        let old_visibility = self
+            .history_checks
+            .iter()
+            .find(|(_, check)| check.get_visible())
+            .map(|(option, _)| option.clone());
+
+        list.set_sensitive(false);
+
+        let server = self.server_url.clone();
+        let access_token = self.access_token.clone();
+        let room_id = self.room.id.clone();
+        let content = json!(HistoryVisibilityEventContent {
+            history_visibility: visibility.clone(),
+        });
+        thread::spawn(move || {
+            let query = room::set_room_state(
+                server,
+                access_token,
+                room_id,
+                "m.room.history_visibility",
+                content,
+            );
+            let visibility = match query {
+                Ok(_) => Some(visibility),
+                Err(err) => {
+                    err.handle_error();
+                    old_visibility
+                }
+            };
+            APPOP!(show_room_history_visibility, (visibility));
+        });
+
+        None
+    }
+
+    fn room_settings_show_notifications(&mut self) {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_notifications_list")
+            .expect("Can't find room_settings_notifications_list in ui file.");
+        for w in list.get_children().iter() {
+            list.remove(w);
+        }
+
+        let options = vec![
+            (RoomNotifications::All, i18n("All messages")),
+            (RoomNotifications::MentionsOnly, i18n("Mentions only")),
+            (RoomNotifications::Mute, i18n("Mute")),
+        ];
+        self.notification_checks = options
+            .into_iter()
+            .map(|(level, description)| (level, add_check_row(&list, &description)))
+            .collect();
+
+        // The level comes from our push rules, which we may not have yet
+        self.show_notifications_level(None);
+    }
+
+    /// Without a level the notifications of the room can't be changed
+    pub fn show_notifications_level(&self, level: Option<RoomNotifications>) {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_notifications_list")
+            .expect("Can't find room_settings_notifications_list in ui file.");
+        for (option, check) in self.notification_checks.iter() {
+            check.set_visible(level == Some(*option));
+        }
+        list.set_sensitive(level.is_some());
+    }
+
+    pub fn update_notifications_level(&self, index: i32) -> Option<()> {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_notifications_list")
+            .expect("Can't find room_settings_notifications_list in ui file.");
+        let (level, check) = self.notification_checks.get(index as usize)?;
+        if check.get_visible() {
+            return None;
+        }
+        let level = *level;
+
+        list.set_sensitive(false);
+
+        let server = self.server_url.clone();
+        let access_token = self.access_token.clone();
+        let room_id = self.room.id.clone();
+        thread::spawn(move || {
+            match user::set_room_notifications(server, access_token, room_id, level) {
+                Ok(rules) => {
+                    APPOP!(set_push_rules, (rules));
+                }
+                Err(err) => {
+                    err.handle_error();
+                    APPOP!(show_room_notifications_level);
+                }
+            }
+        });
+
+        None
+    }
+
+    /* The main address goes first, followed by the alternative ones */
+    pub fn show_aliases(&self, aliases: &CanonicalAliasEventContent) {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_aliases_list")
+            .expect("Can't find room_settings_aliases_list in ui file.");
+        for w in list.get_children().iter() {
+            list.remove(w);
+        }
+
+        let editable = self.can_change("m.room.canonical_alias");
+        let main = aliases.alias.iter().map(|alias| (alias, true));
+        let alt = aliases.alt_aliases.iter().map(|alias| (alias, false));
+        for (alias, is_main) in main.chain(alt) {
+            let row = gtk::ListBoxRow::new();
+            let b = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+            let labels = gtk::Box::new(gtk::Orientation::Vertical, 0);
+
+            let label = gtk::Label::new(Some(alias.as_str()));
+            label.set_xalign(0.);
+            label.set_selectable(true);
+            label.set_ellipsize(pango::EllipsizeMode::End);
+            labels.pack_start(&label, true, true, 0);
+            if is_main {
+                let subtitle = gtk::Label::new(Some(i18n("Main address").as_str()));
+                subtitle.set_xalign(0.);
+                let style = subtitle.get_style_context();
+                style.add_class("small-font");
+                style.add_class("dim-label");
+                labels.pack_start(&subtitle, true, true, 0);
+            }
+            b.pack_start(&labels, true, true, 0);
+
+            if editable {
+                if !is_main {
+                    let main_btn = gtk::Button::new_from_icon_name(
+                        Some("starred-symbolic"),
+                        gtk::IconSize::Menu,
+                    );
+                    main_btn.set_tooltip_text(Some(i18n("Make Main Address").as_str()));
+                    main_btn.set_valign(gtk::Align::Center);
+                    main_btn.set_relief(gtk::ReliefStyle::None);
+                    let this = self.clone();
+                    let aliases = aliases.clone();
+                    let alias = alias.clone();
+                    main_btn.connect_clicked(move |_| {
+                        this.set_main_room_alias(&aliases, alias.clone());
+                    });
+                    b.pack_start(&main_btn, false, false, 0);
+                }
+
+                let remove_btn = gtk::Button::new_from_icon_name(
+                    Some("user-trash-symbolic"),
+                    gtk::IconSize::Menu,
+                );
+                remove_btn.set_tooltip_text(Some(i18n("Remove").as_str()));
+                remove_btn.set_valign(gtk::Align::Center);
+                remove_btn.set_relief(gtk::ReliefStyle::None);
+                let this = self.clone();
+                let aliases = aliases.clone();
+                let alias = alias.clone();
+                remove_btn.connect_clicked(move |_| {
+                    this.remove_room_alias(&aliases, &alias);
+                });
+                b.pack_start(&remove_btn, false, false, 0);
+            }
+
+            b.set_margin_start(12);
+            b.set_margin_end(12);
+            b.set_margin_top(6);
+            b.set_margin_bottom(6);
+            row.add(&b);
+            row.set_selectable(false);
+            row.set_activatable(false);
+            list.insert(&row, -1);
+        }
+
+        if editable {
+            self.add_new_alias_row(&list, aliases);
+        }
+        list.set_sensitive(true);
+        list.show_all();
+    }
+
+    fn add_new_alias_row(&self, list: &gtk::ListBox, aliases: &CanonicalAliasEventContent) {
+        let row = gtk::ListBoxRow::new();
+        let b = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+        let entry = gtk::Entry::new();
+        let button = gtk::Button::new_with_label(&i18n("Add"));
+
+        entry.set_placeholder_text(Some(i18n("#address:server").as_str()));
+        entry.set_hexpand(true);
+        b.set_margin_start(12);
+        b.set_margin_end(12);
+        b.set_margin_top(6);
+        b.set_margin_bottom(6);
+        b.pack_start(&entry, true, true, 0);
+        b.pack_start(&button, false, false, 0);
+        row.add(&b);
+        row.set_selectable(false);
+        row.set_activatable(false);
+        list.insert(&row, -1);
+
+        let this = self.clone();
+        let aliases = aliases.clone();
+        button.connect_clicked(clone!(@weak entry => move |_| {
+            let text = entry.get_text().map(|t| t.to_string()).unwrap_or_default();
+            let text = text.trim();
+            let exists = aliases.alias.iter().chain(aliases.alt_aliases.iter()).any(|a| a == text);
+            match RoomAliasId::try_from(text) {
+                Ok(alias) if !exists => {
+                    entry.get_style_context().remove_class("error");
+                    this.add_room_alias(&aliases, alias);
+                }
+                _ => entry.get_style_context().add_class("error"),
+            }
+        }));
+        entry.connect_activate(clone!(@weak button => move |_| button.clicked()));
+    }
+
+    /* The first address of a room becomes its main one */
+    fn add_room_alias(&self, aliases: &CanonicalAliasEventContent, alias: RoomAliasId) {
+        let mut new_aliases = aliases.clone();
+        if new_aliases.alias.is_none() {
+            new_aliases.alias = Some(alias.to_string());
+        } else {
+            new_aliases.alt_aliases.push(alias.to_string());
+        }
+
+        self.update_aliases(
+            new_aliases,
+            move |server, access_token, room_id, content| {
+                room::create_room_alias(
+                    server.clone(),
+                    access_token.clone(),
+                    room_id.clone(),
+                    alias,
+                )?;
+                room::set_room_state(
+                    server,
+                    access_token,
+                    room_id,
+                    "m.room.canonical_alias",
+                    content,
+                )
+            },
+        );
+    }
+
+    /* Only the aliases of our own server can be deleted from its directory, the others are
+     * just removed from the room */
+    fn remove_room_alias(&self, aliases: &CanonicalAliasEventContent, alias: &str) {
+        let mut new_aliases = aliases.clone();
+        if new_aliases.alias.as_deref() == Some(alias) {
+            new_aliases.alias = None;
+        }
+        new_aliases.alt_aliases.retain(|a| a != alias);
+
+        let own_server = self.uid.to_string().splitn(2, ':').nth(1).map(String::from);
+        let deleted = Some(alias)
+            .filter(|alias| alias.splitn(2, ':').nth(1) == own_server.as_deref())
+            .and_then(|alias| RoomAliasId::try_from(alias).ok());
+
+        self.update_aliases(
+            new_aliases,
+            move |server, access_token, room_id, content| {
+                room::set_room_state(
+                    server.clone(),
+                    access_token.clone(),
+                    room_id,
+                    "m.room.canonical_alias",
+                    content,
+                )?;
+                match deleted {
+                    Some(alias) => room::delete_room_alias(server, access_token, alias),
+                    None => Ok(()),
+                }
+            },
+        );
+    }
+
+    /* The previous main address is kept as an alternative one */
+    fn set_main_room_alias(&self, aliases: &CanonicalAliasEventContent, alias: String) {
+        let mut new_aliases = aliases.clone();
+        new_aliases.alt_aliases.retain(|a| *a != alias);
+        if let Some(old) = new_aliases.alias.replace(alias) {
+            new_aliases.alt_aliases.insert(0, old);
+        }
+
+        self.update_aliases(new_aliases, |server, access_token, room_id, content| {
+            room::set_room_state(
+                server,
+                access_token,
+                room_id,
+                "m.room.canonical_alias",
+                content,
+            )
+        });
+    }
+
+    fn update_aliases<F>(&self, aliases: CanonicalAliasEventContent, query: F)
+    where
+        F: FnOnce(
+                Url,
+                AccessToken,
+                RoomId,
+                serde_json::Value,
+            ) -> Result<(), room::RoomSettingsError>
+            + Send
+            + 'static,
+    {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_aliases_list")
+            .expect("Can't find room_settings_aliases_list in ui file.");
+        list.set_sensitive(false);
+
+        let server = self.server_url.clone();
+        let access_token = self.access_token.clone();
+        let room_id = self.room.id.clone();
+        let content = json!(aliases);
+        thread::spawn(
+            move || match query(server, access_token, room_id.clone(), content) {
+                Ok(_) => {
+                    APPOP!(set_room_aliases, (room_id, aliases));
+                }
+                Err(err) => {
+                    err.handle_error();
+                    APPOP!(show_room_aliases_error);
+                }
+            },
+        );
+    }
+
+    pub fn show_aliases_error(&self) {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("room_settings_aliases_list")
+            .expect("Can't find room_settings_aliases_list in ui file.");
+        list.set_sensitive(true);
+    }
+}
+
+/* A row of a list where the selected option gets a check mark */
+fn add_check_row(list: &gtk::ListBox, description: &str) -> gtk::Image {
+    let row = gtk::ListBoxRow::new();
+    let b = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+    let label = gtk::Label::new(Some(description));
+    let check = gtk::Image::new_from_icon_name(Some("emblem-ok-symbolic"), gtk::IconSize::Menu);
+    label.set_xalign(0.);
+    b.set_margin_start(12);
+    b.set_margin_end(12);
+    b.set_margin_top(12);
+    b.set_margin_bottom(12);
+    b.pack_start(&label, true, true, 0);
+    b.pack_end(&check, false, false, 0);
+    row.add(&b);
+    row.set_selectable(false);
+    check.set_no_show_all(true);
+    row.show_all();
+    list.insert(&row, -1);
+    check
 }
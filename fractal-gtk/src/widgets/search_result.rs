@@ -0,0 +1,165 @@
+use gtk::prelude::*;
+use html2pango::html_escape;
+
+use crate::i18n::i18n;
+
+use crate::types::Message;
+
+use crate::appop::AppOp;
+
+use crate::cache::download_to_cache;
+use crate::globals;
+use crate::widgets;
+use crate::widgets::AvatarExt;
+
+// Message Search item
+pub struct SearchResultBox<'a> {
+    msg: &'a Message,
+    highlights: &'a [String],
+    op: &'a AppOp,
+}
+
+impl<'a> SearchResultBox<'a> {
+    pub fn new(msg: &'a Message, highlights: &'a [String], op: &'a AppOp) -> SearchResultBox<'a> {
+        SearchResultBox {
+            msg,
+            highlights,
+            op,
+        }
+    }
+
+    pub fn widget(&self) -> gtk::ListBoxRow {
+        let row = gtk::ListBoxRow::new();
+        let result_box = self.build_result_box();
+
+        row.set_selectable(false);
+        row.add(&result_box);
+        if let Some(id) = self.msg.id.as_ref() {
+            let data = glib::Variant::from(id.to_string());
+            row.set_action_name(Some("app.open-search-result"));
+            row.set_action_target_value(Some(&data));
+        }
+        row.show_all();
+
+        row
+    }
+
+    fn build_result_box(&self) -> gtk::Box {
+        let widget_box = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+        widget_box.set_margin_top(6);
+        widget_box.set_margin_bottom(6);
+        widget_box.set_margin_start(12);
+        widget_box.set_margin_end(12);
+
+        let msg = self.msg;
+        let sender_name = self
+            .op
+            .rooms
+            .get(&msg.room)
+            .and_then(|room| room.members.get(&msg.sender))
+            .and_then(|member| member.alias.clone())
+            .unwrap_or_else(|| msg.sender.to_string());
+
+        let avatar = widgets::Avatar::avatar_new(Some(globals::MSG_ICON_SIZE));
+        let data = avatar.circle(
+            msg.sender.to_string(),
+            Some(sender_name.clone()),
+            globals::MSG_ICON_SIZE,
+            None,
+            None,
+        );
+        if let Some(login_data) = self.op.login_data.clone() {
+            download_to_cache(
+                self.op.thread_pool.clone(),
+                self.op.user_info_cache.clone(),
+                login_data.server_url,
+                login_data.access_token,
+                msg.sender.clone(),
+                data,
+            );
+        };
+        avatar.set_valign(gtk::Align::Start);
+        widget_box.pack_start(&avatar, false, false, 0);
+
+        let details_box = gtk::Box::new(gtk::Orientation::Vertical, 3);
+        let info_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+
+        let name_label = gtk::Label::new(None);
+        name_label.set_markup(&format!("<b>{}</b>", html_escape(&sender_name)));
+        name_label.set_ellipsize(pango::EllipsizeMode::End);
+        name_label.set_halign(gtk::Align::Start);
+        info_box.pack_start(&name_label, true, true, 0);
+
+        let date = msg.date.format(&i18n("%x %R")).to_string();
+        let date_label = gtk::Label::new(None);
+        date_label.set_markup(&format!("<span alpha=\"60%\">{}</span>", date.trim()));
+        date_label.set_halign(gtk::Align::End);
+        date_label.get_style_context().add_class("timestamp");
+        info_box.pack_end(&date_label, false, false, 0);
+
+        let body_label = gtk::Label::new(None);
+        body_label.set_markup(&highlight_markup(&msg.body, self.highlights));
+        body_label.set_line_wrap(true);
+        body_label.set_line_wrap_mode(pango::WrapMode::WordChar);
+        body_label.set_lines(3);
+        body_label.set_ellipsize(pango::EllipsizeMode::End);
+        body_label.set_justify(gtk::Justification::Left);
+        body_label.set_halign(gtk::Align::Start);
+        body_label.set_xalign(0.0);
+
+        details_box.add(&info_box);
+        details_box.add(&body_label);
+        widget_box.pack_start(&details_box, true, true, 0);
+
+        widget_box.show_all();
+        widget_box
+    }
+}
+
+/* Escape the text for pango and make every highlighted word bold, the server gives us the
+ * highlights already normalized so we compare them ignoring the case */
+fn highlight_markup(text: &str, highlights: &[String]) -> String {
+    let highlights: Vec<String> = highlights
+        .iter()
+        .filter(|h| !h.is_empty())
+        .map(|h| h.to_lowercase())
+        .collect();
+
+    let mut markup = String::new();
+    let mut plain_start = 0;
+    let mut i = 0;
+    while i < text.len() {
+        let hit = highlights
+            .iter()
+            .filter_map(|h| text.get(i..i + h.len()).filter(|s| s.to_lowercase() == *h))
+            .max_by_key(|s| s.len());
+
+        if let Some(hit) = hit {
+            markup.push_str(&html_escape(&text[plain_start..i]));
+            markup.push_str(&format!("<b>{}</b>", html_escape(hit)));
+            i += hit.len();
+            plain_start = i;
+        } else {
+            i += text[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    markup.push_str(&html_escape(&text[plain_start..]));
+
+    markup
+}
+
+#[cfg(test)]
+mod tests {
+    use super::highlight_markup;
+
+    #[test]
+    fn search_result_highlight_markup() {
+        let highlights = vec![String::from("rust"), String::from("fractal")];
+        assert_eq!(
+            highlight_markup("Fractal is written in Rust & GTK", &highlights),
+            "<b>Fractal</b> is written in <b>Rust</b> &amp; GTK"
+        );
+        assert_eq!(highlight_markup("café <3", &[]), "café &lt;3");
+        assert_eq!(highlight_markup("né rusty", &highlights), "né <b>rust</b>y");
+    }
+}
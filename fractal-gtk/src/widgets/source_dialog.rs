@@ -4,6 +4,7 @@ use sourceview4::prelude::*;
 
 struct Widgets {
     msg_src_window: gtk::Window,
+    headerbar: gtk::HeaderBar,
     copy_src_button: gtk::Button,
     close_src_button: gtk::Button,
     source_buffer: sourceview4::Buffer,
@@ -20,6 +21,10 @@ impl Widgets {
             .get_object("msg_src_window")
             .expect("Can't find msg_src_window in ui file.");
 
+        let headerbar: gtk::HeaderBar = builder
+            .get_object("src_headerbar")
+            .expect("Can't find src_headerbar in ui file.");
+
         let copy_src_button: gtk::Button = builder
             .get_object("copy_src_button")
             .expect("Can't find copy_src_button in ui file.");
@@ -34,6 +39,7 @@ impl Widgets {
 
         Widgets {
             msg_src_window,
+            headerbar,
             copy_src_button,
             close_src_button,
             source_buffer,
@@ -59,6 +65,10 @@ impl SourceDialog {
         self.widgets.msg_src_window.show();
     }
 
+    pub fn set_title(&self, title: &str) {
+        self.widgets.headerbar.set_title(Some(title));
+    }
+
     /* This sets the transient_for parent */
     pub fn set_parent_window(&self, parent: &gtk::Window) {
         self.widgets.msg_src_window.set_transient_for(Some(parent));
@@ -12,6 +12,8 @@ pub struct SVEntry {
     pub markdown: gtk::MenuButton,
     pub markdown_img: gtk::Image,
     pub entry_box: gtk::Box,
+    pub reply_revealer: gtk::Revealer,
+    pub reply_label: gtk::Label,
     pub scroll: gtk::ScrolledWindow,
     pub view: sourceview4::View,
     pub buffer: sourceview4::Buffer,
@@ -62,6 +64,25 @@ impl Default for SVEntry {
         let entry_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
         entry_box.get_style_context().add_class("message-input");
 
+        /* Shows which message we reply to while we write the reply */
+        let reply_revealer = gtk::Revealer::new();
+        let reply_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        reply_box.get_style_context().add_class("reply-banner");
+        let reply_label = gtk::Label::new(None);
+        reply_label.set_ellipsize(pango::EllipsizeMode::End);
+        reply_label.set_halign(gtk::Align::Start);
+        reply_label.set_hexpand(true);
+        let reply_cancel = gtk::Button::new();
+        let cancel_img = gtk::Image::new_from_icon_name(Some("window-close-symbolic"), size);
+        reply_cancel.set_image(Some(&cancel_img));
+        reply_cancel.set_relief(gtk::ReliefStyle::None);
+        reply_cancel.set_valign(gtk::Align::Center);
+        reply_cancel.set_action_name(Some("app.cancel-reply"));
+        reply_box.pack_start(&reply_label, true, true, 0);
+        reply_box.pack_start(&reply_cancel, false, false, 0);
+        reply_revealer.add(&reply_box);
+        entry_box.add(&reply_revealer);
+
         let hadjust: Option<&gtk::Adjustment> = None;
         let vadjust: Option<&gtk::Adjustment> = None;
         let scroll = gtk::ScrolledWindow::new(hadjust, vadjust);
@@ -110,6 +131,8 @@ impl Default for SVEntry {
             markdown,
             markdown_img,
             entry_box,
+            reply_revealer,
+            reply_label,
             scroll,
             view,
             buffer,
@@ -0,0 +1,296 @@
+//! The errors the homeserver answers with, see
+//! https://matrix.org/docs/spec/client_server/r0.6.1#api-standards
+
+use reqwest::blocking::Response;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+/// The `errcode` of an error, with the fields that come along with some of them
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorCode {
+    Forbidden,
+    /// With `soft_logout` the session can be resumed by logging in again to the same device
+    UnknownToken {
+        soft_logout: bool,
+    },
+    MissingToken,
+    BadJson,
+    NotJson,
+    NotFound,
+    LimitExceeded {
+        retry_after_ms: Option<u64>,
+    },
+    Unknown,
+    Unrecognized,
+    Unauthorized,
+    UserDeactivated,
+    UserInUse,
+    InvalidUsername,
+    RoomInUse,
+    InvalidRoomState,
+    ThreepidInUse,
+    ThreepidNotFound,
+    ThreepidAuthFailed,
+    ThreepidDenied,
+    ServerNotTrusted,
+    UnsupportedRoomVersion,
+    IncompatibleRoomVersion {
+        room_version: Option<String>,
+    },
+    BadState,
+    GuestAccessForbidden,
+    CaptchaNeeded,
+    CaptchaInvalid,
+    MissingParam,
+    InvalidParam,
+    TooLarge,
+    Exclusive,
+    ResourceLimitExceeded {
+        admin_contact: Option<String>,
+    },
+    CannotLeaveServerNoticeRoom,
+    WeakPassword,
+    /// A code that isn't in the specification
+    Other(String),
+}
+
+impl ErrorCode {
+    fn new(errcode: &str, fields: &Map<String, JsonValue>) -> Self {
+        let string_field = |name| {
+            fields
+                .get(name)
+                .and_then(JsonValue::as_str)
+                .map(ToOwned::to_owned)
+        };
+
+        match errcode {
+            "M_FORBIDDEN" => Self::Forbidden,
+            "M_UNKNOWN_TOKEN" => Self::UnknownToken {
+                soft_logout: fields
+                    .get("soft_logout")
+                    .and_then(JsonValue::as_bool)
+                    .unwrap_or_default(),
+            },
+            "M_MISSING_TOKEN" => Self::MissingToken,
+            "M_BAD_JSON" => Self::BadJson,
+            "M_NOT_JSON" => Self::NotJson,
+            "M_NOT_FOUND" => Self::NotFound,
+            "M_LIMIT_EXCEEDED" => Self::LimitExceeded {
+                retry_after_ms: fields.get("retry_after_ms").and_then(JsonValue::as_u64),
+            },
+            "M_UNKNOWN" => Self::Unknown,
+            "M_UNRECOGNIZED" => Self::Unrecognized,
+            "M_UNAUTHORIZED" => Self::Unauthorized,
+            "M_USER_DEACTIVATED" => Self::UserDeactivated,
+            "M_USER_IN_USE" => Self::UserInUse,
+            "M_INVALID_USERNAME" => Self::InvalidUsername,
+            "M_ROOM_IN_USE" => Self::RoomInUse,
+            "M_INVALID_ROOM_STATE" => Self::InvalidRoomState,
+            "M_THREEPID_IN_USE" => Self::ThreepidInUse,
+            "M_THREEPID_NOT_FOUND" => Self::ThreepidNotFound,
+            "M_THREEPID_AUTH_FAILED" => Self::ThreepidAuthFailed,
+            "M_THREEPID_DENIED" => Self::ThreepidDenied,
+            "M_SERVER_NOT_TRUSTED" => Self::ServerNotTrusted,
+            "M_UNSUPPORTED_ROOM_VERSION" => Self::UnsupportedRoomVersion,
+            "M_INCOMPATIBLE_ROOM_VERSION" => Self::IncompatibleRoomVersion {
+                room_version: string_field("room_version"),
+            },
+            "M_BAD_STATE" => Self::BadState,
+            "M_GUEST_ACCESS_FORBIDDEN" => Self::GuestAccessForbidden,
+            "M_CAPTCHA_NEEDED" => Self::CaptchaNeeded,
+            "M_CAPTCHA_INVALID" => Self::CaptchaInvalid,
+            "M_MISSING_PARAM" => Self::MissingParam,
+            "M_INVALID_PARAM" => Self::InvalidParam,
+            "M_TOO_LARGE" => Self::TooLarge,
+            "M_EXCLUSIVE" => Self::Exclusive,
+            "M_RESOURCE_LIMIT_EXCEEDED" => Self::ResourceLimitExceeded {
+                admin_contact: string_field("admin_contact"),
+            },
+            "M_CANNOT_LEAVE_SERVER_NOTICE_ROOM" => Self::CannotLeaveServerNoticeRoom,
+            "M_WEAK_PASSWORD" => Self::WeakPassword,
+            other => Self::Other(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Forbidden => "M_FORBIDDEN",
+            Self::UnknownToken { .. } => "M_UNKNOWN_TOKEN",
+            Self::MissingToken => "M_MISSING_TOKEN",
+            Self::BadJson => "M_BAD_JSON",
+            Self::NotJson => "M_NOT_JSON",
+            Self::NotFound => "M_NOT_FOUND",
+            Self::LimitExceeded { .. } => "M_LIMIT_EXCEEDED",
+            Self::Unknown => "M_UNKNOWN",
+            Self::Unrecognized => "M_UNRECOGNIZED",
+            Self::Unauthorized => "M_UNAUTHORIZED",
+            Self::UserDeactivated => "M_USER_DEACTIVATED",
+            Self::UserInUse => "M_USER_IN_USE",
+            Self::InvalidUsername => "M_INVALID_USERNAME",
+            Self::RoomInUse => "M_ROOM_IN_USE",
+            Self::InvalidRoomState => "M_INVALID_ROOM_STATE",
+            Self::ThreepidInUse => "M_THREEPID_IN_USE",
+            Self::ThreepidNotFound => "M_THREEPID_NOT_FOUND",
+            Self::ThreepidAuthFailed => "M_THREEPID_AUTH_FAILED",
+            Self::ThreepidDenied => "M_THREEPID_DENIED",
+            Self::ServerNotTrusted => "M_SERVER_NOT_TRUSTED",
+            Self::UnsupportedRoomVersion => "M_UNSUPPORTED_ROOM_VERSION",
+            Self::IncompatibleRoomVersion { .. } => "M_INCOMPATIBLE_ROOM_VERSION",
+            Self::BadState => "M_BAD_STATE",
+            Self::GuestAccessForbidden => "M_GUEST_ACCESS_FORBIDDEN",
+            Self::CaptchaNeeded => "M_CAPTCHA_NEEDED",
+            Self::CaptchaInvalid => "M_CAPTCHA_INVALID",
+            Self::MissingParam => "M_MISSING_PARAM",
+            Self::InvalidParam => "M_INVALID_PARAM",
+            Self::TooLarge => "M_TOO_LARGE",
+            Self::Exclusive => "M_EXCLUSIVE",
+            Self::ResourceLimitExceeded { .. } => "M_RESOURCE_LIMIT_EXCEEDED",
+            Self::CannotLeaveServerNoticeRoom => "M_CANNOT_LEAVE_SERVER_NOTICE_ROOM",
+            Self::WeakPassword => "M_WEAK_PASSWORD",
+            Self::Other(errcode) => errcode,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    errcode: String,
+    #[serde(default)]
+    error: String,
+    #[serde(flatten)]
+    fields: Map<String, JsonValue>,
+}
+
+/// An answer of the homeserver that isn't a success
+#[derive(Clone, Debug)]
+pub struct MatrixError {
+    pub status_code: StatusCode,
+    pub kind: ErrorCode,
+    /// Meant for humans, but never translated
+    pub message: String,
+}
+
+impl MatrixError {
+    /// Bodies that aren't a Matrix error, like the pages of a proxy, only have the HTTP
+    /// status to tell what happened
+    pub fn from_body(status_code: StatusCode, body: &[u8]) -> Self {
+        match serde_json::from_slice::<ErrorBody>(body) {
+            Ok(body) => Self {
+                status_code,
+                kind: ErrorCode::new(&body.errcode, &body.fields),
+                message: body.error,
+            },
+            Err(_) => Self {
+                status_code,
+                kind: match status_code {
+                    StatusCode::TOO_MANY_REQUESTS => ErrorCode::LimitExceeded {
+                        retry_after_ms: None,
+                    },
+                    _ => ErrorCode::Unknown,
+                },
+                message: status_code.to_string(),
+            },
+        }
+    }
+
+    /// How long the server wants us to wait before we try again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self.kind {
+            ErrorCode::LimitExceeded {
+                retry_after_ms: Some(retry_after_ms),
+            } => Some(Duration::from_millis(retry_after_ms)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for MatrixError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.kind, self.status_code, self.message)
+    }
+}
+
+impl std::error::Error for MatrixError {}
+
+/// Passes the successful answers on, and turns the other ones into a `MatrixError`
+pub fn check_response(response: Response) -> Result<Response, MatrixError> {
+    let status_code = response.status();
+    if status_code.is_success() {
+        return Ok(response);
+    }
+
+    let body = response
+        .bytes()
+        .map(|body| body.to_vec())
+        .unwrap_or_default();
+    Err(MatrixError::from_body(status_code, &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_error_codes() {
+        let body = br#"{"errcode": "M_FORBIDDEN", "error": "You are not invited to this room."}"#;
+        let err = MatrixError::from_body(StatusCode::FORBIDDEN, body);
+        assert_eq!(err.kind, ErrorCode::Forbidden);
+        assert_eq!(err.message, "You are not invited to this room.");
+
+        let body = br#"{"errcode": "M_UNKNOWN_TOKEN", "error": "Expired", "soft_logout": true}"#;
+        let err = MatrixError::from_body(StatusCode::UNAUTHORIZED, body);
+        assert_eq!(err.kind, ErrorCode::UnknownToken { soft_logout: true });
+
+        let body = br#"{"errcode": "M_UNKNOWN_TOKEN", "error": "Unknown"}"#;
+        let err = MatrixError::from_body(StatusCode::UNAUTHORIZED, body);
+        assert_eq!(err.kind, ErrorCode::UnknownToken { soft_logout: false });
+
+        let body =
+            br#"{"errcode": "M_LIMIT_EXCEEDED", "error": "Slow down", "retry_after_ms": 2000}"#;
+        let err = MatrixError::from_body(StatusCode::TOO_MANY_REQUESTS, body);
+        assert_eq!(err.retry_after(), Some(Duration::from_millis(2000)));
+
+        let body = br#"{
+            "errcode": "M_RESOURCE_LIMIT_EXCEEDED",
+            "error": "Monthly active user limit exceeded",
+            "admin_contact": "mailto:admin@example.org"
+        }"#;
+        let err = MatrixError::from_body(StatusCode::FORBIDDEN, body);
+        assert_eq!(
+            err.kind,
+            ErrorCode::ResourceLimitExceeded {
+                admin_contact: Some("mailto:admin@example.org".to_string())
+            }
+        );
+
+        let body = br#"{"errcode": "ORG_EXAMPLE_CUSTOM", "error": "Custom"}"#;
+        let err = MatrixError::from_body(StatusCode::BAD_REQUEST, body);
+        assert_eq!(err.kind, ErrorCode::Other("ORG_EXAMPLE_CUSTOM".to_string()));
+        assert_eq!(err.kind.as_str(), "ORG_EXAMPLE_CUSTOM");
+    }
+
+    #[test]
+    fn parse_non_matrix_errors() {
+        let err = MatrixError::from_body(StatusCode::BAD_GATEWAY, b"<html>Bad Gateway</html>");
+        assert_eq!(err.kind, ErrorCode::Unknown);
+        assert_eq!(err.status_code, StatusCode::BAD_GATEWAY);
+
+        let err = MatrixError::from_body(StatusCode::TOO_MANY_REQUESTS, b"");
+        assert_eq!(
+            err.kind,
+            ErrorCode::LimitExceeded {
+                retry_after_ms: None
+            }
+        );
+        assert_eq!(err.retry_after(), None);
+    }
+}
@@ -0,0 +1,299 @@
+pub mod account_data;
+pub mod ephemeral;
+pub mod room;
+
+use log::warn;
+use ruma_identifiers::{EventId, RoomId, UserId};
+use serde::{Deserialize, Deserializer};
+use serde_json::Error as JsonError;
+use serde_json::Value as JsonValue;
+use std::convert::TryFrom;
+use std::fmt::Display;
+
+pub use self::account_data::AccountDataEventContent;
+pub use self::ephemeral::EphemeralEventContent;
+pub use self::room::RoomEventContent;
+
+/// Implemented by the content enums of every event kind. Known event types
+/// are parsed into their typed content, everything else ends up in the
+/// `Custom` variant of the enum so it is never lost.
+pub trait EventContent: Sized {
+    fn from_parts(event_type: &str, content: JsonValue) -> Result<Self, JsonError>;
+}
+
+/// Content of an event type we don't know about.
+#[derive(Clone, Debug)]
+pub struct CustomEventContent {
+    pub event_type: String,
+    pub content: JsonValue,
+}
+
+/// Fields shared by every event envelope, parsed before the content
+#[derive(Deserialize)]
+struct RawEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    content: JsonValue,
+}
+
+fn parse_content<C: EventContent>(value: &JsonValue) -> Result<(String, C), JsonError> {
+    let raw: RawEvent = serde_json::from_value(value.clone())?;
+    let content = C::from_parts(&raw.event_type, raw.content)?;
+
+    Ok((raw.event_type, content))
+}
+
+/// An event of the room timeline, either a message or a state event
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "JsonValue")]
+pub struct RoomEvent {
+    pub event_type: String,
+    pub content: RoomEventContent,
+    pub event_id: EventId,
+    pub sender: UserId,
+    pub origin_server_ts: i64,
+    pub room_id: Option<RoomId>,
+    pub state_key: Option<String>,
+    pub redacts: Option<EventId>,
+    pub unsigned: JsonValue,
+    json: JsonValue,
+}
+
+#[derive(Deserialize)]
+struct RawRoomEvent {
+    event_id: EventId,
+    sender: UserId,
+    #[serde(default)]
+    origin_server_ts: i64,
+    room_id: Option<RoomId>,
+    state_key: Option<String>,
+    redacts: Option<EventId>,
+    #[serde(default)]
+    unsigned: JsonValue,
+}
+
+impl TryFrom<JsonValue> for RoomEvent {
+    type Error = JsonError;
+
+    fn try_from(json: JsonValue) -> Result<Self, Self::Error> {
+        let (event_type, content) = parse_content(&json)?;
+        let raw: RawRoomEvent = serde_json::from_value(json.clone())?;
+
+        Ok(Self {
+            event_type,
+            content,
+            event_id: raw.event_id,
+            sender: raw.sender,
+            origin_server_ts: raw.origin_server_ts,
+            room_id: raw.room_id,
+            state_key: raw.state_key,
+            redacts: raw.redacts,
+            unsigned: raw.unsigned,
+            json,
+        })
+    }
+}
+
+impl RoomEvent {
+    /// The event as it was received from the server
+    pub fn json(&self) -> &JsonValue {
+        &self.json
+    }
+
+    pub fn is_redacted(&self) -> bool {
+        self.unsigned.get("redacted_because").is_some()
+    }
+}
+
+/// A state event from the `state` section of a room
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "JsonValue")]
+pub struct StateEvent {
+    pub event_type: String,
+    pub content: RoomEventContent,
+    pub event_id: Option<EventId>,
+    pub sender: UserId,
+    pub origin_server_ts: i64,
+    pub state_key: String,
+    pub prev_content: Option<JsonValue>,
+    json: JsonValue,
+}
+
+#[derive(Deserialize)]
+struct RawStateEvent {
+    event_id: Option<EventId>,
+    sender: UserId,
+    #[serde(default)]
+    origin_server_ts: i64,
+    state_key: String,
+    prev_content: Option<JsonValue>,
+}
+
+impl TryFrom<JsonValue> for StateEvent {
+    type Error = JsonError;
+
+    fn try_from(json: JsonValue) -> Result<Self, Self::Error> {
+        let (event_type, content) = parse_content(&json)?;
+        let raw: RawStateEvent = serde_json::from_value(json.clone())?;
+
+        Ok(Self {
+            event_type,
+            content,
+            event_id: raw.event_id,
+            sender: raw.sender,
+            origin_server_ts: raw.origin_server_ts,
+            state_key: raw.state_key,
+            prev_content: raw.prev_content,
+            json,
+        })
+    }
+}
+
+impl StateEvent {
+    /// The event as it was received from the server
+    pub fn json(&self) -> &JsonValue {
+        &self.json
+    }
+}
+
+/// A reduced state event, as sent for invited rooms
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "JsonValue")]
+pub struct StrippedStateEvent {
+    pub event_type: String,
+    pub content: RoomEventContent,
+    pub sender: UserId,
+    pub state_key: String,
+}
+
+#[derive(Deserialize)]
+struct RawStrippedStateEvent {
+    sender: UserId,
+    #[serde(default)]
+    state_key: String,
+}
+
+impl TryFrom<JsonValue> for StrippedStateEvent {
+    type Error = JsonError;
+
+    fn try_from(json: JsonValue) -> Result<Self, Self::Error> {
+        let (event_type, content) = parse_content(&json)?;
+        let raw: RawStrippedStateEvent = serde_json::from_value(json)?;
+
+        Ok(Self {
+            event_type,
+            content,
+            sender: raw.sender,
+            state_key: raw.state_key,
+        })
+    }
+}
+
+/// An event of the `ephemeral` section of a room, like typing notifications
+/// and receipts
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "JsonValue")]
+pub struct EphemeralEvent {
+    pub event_type: String,
+    pub content: EphemeralEventContent,
+}
+
+impl TryFrom<JsonValue> for EphemeralEvent {
+    type Error = JsonError;
+
+    fn try_from(json: JsonValue) -> Result<Self, Self::Error> {
+        let (event_type, content) = parse_content(&json)?;
+
+        Ok(Self {
+            event_type,
+            content,
+        })
+    }
+}
+
+/// An account data event, either global or attached to a room
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "JsonValue")]
+pub struct AccountDataEvent {
+    pub event_type: String,
+    pub content: AccountDataEventContent,
+}
+
+impl TryFrom<JsonValue> for AccountDataEvent {
+    type Error = JsonError;
+
+    fn try_from(json: JsonValue) -> Result<Self, Self::Error> {
+        let (event_type, content) = parse_content(&json)?;
+
+        Ok(Self {
+            event_type,
+            content,
+        })
+    }
+}
+
+/// A presence update of an user
+#[derive(Clone, Debug, Deserialize)]
+pub struct PresenceEvent {
+    pub sender: UserId,
+    pub content: PresenceEventContent,
+}
+
+impl TryFrom<JsonValue> for PresenceEvent {
+    type Error = JsonError;
+
+    fn try_from(json: JsonValue) -> Result<Self, Self::Error> {
+        serde_json::from_value(json)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PresenceEventContent {
+    pub presence: String,
+    pub avatar_url: Option<String>,
+    pub displayname: Option<String>,
+    pub last_active_ago: Option<u64>,
+    pub currently_active: Option<bool>,
+    pub status_msg: Option<String>,
+}
+
+/// An event sent directly to this device
+#[derive(Clone, Debug, Deserialize)]
+pub struct ToDeviceEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub sender: UserId,
+    #[serde(default)]
+    pub content: JsonValue,
+}
+
+impl TryFrom<JsonValue> for ToDeviceEvent {
+    type Error = JsonError;
+
+    fn try_from(json: JsonValue) -> Result<Self, Self::Error> {
+        serde_json::from_value(json)
+    }
+}
+
+/// Deserializes a list of events, dropping the ones that don't follow the spec
+/// so a single malformed event can't break the whole response.
+pub(crate) fn deserialize_valid_events<'de, D, T>(de: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<JsonValue>,
+    T::Error: Display,
+{
+    let events: Vec<JsonValue> = Deserialize::deserialize(de)?;
+
+    Ok(events
+        .into_iter()
+        .filter_map(|ev| {
+            // Null when the type itself is missing
+            let event_type = ev["type"].clone();
+            T::try_from(ev)
+                .map_err(|err| warn!("Dropped an event of type {}: {}", event_type, err))
+                .ok()
+        })
+        .collect())
+}
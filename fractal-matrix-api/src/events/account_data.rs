@@ -0,0 +1,189 @@
+use super::{CustomEventContent, EventContent};
+use ruma_identifiers::{EventId, RoomId, UserId};
+use serde::{Deserialize, Deserializer};
+use serde_json::Error as JsonError;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+#[derive(Clone, Debug)]
+pub enum AccountDataEventContent {
+    Direct(DirectEventContent),
+    Tag(TagEventContent),
+    FullyRead(FullyReadEventContent),
+    Custom(CustomEventContent),
+}
+
+impl EventContent for AccountDataEventContent {
+    fn from_parts(event_type: &str, content: JsonValue) -> Result<Self, JsonError> {
+        Ok(match event_type {
+            "m.direct" => Self::Direct(serde_json::from_value(content)?),
+            "m.tag" => Self::Tag(serde_json::from_value(content)?),
+            "m.fully_read" => Self::FullyRead(serde_json::from_value(content)?),
+            _ => Self::Custom(CustomEventContent {
+                event_type: event_type.into(),
+                content,
+            }),
+        })
+    }
+}
+
+/// The direct chats of the user, grouped by the other user of the chat
+#[derive(Clone, Debug, Default)]
+pub struct DirectEventContent(pub HashMap<UserId, Vec<RoomId>>);
+
+impl<'de> Deserialize<'de> for DirectEventContent {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let map: HashMap<String, JsonValue> = Deserialize::deserialize(de)?;
+
+        // Synapse sometimes sends an object with the key "[object Object]"
+        // instead of a user ID, so we have to skip those invalid entries
+        // in order to avoid discarding everything
+        let directs = map
+            .into_iter()
+            .filter_map(|(uid, rooms)| {
+                let uid = UserId::try_from(uid.as_str()).ok()?;
+                let rooms = rooms
+                    .as_array()?
+                    .iter()
+                    .map(|rid| RoomId::try_from(rid.as_str().unwrap_or_default()))
+                    .collect::<Result<Vec<RoomId>, _>>()
+                    .ok()?;
+                Some((uid, rooms))
+            })
+            .collect();
+
+        Ok(Self(directs))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TagEventContent {
+    #[serde(default)]
+    pub tags: HashMap<String, TagInfo>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TagInfo {
+    pub order: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FullyReadEventContent {
+    pub event_id: EventId,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::AccountDataEvent;
+    use serde_json::json;
+
+    #[test]
+    fn account_data_events() {
+        // From the examples of the spec
+        let event = AccountDataEvent::try_from(json!({
+            "content": {
+                "@bob:example.com": [
+                    "!abcdefgh:example.com",
+                    "!hgfedcba:example.com"
+                ]
+            },
+            "type": "m.direct"
+        }))
+        .unwrap();
+        match event.content {
+            AccountDataEventContent::Direct(content) => {
+                let bob = UserId::try_from("@bob:example.com").unwrap();
+                assert_eq!(content.0[&bob].len(), 2);
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+
+        let event = AccountDataEvent::try_from(json!({
+            "content": {
+                "tags": {
+                    "u.work": {
+                        "order": 0.9
+                    }
+                }
+            },
+            "type": "m.tag"
+        }))
+        .unwrap();
+        match event.content {
+            AccountDataEventContent::Tag(content) => {
+                assert_eq!(content.tags["u.work"].order, Some(0.9));
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+
+        let event = AccountDataEvent::try_from(json!({
+            "content": {
+                "event_id": "$someplace:example.org"
+            },
+            "room_id": "!somewhere:example.org",
+            "type": "m.fully_read"
+        }))
+        .unwrap();
+        match event.content {
+            AccountDataEventContent::FullyRead(content) => {
+                assert_eq!(content.event_id.to_string(), "$someplace:example.org");
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+    }
+
+    #[test]
+    fn account_data_unknown_event_type() {
+        let event = AccountDataEvent::try_from(json!({
+            "content": {
+                "theme": "dark"
+            },
+            "type": "org.example.settings"
+        }))
+        .unwrap();
+
+        match event.content {
+            AccountDataEventContent::Custom(content) => {
+                assert_eq!(content.event_type, "org.example.settings");
+                assert_eq!(content.content["theme"], "dark");
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+    }
+
+    #[test]
+    fn account_data_missing_fields() {
+        // Entries that aren't a user with a list of rooms are skipped
+        let event = AccountDataEvent::try_from(json!({
+            "content": {
+                "[object Object]": ["!abcdefgh:example.com"],
+                "@bob:example.com": ["!abcdefgh:example.com"]
+            },
+            "type": "m.direct"
+        }))
+        .unwrap();
+        assert!(
+            matches!(event.content, AccountDataEventContent::Direct(ref content) if content.0.len() == 1)
+        );
+
+        let event = AccountDataEvent::try_from(json!({
+            "content": {},
+            "type": "m.tag"
+        }))
+        .unwrap();
+        assert!(
+            matches!(event.content, AccountDataEventContent::Tag(ref content) if content.tags.is_empty())
+        );
+
+        assert!(AccountDataEvent::try_from(json!({
+            "content": {},
+            "type": "m.fully_read"
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use super::{CustomEventContent, EventContent};
+use ruma_identifiers::{EventId, UserId};
+use serde::Deserialize;
+use serde_json::Error as JsonError;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug)]
+pub enum EphemeralEventContent {
+    Typing(TypingEventContent),
+    Receipt(ReceiptEventContent),
+    Custom(CustomEventContent),
+}
+
+impl EventContent for EphemeralEventContent {
+    fn from_parts(event_type: &str, content: JsonValue) -> Result<Self, JsonError> {
+        Ok(match event_type {
+            "m.typing" => Self::Typing(serde_json::from_value(content)?),
+            "m.receipt" => Self::Receipt(serde_json::from_value(content)?),
+            _ => Self::Custom(CustomEventContent {
+                event_type: event_type.into(),
+                content,
+            }),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TypingEventContent {
+    #[serde(default)]
+    pub user_ids: Vec<UserId>,
+}
+
+/// Maps the id of the read events to the receipts sent for them
+pub type ReceiptEventContent = HashMap<EventId, Receipts>;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Receipts {
+    #[serde(rename = "m.read")]
+    #[serde(default)]
+    pub read: HashMap<UserId, Receipt>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Receipt {
+    // Some servers send receipts without a timestamp, see
+    // https://github.com/matrix-org/synapse/issues/4898
+    pub ts: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EphemeralEvent;
+    use serde_json::json;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn ephemeral_events() {
+        // From the examples of the spec
+        let event = EphemeralEvent::try_from(json!({
+            "content": {
+                "user_ids": [
+                    "@alice:matrix.org",
+                    "@bob:example.com"
+                ]
+            },
+            "room_id": "!jEsUZKDJdhlrceRyVU:example.org",
+            "type": "m.typing"
+        }))
+        .unwrap();
+        match event.content {
+            EphemeralEventContent::Typing(content) => assert_eq!(content.user_ids.len(), 2),
+            content => panic!("Wrong content: {:?}", content),
+        }
+
+        let event = EphemeralEvent::try_from(json!({
+            "content": {
+                "$1435641916114394fHBLK:matrix.org": {
+                    "m.read": {
+                        "@rikj:jki.re": {
+                            "ts": 1436451550453u64
+                        }
+                    }
+                }
+            },
+            "room_id": "!KpjVgQyZpzBwvMBsnT:matrix.org",
+            "type": "m.receipt"
+        }))
+        .unwrap();
+        match event.content {
+            EphemeralEventContent::Receipt(content) => {
+                let event_id = EventId::try_from("$1435641916114394fHBLK:matrix.org").unwrap();
+                let user_id = UserId::try_from("@rikj:jki.re").unwrap();
+                assert_eq!(content[&event_id].read[&user_id].ts, Some(1436451550453));
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+    }
+
+    #[test]
+    fn ephemeral_unknown_event_type() {
+        let event = EphemeralEvent::try_from(json!({
+            "content": {
+                "state": "listening"
+            },
+            "type": "org.example.ephemeral"
+        }))
+        .unwrap();
+
+        match event.content {
+            EphemeralEventContent::Custom(content) => {
+                assert_eq!(content.event_type, "org.example.ephemeral");
+                assert_eq!(content.content["state"], "listening");
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+    }
+
+    #[test]
+    fn ephemeral_missing_fields() {
+        let event = EphemeralEvent::try_from(json!({
+            "content": {},
+            "type": "m.typing"
+        }))
+        .unwrap();
+        assert!(
+            matches!(event.content, EphemeralEventContent::Typing(ref content) if content.user_ids.is_empty())
+        );
+
+        // Some servers send receipts without a timestamp
+        let event = EphemeralEvent::try_from(json!({
+            "content": {
+                "$1435641916114394fHBLK:matrix.org": {
+                    "m.read": {
+                        "@rikj:jki.re": {}
+                    }
+                }
+            },
+            "type": "m.receipt"
+        }))
+        .unwrap();
+        assert!(
+            matches!(event.content, EphemeralEventContent::Receipt(ref content) if content.len() == 1)
+        );
+
+        assert!(EphemeralEvent::try_from(json!({
+            "content": {
+                "user_ids": "@alice:matrix.org"
+            },
+            "type": "m.typing"
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,648 @@
+use super::{CustomEventContent, EventContent};
+use ruma_identifiers::{EventId, RoomId, UserId};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Error as JsonError;
+use serde_json::Map as JsonMap;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// Content of the events that can be found in the state or the timeline of a room
+#[derive(Clone, Debug)]
+pub enum RoomEventContent {
+    Message(MessageEventContent),
+    Sticker(MessageEventContent),
+    Reaction(ReactionEventContent),
+    Redaction(RedactionEventContent),
+    Member(MemberEventContent),
+    Name(NameEventContent),
+    Topic(TopicEventContent),
+    Avatar(AvatarEventContent),
+    CanonicalAlias(CanonicalAliasEventContent),
+    PowerLevels(PowerLevelsEventContent),
+    Create(CreateEventContent),
+    JoinRules(JoinRulesEventContent),
+    HistoryVisibility(HistoryVisibilityEventContent),
+    GuestAccess(GuestAccessEventContent),
+    Tombstone(TombstoneEventContent),
+    Encryption(EncryptionEventContent),
+    Encrypted(EncryptedEventContent),
+    Custom(CustomEventContent),
+}
+
+impl EventContent for RoomEventContent {
+    fn from_parts(event_type: &str, content: JsonValue) -> Result<Self, JsonError> {
+        Ok(match event_type {
+            "m.room.message" => Self::Message(serde_json::from_value(content)?),
+            "m.sticker" => Self::Sticker(serde_json::from_value(content)?),
+            "m.reaction" => Self::Reaction(serde_json::from_value(content)?),
+            "m.room.redaction" => Self::Redaction(serde_json::from_value(content)?),
+            "m.room.member" => Self::Member(serde_json::from_value(content)?),
+            "m.room.name" => Self::Name(serde_json::from_value(content)?),
+            "m.room.topic" => Self::Topic(serde_json::from_value(content)?),
+            "m.room.avatar" => Self::Avatar(serde_json::from_value(content)?),
+            "m.room.canonical_alias" => Self::CanonicalAlias(serde_json::from_value(content)?),
+            "m.room.power_levels" => Self::PowerLevels(serde_json::from_value(content)?),
+            "m.room.create" => Self::Create(serde_json::from_value(content)?),
+            "m.room.join_rules" => Self::JoinRules(serde_json::from_value(content)?),
+            "m.room.history_visibility" => {
+                Self::HistoryVisibility(serde_json::from_value(content)?)
+            }
+            "m.room.guest_access" => Self::GuestAccess(serde_json::from_value(content)?),
+            "m.room.tombstone" => Self::Tombstone(serde_json::from_value(content)?),
+            "m.room.encryption" => Self::Encryption(serde_json::from_value(content)?),
+            "m.room.encrypted" => Self::Encrypted(serde_json::from_value(content)?),
+            _ => Self::Custom(CustomEventContent {
+                event_type: event_type.into(),
+                content,
+            }),
+        })
+    }
+}
+
+// Every field has a default value because redacted messages come
+// with an empty content
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MessageEventContent {
+    #[serde(default)]
+    pub msgtype: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted_body: Option<String>,
+    #[serde(rename = "m.relates_to")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relates_to: Option<JsonValue>,
+    #[serde(flatten)]
+    pub extra: JsonMap<String, JsonValue>,
+}
+
+// Redacted reactions come with an empty content
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ReactionEventContent {
+    #[serde(rename = "m.relates_to")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relates_to: Option<JsonValue>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RedactionEventContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MembershipState {
+    Join,
+    Invite,
+    Leave,
+    Ban,
+    Knock,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MemberEventContent {
+    pub membership: MembershipState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub displayname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    #[serde(default)]
+    pub is_direct: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NameEventContent {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TopicEventContent {
+    #[serde(default)]
+    pub topic: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AvatarEventContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CanonicalAliasEventContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alt_aliases: Vec<String>,
+}
+
+fn default_power_level_50() -> i64 {
+    50
+}
+
+/// Older room versions let the levels be strings, as long as they hold an integer
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PowerLevel {
+    Int(i64),
+    Str(String),
+}
+
+impl PowerLevel {
+    fn value(self) -> Option<i64> {
+        match self {
+            Self::Int(level) => Some(level),
+            Self::Str(level) => level.trim().parse().ok(),
+        }
+    }
+}
+
+fn deserialize_power_level<'de, D>(de: D) -> Result<i64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    PowerLevel::deserialize(de)?
+        .value()
+        .ok_or_else(|| D::Error::custom("the power level isn't an integer"))
+}
+
+/// The entries with a level we can't read are left out, a single bad one shouldn't cost
+/// us the whole event
+fn deserialize_power_levels<'de, D>(de: D) -> Result<HashMap<String, i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let levels: HashMap<String, JsonValue> = Deserialize::deserialize(de)?;
+
+    Ok(levels
+        .into_iter()
+        .filter_map(|(key, level)| {
+            let level = serde_json::from_value::<PowerLevel>(level).ok()?.value()?;
+            Some((key, level))
+        })
+        .collect())
+}
+
+/// Like the other levels, but the users that aren't valid ids are left out too
+fn deserialize_user_power_levels<'de, D>(de: D) -> Result<HashMap<UserId, i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(deserialize_power_levels(de)?
+        .into_iter()
+        .filter_map(|(uid, level)| Some((UserId::try_from(uid.as_str()).ok()?, level)))
+        .collect())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PowerLevelsEventContent {
+    #[serde(default = "default_power_level_50")]
+    #[serde(deserialize_with = "deserialize_power_level")]
+    pub ban: i64,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_power_levels")]
+    pub events: HashMap<String, i64>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_power_level")]
+    pub events_default: i64,
+    #[serde(default = "default_power_level_50")]
+    #[serde(deserialize_with = "deserialize_power_level")]
+    pub invite: i64,
+    #[serde(default = "default_power_level_50")]
+    #[serde(deserialize_with = "deserialize_power_level")]
+    pub kick: i64,
+    #[serde(default = "default_power_level_50")]
+    #[serde(deserialize_with = "deserialize_power_level")]
+    pub redact: i64,
+    #[serde(default = "default_power_level_50")]
+    #[serde(deserialize_with = "deserialize_power_level")]
+    pub state_default: i64,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_user_power_levels")]
+    pub users: HashMap<UserId, i64>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_power_level")]
+    pub users_default: i64,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_power_levels")]
+    pub notifications: HashMap<String, i64>,
+}
+
+impl Default for PowerLevelsEventContent {
+    fn default() -> Self {
+        Self {
+            ban: 50,
+            events: Default::default(),
+            events_default: 0,
+            invite: 50,
+            kick: 50,
+            redact: 50,
+            state_default: 50,
+            users: Default::default(),
+            users_default: 0,
+            notifications: Default::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PreviousRoom {
+    pub room_id: RoomId,
+    pub event_id: EventId,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CreateEventContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creator: Option<UserId>,
+    #[serde(rename = "m.federate")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub federate: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub predecessor: Option<PreviousRoom>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinRule {
+    Public,
+    Knock,
+    Invite,
+    Private,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JoinRulesEventContent {
+    pub join_rule: JoinRule,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryVisibility {
+    Invited,
+    Joined,
+    Shared,
+    WorldReadable,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HistoryVisibilityEventContent {
+    pub history_visibility: HistoryVisibility,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GuestAccess {
+    CanJoin,
+    Forbidden,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GuestAccessEventContent {
+    pub guest_access: GuestAccess,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TombstoneEventContent {
+    pub body: String,
+    pub replacement_room: RoomId,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EncryptionEventContent {
+    pub algorithm: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation_period_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation_period_msgs: Option<u64>,
+}
+
+// The ciphertext is an object for Olm and a string for Megolm, the
+// decryption is left to the client. Like for messages, the fields have a
+// default value because redacted events come with an empty content.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct EncryptedEventContent {
+    #[serde(default)]
+    pub algorithm: String,
+    #[serde(default)]
+    pub sender_key: String,
+    #[serde(default)]
+    pub ciphertext: JsonValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{deserialize_valid_events, RoomEvent, StateEvent, StrippedStateEvent};
+    use serde_json::json;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn room_message_event() {
+        // From the examples of the spec
+        let event = RoomEvent::try_from(json!({
+            "content": {
+                "body": "This is an example text message",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "<b>This is an example text message</b>",
+                "msgtype": "m.text"
+            },
+            "event_id": "$143273582443PhrSn:example.org",
+            "origin_server_ts": 1432735824653u64,
+            "room_id": "!jEsUZKDJdhlrceRyVU:example.org",
+            "sender": "@example:example.org",
+            "type": "m.room.message",
+            "unsigned": {
+                "age": 1234
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(event.event_type, "m.room.message");
+        assert_eq!(event.event_id.to_string(), "$143273582443PhrSn:example.org");
+        assert_eq!(event.sender.to_string(), "@example:example.org");
+        assert_eq!(event.origin_server_ts, 1432735824653);
+        assert_eq!(event.state_key, None);
+        assert!(!event.is_redacted());
+        match event.content {
+            RoomEventContent::Message(content) => {
+                assert_eq!(content.msgtype, "m.text");
+                assert_eq!(content.body, "This is an example text message");
+                assert_eq!(content.format.as_deref(), Some("org.matrix.custom.html"));
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+    }
+
+    #[test]
+    fn room_state_events() {
+        let event = StateEvent::try_from(json!({
+            "content": {
+                "avatar_url": "mxc://example.org/SEsfnsuifSDFSSEF",
+                "displayname": "Alice Margatroid",
+                "membership": "join"
+            },
+            "event_id": "$143273582443PhrSn:example.org",
+            "origin_server_ts": 1432735824653u64,
+            "room_id": "!jEsUZKDJdhlrceRyVU:example.org",
+            "sender": "@example:example.org",
+            "state_key": "@alice:example.org",
+            "type": "m.room.member",
+            "unsigned": {
+                "age": 1234
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(event.state_key, "@alice:example.org");
+        match event.content {
+            RoomEventContent::Member(content) => {
+                assert_eq!(content.membership, MembershipState::Join);
+                assert_eq!(content.displayname.as_deref(), Some("Alice Margatroid"));
+                assert!(!content.is_direct);
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+
+        let event = StateEvent::try_from(json!({
+            "content": {
+                "ban": 50,
+                "events": {
+                    "m.room.name": 100,
+                    "m.room.power_levels": 100
+                },
+                "events_default": 0,
+                "invite": 50,
+                "kick": 50,
+                "notifications": {
+                    "room": 20
+                },
+                "redact": 50,
+                "state_default": 50,
+                "users": {
+                    "@example:localhost": 100
+                },
+                "users_default": 0
+            },
+            "event_id": "$143273582443PhrSn:example.org",
+            "origin_server_ts": 1432735824653u64,
+            "room_id": "!jEsUZKDJdhlrceRyVU:example.org",
+            "sender": "@example:example.org",
+            "state_key": "",
+            "type": "m.room.power_levels"
+        }))
+        .unwrap();
+
+        match event.content {
+            RoomEventContent::PowerLevels(content) => {
+                assert_eq!(content.events.get("m.room.name"), Some(&100));
+                assert_eq!(content.notifications.get("room"), Some(&20));
+                assert_eq!(content.users.len(), 1);
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+
+        let event = StrippedStateEvent::try_from(json!({
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "rotation_period_ms": 604800000,
+                "rotation_period_msgs": 100
+            },
+            "sender": "@example:example.org",
+            "state_key": "",
+            "type": "m.room.encryption"
+        }))
+        .unwrap();
+
+        match event.content {
+            RoomEventContent::Encryption(content) => {
+                assert_eq!(content.algorithm, "m.megolm.v1.aes-sha2");
+                assert_eq!(content.rotation_period_msgs, Some(100));
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+    }
+
+    #[test]
+    fn room_unknown_event_type() {
+        let event = RoomEvent::try_from(json!({
+            "content": {
+                "answer": 42
+            },
+            "event_id": "$143273582443PhrSn:example.org",
+            "origin_server_ts": 1432735824653u64,
+            "sender": "@example:example.org",
+            "type": "org.example.custom"
+        }))
+        .unwrap();
+
+        match event.content {
+            RoomEventContent::Custom(content) => {
+                assert_eq!(content.event_type, "org.example.custom");
+                assert_eq!(content.content["answer"], 42);
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+    }
+
+    #[test]
+    fn room_missing_fields() {
+        // Redacted messages have an empty content
+        let event = RoomEvent::try_from(json!({
+            "content": {},
+            "event_id": "$143273582443PhrSn:example.org",
+            "origin_server_ts": 1432735824653u64,
+            "sender": "@example:example.org",
+            "type": "m.room.message",
+            "unsigned": {
+                "redacted_because": {}
+            }
+        }))
+        .unwrap();
+        assert!(event.is_redacted());
+        assert!(
+            matches!(event.content, RoomEventContent::Message(ref content) if content.body.is_empty())
+        );
+
+        // Without an id
+        assert!(RoomEvent::try_from(json!({
+            "content": {
+                "body": "Hello",
+                "msgtype": "m.text"
+            },
+            "origin_server_ts": 1432735824653u64,
+            "sender": "@example:example.org",
+            "type": "m.room.message"
+        }))
+        .is_err());
+
+        // Without a membership
+        assert!(StateEvent::try_from(json!({
+            "content": {
+                "displayname": "Alice Margatroid"
+            },
+            "sender": "@example:example.org",
+            "state_key": "@alice:example.org",
+            "type": "m.room.member"
+        }))
+        .is_err());
+
+        // Without a type
+        assert!(StateEvent::try_from(json!({
+            "content": {},
+            "sender": "@example:example.org",
+            "state_key": ""
+        }))
+        .is_err());
+
+        // Without a state key
+        assert!(StateEvent::try_from(json!({
+            "content": {
+                "name": "The room name"
+            },
+            "sender": "@example:example.org",
+            "type": "m.room.name"
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn room_lenient_power_levels() {
+        let event = StateEvent::try_from(json!({
+            "content": {
+                "ban": "75",
+                "events": {
+                    "m.room.name": "100",
+                    "m.room.topic": "a lot"
+                },
+                "kick": 60,
+                "users": {
+                    "@example:example.org": "100",
+                    "not a user id": 50,
+                    "@bob:example.org": 1.5
+                }
+            },
+            "sender": "@example:example.org",
+            "state_key": "",
+            "type": "m.room.power_levels"
+        }))
+        .unwrap();
+
+        match event.content {
+            RoomEventContent::PowerLevels(content) => {
+                assert_eq!(content.ban, 75);
+                assert_eq!(content.kick, 60);
+                assert_eq!(content.redact, 50);
+                assert_eq!(content.events.get("m.room.name"), Some(&100));
+                assert_eq!(content.events.get("m.room.topic"), None);
+                assert_eq!(content.users.len(), 1);
+                assert_eq!(
+                    content
+                        .users
+                        .get(&UserId::try_from("@example:example.org").unwrap()),
+                    Some(&100)
+                );
+            }
+            content => panic!("Wrong content: {:?}", content),
+        }
+
+        // A level that isn't an integer at all
+        assert!(StateEvent::try_from(json!({
+            "content": {
+                "ban": "everyone"
+            },
+            "sender": "@example:example.org",
+            "state_key": "",
+            "type": "m.room.power_levels"
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn room_invalid_events_are_dropped() {
+        let events = json!([
+            {
+                "content": {
+                    "name": "The room name"
+                },
+                "sender": "@example:example.org",
+                "state_key": "",
+                "type": "m.room.name"
+            },
+            {
+                "content": {
+                    "join_rule": "everyone"
+                },
+                "sender": "@example:example.org",
+                "state_key": "",
+                "type": "m.room.join_rules"
+            },
+            {
+                "content": {
+                    "topic": "A room topic"
+                },
+                "sender": "not a user id",
+                "state_key": "",
+                "type": "m.room.topic"
+            }
+        ]);
+        let events: Vec<StateEvent> = deserialize_valid_events(events).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "m.room.name");
+    }
+}
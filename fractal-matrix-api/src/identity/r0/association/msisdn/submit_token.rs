@@ -21,5 +21,10 @@ pub fn request(base: Url, body: &Body) -> Result<Request, Error> {
         .join("_matrix/identity/api/v1/validate/msisdn/submitToken")
         .expect("Malformed URL in msisdn submit_token");
 
+    request_to(url, body)
+}
+
+/// Submits the token to the `submit_url` given by the homeserver, it takes the same body
+pub fn request_to(url: Url, body: &Body) -> Result<Request, Error> {
     Client::new().post(url).json(body).build()
 }
@@ -1,3 +1,5 @@
+pub mod error;
+pub mod events;
 #[macro_use]
 pub mod identity;
 pub mod r0;
@@ -2,12 +2,15 @@ pub mod account;
 pub mod config;
 pub mod contact;
 pub mod context;
+pub mod device;
 pub mod directory;
 pub mod filter;
+pub mod keys;
 pub mod media;
 pub mod membership;
 pub mod message;
 pub mod profile;
+pub mod pushrules;
 pub mod read_marker;
 pub mod redact;
 pub mod room;
@@ -17,6 +20,7 @@ pub mod state;
 pub mod sync;
 pub mod tag;
 pub mod thirdparty;
+pub mod to_device;
 pub mod typing;
 
 use serde::{Deserialize, Serialize, Serializer};
@@ -1,11 +1,18 @@
 pub mod change_password;
 pub mod deactivate;
+pub mod get_login_types;
+pub mod get_username_availability;
 pub mod login;
 pub mod logout;
 pub mod register;
+pub mod request_registration_token_email;
+pub mod request_registration_token_msisdn;
 
 use crate::r0::{Medium, ThreePIDCredentials};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use url::Url;
 
 #[derive(Clone, Debug, Serialize)]
 #[serde(tag = "type")]
@@ -93,9 +100,78 @@ pub enum AuthenticationData {
         #[serde(skip_serializing_if = "Option::is_none")]
         session: Option<String>,
     },
+    #[serde(rename = "m.login.msisdn")]
+    Msisdn {
+        threepid_creds: ThreePIDCredentials,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session: Option<String>,
+    },
+    #[serde(rename = "m.login.terms")]
+    Terms {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session: Option<String>,
+    },
     #[serde(rename = "m.login.dummy")]
     Dummy {
         #[serde(skip_serializing_if = "Option::is_none")]
         session: Option<String>,
     },
 }
+
+/// The `auth` field of the requests protected by user-interactive
+/// authentication
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum AuthData {
+    Stage(AuthenticationData),
+    /// Sent after a stage was completed in its fallback web page
+    FallbackAcknowledgement {
+        session: String,
+    },
+}
+
+impl From<AuthenticationData> for AuthData {
+    fn from(data: AuthenticationData) -> Self {
+        Self::Stage(data)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AuthFlow {
+    pub stages: Vec<String>,
+}
+
+/// The body of the 401 responses asking for user-interactive authentication
+#[derive(Clone, Debug, Deserialize)]
+pub struct UiaaResponse {
+    pub flows: Vec<AuthFlow>,
+    #[serde(default)]
+    pub completed: Vec<String>,
+    pub session: Option<String>,
+    #[serde(default)]
+    pub params: HashMap<String, JsonValue>,
+    pub errcode: Option<String>,
+    pub error: Option<String>,
+}
+
+/// The page of the homeserver that starts the single sign-on and ends with a
+/// redirection to `redirect_url` with a `loginToken` parameter
+pub fn sso_redirect_url(base: &Url, redirect_url: &Url) -> Url {
+    let mut url = base
+        .join("_matrix/client/r0/login/sso/redirect")
+        .expect("Malformed URL in sso_redirect_url");
+    url.query_pairs_mut()
+        .append_pair("redirectUrl", redirect_url.as_str());
+
+    url
+}
+
+/// The web page used to complete the stages a client can't do natively
+pub fn auth_fallback_url(base: &Url, stage: &str, session: &str) -> Url {
+    let mut url = base
+        .join(&format!("_matrix/client/r0/auth/{}/fallback/web", stage))
+        .expect("Malformed URL in auth_fallback_url");
+    url.query_pairs_mut().append_pair("session", session);
+
+    url
+}
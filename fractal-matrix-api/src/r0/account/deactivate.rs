@@ -1,4 +1,4 @@
-use super::AuthenticationData;
+use super::AuthData;
 use crate::r0::AccessToken;
 use reqwest::blocking::Client;
 use reqwest::blocking::Request;
@@ -14,7 +14,7 @@ pub struct Parameters {
 #[derive(Clone, Debug, Serialize)]
 pub struct Body {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub auth: Option<AuthenticationData>,
+    pub auth: Option<AuthData>,
 }
 
 pub fn request(base: Url, params: &Parameters, body: &Body) -> Result<Request, Error> {
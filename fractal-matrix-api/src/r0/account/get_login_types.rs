@@ -0,0 +1,25 @@
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use serde::Deserialize;
+use url::Url;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    #[serde(default)]
+    pub flows: Vec<LoginFlow>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LoginFlow {
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+pub fn request(base: Url) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/client/r0/login")
+        .expect("Malformed URL in get_login_types");
+
+    Client::new().get(url).build()
+}
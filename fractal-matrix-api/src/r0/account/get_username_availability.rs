@@ -0,0 +1,23 @@
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    pub available: bool,
+}
+
+pub fn request(base: Url, params: &Parameters) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/client/r0/register/available")
+        .expect("Malformed URL in get_username_availability");
+
+    Client::new().get(url).query(params).build()
+}
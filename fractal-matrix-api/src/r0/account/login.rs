@@ -10,8 +10,8 @@ use url::Url;
 
 #[derive(Clone, Debug, Serialize)]
 pub struct Body {
-    #[serde(flatten)]
-    pub identifier: Identifier,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<Identifier>,
     #[serde(flatten)]
     pub auth: Auth,
     #[serde(skip_serializing_if = "Option::is_none")]
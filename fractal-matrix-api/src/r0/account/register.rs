@@ -1,4 +1,4 @@
-use super::AuthenticationData;
+use super::AuthData;
 use crate::r0::AccessToken;
 use reqwest::blocking::Client;
 use reqwest::blocking::Request;
@@ -38,7 +38,7 @@ impl RegistrationKind {
 #[derive(Clone, Debug, Default, Serialize)]
 pub struct Body {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub auth: Option<AuthenticationData>,
+    pub auth: Option<AuthData>,
     #[serde(skip_serializing_if = "Not::not")]
     pub bind_email: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
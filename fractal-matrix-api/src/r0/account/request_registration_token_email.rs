@@ -0,0 +1,29 @@
+use crate::r0::HostAndPort;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body {
+    pub client_secret: String,
+    pub email: String,
+    pub id_server: HostAndPort<String>,
+    pub send_attempt: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_link: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    pub sid: String,
+}
+
+pub fn request(base: Url, body: &Body) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/client/r0/register/email/requestToken")
+        .expect("Malformed URL in request_registration_token_email");
+
+    Client::new().post(url).json(body).build()
+}
@@ -0,0 +1,16 @@
+pub mod delete_device;
+pub mod delete_devices;
+pub mod get_device;
+pub mod get_devices;
+pub mod update_device;
+
+use ruma_identifiers::DeviceId;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Device {
+    pub device_id: Box<DeviceId>,
+    pub display_name: Option<String>,
+    pub last_seen_ip: Option<String>,
+    pub last_seen_ts: Option<u64>,
+}
@@ -0,0 +1,28 @@
+use crate::r0::account::AuthData;
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::DeviceId;
+use serde::Serialize;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body {
+    pub devices: Vec<Box<DeviceId>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthData>,
+}
+
+pub fn request(base: Url, params: &Parameters, body: &Body) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/client/r0/delete_devices")
+        .expect("Malformed URL in delete_devices");
+
+    Client::new().post(url).query(params).json(body).build()
+}
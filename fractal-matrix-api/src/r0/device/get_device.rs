@@ -0,0 +1,23 @@
+use super::Device;
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::DeviceId;
+use serde::Serialize;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+pub type Response = Device;
+
+pub fn request(base: Url, params: &Parameters, device_id: &DeviceId) -> Result<Request, Error> {
+    let url = base
+        .join(&format!("_matrix/client/r0/devices/{}", device_id))
+        .expect("Malformed URL in get_device");
+
+    Client::new().get(url).query(params).build()
+}
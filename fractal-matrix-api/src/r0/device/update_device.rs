@@ -0,0 +1,31 @@
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::DeviceId;
+use serde::Serialize;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+}
+
+pub fn request(
+    base: Url,
+    params: &Parameters,
+    body: &Body,
+    device_id: &DeviceId,
+) -> Result<Request, Error> {
+    let url = base
+        .join(&format!("_matrix/client/r0/devices/{}", device_id))
+        .expect("Malformed URL in update_device");
+
+    Client::new().put(url).query(params).json(body).build()
+}
@@ -1 +1,5 @@
+pub mod create_room_alias;
+pub mod delete_room_alias;
+pub mod get_room_visibility;
 pub mod post_public_rooms;
+pub mod set_room_visibility;
@@ -0,0 +1,34 @@
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::{RoomAliasId, RoomId};
+use serde::Serialize;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body {
+    pub room_id: RoomId,
+}
+
+pub fn request(
+    base: Url,
+    room_alias: &RoomAliasId,
+    params: &Parameters,
+    body: &Body,
+) -> Result<Request, Error> {
+    let encoded_alias = room_alias.to_string().replace("#", "%23");
+    let url = base
+        .join(&format!(
+            "_matrix/client/r0/directory/room/{}",
+            encoded_alias
+        ))
+        .expect("Malformed URL in create_room_alias");
+
+    Client::new().put(url).query(params).json(body).build()
+}
@@ -0,0 +1,23 @@
+use crate::r0::room::Visibility;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::RoomId;
+use serde::Deserialize;
+use url::Url;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    pub visibility: Visibility,
+}
+
+pub fn request(base: Url, room_id: &RoomId) -> Result<Request, Error> {
+    let url = base
+        .join(&format!(
+            "_matrix/client/r0/directory/list/room/{}",
+            room_id
+        ))
+        .expect("Malformed URL in get_room_visibility");
+
+    Client::new().get(url).build()
+}
@@ -0,0 +1,34 @@
+use crate::r0::room::Visibility;
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::RoomId;
+use serde::Serialize;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body {
+    pub visibility: Visibility,
+}
+
+pub fn request(
+    base: Url,
+    room_id: &RoomId,
+    params: &Parameters,
+    body: &Body,
+) -> Result<Request, Error> {
+    let url = base
+        .join(&format!(
+            "_matrix/client/r0/directory/list/room/{}",
+            room_id
+        ))
+        .expect("Malformed URL in set_room_visibility");
+
+    Client::new().put(url).query(params).json(body).build()
+}
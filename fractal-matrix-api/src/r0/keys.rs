@@ -0,0 +1,3 @@
+pub mod claim_keys;
+pub mod get_keys;
+pub mod upload_keys;
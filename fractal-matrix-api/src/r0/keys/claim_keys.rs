@@ -0,0 +1,38 @@
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::{DeviceId, UserId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    // The algorithm of the key to claim for each device
+    pub one_time_keys: HashMap<UserId, HashMap<Box<DeviceId>, String>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    #[serde(default)]
+    pub failures: HashMap<String, JsonValue>,
+    #[serde(default)]
+    pub one_time_keys: HashMap<UserId, HashMap<Box<DeviceId>, HashMap<String, JsonValue>>>,
+}
+
+pub fn request(base: Url, params: &Parameters, body: &Body) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/client/r0/keys/claim")
+        .expect("Malformed URL in claim_keys");
+
+    Client::new().post(url).query(params).json(body).build()
+}
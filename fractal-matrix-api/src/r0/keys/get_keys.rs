@@ -0,0 +1,41 @@
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::{DeviceId, UserId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    // An empty list of devices means all the devices of the user
+    pub device_keys: HashMap<UserId, Vec<Box<DeviceId>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    #[serde(default)]
+    pub failures: HashMap<String, JsonValue>,
+    // The signed device keys, checked by the crypto layer
+    #[serde(default)]
+    pub device_keys: HashMap<UserId, HashMap<Box<DeviceId>, JsonValue>>,
+}
+
+pub fn request(base: Url, params: &Parameters, body: &Body) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/client/r0/keys/query")
+        .expect("Malformed URL in get_keys");
+
+    Client::new().post(url).query(params).json(body).build()
+}
@@ -0,0 +1,36 @@
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Body {
+    // Signed device keys, as described in the spec
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_keys: Option<JsonValue>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub one_time_keys: HashMap<String, JsonValue>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    #[serde(default)]
+    pub one_time_key_counts: HashMap<String, u64>,
+}
+
+pub fn request(base: Url, params: &Parameters, body: &Body) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/client/r0/keys/upload")
+        .expect("Malformed URL in upload_keys");
+
+    Client::new().post(url).query(params).json(body).build()
+}
@@ -1,3 +1,6 @@
+pub mod ban_user;
 pub mod invite_user;
 pub mod join_room_by_id_or_alias;
+pub mod kick_user;
 pub mod leave_room;
+pub mod unban_user;
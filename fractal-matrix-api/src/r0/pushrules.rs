@@ -0,0 +1,175 @@
+pub mod delete_pushrule;
+pub mod get_pushrules;
+pub mod set_pushrule;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::fmt::{self, Display, Formatter};
+
+/// The push rules of a scope, grouped by kind
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Ruleset {
+    #[serde(default, rename = "override")]
+    pub override_: Vec<PushRule>,
+    #[serde(default)]
+    pub content: Vec<PushRule>,
+    #[serde(default)]
+    pub room: Vec<PushRule>,
+    #[serde(default)]
+    pub sender: Vec<PushRule>,
+    #[serde(default)]
+    pub underride: Vec<PushRule>,
+}
+
+/// The kinds of push rules, in the order they are evaluated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    Override,
+    Content,
+    Room,
+    Sender,
+    Underride,
+}
+
+impl RuleKind {
+    pub const ALL: [RuleKind; 5] = [
+        RuleKind::Override,
+        RuleKind::Content,
+        RuleKind::Room,
+        RuleKind::Sender,
+        RuleKind::Underride,
+    ];
+}
+
+impl Display for RuleKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let kind = match self {
+            RuleKind::Override => "override",
+            RuleKind::Content => "content",
+            RuleKind::Room => "room",
+            RuleKind::Sender => "sender",
+            RuleKind::Underride => "underride",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+impl Ruleset {
+    pub fn rules(&self, kind: RuleKind) -> &Vec<PushRule> {
+        match kind {
+            RuleKind::Override => &self.override_,
+            RuleKind::Content => &self.content,
+            RuleKind::Room => &self.room,
+            RuleKind::Sender => &self.sender,
+            RuleKind::Underride => &self.underride,
+        }
+    }
+
+    pub fn rules_mut(&mut self, kind: RuleKind) -> &mut Vec<PushRule> {
+        match kind {
+            RuleKind::Override => &mut self.override_,
+            RuleKind::Content => &mut self.content,
+            RuleKind::Room => &mut self.room,
+            RuleKind::Sender => &mut self.sender,
+            RuleKind::Underride => &mut self.underride,
+        }
+    }
+
+    pub fn find(&self, kind: RuleKind, rule_id: &str) -> Option<&PushRule> {
+        self.rules(kind).iter().find(|rule| rule.rule_id == rule_id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PushRule {
+    pub rule_id: String,
+    #[serde(default)]
+    pub default: bool,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    pub actions: Vec<Action>,
+    /// Only for override and underride rules, the rules of other kinds have implicit
+    /// conditions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<PushCondition>>,
+    /// Only for content rules, a glob matched against the body of messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PushCondition {
+    EventMatch {
+        key: String,
+        pattern: String,
+    },
+    ContainsDisplayName,
+    /// `is` is a number with an optional comparison prefix: `==`, `<`, `>`, `<=` or `>=`
+    RoomMemberCount {
+        is: String,
+    },
+    SenderNotificationPermission {
+        key: String,
+    },
+    /// Rules with conditions we don't know about never match
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(from = "JsonValue", into = "JsonValue")]
+pub enum Action {
+    Notify,
+    DontNotify,
+    Coalesce,
+    /// The `highlight` tweak is enabled when it has no value
+    SetTweak {
+        set_tweak: String,
+        value: Option<JsonValue>,
+    },
+    Unknown(JsonValue),
+}
+
+impl From<JsonValue> for Action {
+    fn from(value: JsonValue) -> Self {
+        match value.as_str() {
+            Some("notify") => return Action::Notify,
+            Some("dont_notify") => return Action::DontNotify,
+            Some("coalesce") => return Action::Coalesce,
+            _ => {}
+        }
+
+        match value["set_tweak"].as_str() {
+            Some(tweak) => Action::SetTweak {
+                set_tweak: tweak.to_string(),
+                value: value.get("value").cloned(),
+            },
+            None => Action::Unknown(value),
+        }
+    }
+}
+
+impl From<Action> for JsonValue {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Notify => json!("notify"),
+            Action::DontNotify => json!("dont_notify"),
+            Action::Coalesce => json!("coalesce"),
+            Action::SetTweak {
+                set_tweak,
+                value: Some(value),
+            } => json!({ "set_tweak": set_tweak, "value": value }),
+            Action::SetTweak {
+                set_tweak,
+                value: None,
+            } => json!({ "set_tweak": set_tweak }),
+            Action::Unknown(value) => value,
+        }
+    }
+}
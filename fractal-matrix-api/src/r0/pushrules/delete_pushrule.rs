@@ -0,0 +1,29 @@
+use super::RuleKind;
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use serde::Serialize;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+pub fn request(
+    base: Url,
+    kind: RuleKind,
+    rule_id: &str,
+    params: &Parameters,
+) -> Result<Request, Error> {
+    let mut url = base
+        .join(&format!("_matrix/client/r0/pushrules/global/{}/", kind))
+        .expect("Malformed URL in delete_pushrule");
+    url.path_segments_mut()
+        .expect("Malformed URL in delete_pushrule")
+        .pop_if_empty()
+        .push(rule_id);
+
+    Client::new().delete(url).query(params).build()
+}
@@ -0,0 +1,45 @@
+use super::{Action, PushCondition, RuleKind};
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use serde::Serialize;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+    /// The rule ID of the rule this one should be evaluated before
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    /// The rule ID of the rule this one should be evaluated after
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body {
+    pub actions: Vec<Action>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<PushCondition>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+}
+
+pub fn request(
+    base: Url,
+    kind: RuleKind,
+    rule_id: &str,
+    params: &Parameters,
+    body: &Body,
+) -> Result<Request, Error> {
+    let mut url = base
+        .join(&format!("_matrix/client/r0/pushrules/global/{}/", kind))
+        .expect("Malformed URL in set_pushrule");
+    url.path_segments_mut()
+        .expect("Malformed URL in set_pushrule")
+        .pop_if_empty()
+        .push(rule_id);
+
+    Client::new().put(url).query(params).json(body).build()
+}
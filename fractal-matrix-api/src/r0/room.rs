@@ -1,8 +1,9 @@
 pub mod create_room;
+pub mod upgrade_room;
 
-pub use serde::Serialize;
+pub use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Visibility {
     Public,
@@ -0,0 +1,152 @@
+use crate::r0::filter::RoomEventFilter;
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::RoomId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::ops::Not;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_batch: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body<'a> {
+    pub search_categories: Categories<'a>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Categories<'a> {
+    pub room_events: RoomEventsCriteria<'a>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RoomEventsCriteria<'a> {
+    pub search_term: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<Vec<Key>>,
+    #[serde(skip_serializing_if = "RoomEventFilter::is_default")]
+    pub filter: RoomEventFilter<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_by: Option<OrderBy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_context: Option<EventContext>,
+    #[serde(skip_serializing_if = "Not::not")]
+    pub include_state: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groupings: Option<Groupings>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Key {
+    #[serde(rename = "content.body")]
+    ContentBody,
+    #[serde(rename = "content.name")]
+    ContentName,
+    #[serde(rename = "content.topic")]
+    ContentTopic,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderBy {
+    Recent,
+    Rank,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EventContext {
+    pub before_limit: u64,
+    pub after_limit: u64,
+    pub include_profile: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Groupings {
+    pub group_by: Vec<Grouping>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Grouping {
+    pub key: GroupingKey,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupingKey {
+    RoomId,
+    Sender,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    pub search_categories: ResultCategories,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ResultCategories {
+    #[serde(default)]
+    pub room_events: RoomEventsResults,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RoomEventsResults {
+    pub count: Option<u64>,
+    #[serde(default)]
+    pub highlights: Vec<String>,
+    #[serde(default)]
+    pub results: Vec<SearchResult>,
+    pub next_batch: Option<String>,
+    #[serde(default)]
+    pub groups: HashMap<String, HashMap<String, GroupValue>>,
+    #[serde(default)]
+    pub state: HashMap<RoomId, Vec<JsonValue>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SearchResult {
+    pub rank: Option<f64>,
+    pub result: JsonValue,
+    pub context: Option<EventContextResult>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EventContextResult {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    #[serde(default)]
+    pub events_before: Vec<JsonValue>,
+    #[serde(default)]
+    pub events_after: Vec<JsonValue>,
+    #[serde(default)]
+    pub profile_info: HashMap<String, UserProfile>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UserProfile {
+    pub displayname: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GroupValue {
+    pub next_batch: Option<String>,
+    pub order: Option<u64>,
+    #[serde(default)]
+    pub results: Vec<String>,
+}
+
+pub fn request(base: Url, params: &Parameters, body: &Body) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/client/r0/search")
+        .expect("Malformed URL in search_events");
+
+    Client::new().post(url).query(params).json(body).build()
+}
@@ -1 +1,2 @@
 pub mod domain_info;
+pub mod get_capabilities;
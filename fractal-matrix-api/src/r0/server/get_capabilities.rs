@@ -0,0 +1,38 @@
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    pub capabilities: Capabilities,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Capabilities {
+    #[serde(rename = "m.room_versions")]
+    pub room_versions: Option<RoomVersionsCapability>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RoomVersionsCapability {
+    pub default: String,
+    /// The stability of every version, either `stable` or `unstable`
+    pub available: BTreeMap<String, String>,
+}
+
+pub fn request(base: Url, params: &Parameters) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/client/r0/capabilities")
+        .expect("Malformed URL in get_capabilities");
+
+    Client::new().get(url).query(params).build()
+}
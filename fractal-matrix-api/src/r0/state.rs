@@ -1,2 +1,3 @@
 pub mod create_state_events_for_key;
+pub mod get_state_events;
 pub mod get_state_events_for_key;
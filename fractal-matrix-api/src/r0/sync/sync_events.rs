@@ -1,3 +1,8 @@
+use crate::events::deserialize_valid_events;
+use crate::events::{
+    AccountDataEvent, EphemeralEvent, PresenceEvent, RoomEvent, StateEvent, StrippedStateEvent,
+    ToDeviceEvent,
+};
 use crate::r0::filter::{serialize_filter_as_str, Filter};
 use crate::r0::AccessToken;
 use reqwest::blocking::Client;
@@ -6,7 +11,6 @@ use reqwest::Error;
 use ruma_identifiers::{RoomId, UserId};
 use serde::ser::SerializeMap;
 use serde::{Deserialize, Serialize, Serializer};
-use serde_json::Value as JsonValue;
 use std::collections::HashMap;
 use std::time::Duration;
 use url::Url;
@@ -135,9 +139,9 @@ pub struct JoinedRoom {
 
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct Ephemeral {
-    // TODO: Implement Event
     #[serde(default)]
-    pub events: Vec<JsonValue>,
+    #[serde(deserialize_with = "deserialize_valid_events")]
+    pub events: Vec<EphemeralEvent>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -156,9 +160,9 @@ pub struct InvitedRoom {
 
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct InviteState {
-    // TODO: Implement StrippedState
     #[serde(default)]
-    pub events: Vec<JsonValue>,
+    #[serde(deserialize_with = "deserialize_valid_events")]
+    pub events: Vec<StrippedStateEvent>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -173,9 +177,9 @@ pub struct LeftRoom {
 
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct State {
-    // TODO: Implement StateEvent
     #[serde(default)]
-    pub events: Vec<JsonValue>,
+    #[serde(deserialize_with = "deserialize_valid_events")]
+    pub events: Vec<StateEvent>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -183,23 +187,23 @@ pub struct Timeline {
     #[serde(default)]
     pub limited: bool,
     pub prev_batch: Option<String>,
-    // TODO: Implement RoomEvent
     #[serde(default)]
-    pub events: Vec<JsonValue>,
+    #[serde(deserialize_with = "deserialize_valid_events")]
+    pub events: Vec<RoomEvent>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Presence {
-    // TODO: Implement Event
     #[serde(default)]
-    pub events: Vec<JsonValue>,
+    #[serde(deserialize_with = "deserialize_valid_events")]
+    pub events: Vec<PresenceEvent>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct AccountData {
-    // TODO: Implement Event
     #[serde(default)]
-    pub events: Vec<JsonValue>,
+    #[serde(deserialize_with = "deserialize_valid_events")]
+    pub events: Vec<AccountDataEvent>,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -209,9 +213,9 @@ pub struct Language {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct ToDevice {
-    // TODO: Implement Event
     #[serde(default)]
-    pub events: Vec<JsonValue>,
+    #[serde(deserialize_with = "deserialize_valid_events")]
+    pub events: Vec<ToDeviceEvent>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
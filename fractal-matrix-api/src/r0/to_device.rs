@@ -0,0 +1 @@
+pub mod send_event_to_device;
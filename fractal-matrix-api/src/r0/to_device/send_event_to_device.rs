@@ -0,0 +1,37 @@
+use crate::r0::AccessToken;
+use reqwest::blocking::Client;
+use reqwest::blocking::Request;
+use reqwest::Error;
+use ruma_identifiers::{DeviceId, UserId};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Body {
+    // The content of the event for each device of each user
+    pub messages: HashMap<UserId, HashMap<Box<DeviceId>, JsonValue>>,
+}
+
+pub fn request(
+    base: Url,
+    params: &Parameters,
+    body: &Body,
+    event_type: &str,
+    txn_id: &str,
+) -> Result<Request, Error> {
+    let url = base
+        .join(&format!(
+            "_matrix/client/r0/sendToDevice/{}/{}",
+            event_type, txn_id
+        ))
+        .expect("Malformed URL in send_event_to_device");
+
+    Client::new().put(url).query(params).json(body).build()
+}